@@ -0,0 +1,677 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::RANGE;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use sha2::Digest;
+use tokio::io::AsyncWriteExt;
+
+use crate::checksum::{self, Sha256};
+use crate::client::{
+    check_keywords, content_type, field_fetches, index_url_for, is_field_failure, is_missing_from_source, latest_candidates, latest_or_fallback, name_target, planned_bytes, request_defaults,
+    request_url, resolve_urls, sas_token_url, select_index_entries, set_cycle, unsigned, uses_sas_token,
+    sort_fields_in_file_order, tree_parts, join_parts, writes_tree, write_fetch_parts, ClientOptions, FAST_WRITE_BUFFER, LatestSearch, MatchedField, RemoteVersion, Result, UrlReport,
+};
+use crate::dedup::Transfer;
+use crate::error::{Error, Result as EResult};
+use crate::events::{emit, DownloadEvent};
+use crate::http::{
+    build_async_http_client, check_data_response, check_host, redirect_error, without_query, HttpEvent, SourceHeaders,
+};
+use crate::local;
+use crate::param_catalog::check_params;
+use crate::probe::ProbeOutcome;
+use crate::ranges::{plan_fetches, split_url_ranges, Fetch};
+use crate::quota::{Usage, UsageMeter};
+use crate::rate_limit::RateLimiter;
+use crate::request::Request;
+use crate::sas::SasToken;
+use crate::target::{create_parent_dirs, target_path, Layout};
+use crate::telemetry::{self, RetryReason};
+use crate::throttle::{retry_after, Throttle};
+
+/// Async counterpart of [`crate::Client`], built on `reqwest::Client`, for use
+/// inside tokio services without `spawn_blocking` wrappers.
+///
+/// URL resolution and index selection are shared with the blocking client; it
+/// mirrors `retrieve*`, `download*` and `latest`. Requires the `async` feature.
+#[derive(Debug, Clone)]
+pub struct AsyncClient {
+    opts: ClientOptions,
+    base_url: String,
+    http: reqwest::Client,
+    /// Shared with clones, so a refresh by one is seen by all.
+    sas_token: Option<Arc<Mutex<SasToken>>>,
+    throttle: Arc<Throttle>,
+    rate_limiter: Arc<RateLimiter>,
+    /// Shared with clones and the fallback client.
+    usage: Arc<UsageMeter>,
+    source_headers: SourceHeaders,
+    fallback: Option<Box<AsyncClient>>,
+}
+
+impl AsyncClient {
+    pub async fn new(opts: ClientOptions) -> EResult<Self> {
+        let base_url = opts.source.base_url().to_string();
+        let http = build_async_http_client(&opts)?;
+        let source_headers = SourceHeaders::new(&opts)?;
+        let throttle = Arc::new(Throttle::new(opts.throttle_policy.clone(), opts.max_concurrent_ranges));
+        let rate_limiter = Arc::new(RateLimiter::new(opts.rate_limit.clone()));
+        let usage = Arc::new(UsageMeter::new(opts.quota));
+        let mut fallback = match &opts.fallback_source {
+            Some(source) => Some(Box::new(
+                Box::pin(AsyncClient::new(ClientOptions {
+                    source: source.clone(),
+                    fallback_source: None,
+                    headers: Vec::new(),
+                    ..opts.clone()
+                }))
+                .await?,
+            )),
+            None => None,
+        };
+        if let Some(fallback) = &mut fallback {
+            fallback.usage = usage.clone();
+        }
+
+        let mut client = Self {
+            base_url,
+            http,
+            opts,
+            sas_token: None,
+            throttle,
+            rate_limiter,
+            usage,
+            source_headers,
+            fallback,
+        };
+
+        if uses_sas_token(&client.opts) {
+            let token = client.get_azure_sas_token().await?;
+            client.sas_token = Some(Arc::new(Mutex::new(token)));
+        }
+
+        Ok(client)
+    }
+
+    pub async fn retrieve(&self, request: Request, target: impl Into<String>) -> EResult<Result> {
+        let target = target.into();
+        let use_index = !request.force_full_files;
+        self.within_deadline(async {
+            let res = self.get_urls(&request, use_index, Some(&target)).await?;
+            self.download_result(&res, use_index).await
+        })
+        .await
+    }
+
+    /// See [`crate::Client::retrieve_request`].
+    pub async fn retrieve_request(&self, request: Request) -> EResult<Result> {
+        let use_index = !request.force_full_files;
+        self.within_deadline(async {
+            let res = self.get_urls(&request, use_index, None).await?;
+            self.download_result(&res, use_index).await
+        })
+        .await
+    }
+
+    pub async fn download(&self, request: Request, target: impl Into<String>) -> EResult<Result> {
+        let target = target.into();
+        self.within_deadline(async {
+            let res = self.get_urls(&request, false, Some(&target)).await?;
+            self.download_result(&res, false).await
+        })
+        .await
+    }
+
+    /// See [`crate::Client::download_request`].
+    pub async fn download_request(&self, request: Request) -> EResult<Result> {
+        self.within_deadline(async {
+            let res = self.get_urls(&request, false, None).await?;
+            self.download_result(&res, false).await
+        })
+        .await
+    }
+
+    /// Run one call, bounded by [`ClientOptions::deadline`].
+    async fn within_deadline(&self, call: impl Future<Output = EResult<Result>>) -> EResult<Result> {
+        let Some(deadline) = self.opts.deadline else {
+            return call.await;
+        };
+        tokio::time::timeout(deadline, call)
+            .await
+            .unwrap_or(Err(Error::DeadlineExceeded(deadline)))
+    }
+
+    /// See [`crate::Client::latest`].
+    pub async fn latest(&self, request: Request) -> EResult<DateTime<Utc>> {
+        self.latest_inner(&request).await
+    }
+
+    async fn latest_inner(&self, request: &Request) -> EResult<DateTime<Utc>> {
+        let candidates = latest_candidates(request, self.opts.source.retention())?;
+        let mut search = LatestSearch::new(&candidates);
+        'candidates: for (candidate, tmp_req) in candidates {
+            let (model, params) = request_defaults(&self.opts, Some(&tmp_req));
+            let res = resolve_urls(&self.opts, &self.base_url, &model, &params, None, Vec::new())?;
+            if res.urls.is_empty() {
+                continue;
+            }
+            for (i, u) in self.opts.probe_target.urls(&self.opts, &res.urls).iter().enumerate() {
+                match self.probe(&self.request_url(u).await).await? {
+                    ProbeOutcome::Exists => {}
+                    ProbeOutcome::Absent(status) => {
+                        search.absent(candidate, status, i, u);
+                        continue 'candidates;
+                    }
+                    ProbeOutcome::Unavailable(status) | ProbeOutcome::Unexpected(status) => {
+                        return Err(Error::ProbeUnavailable {
+                            url: u.clone(),
+                            status,
+                        });
+                    }
+                }
+            }
+            return Ok(candidate);
+        }
+        Err(search.into_error())
+    }
+
+    /// Resolve a request, trying [`ClientOptions::fallback_source`] when
+    /// `source` lacks it.
+    async fn get_urls(&self, request: &Request, use_index: bool, target: Option<&str>) -> EResult<Result> {
+        match self.resolve_request(request, use_index, target).await {
+            Err(e) if is_missing_from_source(&e) && let Some(fallback) = &self.fallback => {
+                let mut res = Box::pin(fallback.get_urls(request, use_index, target)).await?;
+                res.warnings
+                    .push(format!("{} could not serve the request ({e}); used {}", self.opts.source, fallback.opts.source));
+                Ok(res)
+            }
+            outcome => outcome,
+        }
+    }
+
+    async fn resolve_request(&self, request: &Request, use_index: bool, target: Option<&str>) -> EResult<Result> {
+        if use_index && request.is_unguarded_full_file() {
+            return Err(Error::FullFileRequest);
+        }
+        if self.opts.strict_keywords {
+            check_keywords(request)?;
+        }
+        if self.opts.strict_params {
+            check_params(request, &self.opts.model)?;
+        }
+
+        let (model, mut params) = request_defaults(&self.opts, Some(request));
+        let mut warnings = Vec::new();
+        if !params.contains_key("date") {
+            let outcome = self.latest_inner(&Request::from_inner(params.clone())).await;
+            let latest = latest_or_fallback(&self.opts, &params, outcome, &mut warnings)?;
+            set_cycle(&mut params, latest);
+        }
+
+        let mut res = resolve_urls(&self.opts, &self.base_url, &model, &params, target, warnings)?;
+        res.order_by = request.order_by.clone();
+        res.fast_writes = request.fast_writes;
+        if use_index && !res.index_components.is_empty() {
+            let (urls, fields) = self
+                .expand_urls_to_ranges(&res.urls, &res.for_index(), &res.order_by, &mut res.warnings)
+                .await?;
+            res.urls = urls;
+            res.fields = fields;
+        }
+        name_target(&self.opts, &mut res);
+        Ok(res)
+    }
+
+    async fn expand_urls_to_ranges(
+        &self,
+        urls: &[String],
+        for_index: &BTreeMap<String, Vec<String>>,
+        order_by: &[String],
+        warnings: &mut Vec<String>,
+    ) -> EResult<(Vec<String>, Vec<MatchedField>)> {
+        let mut out = Vec::new();
+        let mut fields = Vec::new();
+        for url in urls {
+            let index_url = self.request_url(&index_url_for(url)).await;
+            let body = if let Some(path) = local::file_path(&index_url) {
+                if self.opts.is_track_file(url) && local::status(&path) == 404 {
+                    out.push(url.clone());
+                    continue;
+                }
+                String::from_utf8_lossy(&local::read_async(&path, None).await?).into_owned()
+            } else {
+                match self.get_throttled(&index_url, None).await {
+                    Ok(resp) => resp.text().await?,
+                    // Tropical cyclone tracks may be published without an index.
+                    Err(Error::Http(e))
+                        if self.opts.is_track_file(url) && e.status() == Some(StatusCode::NOT_FOUND) =>
+                    {
+                        out.push(url.clone());
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
+            if let Some((encoded, url_fields)) =
+                select_index_entries(&self.opts, url, &body, for_index, order_by, warnings)?
+            {
+                out.push(encoded);
+                fields.extend(url_fields);
+            }
+        }
+
+        if out.is_empty() {
+            return Err(Error::NoMatchingIndex);
+        }
+        Ok((out, fields))
+    }
+
+    async fn download_result(&self, res: &Result, is_partial: bool) -> EResult<Result> {
+        self.emit(DownloadEvent::Planned {
+            target: res.target.clone(),
+            urls: res.urls.len(),
+            total_bytes: planned_bytes(res, is_partial)?,
+        });
+
+        let outcome = self.write_result(res, is_partial).await;
+        self.emit(match &outcome {
+            Ok(done) => DownloadEvent::Completed {
+                target: done.target.clone(),
+                size_bytes: done.size_bytes,
+            },
+            Err(e) => DownloadEvent::Failed {
+                target: res.target.clone(),
+                error: e.to_string(),
+            },
+        });
+        outcome
+    }
+
+    async fn write_result(&self, res: &Result, is_partial: bool) -> EResult<Result> {
+        if !writes_tree(&self.opts) {
+            return self.write_target(res, is_partial).await;
+        }
+        let mut bases = vec![self.base_url.as_str()];
+        bases.extend(self.fallback.iter().map(|f| f.base_url.as_str()));
+        let mut parts = Vec::with_capacity(res.urls.len());
+        for part in tree_parts(res, &bases) {
+            parts.push(self.write_target(&part, is_partial).await?);
+        }
+        Ok(join_parts(res, parts))
+    }
+
+    async fn write_target(&self, res: &Result, is_partial: bool) -> EResult<Result> {
+        let mut total: u64 = 0;
+        let mut final_urls = BTreeMap::new();
+        let mut remote_versions = BTreeMap::new();
+        let path = target_path(&res.target);
+        if self.opts.layout != Layout::Flat || self.opts.target_namer.is_some() {
+            create_parent_dirs(&path)?;
+        }
+        // Without a buffer, every range goes straight to the file.
+        let capacity = if res.fast_writes { FAST_WRITE_BUFFER } else { 0 };
+        let mut file = tokio::io::BufWriter::with_capacity(capacity, tokio::fs::File::create(path).await?);
+        let mut hasher = Sha256::default();
+        let mut failed = Vec::new();
+        let mut reports = Vec::with_capacity(res.urls.len());
+
+        for u in &res.urls {
+            let started = Instant::now();
+            if is_partial && u.contains('|') {
+                let (data_url, mut ranges) = split_url_ranges(u)?;
+                let mut report = UrlReport::new(data_url);
+                if self.opts.sequential_ranges {
+                    ranges.sort_unstable();
+                }
+                for fetch in plan_fetches(&ranges, self.opts.max_requests_per_url, self.opts.max_range_gap_bytes) {
+                    let transfers = match self.fetch_range(data_url, &fetch).await {
+                        Ok(transfer) => vec![(fetch, transfer)],
+                        Err(e) if self.opts.skip_failed_fields && is_field_failure(&e) => {
+                            let Some(singles) = field_fetches(data_url, &fetch, &res.fields) else {
+                                return Err(e);
+                            };
+                            let mut transfers = Vec::new();
+                            for (single, field) in singles {
+                                match self.fetch_range(data_url, &single).await {
+                                    Ok(transfer) => transfers.push((single, transfer)),
+                                    Err(e) if is_field_failure(&e) => failed.push(field.clone()),
+                                    Err(e) => return Err(e),
+                                }
+                            }
+                            transfers
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    for (fetch, transfer) in transfers {
+                        report.add(&fetch, &transfer);
+                        final_urls.insert(data_url.to_string(), transfer.final_url);
+                        remote_versions.insert(data_url.to_string(), transfer.version);
+                        let mut buf = Vec::new();
+                        let bytes = write_fetch_parts(&mut buf, data_url, &fetch, &transfer.bytes)?;
+                        hasher.update(&buf);
+                        file.write_all(&buf).await?;
+                        total += bytes;
+                        self.emit_written(&res.target, bytes, total);
+                    }
+                }
+                report.elapsed = started.elapsed();
+                reports.push(report);
+            } else {
+                self.emit(DownloadEvent::RangeStarted {
+                    url: u.clone(),
+                    range: None,
+                });
+                let transfer = self.fetch_data(u, None).await?;
+                final_urls.insert(u.clone(), transfer.final_url);
+                remote_versions.insert(u.clone(), transfer.version);
+                hasher.update(transfer.bytes.as_slice());
+                file.write_all(&transfer.bytes).await?;
+                total += transfer.bytes.len() as u64;
+                self.emit_written(&res.target, transfer.bytes.len() as u64, total);
+                reports.push(UrlReport {
+                    status: transfer.status,
+                    bytes: transfer.bytes.len() as u64,
+                    elapsed: started.elapsed(),
+                    ..UrlReport::new(u)
+                });
+            }
+        }
+        file.flush().await?;
+
+        let mut out = res.clone();
+        out.size_bytes = total;
+        out.final_urls = final_urls;
+        out.remote_versions = remote_versions;
+        out.sha256 = Some(checksum::hex(hasher));
+        out.url_reports = reports;
+        out.warnings.extend(self.usage.warning());
+        if self.opts.sequential_ranges {
+            sort_fields_in_file_order(&mut out);
+        }
+        out.fields.retain(|f| !failed.contains(f));
+        out.failed_fields = failed;
+        Ok(out)
+    }
+
+    async fn fetch_range(&self, data_url: &str, fetch: &Fetch) -> EResult<Transfer> {
+        self.emit(DownloadEvent::RangeStarted {
+            url: data_url.to_string(),
+            range: Some((fetch.start, fetch.end)),
+        });
+        self.fetch_data(data_url, Some(&format!("bytes={}-{}", fetch.start, fetch.end))).await
+    }
+
+    fn emit(&self, event: DownloadEvent) {
+        emit(self.opts.download_events.as_ref(), event);
+    }
+
+    fn emit_written(&self, target: &str, bytes: u64, written: u64) {
+        self.emit(DownloadEvent::BytesWritten {
+            target: target.to_string(),
+            bytes,
+            written,
+        });
+    }
+
+    async fn get_azure_sas_token(&self) -> EResult<SasToken> {
+        let url = sas_token_url(&self.opts)?;
+        let v: serde_json::Value = self.send(self.http.get(url)).await?.error_for_status()?.json().await?;
+        SasToken::from_response(&v)
+    }
+
+    /// See [`crate::Client::usage`].
+    pub fn usage(&self) -> Usage {
+        self.usage.snapshot()
+    }
+
+    /// See [`crate::Client::reset_usage`].
+    pub fn reset_usage(&self) {
+        self.usage.reset();
+    }
+
+    /// See [`crate::Client::sas_token_expiry`].
+    pub fn sas_token_expiry(&self) -> Option<DateTime<Utc>> {
+        let sas = self.sas_token.as_ref()?;
+        sas.lock().unwrap_or_else(|e| e.into_inner()).expiry
+    }
+
+    /// Current SAS token, re-fetched first when it is about to expire (the
+    /// lock is not held while fetching, so concurrent tasks may both fetch).
+    async fn sas_token(&self) -> Option<String> {
+        let sas = self.sas_token.as_ref()?;
+        let current = sas.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if current.is_expiring(Utc::now())
+            && let Ok(fresh) = self.get_azure_sas_token().await
+        {
+            let token = fresh.token.clone();
+            *sas.lock().unwrap_or_else(|e| e.into_inner()) = fresh;
+            return Some(token);
+        }
+        Some(current.token)
+    }
+
+    /// Replace the SAS token after a request was refused. Returns whether
+    /// this client uses one.
+    async fn refresh_sas_token(&self) -> EResult<bool> {
+        let Some(sas) = &self.sas_token else {
+            return Ok(false);
+        };
+        let fresh = self.get_azure_sas_token().await?;
+        *sas.lock().unwrap_or_else(|e| e.into_inner()) = fresh;
+        Ok(true)
+    }
+
+    async fn request_url(&self, url: &str) -> String {
+        request_url(&self.opts, self.sas_token().await.as_deref(), url)
+    }
+
+    /// Download a data URL (or one range of it).
+    async fn fetch_data(&self, data_url: &str, range: Option<&str>) -> EResult<Transfer> {
+        let url = self.request_url(data_url).await;
+        if let Some(path) = local::file_path(&url) {
+            return Ok(Transfer {
+                bytes: Arc::new(local::read_async(&path, range).await?),
+                final_url: data_url.to_string(),
+                version: local::version(&path),
+                status: None,
+            });
+        }
+        self.usage.check()?;
+        let resp = self.get_throttled(&url, range).await?;
+        let final_url = without_query(resp.url());
+        let content_type = content_type(resp.headers());
+        let version = RemoteVersion::from_headers(resp.headers());
+        let status = resp.status().as_u16();
+        let bytes = resp.bytes().await?.to_vec();
+        telemetry::transfer(&self.opts.source, range.is_some(), bytes.len());
+        self.usage.record(&self.opts.source, bytes.len() as u64);
+        check_data_response(data_url, content_type.as_deref(), &bytes)?;
+        Ok(Transfer {
+            bytes: Arc::new(bytes),
+            final_url,
+            version,
+            status: Some(status),
+        })
+    }
+
+    /// See [`crate::Client::probe`].
+    async fn probe(&self, url: &str) -> EResult<ProbeOutcome> {
+        let policy = &self.opts.probe_policy;
+        let mut attempt = 0;
+        let mut transport_retries = 0;
+        let mut url = url.to_string();
+        let mut resigned = false;
+        loop {
+            let outcome = match self.probe_once(&url).await {
+                Err(Error::Http(e))
+                    if self.opts.retry_policy.is_retryable_error(&e)
+                        && transport_retries < self.opts.retry_policy.max_retries =>
+                {
+                    telemetry::retry(&self.opts.source, RetryReason::Transport);
+                    tokio::time::sleep(self.opts.retry_policy.delay(transport_retries)).await;
+                    transport_retries += 1;
+                    continue;
+                }
+                outcome => outcome?,
+            };
+            match outcome {
+                ProbeOutcome::Unavailable(_) if attempt < policy.unavailable_retries => {
+                    telemetry::retry(&self.opts.source, RetryReason::Unavailable);
+                    attempt += 1;
+                    tokio::time::sleep(policy.retry_delay).await;
+                }
+                // The SAS token may have expired or been revoked.
+                ProbeOutcome::Absent(403) if !resigned && self.refresh_sas_token().await? => {
+                    telemetry::retry(&self.opts.source, RetryReason::Resigned);
+                    resigned = true;
+                    url = self.request_url(unsigned(&url)).await;
+                }
+                _ => return Ok(outcome),
+            }
+        }
+    }
+
+    async fn probe_once(&self, url: &str) -> EResult<ProbeOutcome> {
+        if let Some(path) = local::file_path(url) {
+            return Ok(self.opts.probe_policy.classify(local::status(&path)));
+        }
+        if let Ok(resp) = self.send(self.http.head(url)).await {
+            let status = resp.status().as_u16();
+            if status == 200 {
+                return Ok(ProbeOutcome::Exists);
+            }
+            if !matches!(status, 403 | 404 | 405 | 409 | 429 | 500 | 501 | 502 | 503) {
+                return Ok(self.opts.probe_policy.classify(status));
+            }
+        }
+
+        let resp = self.send(self.http.get(url).header(RANGE, "bytes=0-0")).await?;
+        Ok(self.opts.probe_policy.classify(resp.status().as_u16()))
+    }
+
+    async fn get_throttled(&self, url: &str, range: Option<&str>) -> EResult<Response> {
+        let retry = &self.opts.retry_policy;
+        let mut attempt = 0;
+        let mut retries = 0;
+        let mut url = url.to_string();
+        let mut resigned = false;
+        loop {
+            let mut req = self.http.get(&url);
+            if let Some(range) = range {
+                req = req.header(RANGE, range);
+            }
+            let resp = match self.send(req).await {
+                Err(Error::Http(e)) if retry.is_retryable_error(&e) && retries < retry.max_retries => {
+                    telemetry::retry(&self.opts.source, RetryReason::Transport);
+                    tokio::time::sleep(retry.delay(retries)).await;
+                    retries += 1;
+                    continue;
+                }
+                resp => resp?,
+            };
+
+            let status = resp.status().as_u16();
+            // The SAS token may have expired or been revoked.
+            if status == 403 && !resigned && self.refresh_sas_token().await? {
+                telemetry::retry(&self.opts.source, RetryReason::Resigned);
+                resigned = true;
+                url = self.request_url(unsigned(&url)).await;
+                continue;
+            }
+            if self.throttle.is_throttled(status) && attempt < self.throttle.max_retries() {
+                telemetry::retry(&self.opts.source, RetryReason::Throttled);
+                attempt += 1;
+                tokio::time::sleep(self.throttle.on_throttle(retry_after(resp.headers()))).await;
+                continue;
+            }
+            if retry.is_retryable_status(status) && retries < retry.max_retries {
+                telemetry::retry(&self.opts.source, RetryReason::Status);
+                tokio::time::sleep(retry.delay(retries)).await;
+                retries += 1;
+                continue;
+            }
+            if resp.status().is_success() {
+                self.throttle.on_success();
+            }
+            return Ok(resp.error_for_status()?);
+        }
+    }
+
+    async fn send(&self, req: RequestBuilder) -> EResult<Response> {
+        let mut req = req.build()?;
+        // Cross-host redirects handed back are followed here, within the
+        // policy's limit, so a loop between hosts cannot go on forever.
+        let max = self.opts.redirect_policy.max_redirects;
+        let mut hops_left = max;
+        loop {
+            tokio::time::sleep(self.rate_limiter.reserve()).await;
+            let url = req.url().clone();
+            self.source_headers.attach(&url, req.headers_mut());
+            check_host(self.opts.allowed_hosts.as_deref(), &url)?;
+            let next = req.try_clone();
+            let started = Instant::now();
+            let outcome = self.execute(req).await;
+            telemetry::request(
+                &self.opts.source,
+                outcome.as_ref().ok().map(|r| r.status().as_u16()),
+                started.elapsed(),
+            );
+            let resp = outcome.map_err(redirect_error)?;
+            match (next, self.source_headers.redirect(resp.url(), resp.status(), resp.headers())) {
+                (Some(next), Some(location)) => {
+                    if hops_left == 0 {
+                        return Err(Error::TooManyRedirects {
+                            url: without_query(next.url()),
+                            max,
+                        });
+                    }
+                    hops_left -= 1;
+                    req = next;
+                    *req.url_mut() = location;
+                }
+                _ => return Ok(resp),
+            }
+        }
+    }
+
+    async fn execute(&self, req: reqwest::Request) -> reqwest::Result<Response> {
+        let Some(hook) = &self.opts.http_hook else {
+            return self.http.execute(req).await;
+        };
+
+        let method = req.method().to_string();
+        let url = without_query(req.url());
+        hook.call(&HttpEvent::Request {
+            method: method.clone(),
+            url: url.clone(),
+            range: req
+                .headers()
+                .get(RANGE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string()),
+        });
+
+        match self.http.execute(req).await {
+            Ok(resp) => {
+                hook.call(&HttpEvent::Response {
+                    method,
+                    url,
+                    status: resp.status().as_u16(),
+                    content_length: resp.content_length(),
+                });
+                Ok(resp)
+            }
+            Err(e) => {
+                hook.call(&HttpEvent::Failed {
+                    method,
+                    url,
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+}
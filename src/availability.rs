@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+
+use crate::index::IndexEntry;
+use crate::request::Request;
+use crate::steps::published_steps;
+
+/// What a cycle publishes, read from its `.index` files by
+/// [`crate::Client::availability`]: the params present for each stream, type
+/// and step.
+///
+/// Values are as written in the index files, so the control and perturbed
+/// members of an `ef` file show up as types `cf` and `pf`.
+///
+/// ```no_run
+/// use ecmwf_opendata::{Client, ClientOptions};
+///
+/// let client = Client::new(ClientOptions::default())?;
+/// let availability = client.availability(-1, 0)?;
+/// for step in availability.steps("oper", "fc") {
+///     println!("{step}: {}", availability.params("oper", "fc", step).join(","));
+/// }
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Availability {
+    pub datetime: DateTime<Utc>,
+    /// Params published, keyed by (stream, type, step).
+    pub matrix: BTreeMap<(String, String, String), BTreeSet<String>>,
+}
+
+impl Availability {
+    pub fn new(datetime: DateTime<Utc>) -> Self {
+        Self { datetime, matrix: BTreeMap::new() }
+    }
+
+    /// Record the fields of an index file. Entries without a stream, type,
+    /// step or param are ignored.
+    pub fn add(&mut self, entries: &[IndexEntry]) {
+        for e in entries {
+            let (Some(stream), Some(typ), Some(step), Some(param)) =
+                (e.get("stream"), e.get("type"), e.get("step"), e.get("param"))
+            else {
+                continue;
+            };
+            self.matrix
+                .entry((stream.to_string(), typ.to_string(), step.to_string()))
+                .or_default()
+                .insert(param.to_string());
+        }
+    }
+
+    /// Record a file published without an index (tropical cyclone tracks):
+    /// its stream, type and step are listed, without params.
+    pub fn add_file(&mut self, stream: &str, typ: &str, step: &str) {
+        self.matrix
+            .entry((stream.to_string(), typ.to_string(), step.to_string()))
+            .or_default();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matrix.is_empty()
+    }
+
+    pub fn contains(&self, stream: &str, typ: &str, step: &str, param: &str) -> bool {
+        self.matrix
+            .get(&(stream.to_string(), typ.to_string(), step.to_string()))
+            .is_some_and(|params| params.contains(param))
+    }
+
+    pub fn streams(&self) -> Vec<&str> {
+        let streams: BTreeSet<_> = self.matrix.keys().map(|(s, _, _)| s.as_str()).collect();
+        streams.into_iter().collect()
+    }
+
+    pub fn types(&self, stream: &str) -> Vec<&str> {
+        let types: BTreeSet<_> = self
+            .matrix
+            .keys()
+            .filter(|(s, _, _)| s == stream)
+            .map(|(_, t, _)| t.as_str())
+            .collect();
+        types.into_iter().collect()
+    }
+
+    /// Steps of `stream` and `typ`, in numeric order (step ranges such as
+    /// `0-24` by their start).
+    pub fn steps(&self, stream: &str, typ: &str) -> Vec<&str> {
+        let mut steps: Vec<_> = self
+            .matrix
+            .keys()
+            .filter(|(s, t, _)| s == stream && t == typ)
+            .map(|(_, _, step)| step.as_str())
+            .collect();
+        steps.sort_by_key(|step| {
+            let start = step.split('-').next().and_then(|s| s.parse::<u32>().ok());
+            (start.unwrap_or(u32::MAX), step.len(), *step)
+        });
+        steps
+    }
+
+    pub fn params(&self, stream: &str, typ: &str, step: &str) -> Vec<&str> {
+        self.matrix
+            .get(&(stream.to_string(), typ.to_string(), step.to_string()))
+            .map(|params| params.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Requests covering the files [`crate::Client::availability`] looks for:
+/// each product of the open-data catalogue of `model` at cycle hour `hour`,
+/// with every step it may be published at ([`published_steps`]). Files that
+/// turn out not to exist are skipped by the caller.
+pub(crate) fn catalogue(model: &str, hour: u32) -> Vec<Request> {
+    // `cf` stands for the `ef` files, which hold `pf` too.
+    let products: &[(&str, &str)] = match model {
+        "aifs-single" => &[("oper", "fc")],
+        "aifs-ens" => &[("enfo", "cf"), ("enfo", "pf")],
+        _ if hour.is_multiple_of(12) => &[
+            ("oper", "fc"),
+            ("wave", "fc"),
+            ("enfo", "cf"),
+            ("waef", "cf"),
+            ("enfo", "em"),
+            ("enfo", "ep"),
+            ("oper", "tf"),
+            ("enfo", "tf"),
+        ],
+        _ => &[
+            ("scda", "fc"),
+            ("scwv", "fc"),
+            ("enfo", "cf"),
+            ("waef", "cf"),
+            ("enfo", "em"),
+            ("scda", "tf"),
+            ("enfo", "tf"),
+        ],
+    };
+    products
+        .iter()
+        .map(|(stream, typ)| {
+            Request::new()
+                .model(model)
+                .stream(*stream)
+                .r#type(*typ)
+                .step(published_steps(model, stream, typ, hour))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(stream: &str, typ: &str, step: &str, param: &str) -> IndexEntry {
+        IndexEntry {
+            stream: Some(stream.into()),
+            typ: Some(typ.into()),
+            step: Some(step.into()),
+            param: Some(param.into()),
+            ..IndexEntry::default()
+        }
+    }
+
+    #[test]
+    fn matrix_is_queried_by_stream_type_and_step() {
+        let mut availability = Availability::new(DateTime::default());
+        availability.add(&[
+            entry("oper", "fc", "12", "2t"),
+            entry("oper", "fc", "3", "2t"),
+            entry("oper", "fc", "3", "msl"),
+            entry("enfo", "pf", "0-24", "tp"),
+            IndexEntry::default(),
+        ]);
+
+        assert_eq!(availability.streams(), ["enfo", "oper"]);
+        assert_eq!(availability.types("enfo"), ["pf"]);
+        assert_eq!(availability.steps("oper", "fc"), ["3", "12"]);
+        assert_eq!(availability.params("oper", "fc", "3"), ["2t", "msl"]);
+        assert!(availability.contains("enfo", "pf", "0-24", "tp"));
+        assert!(!availability.contains("oper", "fc", "12", "msl"));
+        assert!(availability.params("oper", "cf", "3").is_empty());
+    }
+
+    #[test]
+    fn catalogue_follows_the_run() {
+        let streams = |hour| -> Vec<String> {
+            catalogue("ifs", hour)
+                .iter()
+                .map(|r| r.get("stream").unwrap().as_strings().join(""))
+                .collect()
+        };
+        assert_eq!(streams(0), ["oper", "wave", "enfo", "waef", "enfo", "enfo", "oper", "enfo"]);
+        assert_eq!(streams(18), ["scda", "scwv", "enfo", "waef", "enfo", "scda", "enfo"]);
+        let steps = |typ: &str| {
+            let products = catalogue("ifs", 0);
+            let product = products.iter().find(|r| r.get("type").unwrap().as_strings() == [typ]).unwrap();
+            product.get("step").unwrap().as_strings()
+        };
+        assert_eq!(steps("ep"), ["360"]);
+        assert_eq!(steps("tf"), ["240"]);
+        assert_eq!(catalogue("aifs-single", 6).len(), 1);
+    }
+}
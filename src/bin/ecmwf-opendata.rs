@@ -0,0 +1,610 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use clap::{Args, Parser, Subcommand};
+use ecmwf_opendata::{Client, ClientBuilder, ClientOptions, HttpEvent, HttpHook, Request, Severity, Source, UrlReport};
+
+/// Command-line client for ECMWF Open Data.
+///
+/// Requests are given as `--key value` pairs after the subcommand, with the
+/// same keywords and value syntax as `Request::from_str_pairs`, e.g.
+/// `retrieve --type fc --step 0,24 --param msl --target data.grib2`.
+///
+/// Downloading implies CC BY 4.0 attribution requirements (see the ECMWF Open
+/// Data license).
+#[derive(Debug, Parser)]
+#[command(name = "ecmwf-opendata", version)]
+struct Cli {
+    /// Mirror to use: ecmwf, aws, azure, google or a base URL.
+    #[arg(long, global = true, default_value = "ecmwf")]
+    source: Source,
+    #[arg(long, global = true, default_value = "ifs")]
+    model: String,
+    #[arg(long, global = true, default_value = "0p25")]
+    resol: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Download the fields selected through the `.index` files.
+    Retrieve(Transfer),
+    /// Download whole data files.
+    Download(Transfer),
+    /// Print the latest cycle for which the request's files exist.
+    Latest(RequestArgs),
+    /// Print the data file URLs of a request.
+    Urls(RequestArgs),
+    /// Print the index entries a request selects.
+    Index(RequestArgs),
+    /// Check the request of a config file and print its problems, without
+    /// network access unless `--check-availability` is given. Client options
+    /// are read from the same file.
+    Lint {
+        file: PathBuf,
+        /// Profile of the file to apply.
+        #[arg(long)]
+        profile: Option<String>,
+        /// Also read the request's `.index` files to report data that is not
+        /// published.
+        #[arg(long)]
+        check_availability: bool,
+    },
+    /// Download the same small field (msl, step 0) from every mirror and
+    /// report time to first response, total time and throughput.
+    Bench {
+        /// Print results as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+struct Transfer {
+    /// Resolve the latest cycle, URLs, ranges and estimated sizes, print the
+    /// plan and exit without writing files.
+    #[arg(long)]
+    dry_run: bool,
+    /// Print the outcome as JSON, with every HTTP request sent per URL
+    /// (status, advertised bytes, time to response, error).
+    #[arg(long)]
+    json: bool,
+    #[command(flatten)]
+    request: RequestArgs,
+}
+
+#[derive(Debug, Args)]
+struct RequestArgs {
+    /// Request keywords as `--key value` (or `--key=value`) pairs.
+    #[arg(
+        value_name = "--KEY VALUE",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    pairs: Vec<String>,
+}
+
+impl RequestArgs {
+    fn request(&self) -> Request {
+        match parse_pairs(&self.pairs) {
+            Ok(pairs) => Request::from_str_pairs(pairs),
+            Err(e) => {
+                eprintln!("invalid request: {e}");
+                std::process::exit(2);
+            }
+        }
+    }
+}
+
+/// Split `--key value` / `--key=value` arguments into request pairs.
+fn parse_pairs(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let Some(key) = arg.strip_prefix("--").filter(|k| !k.is_empty()) else {
+            return Err(format!("expected --key, got {arg:?}"));
+        };
+        let (key, value) = match key.split_once('=') {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => match it.next() {
+                Some(v) => (key.to_string(), v.clone()),
+                None => return Err(format!("missing value for --{key}")),
+            },
+        };
+        pairs.push((key, value));
+    }
+    Ok(pairs)
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let opts = ClientOptions {
+        source: cli.source.clone(),
+        model: cli.model.clone(),
+        resol: cli.resol.clone(),
+        ..ClientOptions::default()
+    };
+
+    match cli.command {
+        Command::Retrieve(args) => {
+            let log = args.json.then(TransferLog::shared);
+            let client = client(with_log(opts, log.as_ref()));
+            let request = args.request.request();
+            if args.dry_run {
+                print_plan(&client, request, true);
+                return;
+            }
+            let outcome = client.retrieve_request(request);
+            match log {
+                Some(log) => report_json(outcome, &log.lock().unwrap()),
+                None => report(outcome, "retrieve"),
+            }
+        }
+        Command::Download(args) => {
+            let log = args.json.then(TransferLog::shared);
+            let client = client(with_log(opts, log.as_ref()));
+            let request = args.request.request();
+            if args.dry_run {
+                print_plan(&client, request, false);
+                return;
+            }
+            let outcome = client.download_request(request);
+            match log {
+                Some(log) => report_json(outcome, &log.lock().unwrap()),
+                None => report(outcome, "download"),
+            }
+        }
+        Command::Latest(args) => match client(opts).latest(args.request()) {
+            Ok(dt) => println!("{}", dt.format("%Y-%m-%d %H:%M:%S")),
+            Err(e) => fail("latest", e),
+        },
+        Command::Urls(args) => match client(opts).urls_for(&args.request()) {
+            Ok(urls) => {
+                for url in &urls {
+                    println!("{url}");
+                }
+            }
+            Err(e) => fail("urls", e),
+        },
+        Command::Index(args) => match client(opts).plan(args.request()) {
+            Ok(plan) => {
+                for f in &plan.fields {
+                    println!(
+                        "{}\tlevelist={}\tstep={}\tnumber={}\toffset={}\tlength={}\t{}",
+                        f.param,
+                        f.levelist.as_deref().unwrap_or("-"),
+                        f.step.as_deref().unwrap_or("-"),
+                        f.number.as_deref().unwrap_or("-"),
+                        f.offset,
+                        f.length,
+                        f.url
+                    );
+                }
+            }
+            Err(e) => fail("index", e),
+        },
+        Command::Lint { file, profile, check_availability } => lint(&file, profile.as_deref(), check_availability),
+        Command::Bench { json } => bench(json),
+    }
+}
+
+fn client(opts: ClientOptions) -> Client {
+    match ClientBuilder::from_options(opts).build() {
+        Ok(client) => client,
+        Err(e) => fail("create client", e),
+    }
+}
+
+fn fail(what: &str, e: ecmwf_opendata::Error) -> ! {
+    eprintln!("{what} failed: {e}");
+    std::process::exit(1);
+}
+
+fn report(outcome: ecmwf_opendata::EResult<ecmwf_opendata::Result>, what: &str) {
+    match outcome {
+        Ok(result) => {
+            println!(
+                "Downloaded {bytes} bytes to {target}",
+                bytes = result.size_bytes,
+                target = result.target
+            );
+            for datetime in &result.datetimes {
+                println!("Forecast datetime: {datetime}");
+            }
+        }
+        Err(e) => {
+            eprintln!("{what} failed: {e}");
+            eprintln!("Tip: try setting an explicit --date/--time, or use a replicated source (--source aws/google/azure) if the main portal is busy.");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// One HTTP request, as seen through an [`HttpHook`].
+#[derive(Debug, Default)]
+struct Attempt {
+    method: String,
+    range: Option<String>,
+    status: Option<u16>,
+    content_length: Option<u64>,
+    error: Option<String>,
+    elapsed: Duration,
+}
+
+/// Requests sent, grouped by URL in the order URLs were first requested.
+#[derive(Debug, Default)]
+struct TransferLog {
+    urls: Vec<(String, Vec<Attempt>)>,
+    /// Request in flight on each thread, as (url, attempt) indices and start.
+    pending: HashMap<ThreadId, (usize, usize, Instant)>,
+}
+
+impl TransferLog {
+    fn shared() -> Arc<Mutex<Self>> {
+        Arc::default()
+    }
+
+    fn record(&mut self, event: &HttpEvent) {
+        match event {
+            HttpEvent::Request { method, url, range } => {
+                let u = match self.urls.iter().position(|(seen, _)| seen == url) {
+                    Some(u) => u,
+                    None => {
+                        self.urls.push((url.clone(), Vec::new()));
+                        self.urls.len() - 1
+                    }
+                };
+                let attempts = &mut self.urls[u].1;
+                attempts.push(Attempt {
+                    method: method.clone(),
+                    range: range.clone(),
+                    ..Attempt::default()
+                });
+                self.pending.insert(thread::current().id(), (u, attempts.len() - 1, Instant::now()));
+            }
+            HttpEvent::Response { status, content_length, .. } => {
+                if let Some(attempt) = self.finish() {
+                    attempt.status = Some(*status);
+                    attempt.content_length = *content_length;
+                }
+            }
+            HttpEvent::Failed { error, .. } => {
+                if let Some(attempt) = self.finish() {
+                    attempt.error = Some(error.clone());
+                }
+            }
+        }
+    }
+
+    /// The request in flight on this thread, with its time to response set.
+    fn finish(&mut self) -> Option<&mut Attempt> {
+        let (u, a, started) = self.pending.remove(&thread::current().id())?;
+        let attempt = &mut self.urls[u].1[a];
+        attempt.elapsed = started.elapsed();
+        Some(attempt)
+    }
+
+    /// One entry per data URL of `reports`, with the bytes, status and time
+    /// the download measured, then the other URLs requested (index files,
+    /// size probes). Each lists the HTTP requests sent for it.
+    fn to_json(&self, reports: &[UrlReport]) -> serde_json::Value {
+        let attempts = |url: &str| -> Vec<serde_json::Value> {
+            let Some((_, attempts)) = self.urls.iter().find(|(seen, _)| seen == unsigned(url)) else {
+                return Vec::new();
+            };
+            attempts
+                .iter()
+                .map(|a| serde_json::json!({
+                    "method": a.method,
+                    "range": a.range,
+                    "status": a.status,
+                    "content_length": a.content_length,
+                    "response_ms": a.elapsed.as_millis() as u64,
+                    "error": a.error,
+                }))
+                .collect()
+        };
+        let mut urls: Vec<serde_json::Value> = reports
+            .iter()
+            .map(|r| {
+                let attempts = attempts(&r.url);
+                serde_json::json!({
+                    "url": r.url,
+                    "ranges": r.ranges,
+                    "status": r.status,
+                    "bytes": r.bytes,
+                    "elapsed_ms": r.elapsed.as_millis() as u64,
+                    "requests": attempts.len(),
+                    "attempts": attempts,
+                })
+            })
+            .collect();
+        let reported = |url: &str| reports.iter().any(|r| unsigned(&r.url) == url);
+        for (url, _) in self.urls.iter().filter(|(url, _)| !reported(url)) {
+            let attempts = attempts(url);
+            urls.push(serde_json::json!({
+                "url": url,
+                "requests": attempts.len(),
+                "attempts": attempts,
+            }));
+        }
+        serde_json::Value::Array(urls)
+    }
+}
+
+/// `url` without its query, as the [`HttpHook`] reports it.
+fn unsigned(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(u, _)| u)
+}
+
+/// `opts` with an [`HttpHook`] feeding `log`, if any.
+fn with_log(opts: ClientOptions, log: Option<&Arc<Mutex<TransferLog>>>) -> ClientOptions {
+    let Some(log) = log.cloned() else {
+        return opts;
+    };
+    ClientOptions {
+        http_hook: Some(HttpHook::new(move |e| log.lock().unwrap().record(e))),
+        ..opts
+    }
+}
+
+fn report_json(outcome: ecmwf_opendata::EResult<ecmwf_opendata::Result>, log: &TransferLog) {
+    let failed = outcome.is_err();
+    let out = match outcome {
+        Ok(result) => serde_json::json!({
+            "target": result.target,
+            "datetimes": result.datetimes,
+            "size_bytes": result.size_bytes,
+            "sha256": result.sha256,
+            "urls": log.to_json(&result.url_reports),
+        }),
+        Err(e) => serde_json::json!({
+            "error": e.to_string(),
+            "urls": log.to_json(&[]),
+        }),
+    };
+    println!("{}", serde_json::to_string_pretty(&out).expect("serialize"));
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn print_plan(client: &Client, request: Request, use_index: bool) {
+    match client.dry_run(request, use_index) {
+        Ok(plan) => {
+            println!("Dry run (nothing written)");
+            for datetime in &plan.datetimes {
+                println!("Forecast datetime: {datetime}");
+            }
+            println!("Target: {}", plan.target);
+            for url in &plan.urls {
+                println!("  {url}");
+            }
+            println!(
+                "Estimated {bytes} bytes in {n} URL(s)",
+                bytes = plan.size_bytes,
+                n = plan.urls.len()
+            );
+        }
+        Err(e) => fail("dry run", e),
+    }
+}
+
+/// Print the problems of the request in `file`; exit with 1 if any is an
+/// error.
+fn lint(file: &Path, profile: Option<&str>, check_availability: bool) {
+    let loaded = match profile {
+        Some(p) => ClientOptions::from_file_profile(file, p).and_then(|o| Ok((o, Request::from_file_profile(file, p)?))),
+        None => ClientOptions::from_file(file).and_then(|o| Ok((o, Request::from_file(file)?))),
+    };
+    let (opts, request) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => fail(&format!("reading {}", file.display()), e),
+    };
+    let lints = client(opts).lint(&request, check_availability);
+    for lint in &lints {
+        println!("{}: {lint}", file.display());
+    }
+    if lints.is_empty() {
+        println!("{}: no problems found", file.display());
+    }
+    if lints.iter().any(|l| l.severity == Severity::Error) {
+        std::process::exit(1);
+    }
+}
+
+/// Download the same small field (msl, step 0) from every mirror and report
+/// time to first response, total time and throughput.
+fn bench(json: bool) {
+    let reference = Client::new(ClientOptions::default()).expect("create client");
+    let cycle = match reference.latest(Request::new().step(0)) {
+        Ok(dt) => dt,
+        Err(e) => {
+            eprintln!("cannot establish latest cycle: {e}");
+            std::process::exit(1);
+        }
+    };
+    let date = cycle.format("%Y%m%d").to_string();
+    let time = cycle.format("%H").to_string();
+
+    let mut rows = Vec::new();
+    for source in [Source::Ecmwf, Source::Aws, Source::Azure, Source::Google] {
+        let first_response: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+        let seen = first_response.clone();
+        let opts = ClientOptions {
+            source: source.clone(),
+            http_hook: Some(HttpHook::new(move |e| {
+                if let HttpEvent::Response { .. } = e {
+                    seen.lock().unwrap().get_or_insert_with(Instant::now);
+                }
+            })),
+            ..ClientOptions::default()
+        };
+
+        let target = env::temp_dir().join(format!("ecmwf-opendata-bench-{source}.grib2"));
+        let request = Request::new()
+            .date(date.as_str())
+            .time(time.as_str())
+            .r#type("fc")
+            .step(0)
+            .param("msl")
+            .target(target.to_string_lossy().as_ref());
+
+        let started = Instant::now();
+        let outcome = Client::new(opts).and_then(|c| c.retrieve_request(request));
+        let elapsed = started.elapsed();
+        let _ = std::fs::remove_file(&target);
+
+        let latency = first_response
+            .lock()
+            .unwrap()
+            .map(|t| t.duration_since(started))
+            .unwrap_or(Duration::ZERO);
+        rows.push((source, outcome.map(|r| r.size_bytes), latency, elapsed));
+    }
+
+    if json {
+        let out: Vec<serde_json::Value> = rows
+            .iter()
+            .map(|(source, outcome, latency, elapsed)| match outcome {
+                Ok(bytes) => serde_json::json!({
+                    "source": source.to_string(),
+                    "bytes": bytes,
+                    "first_response_ms": latency.as_millis() as u64,
+                    "total_ms": elapsed.as_millis() as u64,
+                    "mib_per_s": mib_per_s(*bytes, *elapsed),
+                }),
+                Err(e) => serde_json::json!({
+                    "source": source.to_string(),
+                    "error": e.to_string(),
+                }),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&out).expect("serialize"));
+        return;
+    }
+
+    println!("Cycle {date} {time}z, msl step 0");
+    println!("{:<8} {:>10} {:>12} {:>10} {:>10}", "source", "bytes", "first resp", "total", "MiB/s");
+    for (source, outcome, latency, elapsed) in &rows {
+        match outcome {
+            Ok(bytes) => println!(
+                "{:<8} {:>10} {:>10}ms {:>8}ms {:>10.2}",
+                source.to_string(),
+                bytes,
+                latency.as_millis(),
+                elapsed.as_millis(),
+                mib_per_s(*bytes, *elapsed)
+            ),
+            Err(e) => println!("{:<8} failed: {e}", source.to_string()),
+        }
+    }
+}
+
+fn mib_per_s(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        bytes as f64 / (1024.0 * 1024.0) / secs
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &[&str]) -> Vec<String> {
+        s.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_key_value_pairs() {
+        let pairs = parse_pairs(&args(&["--type", "fc", "--step=0,24", "--param", "msl"])).unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                ("type".to_string(), "fc".to_string()),
+                ("step".to_string(), "0,24".to_string()),
+                ("param".to_string(), "msl".to_string()),
+            ]
+        );
+        assert!(parse_pairs(&args(&["fc"])).is_err());
+        assert!(parse_pairs(&args(&["--param"])).is_err());
+    }
+
+    #[test]
+    fn request_pairs_follow_the_subcommand() {
+        let cli = Cli::try_parse_from(["ecmwf-opendata", "--source", "aws", "retrieve", "--dry-run", "--type", "fc", "--step", "-1"]).unwrap();
+        assert_eq!(cli.source, Source::Aws);
+        let Command::Retrieve(t) = cli.command else {
+            panic!("expected retrieve");
+        };
+        assert!(t.dry_run);
+        assert_eq!(t.request.pairs, args(&["--type", "fc", "--step", "-1"]));
+    }
+
+    #[test]
+    fn transfer_log_groups_requests_by_url() {
+        let mut log = TransferLog::default();
+        let request = |url: &str| HttpEvent::Request {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            range: Some("bytes=0-9".to_string()),
+        };
+        log.record(&request("https://h/a.grib2"));
+        log.record(&HttpEvent::Failed {
+            method: "GET".to_string(),
+            url: "https://h/a.grib2".to_string(),
+            error: "timeout".to_string(),
+        });
+        log.record(&request("https://h/a.grib2"));
+        log.record(&HttpEvent::Response {
+            method: "GET".to_string(),
+            url: "https://h/a.grib2".to_string(),
+            status: 206,
+            content_length: Some(10),
+        });
+
+        log.record(&HttpEvent::Request {
+            method: "GET".to_string(),
+            url: "https://h/a.index".to_string(),
+            range: None,
+        });
+
+        let report = UrlReport {
+            url: "https://h/a.grib2".to_string(),
+            ranges: vec![(0, 9)],
+            status: Some(206),
+            bytes: 10,
+            elapsed: Duration::from_millis(1500),
+        };
+        let json = log.to_json(&[report]);
+        assert_eq!(json[0]["url"], "https://h/a.grib2");
+        assert_eq!(json[0]["requests"], 2);
+        assert_eq!(json[0]["bytes"], 10);
+        assert_eq!(json[0]["elapsed_ms"], 1500);
+        assert_eq!(json[0]["status"], 206);
+        assert_eq!(json[0]["attempts"][0]["error"], "timeout");
+        assert_eq!(json[0]["attempts"][1]["status"], 206);
+        assert_eq!(json[1]["url"], "https://h/a.index");
+        assert!(json[1].get("bytes").is_none());
+        assert_eq!(log.to_json(&[])[0]["requests"], 2);
+    }
+
+    #[test]
+    fn lint_takes_a_file() {
+        let cli = Cli::try_parse_from(["ecmwf-opendata", "lint", "request.yaml", "--check-availability"]).unwrap();
+        let Command::Lint { file, profile, check_availability } = cli.command else {
+            panic!("expected lint");
+        };
+        assert_eq!(file, PathBuf::from("request.yaml"));
+        assert_eq!(profile, None);
+        assert!(check_availability);
+    }
+}
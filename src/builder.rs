@@ -0,0 +1,319 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::blocking::Client as HttpClient;
+
+#[cfg(feature = "async")]
+use crate::async_client::AsyncClient;
+use crate::client::{sas_token_url, uses_sas_token, Client, ClientOptions};
+use crate::error::{Error, Result as EResult};
+use crate::http::{check_host, Identity};
+use crate::quota::Quota;
+use crate::rate_limit::RateLimit;
+use crate::retry::RetryPolicy;
+use crate::sources::Source;
+use crate::target::{Layout, TargetNamer};
+use crate::throttle::ThrottlePolicy;
+
+/// Builder of a [`Client`] that checks the combination of options when the
+/// client is built, rather than leaving a contradictory [`ClientOptions`] to
+/// fail (or be silently ignored) at the first request.
+///
+/// ```no_run
+/// use ecmwf_opendata::{Client, RetryPolicy, Source};
+///
+/// let client = Client::builder()
+///     .source(Source::Aws)
+///     .model("ifs")
+///     .resol("0p25")
+///     .retry(RetryPolicy { max_retries: 5, ..RetryPolicy::default() })
+///     .build()?;
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ClientBuilder {
+    opts: ClientOptions,
+    http: Option<HttpClient>,
+}
+
+impl Client {
+    /// Start a [`ClientBuilder`] from [`ClientOptions::default`].
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+}
+
+impl ClientBuilder {
+    /// Start from existing options, e.g. read with
+    /// [`ClientOptions::from_file`].
+    pub fn from_options(opts: ClientOptions) -> Self {
+        Self { opts, http: None }
+    }
+
+    pub fn source(mut self, source: Source) -> Self {
+        self.opts.source = source;
+        self
+    }
+
+    pub fn fallback_source(mut self, source: Source) -> Self {
+        self.opts.fallback_source = Some(source);
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.opts.model = model.into();
+        self
+    }
+
+    pub fn resol(mut self, resol: impl Into<String>) -> Self {
+        self.opts.resol = resol.into();
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.opts.layout = layout;
+        self
+    }
+
+    pub fn target_namer(mut self, namer: impl TargetNamer + 'static) -> Self {
+        self.opts.target_namer = Some(Arc::new(namer));
+        self
+    }
+
+    pub fn preserve_request_order(mut self, preserve: bool) -> Self {
+        self.opts.preserve_request_order = preserve;
+        self
+    }
+
+    pub fn strict_keywords(mut self, strict: bool) -> Self {
+        self.opts.strict_keywords = strict;
+        self
+    }
+
+    pub fn strict_params(mut self, strict: bool) -> Self {
+        self.opts.strict_params = strict;
+        self
+    }
+
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.opts.identity = Some(identity);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.opts.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Add a header sent with every request (see [`ClientOptions::headers`]).
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.opts.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Add a query parameter appended to every request URL (see
+    /// [`ClientOptions::query_params`]).
+    pub fn query_param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.opts.query_params.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn use_sas_token(mut self, use_sas_token: bool) -> Self {
+        self.opts.use_sas_token = Some(use_sas_token);
+        self
+    }
+
+    /// Fetch SAS tokens from `url` instead of the Planetary Computer endpoint
+    /// of the ECMWF container.
+    pub fn sas_custom_url(mut self, key: impl Into<String>, url: impl Into<String>) -> Self {
+        self.opts.sas_known_key = key.into();
+        self.opts.sas_custom_url = Some(url.into());
+        self
+    }
+
+    /// Only contact these hosts (see [`ClientOptions::allowed_hosts`]).
+    pub fn allowed_hosts<S: Into<String>>(mut self, hosts: impl IntoIterator<Item = S>) -> Self {
+        self.opts.allowed_hosts = Some(hosts.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.opts.retry_policy = policy;
+        self
+    }
+
+    pub fn throttle(mut self, policy: ThrottlePolicy) -> Self {
+        self.opts.throttle_policy = policy;
+        self
+    }
+
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.opts.rate_limit = Some(limit);
+        self
+    }
+
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.opts.quota = Some(quota);
+        self
+    }
+
+    pub fn max_concurrent_ranges(mut self, n: usize) -> Self {
+        self.opts.max_concurrent_ranges = n;
+        self
+    }
+
+    pub fn sequential_ranges(mut self, sequential: bool) -> Self {
+        self.opts.sequential_ranges = sequential;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.opts.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.opts.read_timeout = Some(timeout);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.opts.deadline = Some(deadline);
+        self
+    }
+
+    /// Send requests through `http` (see [`Client::with_http_client`]).
+    pub fn http_client(mut self, http: HttpClient) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    /// Options configured so far, for settings without a builder method.
+    pub fn options_mut(&mut self) -> &mut ClientOptions {
+        &mut self.opts
+    }
+
+    /// Check the options without building a client.
+    pub fn validate(&self) -> EResult<()> {
+        self.opts.validate()?;
+        if self.http.is_some() {
+            let opts = &self.opts;
+            let ignored = [
+                ("identity", opts.identity.is_some()),
+                ("user_agent", opts.user_agent.is_some()),
+                ("headers", !opts.headers.is_empty()),
+                ("verify_tls", !opts.verify_tls),
+                ("connect_timeout", opts.connect_timeout.is_some()),
+                ("allowed_hosts", opts.allowed_hosts.is_some()),
+            ];
+            if let Some((name, _)) = ignored.iter().find(|(_, set)| *set) {
+                return Err(Error::Config(format!(
+                    "`{name}` has no effect with an injected HTTP client; configure that client instead"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> EResult<Client> {
+        self.validate()?;
+        match self.http {
+            Some(http) => Client::with_http_client(self.opts, http),
+            None => Client::new(self.opts),
+        }
+    }
+
+    /// Build an [`AsyncClient`] instead. An injected blocking HTTP client is
+    /// rejected.
+    #[cfg(feature = "async")]
+    pub async fn build_async(self) -> EResult<AsyncClient> {
+        if self.http.is_some() {
+            return Err(Error::Config("an injected blocking HTTP client cannot serve an AsyncClient".into()));
+        }
+        self.opts.validate()?;
+        AsyncClient::new(self.opts).await
+    }
+}
+
+impl ClientOptions {
+    /// Reject combinations of options that cannot work as written, such as
+    /// SAS tokens for a source other than Azure. [`ClientBuilder::build`]
+    /// calls this; [`Client::new`] does not.
+    pub fn validate(&self) -> EResult<()> {
+        let invalid = |message: &str| Err(Error::Config(message.to_string()));
+        let sas = uses_sas_token(self);
+        if sas && self.source != Source::Azure && self.sas_known_key == "ecmwf" {
+            return invalid("the ECMWF SAS token only signs Source::Azure URLs; use a custom SAS url for other sources");
+        }
+        if self.sas_custom_url.is_some() && !sas {
+            return invalid("`sas_custom_url` is set but SAS tokens are not used");
+        }
+        if self.sas_custom_url.is_some() && self.sas_known_key == "ecmwf" {
+            return invalid("`sas_custom_url` is ignored while `sas_known_key` is \"ecmwf\"");
+        }
+        if self.fallback_source.as_ref() == Some(&self.source) {
+            return invalid("`fallback_source` is the same as `source`");
+        }
+        if self.target_namer.is_some() && self.layout != Layout::default() {
+            return invalid("`target_namer` replaces `layout`; set only one of them");
+        }
+        if self.max_concurrent_ranges == 0 {
+            return invalid("`max_concurrent_ranges` must be at least 1");
+        }
+        if self.stream_buffer == 0 {
+            return invalid("`stream_buffer` must be at least 1");
+        }
+        if let Some(limit) = &self.rate_limit
+            && !(limit.requests_per_second > 0.0 && limit.burst > 0 && limit.max_concurrent > 0)
+        {
+            return invalid("`rate_limit` needs a positive `requests_per_second`, `burst` and `max_concurrent`");
+        }
+        if let Some(allowed) = &self.allowed_hosts {
+            let mut urls = vec![self.source.base_url().to_string()];
+            urls.extend(self.fallback_source.as_ref().map(|s| s.base_url().to_string()));
+            if uses_sas_token(self) {
+                urls.extend(sas_token_url(self).ok());
+            }
+            for url in urls.iter().filter(|u| !u.starts_with("file:")) {
+                let url = reqwest::Url::parse(url).map_err(|e| Error::Config(format!("invalid url {url}: {e}")))?;
+                if let Err(e) = check_host(Some(allowed), &url) {
+                    return Err(Error::Config(format!("`allowed_hosts` blocks a configured endpoint: {e}")));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(builder: ClientBuilder) -> String {
+        builder.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn contradictory_options_are_rejected() {
+        assert!(Client::builder().source(Source::Aws).model("aifs-single").validate().is_ok());
+        assert!(Client::builder().source(Source::Azure).use_sas_token(true).validate().is_ok());
+
+        assert!(error(Client::builder().source(Source::Aws).use_sas_token(true)).contains("Source::Azure"));
+        assert!(error(Client::builder().sas_custom_url("mine", "https://t")).contains("not used"));
+        let custom = Client::builder().source(Source::Aws).use_sas_token(true).sas_custom_url("mine", "https://t");
+        assert!(custom.validate().is_ok());
+        assert!(error(Client::builder().fallback_source(Source::Ecmwf)).contains("fallback_source"));
+        assert!(error(Client::builder().max_concurrent_ranges(0)).contains("max_concurrent_ranges"));
+        let limit = RateLimit { requests_per_second: 0.0, ..RateLimit::default() };
+        assert!(error(Client::builder().rate_limit(limit)).contains("rate_limit"));
+        let namer = |_: &crate::TargetContext| "x".to_string();
+        assert!(error(Client::builder().layout(Layout::EcmwfTree).target_namer(namer)).contains("layout"));
+
+        let injected = Client::builder().http_client(HttpClient::new()).user_agent("app/1.0");
+        assert!(error(injected).contains("`user_agent`"));
+
+        assert!(Client::builder().allowed_hosts(["data.ecmwf.int"]).validate().is_ok());
+        let blocked = Client::builder().source(Source::Azure).allowed_hosts(["*.blob.core.windows.net"]);
+        assert!(error(blocked).contains("planetarycomputer.microsoft.com"));
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Handle to interrupt a [`crate::Client::retrieve_cancellable`] call from
+/// another thread. Clones share the same flag.
+///
+/// Cancellation is cooperative: the call stops before its next request (or
+/// range), fails with [`crate::Error::Cancelled`] and removes the partially
+/// written target. A transfer already in progress is finished first.
+///
+/// ```no_run
+/// use ecmwf_opendata::{CancellationToken, Client, ClientOptions, Request};
+///
+/// let client = Client::new(ClientOptions::default())?;
+/// let cancel = CancellationToken::new();
+/// let handle = cancel.clone();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     handle.cancel();
+/// });
+/// client.retrieve_cancellable(Request::new().param("msl"), &cancel)?;
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
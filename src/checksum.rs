@@ -0,0 +1,49 @@
+use std::io::{self, Write};
+
+pub(crate) use sha2::Sha256;
+use sha2::Digest;
+
+/// Lowercase hex digest of everything fed to `hasher`.
+pub(crate) fn hex(hasher: Sha256) -> String {
+    hasher.finalize().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Writer that hashes everything written through it when a hasher is set.
+pub(crate) struct HashingWriter<W> {
+    pub inner: W,
+    pub hasher: Option<Sha256>,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(data)?;
+        if let Some(h) = &mut self.hasher {
+            h.update(&data[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_what_is_written() {
+        let mut writer = HashingWriter {
+            inner: Vec::new(),
+            hasher: Some(Sha256::default()),
+        };
+        writer.write_all(b"abcdbcdecdefdefgefghfghighij").unwrap();
+        writer.write_all(b"hijkijkljklmklmnlmnomnopnopq").unwrap();
+        assert_eq!(writer.inner.len(), 56);
+        assert_eq!(
+            hex(writer.hasher.unwrap()),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
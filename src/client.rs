@@ -1,16 +1,51 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::io::{BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
-use reqwest::blocking::Client as HttpClient;
-use reqwest::header::{HeaderMap, HeaderValue, RANGE, USER_AGENT};
-
-use crate::date::{canonical_time_to_hour, expand_date_value, expand_time_value, full_datetime_from_date_time};
+use reqwest::blocking::{Client as HttpClient, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::availability::{self, Availability};
+use crate::cancel::CancellationToken;
+use crate::checksum::{self, HashingWriter, Sha256};
+use crate::components::{IndexComponents, UrlComponents};
+use crate::date::{canonical_time_to_hour, end_step, full_datetime_from_date_time};
+use crate::dedup::{InFlight, Transfer};
 use crate::error::{Error, Result as EResult};
-use crate::request::{expand_numeric_syntax, Request, RequestValue};
-use crate::sources::{is_http_url, source_to_base_url};
-use crate::url_builder::{format_url, patch_stream, user_to_url_value, HOURLY_PATTERN, MONTHLY_PATTERN};
+use crate::events::{emit, DownloadEvent};
+use crate::http::{
+    build_http_client, check_data_response, check_host, redirect_error, without_query, HttpEvent, HttpHook, Identity,
+    RedirectPolicy, SourceHeaders,
+};
+use crate::index::{DuplicateFieldPolicy, IndexEntry};
+use crate::keywords::unknown_values;
+use crate::param_catalog::{check_params, ParamCatalog};
+use crate::lint::{Lint, Severity};
+use crate::local;
+use crate::probe::{LatestFallback, ProbeOutcome, ProbePolicy, ProbeTarget};
+use crate::ranges::{encode_url_ranges, merge_ranges, merge_ranges_in_order, plan_fetches, skip_written, split_url_ranges, Fetch};
+use crate::quota::{Quota, Usage, UsageMeter};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::request::{expand_keyword_values, split_slash_list, Request, RequestValue};
+use crate::retry::RetryPolicy;
+use crate::sas::SasToken;
+use crate::sources::Source;
+use crate::target::{create_parent_dirs, target_path, tree_path, Layout, TargetContext, TargetNamer};
+use crate::telemetry::{self, RetryReason};
+use crate::throttle::{retry_after, Throttle, ThrottlePolicy};
+use crate::url_builder::{
+    extension_for_type, patch_stream, target_with_extension, user_to_url_value, UrlBuilder, HOURLY_PATTERN,
+    MONTHLY_PATTERN,
+};
+use crate::wave;
 
 const URL_COMPONENTS: [&str; 8] = [
     "date", "time", "model", "resol", "stream", "type", "step", "fcmonth",
@@ -18,33 +53,221 @@ const URL_COMPONENTS: [&str; 8] = [
 
 const INDEX_COMPONENTS: [&str; 6] = ["param", "type", "step", "fcmonth", "number", "levelist"];
 
+/// Keywords accepted in requests besides the URL and index components.
+const ANCILLARY_KEYWORDS: [&str; 2] = ["levtype", "target"];
+
+/// Write buffer of [`Request::fast_writes`] downloads.
+pub(crate) const FAST_WRITE_BUFFER: usize = 8 << 20;
+
+/// (sort_key, field) used when preserving request order.
+type OrderedPart = (Vec<(usize, usize)>, MatchedField);
+
 #[derive(Debug, Clone)]
 pub struct ClientOptions {
-    pub source: String,
+    pub source: Source,
+    /// Source to resolve a request from when `source` does not have it (no
+    /// cycle found, or a missing or empty index), e.g. [`Source::Esuites`]
+    /// falling back to [`Source::Ecmwf`]. The switch is recorded in
+    /// [`Result::warnings`].
+    pub fallback_source: Option<Source>,
     pub model: String,
     pub resol: String,
+    /// URL template of hourly products, for mirrors with a different
+    /// directory layout. Placeholders: `{url}`, `{yyyymmdd}`, `{H}`,
+    /// `{yyyymmddHHMMSS}`, `{model}`, `{resol}`, `{stream}`, `{type}`,
+    /// `{step}` and `{ext}`. Indexes are looked up next to the data file with
+    /// the extension replaced by `.index`, so templates should end in `.{ext}`.
+    pub hourly_pattern: String,
+    /// Like [`ClientOptions::hourly_pattern`], for monthly products
+    /// (`{fcmonth}` instead of `{step}`).
+    pub monthly_pattern: String,
+    /// URL type → file extension, for products whose payload is neither
+    /// GRIB2 nor the built-in BUFR of `tf` (see [`extension_for_type`]).
+    pub type_extensions: BTreeMap<String, String>,
+    /// Placement of targets; [`Layout::EcmwfTree`] writes each data file at
+    /// its upstream path below the target.
+    pub layout: Layout,
+    /// Custom naming of targets, replacing [`ClientOptions::layout`].
+    pub target_namer: Option<Arc<dyn TargetNamer>>,
     pub beta: bool,
+    /// Keep fields in the order of the request's keyword values instead of file
+    /// order, with levels in the order of the request's `levelist` (list them
+    /// ascending for monotonic levels). Otherwise fields are written in file
+    /// order, and only the `levelist` reported in [`Result::index_components`]
+    /// is sorted ascending (numerically).
     pub preserve_request_order: bool,
+    /// Which entry is downloaded when an `.index` lists a selected field more
+    /// than once.
+    pub duplicate_fields: DuplicateFieldPolicy,
     pub infer_stream_keyword: bool,
+    /// Reject requests with keywords that are neither URL nor index
+    /// components nor `levtype`/`target` (e.g. a misspelt `levellist`),
+    /// instead of ignoring them, and `stream`, `type` and `model` values
+    /// unknown to [`crate::Stream`], [`crate::DataType`] and [`crate::Model`]
+    /// (e.g. `enso`).
+    pub strict_keywords: bool,
+    /// Reject params the IFS open-data catalogue does not publish for the
+    /// request's streams and levtypes (see [`crate::ParamCatalog`]), with the
+    /// closest known param, before any index is read.
+    pub strict_params: bool,
     pub verify_tls: bool,
+    /// Application name and contact address sent with every request (see
+    /// [`Identity`]). Recommended for automated, high-volume use.
+    pub identity: Option<Identity>,
+    /// `User-Agent` sent instead of the library's (and the [`Identity`]'s).
+    pub user_agent: Option<String>,
+    /// Extra headers sent with every probe, index and data request to the
+    /// host of `source` (e.g. an API key for a private mirror). They replace
+    /// default headers of the same name. The fallback source, the SAS token
+    /// endpoint and hosts redirected to never see them.
+    pub headers: Vec<(String, String)>,
     pub use_sas_token: Option<bool>,
     pub sas_known_key: String,
     pub sas_custom_url: Option<String>,
+    /// Fixed query parameters (API keys, trace IDs, ...) appended to every
+    /// request URL, before any SAS token. Needed by some CDNs and gateways.
+    pub query_params: Vec<(String, String)>,
+    /// Interpretation of HTTP statuses when probing for the latest cycle.
+    pub probe_policy: ProbePolicy,
+    /// Files requested to establish the latest cycle.
+    pub probe_target: ProbeTarget,
+    /// Behaviour when the latest cycle cannot be established for a request
+    /// without `date` because the endpoint is temporarily unavailable.
+    pub latest_fallback: LatestFallback,
+    /// Retries of transient failures (connection errors, timeouts, 5xx) for
+    /// probes, index fetches and downloads.
+    pub retry_policy: RetryPolicy,
+    /// Backoff and concurrency reduction when downloads are throttled.
+    pub throttle_policy: ThrottlePolicy,
+    pub redirect_policy: RedirectPolicy,
+    /// Hosts the client may contact, including after redirects (e.g.
+    /// `data.ecmwf.int`, or `*.example.org` for its subdomains). Requests to
+    /// other hosts fail with [`Error::HostNotAllowed`]. `None` allows any.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Time allowed to establish a connection. `None` waits as long as the
+    /// operating system does.
+    pub connect_timeout: Option<std::time::Duration>,
+    /// Time allowed for each read (and, for the blocking client, each write)
+    /// on a connection. `None` keeps reqwest's defaults: 30 seconds for the
+    /// blocking client, unbounded for `AsyncClient` (feature `async`).
+    pub read_timeout: Option<std::time::Duration>,
+    /// Upper bound on one `retrieve*`, `download*`, [`Client::resume`] or
+    /// [`Client::retrieve_delta`] call, covering cycle probing, index fetches,
+    /// retries, throttling pauses and downloads. A call running past it fails
+    /// with [`Error::DeadlineExceeded`]; the target may then hold a partial
+    /// download.
+    pub deadline: Option<std::time::Duration>,
+    /// Upper bound on range requests per data URL. When index selection yields
+    /// more ranges, nearby ranges are fetched together and the bytes in between
+    /// are discarded. `None` issues one request per merged range.
+    pub max_requests_per_url: Option<usize>,
+    /// Neighbouring ranges at most this many bytes apart are fetched in one
+    /// request and the bytes in between discarded, trading a little bandwidth
+    /// for fewer requests on sparse index matches. `0` only joins ranges that
+    /// touch.
+    pub max_range_gap_bytes: u64,
+    /// Range requests of one data file downloaded in parallel by the blocking
+    /// client. Each batch is reassembled in order and written before the next
+    /// one starts, so at most this many ranges are held in memory; the
+    /// effective parallelism drops when the mirror throttles (see
+    /// [`ClientOptions::throttle_policy`]). `1` downloads serially.
+    pub max_concurrent_ranges: usize,
+    /// Fetch the ranges of each data file one at a time in ascending offset
+    /// order, writing each as it arrives, whatever
+    /// [`ClientOptions::max_concurrent_ranges`] says. Suits HDD-backed targets
+    /// and network filesystems that slow down on non-sequential access.
+    /// Fields are then written in file order, even with
+    /// [`ClientOptions::preserve_request_order`].
+    pub sequential_ranges: bool,
+    /// When a range still fails after retries, fetch its fields one by one and
+    /// skip those that keep failing, listing them in
+    /// [`Result::failed_fields`], instead of failing the whole download.
+    pub skip_failed_fields: bool,
+    /// Keywords whose values are never split on `/` (e.g. params or custom
+    /// keywords that legitimately contain slashes). A single slash can also be
+    /// kept by escaping it as `\/`.
+    pub no_split_keywords: Vec<String>,
+    /// Debug callback invoked for every HTTP request and response.
+    pub http_hook: Option<HttpHook>,
+    /// Channel receiving [`DownloadEvent`]s while downloading.
+    pub download_events: Option<Sender<DownloadEvent>>,
+    /// Chunks [`Client::retrieve_stream`] downloads ahead of its consumer.
+    pub stream_buffer: usize,
+    /// Client-side request rate and concurrency cap; `None` sends requests
+    /// as fast as the other policies allow.
+    pub rate_limit: Option<RateLimit>,
+    /// Cap on the data downloaded by this client in a session; `None` only
+    /// counts it (see [`Client::usage`]).
+    pub quota: Option<Quota>,
+}
+
+impl ClientOptions {
+    /// File extension of URL type `typ`: [`ClientOptions::type_extensions`],
+    /// else [`extension_for_type`].
+    pub fn extension_for(&self, typ: &str) -> String {
+        self.type_extensions
+            .get(typ)
+            .cloned()
+            .unwrap_or_else(|| extension_for_type(typ).to_string())
+    }
+
+    /// Whether `url` is a tropical cyclone track file, which is published
+    /// without an `.index`: its extension is the one configured for `tf`,
+    /// unless that is also the extension of forecasts.
+    pub(crate) fn is_track_file(&self, url: &str) -> bool {
+        let ext = self.extension_for("tf");
+        let data_url = url.split('|').next().unwrap_or(url);
+        ext != self.extension_for("fc") && data_url.strip_suffix(ext.as_str()).is_some_and(|s| s.ends_with('.'))
+    }
 }
 
 impl Default for ClientOptions {
     fn default() -> Self {
         Self {
-            source: "ecmwf".to_string(),
+            source: Source::Ecmwf,
+            fallback_source: None,
             model: "ifs".to_string(),
             resol: "0p25".to_string(),
+            hourly_pattern: HOURLY_PATTERN.to_string(),
+            monthly_pattern: MONTHLY_PATTERN.to_string(),
+            type_extensions: BTreeMap::new(),
+            layout: Layout::default(),
+            target_namer: None,
             beta: false,
             preserve_request_order: false,
+            duplicate_fields: DuplicateFieldPolicy::default(),
             infer_stream_keyword: true,
+            strict_keywords: false,
+            strict_params: false,
             verify_tls: true,
+            identity: None,
+            user_agent: None,
+            headers: Vec::new(),
             use_sas_token: None,
             sas_known_key: "ecmwf".to_string(),
             sas_custom_url: None,
+            query_params: Vec::new(),
+            probe_policy: ProbePolicy::default(),
+            probe_target: ProbeTarget::default(),
+            latest_fallback: LatestFallback::default(),
+            retry_policy: RetryPolicy::default(),
+            throttle_policy: ThrottlePolicy::default(),
+            redirect_policy: RedirectPolicy::default(),
+            allowed_hosts: None,
+            connect_timeout: None,
+            read_timeout: None,
+            deadline: None,
+            max_requests_per_url: None,
+            max_range_gap_bytes: 0,
+            max_concurrent_ranges: 1,
+            sequential_ranges: false,
+            skip_failed_fields: false,
+            no_split_keywords: Vec::new(),
+            http_hook: None,
+            download_events: None,
+            stream_buffer: 4,
+            rate_limit: None,
+            quota: None,
         }
     }
 }
@@ -53,56 +276,347 @@ impl Default for ClientOptions {
 pub struct Result {
     pub urls: Vec<String>,
     pub target: String,
-    pub datetime: DateTime<Utc>,
-    pub for_urls: BTreeMap<String, Vec<String>>,
-    pub for_index: BTreeMap<String, Vec<String>>,
+    /// Cycles the request resolved to, in ascending order: one unless it
+    /// lists several dates or times.
+    pub datetimes: Vec<DateTime<Utc>>,
+    /// Keywords the data URLs were built from.
+    pub url_components: UrlComponents,
+    /// Keywords the fields were selected by; empty for whole-file requests.
+    pub index_components: IndexComponents,
     pub size_bytes: u64,
+    /// Data URL → URL the transfer was finally served from after redirects
+    /// (query string removed).
+    pub final_urls: BTreeMap<String, String>,
+    /// Data URL → `Last-Modified`/`ETag` reported when it was downloaded, to
+    /// detect when a cycle is republished.
+    pub remote_versions: BTreeMap<String, RemoteVersion>,
+    /// File extension of the downloaded payload (`grib2`, or `bufr` for `type=tf`).
+    pub extension: String,
+    /// Source the URLs were resolved against: [`ClientOptions::source`], or
+    /// [`ClientOptions::fallback_source`] when the request was served from it.
+    pub source: Source,
+    /// Request values that were rewritten for URL building (type aliases,
+    /// inferred streams, probability steps), so URLs can be traced back to the
+    /// request.
+    pub substitutions: Vec<Substitution>,
+    /// Fields selected from the indexes, in the order they are written to the
+    /// target. Empty for whole-file downloads. See also
+    /// [`Result::index_entries`].
+    pub fields: Vec<MatchedField>,
+    /// Non-fatal problems worked around while resolving the request (e.g. a
+    /// [`LatestFallback`] cycle used because probing failed).
+    pub warnings: Vec<String>,
+    /// Lowercase hex SHA-256 of the target, computed while it was written.
+    /// `None` until downloaded, and for passes that appended to an existing
+    /// target (resumes, incremental retrievals).
+    pub sha256: Option<String>,
+    /// Keyword precedence from [`Request::order_by`], applied when
+    /// [`ClientOptions::preserve_request_order`] is set.
+    pub order_by: Vec<String>,
+    /// From [`Request::fast_writes`]: the target is written through a large
+    /// buffer.
+    pub fast_writes: bool,
+    /// Fields skipped because their ranges kept failing (see
+    /// [`ClientOptions::skip_failed_fields`]); they are not in `fields` and
+    /// not in the target.
+    pub failed_fields: Vec<MatchedField>,
+    /// How each data URL was transferred, in download order. Empty until
+    /// downloaded.
+    pub url_reports: Vec<UrlReport>,
+}
+
+/// Transfer of one data URL, in [`Result::url_reports`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlReport {
+    pub url: String,
+    /// Byte ranges requested (inclusive), in request order; empty for a
+    /// whole file.
+    pub ranges: Vec<(u64, u64)>,
+    /// HTTP status of the last response; `None` for local files.
+    pub status: Option<u16>,
+    /// Bytes received, including gaps between fields that were requested
+    /// with them and discarded.
+    pub bytes: u64,
+    /// Time from the first request to the last byte written.
+    pub elapsed: std::time::Duration,
+}
+
+impl UrlReport {
+    pub(crate) fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            ranges: Vec::new(),
+            status: None,
+            bytes: 0,
+            elapsed: std::time::Duration::ZERO,
+        }
+    }
+
+    /// Account for `transfer`, the response to `fetch`.
+    pub(crate) fn add(&mut self, fetch: &Fetch, transfer: &Transfer) {
+        self.ranges.push((fetch.start, fetch.end));
+        self.status = transfer.status;
+        self.bytes += transfer.bytes.len() as u64;
+    }
+}
+
+/// Validators the server reported for a downloaded data file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteVersion {
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+}
+
+impl RemoteVersion {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        Self {
+            last_modified: get(LAST_MODIFIED),
+            etag: get(ETAG),
+        }
+    }
+
+    /// Whether a validator reported both times differs, i.e. the file was
+    /// republished in between. Servers reporting neither tell nothing.
+    pub fn changed_since(&self, before: &RemoteVersion) -> bool {
+        let differs =
+            |now: &Option<String>, then: &Option<String>| matches!((now, then), (Some(a), Some(b)) if a != b);
+        differs(&self.etag, &before.etag) || differs(&self.last_modified, &before.last_modified)
+    }
+}
+
+/// One index entry selected for download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchedField {
+    pub param: String,
+    pub levelist: Option<String>,
+    pub step: Option<String>,
+    pub number: Option<String>,
+    pub offset: u64,
+    pub length: u64,
+    /// Data URL the field is read from.
+    pub url: String,
+    /// The whole index entry, with the keys the fields above leave out
+    /// (`type`, `stream`, `levtype`, ...). Default in manifests written
+    /// before it was recorded.
+    #[serde(default)]
+    pub entry: IndexEntry,
+}
+
+impl MatchedField {
+    pub(crate) fn from_index_entry(url: &str, entry: &IndexEntry) -> Self {
+        Self {
+            param: entry.param.clone().unwrap_or_default(),
+            levelist: entry.levelist.clone(),
+            step: entry.step.clone(),
+            number: entry.number.clone(),
+            offset: entry.offset,
+            length: entry.length,
+            url: url.to_string(),
+            entry: entry.clone(),
+        }
+    }
+
+    /// Cycle of the data file the field is read from, from its file name
+    /// (`20240101000000-6h-oper-fc.grib2`). `None` for other file names.
+    pub fn cycle(&self) -> Option<DateTime<Utc>> {
+        let name = self.url.rsplit('/').next()?;
+        let stamp = name.get(..14).filter(|s| s.bytes().all(|b| b.is_ascii_digit()))?;
+        let naive = chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%d%H%M%S").ok()?;
+        Some(naive.and_utc())
+    }
+}
+
+/// One request value replaced by a different value in the generated URLs,
+/// e.g. `type: cf → ef` or `stream: oper → scda`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substitution {
+    pub keyword: String,
+    pub original: String,
+    pub used: String,
+}
+
+/// One forecast lead time of a [`Result`], as yielded by [`Result::iter_lead_times`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeadTime {
+    /// Cycle the step counts from.
+    pub datetime: DateTime<Utc>,
+    /// Step as requested (e.g. `"24"` or `"0-24"` for accumulations/probabilities).
+    pub step: String,
+    /// `datetime` plus the (end) step in hours.
+    pub valid_time: DateTime<Utc>,
+    /// Params selected for this step.
+    pub params: Vec<String>,
+}
+
+impl Result {
+    /// Earliest cycle of the request.
+    #[deprecated(note = "a request may span several cycles; use `datetimes`")]
+    pub fn datetime(&self) -> DateTime<Utc> {
+        self.first_datetime()
+    }
+
+    /// Earliest cycle, for callers that handle one cycle per result.
+    pub(crate) fn first_datetime(&self) -> DateTime<Utc> {
+        self.datetimes.first().copied().unwrap_or_default()
+    }
+
+    /// [`Result::url_components`] as a keyword → values map.
+    pub fn for_urls(&self) -> BTreeMap<String, Vec<String>> {
+        self.url_components.to_map()
+    }
+
+    /// [`Result::index_components`] as a keyword → values map.
+    pub fn for_index(&self) -> BTreeMap<String, Vec<String>> {
+        self.index_components.to_map()
+    }
+
+    /// Index entries of the GRIB messages in the target, in the order they
+    /// were written. Empty for whole-file downloads.
+    pub fn index_entries(&self) -> impl Iterator<Item = &IndexEntry> + '_ {
+        self.fields.iter().map(|f| &f.entry)
+    }
+
+    /// Iterate over the requested steps in order, grouped per step, for each
+    /// cycle of [`Result::datetimes`] in turn.
+    ///
+    /// Steps come from the index selection when available (the user-facing
+    /// values), otherwise from the URL steps. Valid times count from the
+    /// step's own cycle. Params are those matched in that cycle's indexes for
+    /// each step, or the requested params for whole-file downloads.
+    pub fn iter_lead_times(&self) -> impl Iterator<Item = LeadTime> + '_ {
+        let steps = self
+            .index_components
+            .step
+            .as_ref()
+            .or(self.url_components.step.as_ref())
+            .cloned()
+            .unwrap_or_default();
+        let params = self.index_components.param.clone().unwrap_or_default();
+        let single_cycle = self.datetimes.len() == 1;
+
+        self.datetimes.iter().flat_map(move |&datetime| {
+            let params = params.clone();
+            steps.clone().into_iter().filter_map(move |step| {
+                let hours = end_step(&step)?;
+                // Prefer what was actually matched in the indexes.
+                let params = if self.fields.is_empty() {
+                    params.clone()
+                } else {
+                    unique_preserve(
+                        self.fields
+                            .iter()
+                            .filter(|f| f.step.as_deref() == Some(step.as_str()))
+                            .filter(|f| single_cycle || f.cycle() == Some(datetime))
+                            .map(|f| f.param.clone())
+                            .collect(),
+                    )
+                };
+                Some(LeadTime {
+                    datetime,
+                    valid_time: datetime + Duration::hours(hours),
+                    step,
+                    params,
+                })
+            })
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Client {
-    opts: ClientOptions,
+    pub(crate) opts: ClientOptions,
     base_url: String,
     http: HttpClient,
-    sas_token: Option<String>,
+    /// Shared with clones, so a refresh by one is seen by all.
+    sas_token: Option<Arc<Mutex<SasToken>>>,
+    throttle: Arc<Throttle>,
+    rate_limiter: Arc<RateLimiter>,
+    /// Shared with clones and the fallback client.
+    usage: Arc<UsageMeter>,
+    inflight: Arc<InFlight>,
+    source_headers: SourceHeaders,
+    fallback: Option<Box<Client>>,
+    /// End of the current call's [`ClientOptions::deadline`], set on the clone
+    /// the call runs on.
+    deadline: Option<Instant>,
+    /// Token of the current [`Client::retrieve_cancellable`] call, set on the
+    /// clone the call runs on.
+    cancel: Option<CancellationToken>,
 }
 
 impl Client {
     pub fn new(opts: ClientOptions) -> EResult<Self> {
-        let base_url = if is_http_url(&opts.source) {
-            opts.source.clone()
-        } else {
-            source_to_base_url(&opts.source)
-                .ok_or_else(|| Error::InvalidRequest(format!("unknown source: {}", opts.source)))?
-                .to_string()
-        };
+        let http = build_http_client(&opts)?;
+        let source_headers = SourceHeaders::new(&opts)?;
+        Self::with_parts(opts, http, source_headers)
+    }
 
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            USER_AGENT,
-            HeaderValue::from_static("ecmwf-opendata-rs/0.1"),
-        );
+    /// Like [`Client::new`], but send every request (including those to the
+    /// fallback source) through `http`, for applications that manage their
+    /// own connection pool, TLS configuration or middleware.
+    ///
+    /// Options applied when building the HTTP client are then ignored:
+    /// `identity`, `user_agent`, `headers`, `redirect_policy`, `verify_tls`
+    /// and `connect_timeout`. `read_timeout` and `deadline` still bound each
+    /// request. `allowed_hosts` is refused with [`Error::InvalidRequest`], as
+    /// redirects followed by `http` could not be checked against it.
+    pub fn with_http_client(opts: ClientOptions, http: HttpClient) -> EResult<Self> {
+        if opts.allowed_hosts.is_some() {
+            return Err(Error::InvalidRequest(
+                "`allowed_hosts` cannot be enforced on the redirects of an injected HTTP client".to_string(),
+            ));
+        }
+        Self::with_parts(opts, http, SourceHeaders::default())
+    }
 
-        let mut builder = HttpClient::builder().default_headers(headers);
-        if !opts.verify_tls {
-            builder = builder.danger_accept_invalid_certs(true);
+    fn with_parts(opts: ClientOptions, http: HttpClient, source_headers: SourceHeaders) -> EResult<Self> {
+        let base_url = opts.source.base_url().to_string();
+        let usage = Arc::new(UsageMeter::new(opts.quota));
+        let mut fallback = match &opts.fallback_source {
+            Some(source) => Some(Box::new(Client::with_parts(
+                ClientOptions {
+                    source: source.clone(),
+                    fallback_source: None,
+                    ..opts.clone()
+                },
+                http.clone(),
+                source_headers.without_headers(),
+            )?)),
+            None => None,
+        };
+        if let Some(fallback) = &mut fallback {
+            fallback.usage = usage.clone();
         }
-        let http = builder.build()?;
 
-        let use_sas = opts
-            .use_sas_token
-            .unwrap_or_else(|| opts.source == "azure");
+        let use_sas = uses_sas_token(&opts);
+
+        let throttle = Arc::new(Throttle::new(opts.throttle_policy.clone(), opts.max_concurrent_ranges));
+        let rate_limiter = Arc::new(RateLimiter::new(opts.rate_limit.clone()));
 
         let mut client = Self {
             base_url,
             http,
             opts,
             sas_token: None,
+            throttle,
+            rate_limiter,
+            usage,
+            inflight: Arc::new(InFlight::default()),
+            source_headers,
+            fallback,
+            deadline: None,
+            cancel: None,
         };
 
         if use_sas {
             let token = client.get_azure_sas_token()?;
-            client.sas_token = Some(token);
+            client.sas_token = Some(Arc::new(Mutex::new(token)));
         }
 
         Ok(client)
@@ -110,15 +624,101 @@ impl Client {
 
     pub fn retrieve(&self, request: Request, target: impl Into<String>) -> EResult<Result> {
         let target = target.into();
-        let res = self.get_urls(Some(&request), true, Some(&target))?;
-        self.download_result(&res, true)
+        let use_index = !request.force_full_files;
+        let client = self.bounded();
+        let res = client.get_urls(Some(&request), use_index, Some(&target))?;
+        client.download_result(&res, use_index, false)
     }
 
     /// Python-like convenience: `retrieve(request)` where `target` may be inside the request.
     /// If no target is provided, defaults to `data.grib2`.
+    ///
+    /// Fields are selected through the `.index` files unless the request sets
+    /// [`Request::force_full_files`].
     pub fn retrieve_request(&self, request: Request) -> EResult<Result> {
-        let res = self.get_urls(Some(&request), true, None)?;
-        self.download_result(&res, true)
+        let use_index = !request.force_full_files;
+        self.retrieve_with(request, use_index)
+    }
+
+    /// Retrieve a request, choosing per call whether to select fields through
+    /// the `.index` files (`use_index = true`, like [`Client::retrieve_request`])
+    /// or to download whole files (like [`Client::download_request`]).
+    pub fn retrieve_with(&self, request: Request, use_index: bool) -> EResult<Result> {
+        let client = self.bounded();
+        let res = client.get_urls(Some(&request), use_index, None)?;
+        client.download_result(&res, use_index, false)
+    }
+
+    /// Like [`Client::retrieve_request`], also requiring index entries to pass
+    /// `keep`, for selections that keyword lists cannot express (every 100
+    /// hPa, params matching a pattern, ...).
+    ///
+    /// Fields are written in file order (`preserve_request_order` does not
+    /// apply). A request without `param` or `levelist` is accepted, since
+    /// `keep` narrows the selection. Files published without an `.index`
+    /// (tropical cyclone tracks) cannot be filtered and fail with
+    /// [`Error::InvalidRequest`] naming the file.
+    ///
+    /// ```no_run
+    /// use ecmwf_opendata::{Client, ClientOptions, Request};
+    ///
+    /// let client = Client::new(ClientOptions::default())?;
+    /// let req = Request::new().param("t").step(24);
+    /// let every_100_hpa = |e: &ecmwf_opendata::IndexEntry| {
+    ///     e.levelist.as_deref().and_then(|l| l.parse::<u32>().ok()).is_some_and(|l| l % 100 == 0)
+    /// };
+    /// client.retrieve_filtered(req, every_100_hpa)?;
+    /// # Ok::<(), ecmwf_opendata::Error>(())
+    /// ```
+    pub fn retrieve_filtered(&self, request: Request, keep: impl Fn(&IndexEntry) -> bool) -> EResult<Result> {
+        let client = self.bounded();
+        let mut res = client.get_unnamed_urls(Some(&request), false, None)?;
+        let for_index = res.for_index();
+        let ordered_keys = ordered_index_keys(&for_index, &[]);
+        let mut urls = Vec::new();
+        for url in &res.urls {
+            let Some(body) = client.fetch_index_body(url)? else {
+                return Err(Error::InvalidRequest(format!("{url} has no index to filter; retrieve it whole instead")));
+            };
+            let entries: Vec<IndexEntry> = IndexEntry::parse(&body)?
+                .into_iter()
+                .filter(|e| index_matches(e, &ordered_keys, &for_index) && keep(e))
+                .collect();
+            let entries = client.opts.duplicate_fields.apply(&index_url_for(url), entries, &mut res.warnings)?;
+            let mut fields: Vec<MatchedField> =
+                entries.iter().map(|e| MatchedField::from_index_entry(url, e)).collect();
+            if fields.is_empty() {
+                continue;
+            }
+            fields.sort_by_key(|f| f.offset);
+            urls.push(encode_url_ranges(url, &merge_ranges(fields.iter().map(|f| (f.offset, f.length)).collect())));
+            res.fields.extend(fields);
+        }
+        if urls.is_empty() {
+            return Err(Error::NoMatchingIndex);
+        }
+        res.urls = urls;
+        name_target(&client.opts, &mut res);
+        client.download_result(&res, true, false)
+    }
+
+    /// Like [`Client::retrieve_request`], stopping with [`Error::Cancelled`]
+    /// before the next request once `cancel` is cancelled. The partially
+    /// written target is then removed.
+    pub fn retrieve_cancellable(&self, request: Request, cancel: &CancellationToken) -> EResult<Result> {
+        let use_index = !request.force_full_files;
+        let mut client = self.bounded().into_owned();
+        client.cancel = Some(cancel.clone());
+        if let Some(fallback) = &mut client.fallback {
+            fallback.cancel = Some(cancel.clone());
+        }
+
+        let res = client.get_urls(Some(&request), use_index, None)?;
+        let outcome = client.download_result(&res, use_index, false);
+        if matches!(outcome, Err(Error::Cancelled)) {
+            let _ = std::fs::remove_file(target_path(&res.target));
+        }
+        outcome
     }
 
     /// Python-kwargs-like convenience: build a request from pairs and retrieve it.
@@ -137,130 +737,394 @@ impl Client {
 
     pub fn download(&self, request: Request, target: impl Into<String>) -> EResult<Result> {
         let target = target.into();
-        let res = self.get_urls(Some(&request), false, Some(&target))?;
-        self.download_result(&res, false)
+        let client = self.bounded();
+        let res = client.get_urls(Some(&request), false, Some(&target))?;
+        client.download_result(&res, false, false)
     }
 
     /// Python-like convenience: `download(request)` where `target` may be inside the request.
     /// If no target is provided, defaults to `data.grib2`.
     pub fn download_request(&self, request: Request) -> EResult<Result> {
-        let res = self.get_urls(Some(&request), false, None)?;
-        self.download_result(&res, false)
+        let client = self.bounded();
+        let res = client.get_urls(Some(&request), false, None)?;
+        client.download_result(&res, false, false)
+    }
+
+    /// Continue an interrupted [`Client::retrieve`] (or, with
+    /// [`Request::force_full_files`], [`Client::download`]) into `target`.
+    ///
+    /// The bytes already in `target` are matched against the planned layout
+    /// (selected ranges, or whole files sized with `HEAD`), and only what is
+    /// missing is requested, with `Range` headers. Fails if the target is
+    /// larger than the planned download. The cycle must not have been
+    /// republished in between; compare [`Result::remote_versions`] when in doubt.
+    /// Not available with [`Layout::EcmwfTree`], which writes several files.
+    pub fn resume(&self, request: Request, target: impl Into<String>) -> EResult<Result> {
+        let target = target.into();
+        let use_index = !request.force_full_files;
+        if writes_tree(&self.opts) {
+            return Err(Error::InvalidRequest(
+                "resume continues a single target; Layout::EcmwfTree writes one file per URL".into(),
+            ));
+        }
+        let client = self.bounded();
+        let res = client.get_urls(Some(&request), use_index, Some(&target))?;
+
+        let have = std::fs::metadata(target_path(&res.target)).map(|m| m.len()).unwrap_or(0);
+        let mut skip = have;
+        let mut missing = Vec::new();
+        for u in &res.urls {
+            if use_index && u.contains('|') {
+                let (data_url, ranges) = split_url_ranges(u)?;
+                let left = skip_written(&ranges, &mut skip);
+                if !left.is_empty() {
+                    missing.push(encode_url_ranges(data_url, &left));
+                }
+            } else if skip == 0 {
+                missing.push(u.clone());
+            } else {
+                let size = client.estimate_url_size(u)?;
+                if size == 0 {
+                    return Err(Error::InvalidRequest(format!("size of {u} unknown; cannot resume")));
+                }
+                let left = skip_written(&[(0, size - 1)], &mut skip);
+                if !left.is_empty() {
+                    missing.push(encode_url_ranges(u, &left));
+                }
+            }
+        }
+        if skip > 0 {
+            return Err(Error::InvalidRequest(format!(
+                "{} is larger than the planned download; cannot resume",
+                res.target
+            )));
+        }
+
+        let mut pass = res.clone();
+        pass.urls = missing;
+        let done = client.download_result(&pass, true, true)?;
+
+        let mut out = res;
+        out.size_bytes = have + done.size_bytes;
+        out.final_urls = done.final_urls;
+        out.url_reports = done.url_reports;
+        out.remote_versions = done.remote_versions;
+        Ok(out)
+    }
+
+    /// Resolve a request exactly like [`Client::retrieve_request`] (`use_index = true`)
+    /// or [`Client::download_request`] (`use_index = false`) would, without
+    /// downloading any data or touching the target file.
+    ///
+    /// `size_bytes` on the returned [`Result`] is an estimate: the sum of the
+    /// selected byte ranges, or the advertised `Content-Length` for whole files.
+    /// Like a retrieval, it fails once [`ClientOptions::deadline`] has passed.
+    pub fn dry_run(&self, request: Request, use_index: bool) -> EResult<Result> {
+        let use_index = use_index && !request.force_full_files;
+        let client = self.bounded();
+        let mut res = client.get_urls(Some(&request), use_index, None)?;
+        let mut total: u64 = 0;
+        for u in &res.urls {
+            client.check_interrupted()?;
+            total += client.estimate_url_size(u)?;
+        }
+        res.size_bytes = total;
+        Ok(res)
+    }
+
+    /// Bytes retrieving `request` would download, to check quotas or disk
+    /// space first: the `_length`s of the fields its `.index` files select,
+    /// or the `Content-Length` of whole files (`HEAD` requests) when it
+    /// selects no fields. No data is downloaded.
+    pub fn estimate_size(&self, request: &Request) -> EResult<u64> {
+        let use_index = !request.is_unguarded_full_file();
+        Ok(self.dry_run(request.clone(), use_index)?.size_bytes)
     }
 
     pub fn latest(&self, request: Request) -> EResult<DateTime<Utc>> {
         self.latest_inner(&request)
     }
 
-    /// Convenience constructor similar to Python's `Client()` defaults.
-    pub fn default_client() -> EResult<Self> {
-        Self::new(ClientOptions::default())
-    }
+    /// Like [`Client::latest`], but only accept a cycle whose `.index` files
+    /// list every requested value (steps, params, levels, members) and every
+    /// combination of them each file should hold, so a run that is still
+    /// being published is skipped rather than returned.
+    ///
+    /// If the newest cycle is incomplete and no older one qualifies, this
+    /// fails with [`Error::CyclePartiallyPublished`].
+    pub fn latest_complete(&self, request: Request) -> EResult<DateTime<Utc>> {
+        let candidates = latest_candidates(&request, self.opts.source.retention())?;
+        let mut search = LatestSearch::new(&candidates);
+        'candidates: for (candidate, tmp_req) in candidates {
+            let res = self.get_urls(Some(&tmp_req), false, None)?;
+            if res.urls.is_empty() {
+                continue;
+            }
 
-    fn latest_inner(&self, request: &Request) -> EResult<DateTime<Utc>> {
-        let mut params = request.clone().into_inner();
+            let mut bodies = Vec::with_capacity(res.urls.len());
+            for (i, u) in res.urls.iter().enumerate() {
+                let index_url = index_url_for(u);
+                match self.probe(&self.request_url(&index_url))? {
+                    ProbeOutcome::Exists => {}
+                    ProbeOutcome::Absent(status) => {
+                        search.absent(candidate, status, i, &index_url);
+                        continue 'candidates;
+                    }
+                    ProbeOutcome::Unavailable(status) | ProbeOutcome::Unexpected(status) => {
+                        return Err(Error::ProbeUnavailable { url: index_url, status });
+                    }
+                }
+                // BUFR tracks without an index cannot be checked further.
+                if let Some(body) = self.fetch_index_body(u)? {
+                    bodies.push(body);
+                }
+            }
 
-        let now = Utc::now();
+            match missing_index_value(&bodies, &res.for_index())? {
+                None => return Ok(candidate),
+                Some(missing) => search.incomplete(candidate, missing),
+            }
+        }
+        Err(search.into_error())
+    }
 
-        // If time not in request: probe the most recent 6-hour cycle and step back by 6 hours.
-        // If time is in request: keep that hour and step back by 1 day.
-        let has_time = params.contains_key("time");
-        let delta = if has_time { Duration::days(1) } else { Duration::hours(6) };
+    /// Number of perturbed members in the ensemble files of `request`,
+    /// counted in the `.index` of its first file rather than assumed to be
+    /// 50. The control forecast is not counted.
+    ///
+    /// `type` and `number` of the request are ignored: the `ef` file holding
+    /// both control and perturbed members is looked at.
+    pub fn ensemble_size(&self, request: Request) -> EResult<u32> {
+        let mut request = request.r#type("pf");
+        request.remove("number");
+        let client = self.bounded();
+        let res = client.get_urls(Some(&request), false, None)?;
+        let url = res.urls.first().ok_or(Error::NoMatchingIndex)?;
+        let body = client
+            .fetch_index_body(url)?
+            .ok_or_else(|| Error::InvalidRequest(format!("no index for {url}")))?;
+        if body.trim().is_empty() {
+            return Err(Error::IndexNotReady { url: index_url_for(url) });
+        }
+        let members: BTreeSet<_> = IndexEntry::parse(&body)?
+            .into_iter()
+            .filter(|e| e.typ.as_deref() == Some("pf"))
+            .filter_map(|e| e.number)
+            .collect();
+        Ok(members.len() as u32)
+    }
 
-        let time_hour = if let Some(tv) = params.get("time") {
-            let t = tv.as_strings().get(0).cloned().unwrap_or_else(|| "18".into());
-            canonical_time_to_hour(&t)?
-        } else {
-            18
-        };
+    /// Full contents of the `.index` files of `request`'s data files, per data
+    /// URL, without downloading any data. Index keywords of the request
+    /// (`param`, `levelist`, ...) do not filter the entries; files without an
+    /// index (BUFR tracks) are left out.
+    pub fn index(&self, request: &Request) -> EResult<Vec<(String, Vec<IndexEntry>)>> {
+        let client = self.bounded();
+        let res = client.get_urls(Some(request), false, None)?;
+        let mut out = Vec::with_capacity(res.urls.len());
+        for url in res.urls {
+            if let Some(body) = client.fetch_index_body(&url)? {
+                out.push((url, IndexEntry::parse(&body)?));
+            }
+        }
+        Ok(out)
+    }
 
-        let mut candidate = if has_time {
-            // Start at today with that hour, but never in the future.
-            let start_date = now.date_naive();
-            let mut dt = Utc
-                .with_ymd_and_hms(
-                    start_date.year(),
-                    start_date.month(),
-                    start_date.day(),
-                    time_hour,
-                    0,
-                    0,
-                )
-                .single()
-                .ok_or_else(|| Error::InvalidRequest("invalid start datetime".into()))?;
-            if dt > now {
-                dt = dt - Duration::days(1);
-            }
-            dt
-        } else {
-            // Round down to the nearest 6-hour cycle: 00/06/12/18.
-            let hour = (now.hour() / 6) * 6;
-            Utc.with_ymd_and_hms(now.year(), now.month(), now.day(), hour, 0, 0)
-                .single()
-                .ok_or_else(|| Error::InvalidRequest("invalid start datetime".into()))?
-        };
+    /// What the cycle at `date` and hour `time` publishes, as a matrix of
+    /// stream × type × step × param read from its `.index` files, so requests
+    /// can be built from what is actually there.
+    ///
+    /// Every product of the catalogue of [`ClientOptions::model`] is looked
+    /// for, one index request per step it may be published at; files that do
+    /// not exist (yet) are skipped. Tropical cyclone tracks, which have no
+    /// index, are probed and listed without params. Nothing else is
+    /// downloaded.
+    pub fn availability(&self, date: impl Into<RequestValue>, time: u32) -> EResult<Availability> {
+        let client = self.bounded();
+        let date = date.into();
+        let mut out: Option<Availability> = None;
+        for product in availability::catalogue(&self.opts.model, time) {
+            let res = client.get_urls(Some(&product.clone().date(date.clone()).time(time)), false, None)?;
+            let availability = out.get_or_insert_with(|| Availability::new(res.first_datetime()));
+            for url in &res.urls {
+                match client.fetch_index_body(url) {
+                    Ok(Some(body)) => availability.add(&IndexEntry::parse(&body)?),
+                    // Track files have no index: list them if they exist.
+                    Ok(None) if client.probe(&client.request_url(url))? == ProbeOutcome::Exists => {
+                        let value = |key: &str| product.get(key).map(|v| v.as_strings().join("/")).unwrap_or_default();
+                        availability.add_file(&value("stream"), &value("type"), &value("step"));
+                    }
+                    Ok(None) => {}
+                    Err(e) if is_missing_from_source(&e) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        out.ok_or(Error::NoMatchingIndex)
+    }
 
-        // Search back up to ~5 days.
-        let stop = candidate - Duration::days(5);
+    /// Check `request` without downloading it: unknown keywords (whatever
+    /// [`ClientOptions::strict_keywords`] says), `stream`, `type` and `model`
+    /// values unknown to [`crate::Stream`], [`crate::DataType`] and
+    /// [`crate::Model`], params missing from [`ParamCatalog`], values that do
+    /// not resolve
+    /// to URLs, full-file selections and the warnings resolution would add.
+    ///
+    /// Nothing is sent unless `check_availability` is set; the `.index` files
+    /// of the request are then read (after looking up the latest cycle if the
+    /// request has no `date`) to report files and values not published.
+    pub fn lint(&self, request: &Request, check_availability: bool) -> Vec<Lint> {
+        let mut lints: Vec<Lint> = unknown_keywords(request).into_iter().map(Lint::error).collect();
+        lints.extend(unknown_values(request).into_iter().map(Lint::warning));
+        let unknown_params = ParamCatalog::default().unknown_params_for(request, &self.opts.model);
+        lints.extend(unknown_params.into_iter().map(Lint::warning));
+        if request.is_unguarded_full_file() {
+            lints.push(Lint::warning(
+                "request selects neither param nor levelist: retrieving it fails and downloading it fetches whole \
+                 files; add a param or levelist to select fields",
+            ));
+        }
 
-        loop {
-            if candidate <= stop {
-                break;
+        let (model, mut params) = request_defaults(&self.opts, Some(request));
+        if !params.contains_key("date") {
+            set_cycle(&mut params, Utc::now().with_hour(0).unwrap_or_else(Utc::now));
+        }
+        match resolve_urls(&self.opts, &self.base_url, &model, &params, None, Vec::new()) {
+            Ok(res) => lints.extend(res.warnings.into_iter().map(Lint::warning)),
+            Err(e) => lints.push(Lint::error(e.to_string())),
+        }
+        if check_availability && !lints.iter().any(|l| l.severity == Severity::Error) {
+            lints.extend(self.lint_availability(request));
+        }
+        lints
+    }
+
+    fn lint_availability(&self, request: &Request) -> Vec<Lint> {
+        let client = self.bounded();
+        let res = match client.get_urls(Some(request), false, None) {
+            Ok(res) => res,
+            Err(e) => return vec![Lint::error(e.to_string())],
+        };
+        let mut lints = Vec::new();
+        let mut bodies = Vec::new();
+        for url in &res.urls {
+            match client.fetch_index_body(url) {
+                Ok(Some(body)) => bodies.push(body),
+                Ok(None) => {}
+                Err(e) if is_missing_from_source(&e) => {
+                    lints.push(Lint::error(format!("{} is not published", index_url_for(url))));
+                }
+                Err(e) => lints.push(Lint::warning(format!("could not check {}: {e}", index_url_for(url)))),
             }
+        }
+        match missing_index_value(&bodies, &res.for_index()) {
+            Ok(Some(missing)) if lints.is_empty() => lints.push(Lint::error(format!(
+                "{missing} is not in the index files of the {} cycle",
+                res.first_datetime().format("%Y-%m-%d %Hz")
+            ))),
+            Ok(_) => {}
+            Err(e) => lints.push(Lint::warning(e.to_string())),
+        }
+        lints
+    }
 
-            params.insert(
-                "date".to_string(),
-                RequestValue::Str(candidate.format("%Y%m%d").to_string()),
-            );
-            let probe_hour: u32 = if has_time {
-                time_hour
-            } else {
-                candidate.hour()
-            };
-            params.insert("time".to_string(), RequestValue::Int(probe_hour as i64));
+    /// Convenience constructor similar to Python's `Client()` defaults.
+    pub fn default_client() -> EResult<Self> {
+        Self::new(ClientOptions::default())
+    }
 
-            let tmp_req = Request::from_inner(params.clone());
+    fn latest_inner(&self, request: &Request) -> EResult<DateTime<Utc>> {
+        let candidates = latest_candidates(request, self.opts.source.retention())?;
+        let mut search = LatestSearch::new(&candidates);
+        for (candidate, tmp_req) in candidates {
             let res = self.get_urls(Some(&tmp_req), false, None)?;
 
             let mut ok = !res.urls.is_empty();
-            for u in &res.urls {
-                let url = self.apply_sas_to_url(u);
-                if !self.probe_exists(&url)? {
-                    ok = false;
-                    break;
+            for (i, u) in self.opts.probe_target.urls(&self.opts, &res.urls).iter().enumerate() {
+                let url = self.request_url(u);
+                match self.probe(&url)? {
+                    ProbeOutcome::Exists => {}
+                    ProbeOutcome::Absent(status) => {
+                        search.absent(candidate, status, i, u);
+                        ok = false;
+                        break;
+                    }
+                    ProbeOutcome::Unavailable(status) | ProbeOutcome::Unexpected(status) => {
+                        // Do not walk back to an older cycle on throttling/outages.
+                        return Err(Error::ProbeUnavailable {
+                            url: u.clone(),
+                            status,
+                        });
+                    }
                 }
             }
             if ok {
                 return Ok(candidate);
             }
-
-            candidate = candidate - delta;
         }
+        Err(search.into_error())
+    }
 
-        Err(Error::CannotEstablishLatest)
+    /// Probe a URL for existence, retrying "unavailable" answers as configured
+    /// by [`ClientOptions::probe_policy`].
+    pub(crate) fn probe(&self, url: &str) -> EResult<ProbeOutcome> {
+        let policy = &self.opts.probe_policy;
+        let mut attempt = 0;
+        let mut transport_retries = 0;
+        let mut url = url.to_string();
+        let mut resigned = false;
+        loop {
+            self.check_interrupted()?;
+            let outcome = match self.probe_once(&url) {
+                Err(Error::Http(e))
+                    if self.opts.retry_policy.is_retryable_error(&e)
+                        && transport_retries < self.opts.retry_policy.max_retries =>
+                {
+                    telemetry::retry(&self.opts.source, RetryReason::Transport);
+                    std::thread::sleep(self.opts.retry_policy.delay(transport_retries));
+                    transport_retries += 1;
+                    continue;
+                }
+                outcome => outcome?,
+            };
+            match outcome {
+                ProbeOutcome::Unavailable(_) if attempt < policy.unavailable_retries => {
+                    telemetry::retry(&self.opts.source, RetryReason::Unavailable);
+                    attempt += 1;
+                    std::thread::sleep(policy.retry_delay);
+                }
+                // The SAS token may have expired or been revoked.
+                ProbeOutcome::Absent(403) if !resigned && self.refresh_sas_token()? => {
+                    telemetry::retry(&self.opts.source, RetryReason::Resigned);
+                    resigned = true;
+                    url = self.request_url(unsigned(&url));
+                }
+                _ => return Ok(outcome),
+            }
+        }
     }
 
-    /// Probe a URL for existence.
+    /// Probe a URL once.
     ///
     /// Upstream Python uses HTTP HEAD. Some endpoints may block HEAD or respond
     /// with non-200 even though GET works; in that case we fall back to a tiny
-    /// ranged GET.
-    fn probe_exists(&self, url: &str) -> EResult<bool> {
+    /// ranged GET, whose status is then classified by the probe policy.
+    fn probe_once(&self, url: &str) -> EResult<ProbeOutcome> {
+        if let Some(path) = local::file_path(url) {
+            return Ok(self.opts.probe_policy.classify(local::status(&path)));
+        }
+        let _slot = self.rate_limiter.slot();
         // Try HEAD first (cheap when supported).
-        match self.http.head(url).send() {
+        match self.send(self.http.head(url)) {
             Ok(resp) => {
-                if resp.status() == 200 {
-                    return Ok(true);
+                let status = resp.status().as_u16();
+                if status == 200 {
+                    return Ok(ProbeOutcome::Exists);
                 }
 
                 // If HEAD is not usable, fall back to a ranged GET.
-                if matches!(
-                    resp.status().as_u16(),
-                    403 | 404 | 405 | 409 | 429 | 500 | 501 | 502 | 503
-                ) {
-                    // continue to GET probe
-                } else {
-                    return Ok(false);
+                if !matches!(status, 403 | 404 | 405 | 409 | 429 | 500 | 501 | 502 | 503) {
+                    return Ok(self.opts.probe_policy.classify(status));
                 }
             }
             Err(_) => {
@@ -269,368 +1133,354 @@ impl Client {
         }
 
         // GET with a single byte range; accept 206 (partial) or 200.
-        let resp = self
-            .http
-            .get(url)
-            .header(RANGE, "bytes=0-0")
-            .send()?;
+        let resp = self.send(self.http.get(url).header(RANGE, "bytes=0-0"))?;
+
+        Ok(self.opts.probe_policy.classify(resp.status().as_u16()))
+    }
+
+    /// Estimated transfer size of a (possibly range-encoded) URL.
+    pub(crate) fn estimate_url_size(&self, u: &str) -> EResult<u64> {
+        if u.contains('|') {
+            let (_, ranges) = split_url_ranges(u)?;
+            return Ok(ranges.iter().map(|(start, end)| end - start + 1).sum());
+        }
 
-        Ok(matches!(resp.status().as_u16(), 200 | 206))
+        let url = self.request_url(u);
+        if let Some(path) = local::file_path(&url) {
+            return local::size(&path);
+        }
+        let _slot = self.rate_limiter.slot();
+        let resp = self.send(self.http.head(url))?.error_for_status()?;
+        Ok(resp
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0))
     }
 
-    fn get_urls(
+    /// Resolve a request into URLs (and index ranges when `use_index`),
+    /// trying [`ClientOptions::fallback_source`] when `source` lacks it, and
+    /// name the target with [`ClientOptions::target_namer`].
+    pub(crate) fn get_urls(
         &self,
         request: Option<&Request>,
         use_index: bool,
         target: Option<&str>,
     ) -> EResult<Result> {
-        let mut params = match request {
-            Some(r) => r.clone().into_inner(),
-            None => BTreeMap::new(),
-        };
+        let mut res = self.get_unnamed_urls(request, use_index, target)?;
+        name_target(&self.opts, &mut res);
+        Ok(res)
+    }
 
-        // defaults
-        let model = params
-            .get("model")
-            .map(|v| v.as_strings().get(0).cloned().unwrap_or_else(|| self.opts.model.clone()))
-            .unwrap_or_else(|| self.opts.model.clone());
+    /// Like [`Client::get_urls`], leaving the target to name to callers that
+    /// select the fields themselves (then calling `name_target`) or write
+    /// to a target named already.
+    pub(crate) fn get_unnamed_urls(
+        &self,
+        request: Option<&Request>,
+        use_index: bool,
+        target: Option<&str>,
+    ) -> EResult<Result> {
+        match self.resolve_request(request, use_index, target) {
+            Err(e) if is_missing_from_source(&e) && let Some(fallback) = &self.fallback => {
+                let mut res = fallback.get_unnamed_urls(request, use_index, target)?;
+                res.warnings
+                    .push(format!("{} could not serve the request ({e}); used {}", self.opts.source, fallback.opts.source));
+                Ok(res)
+            }
+            outcome => outcome,
+        }
+    }
 
-        if model == "aifs-ens" && !params.contains_key("stream") {
-            params.insert("stream".to_string(), RequestValue::Str("enfo".to_string()));
+    fn resolve_request(
+        &self,
+        request: Option<&Request>,
+        use_index: bool,
+        target: Option<&str>,
+    ) -> EResult<Result> {
+        if use_index && request.is_some_and(|r| r.is_unguarded_full_file()) {
+            return Err(Error::FullFileRequest);
+        }
+        if self.opts.strict_keywords
+            && let Some(request) = request
+        {
+            check_keywords(request)?;
+        }
+        if self.opts.strict_params
+            && let Some(request) = request
+        {
+            check_params(request, &self.opts.model)?;
         }
 
-        params.entry("model".to_string()).or_insert(RequestValue::Str(model.clone()));
-        params
-            .entry("resol".to_string())
-            .or_insert(RequestValue::Str(self.opts.resol.clone()));
+        let (model, mut params) = request_defaults(&self.opts, request);
 
-        params.entry("type".to_string()).or_insert(RequestValue::Str("fc".to_string()));
-        params
-            .entry("stream".to_string())
-            .or_insert(RequestValue::Str("oper".to_string()));
+        let mut warnings = Vec::new();
 
         // If date missing, resolve latest.
         if !params.contains_key("date") {
             let tmp_req = Request::from_inner(params.clone());
-            let latest = self.latest_inner(&tmp_req)?;
-            params.insert(
-                "date".to_string(),
-                RequestValue::Str(latest.format("%Y%m%d").to_string()),
-            );
-            // Keep request's time if present; else use latest hour.
-            if !params.contains_key("time") {
-                params.insert("time".to_string(), RequestValue::Int(latest.hour() as i64));
-            }
+            let latest = latest_or_fallback(&self.opts, &params, self.latest_inner(&tmp_req), &mut warnings)?;
+            set_cycle(&mut params, latest);
         }
 
-        // Normalize / expand into for_urls and for_index
-        let now = Utc::now();
+        let mut res = resolve_urls(&self.opts, &self.base_url, &model, &params, target, warnings)?;
+        res.order_by = request.map(|r| r.order_by.clone()).unwrap_or_default();
+        res.fast_writes = request.is_some_and(|r| r.fast_writes);
 
-        let mut for_urls: BTreeMap<String, Vec<String>> = BTreeMap::new();
-        let mut for_index: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        if use_index && !res.index_components.is_empty() {
+            let (urls, fields) =
+                self.expand_urls_to_ranges(&res.urls, &res.for_index(), &res.order_by, &mut res.warnings)?;
+            res.urls = urls;
+            res.fields = fields;
+        }
 
-        // Build for_urls types first to allow step mapping for probabilities.
-        let typ_values_user: Vec<String> = params
-            .get("type")
-            .map(|v| v.as_strings())
-            .unwrap_or_else(|| vec!["fc".to_string()]);
+        Ok(res)
+    }
 
-        let mut for_urls_type: Vec<String> = Vec::new();
-        for tv in typ_values_user {
-            for_urls_type.push(user_to_url_value(&model, "type", &tv, &[]));
-        }
-        if for_urls_type.is_empty() {
-            for_urls_type.push("fc".to_string());
+    /// This client, or a clone of it bounded by [`ClientOptions::deadline`]
+    /// from now when a call starts.
+    pub(crate) fn bounded(&self) -> Cow<'_, Client> {
+        let Some(deadline) = self.opts.deadline.filter(|_| self.deadline.is_none()) else {
+            return Cow::Borrowed(self);
+        };
+        let mut client = self.clone();
+        let end = Instant::now() + deadline;
+        client.deadline = Some(end);
+        if let Some(fallback) = &mut client.fallback {
+            fallback.deadline = Some(end);
         }
-        for_urls.insert("type".to_string(), unique_preserve(for_urls_type));
+        Cow::Owned(client)
+    }
 
-        // Process each param
-        for (k, v) in params.iter() {
-            let mut values = v.as_strings();
+    /// Fail once the call has been cancelled or has run past its deadline.
+    fn check_interrupted(&self) -> EResult<()> {
+        if self.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+        match (self.deadline, self.opts.deadline) {
+            (Some(end), Some(deadline)) if Instant::now() >= end => Err(Error::DeadlineExceeded(deadline)),
+            _ => Ok(()),
+        }
+    }
 
-            // allow slash-separated lists
-            if values.len() == 1 && values[0].contains('/') {
-                values = values[0]
-                    .split('/')
-                    .filter(|t| !t.is_empty())
-                    .map(|t| t.to_string())
-                    .collect();
+    /// Send a request, reporting it to the HTTP hook when one is configured.
+    /// Hosts outside [`ClientOptions::allowed_hosts`] are refused before
+    /// anything is sent. [`ClientOptions::headers`] are only sent to the
+    /// primary source; cross-host redirects are followed here without them.
+    fn send(&self, req: RequestBuilder) -> EResult<Response> {
+        // A request may not outlive the call's deadline.
+        let req = match self.deadline {
+            Some(end) => {
+                let left = end.saturating_duration_since(Instant::now());
+                req.timeout(self.opts.read_timeout.map_or(left, |t| t.min(left)))
             }
-
-            let expanded: Vec<String> = match k.as_str() {
-                "date" => {
-                    let mut out = Vec::new();
-                    for x in values {
-                        out.extend(expand_date_value(&x, now)?);
-                    }
-                    out
-                }
-                "time" => {
-                    let mut out = Vec::new();
-                    for x in values {
-                        out.extend(expand_time_value(&x)?);
-                    }
-                    out
-                }
-                "step" | "fcmonth" | "number" | "levelist" => {
-                    let mut out = Vec::new();
-                    for x in values {
-                        out.extend(expand_numeric_syntax(&x)?);
-                    }
-                    out
-                }
-                _ => values,
-            };
-
-            if URL_COMPONENTS.contains(&k.as_str()) {
-                let mut mapped = Vec::new();
-                for x in &expanded {
-                    let url_t = for_urls.get("type").cloned().unwrap_or_default();
-                    mapped.push(user_to_url_value(&model, k, x, &url_t));
-                }
-                for_urls
-                    .entry(k.clone())
-                    .or_default()
-                    .extend(mapped);
-            }
-
-            if INDEX_COMPONENTS.contains(&k.as_str()) {
-                // user_to_index: type=ef expands to cf/pf for index selection.
-                let mut mapped = Vec::new();
-                if k == "type" {
-                    for x in &expanded {
-                        if x == "ef" {
-                            mapped.push("cf".to_string());
-                            mapped.push("pf".to_string());
-                        } else {
-                            mapped.push(x.clone());
-                        }
+            None => req,
+        };
+        let mut req = req.build()?;
+        // Cross-host redirects handed back are followed here, within the
+        // policy's limit, so a loop between hosts cannot go on forever.
+        let max = self.opts.redirect_policy.max_redirects;
+        let mut hops_left = max;
+        loop {
+            std::thread::sleep(self.rate_limiter.reserve());
+            let url = req.url().clone();
+            self.source_headers.attach(&url, req.headers_mut());
+            check_host(self.opts.allowed_hosts.as_deref(), &url)?;
+            let next = req.try_clone();
+            let started = Instant::now();
+            let outcome = self.execute(req);
+            telemetry::request(
+                &self.opts.source,
+                outcome.as_ref().ok().map(|r| r.status().as_u16()),
+                started.elapsed(),
+            );
+            let resp = outcome.map_err(redirect_error)?;
+            match (next, self.source_headers.redirect(resp.url(), resp.status(), resp.headers())) {
+                (Some(next), Some(location)) => {
+                    if hops_left == 0 {
+                        return Err(Error::TooManyRedirects {
+                            url: without_query(next.url()),
+                            max,
+                        });
                     }
-                } else {
-                    mapped = expanded.clone();
+                    hops_left -= 1;
+                    req = next;
+                    *req.url_mut() = location;
                 }
-                for_index.entry(k.clone()).or_default().extend(mapped);
+                _ => return Ok(resp),
             }
         }
+    }
 
-        // Canonicalize time: store hour string (00/06/12/18)
-        if let Some(times) = for_urls.get_mut("time") {
-            let mut out = Vec::new();
-            for t in times.drain(..) {
-                let hour = canonical_time_to_hour(&t)?;
-                out.push(format!("{hour:02}"));
-            }
-            *times = unique_preserve(out);
-        }
+    fn execute(&self, req: reqwest::blocking::Request) -> reqwest::Result<Response> {
+        let Some(hook) = &self.opts.http_hook else {
+            return self.http.execute(req);
+        };
 
-        // Infer/patch stream in URL building; we keep stream values but will patch later per product.
-        for (k, vals) in for_urls.iter_mut() {
-            *vals = unique_preserve(std::mem::take(vals));
-            if k == "stream" || k == "type" {
-                vals.iter_mut().for_each(|s| s.make_ascii_lowercase());
+        let method = req.method().to_string();
+        let url = without_query(req.url());
+        hook.call(&HttpEvent::Request {
+            method: method.clone(),
+            url: url.clone(),
+            range: req
+                .headers()
+                .get(RANGE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string()),
+        });
+
+        match self.http.execute(req) {
+            Ok(resp) => {
+                hook.call(&HttpEvent::Response {
+                    method,
+                    url,
+                    status: resp.status().as_u16(),
+                    content_length: resp.content_length(),
+                });
+                Ok(resp)
             }
-        }
-        for (k, vals) in for_index.iter_mut() {
-            *vals = unique_preserve(std::mem::take(vals));
-            if k == "stream" || k == "type" {
-                vals.iter_mut().for_each(|s| s.make_ascii_lowercase());
+            Err(e) => {
+                hook.call(&HttpEvent::Failed {
+                    method,
+                    url,
+                    error: e.to_string(),
+                });
+                Err(e)
             }
         }
+    }
 
-        // If tf (tropical cyclone tracks), do not use index selection.
-        let user_type = params
-            .get("type")
-            .map(|v| v.as_strings().get(0).cloned().unwrap_or_else(|| "fc".into()))
-            .unwrap_or_else(|| "fc".into());
-        if user_type == "tf" {
-            for_index.clear();
-        }
-
-        // If time missing (possible if date contains time), default time based on date.
-        if !for_urls.contains_key("time") {
-            for_urls.insert("time".to_string(), vec!["18".to_string()]);
-        }
-
-        // Now expand into concrete URLs
-        let mut urls = Vec::new();
-        let mut dates = BTreeSet::new();
-
-        let date_vals = for_urls
-            .get("date")
-            .cloned()
-            .ok_or_else(|| Error::InvalidRequest("date missing after normalization".into()))?;
-        let time_vals = for_urls
-            .get("time")
-            .cloned()
-            .ok_or_else(|| Error::InvalidRequest("time missing after normalization".into()))?;
-
-        let model_vals = for_urls.get("model").cloned().unwrap_or_else(|| vec![model.clone()]);
-        let resol_vals = for_urls
-            .get("resol")
-            .cloned()
-            .unwrap_or_else(|| vec![self.opts.resol.clone()]);
-        let stream_vals = for_urls
-            .get("stream")
-            .cloned()
-            .unwrap_or_else(|| vec!["oper".to_string()]);
-        let type_vals = for_urls
-            .get("type")
-            .cloned()
-            .unwrap_or_else(|| vec!["fc".to_string()]);
-        let step_vals_opt = for_urls.get("step").cloned();
-        let fcmonth_vals = for_urls
-            .get("fcmonth")
-            .cloned()
-            .unwrap_or_else(|| vec!["1".to_string()]);
-
-        for d in &date_vals {
-            for t in &time_vals {
-                let dt = full_datetime_from_date_time(d, t.parse::<u32>().map_err(|_| {
-                    Error::InvalidRequest(format!("invalid canonical time hour: {t}"))
-                })?)?;
-                dates.insert(dt);
-
-                for m in &model_vals {
-                    for r in &resol_vals {
-                        for s in &stream_vals {
-                            for ty in &type_vals {
-                                // patch stream based on time and type
-                                let hour_2d = dt.format("%H").to_string();
-                                let patched_stream = patch_stream(
-                                    self.opts.infer_stream_keyword,
-                                    m,
-                                    s,
-                                    &hour_2d,
-                                    ty,
-                                );
-
-                                let is_monthly = s == "mmsa" || s == "mmsf";
-                                let pattern = if is_monthly {
-                                    MONTHLY_PATTERN
-                                } else {
-                                    HOURLY_PATTERN
-                                };
-
-                                // beta tweaks
-                                let mut resol = r.clone();
-                                if self.opts.beta {
-                                    resol = format!("{resol}/experimental");
-                                }
-
-                                if is_monthly {
-                                    for fcmonth in &fcmonth_vals {
-                                        let u = format_url(
-                                            pattern,
-                                            &self.base_url,
-                                            dt,
-                                            m,
-                                            &resol,
-                                            &patched_stream,
-                                            ty,
-                                            None,
-                                            Some(fcmonth),
-                                        );
-                                        urls.push(self.fix_0p4_beta(u));
-                                    }
-                                } else {
-                                    let steps_for_url: Vec<String> = match &step_vals_opt {
-                                        Some(v) => v.clone(),
-                                        None => vec![default_step_for_url(&patched_stream, ty, dt.hour())],
-                                    };
-                                    for step in &steps_for_url {
-                                        let u = format_url(
-                                            pattern,
-                                            &self.base_url,
-                                            dt,
-                                            m,
-                                            &resol,
-                                            &patched_stream,
-                                            ty,
-                                            Some(step),
-                                            None,
-                                        );
-                                        urls.push(self.fix_0p4_beta(u));
-                                    }
-                                }
-                            }
-                        }
-                    }
+    /// GET `url` (optionally with a `Range` header), pausing and retrying while
+    /// the mirror answers with a throttling status.
+    fn get_throttled(&self, url: &str, range: Option<&str>) -> EResult<Response> {
+        let retry = &self.opts.retry_policy;
+        let mut attempt = 0;
+        let mut retries = 0;
+        let mut url = url.to_string();
+        let mut resigned = false;
+        loop {
+            self.check_interrupted()?;
+            let mut req = self.http.get(&url);
+            if let Some(range) = range {
+                req = req.header(RANGE, range);
+            }
+            let resp = match self.send(req) {
+                Err(Error::Http(e)) if retry.is_retryable_error(&e) && retries < retry.max_retries => {
+                    telemetry::retry(&self.opts.source, RetryReason::Transport);
+                    std::thread::sleep(retry.delay(retries));
+                    retries += 1;
+                    continue;
                 }
+                resp => resp?,
+            };
+
+            let status = resp.status().as_u16();
+            // The SAS token may have expired or been revoked.
+            if status == 403 && !resigned && self.refresh_sas_token()? {
+                telemetry::retry(&self.opts.source, RetryReason::Resigned);
+                resigned = true;
+                url = self.request_url(unsigned(&url));
+                continue;
+            }
+            if self.throttle.is_throttled(status) && attempt < self.throttle.max_retries() {
+                telemetry::retry(&self.opts.source, RetryReason::Throttled);
+                attempt += 1;
+                std::thread::sleep(self.throttle.on_throttle(retry_after(resp.headers())));
+                continue;
             }
+            if retry.is_retryable_status(status) && retries < retry.max_retries {
+                telemetry::retry(&self.opts.source, RetryReason::Status);
+                std::thread::sleep(retry.delay(retries));
+                retries += 1;
+                continue;
+            }
+            if resp.status().is_success() {
+                self.throttle.on_success();
+            }
+            return Ok(resp.error_for_status()?);
         }
+    }
 
-        urls = unique_preserve(urls);
+    fn get_azure_sas_token(&self) -> EResult<SasToken> {
+        let url = sas_token_url(&self.opts)?;
+        // No concurrency slot: refreshes happen while the caller holds one.
+        let v: serde_json::Value = self.send(self.http.get(url))?.error_for_status()?.json()?;
+        SasToken::from_response(&v)
+    }
 
-        let dt = *dates
-            .iter()
-            .next()
-            .ok_or_else(|| Error::InvalidRequest("no datetime".into()))?;
-
-        let target_path = target
-            .map(|s| s.to_string())
-            .or_else(|| params.get("target").map(|v| v.as_strings().get(0).cloned()).flatten())
-            .unwrap_or_else(|| "data.grib2".to_string());
-
-        let mut res = Result {
-            urls,
-            target: target_path,
-            datetime: dt,
-            for_urls,
-            for_index,
-            size_bytes: 0,
-        };
+    /// Data downloaded so far by this client, its clones and its fallback
+    /// source, counted against [`ClientOptions::quota`].
+    pub fn usage(&self) -> Usage {
+        self.usage.snapshot()
+    }
 
-        if use_index && !res.for_index.is_empty() {
-            res.urls = self.expand_urls_to_ranges(&res.urls, &res.for_index)?;
-        }
+    /// Start a new session: forget the data downloaded so far.
+    pub fn reset_usage(&self) {
+        self.usage.reset();
+    }
 
-        Ok(res)
+    /// Expiry of the current Azure SAS token, when one is used and the token
+    /// service reported it.
+    pub fn sas_token_expiry(&self) -> Option<DateTime<Utc>> {
+        let sas = self.sas_token.as_ref()?;
+        sas.lock().unwrap_or_else(|e| e.into_inner()).expiry
     }
 
-    fn fix_0p4_beta(&self, url: String) -> String {
-        if self.opts.resol == "0p4-beta" {
-            url.replace("/ifs/", "/")
-        } else {
-            url
+    /// Current SAS token, re-fetched first when it is about to expire. A
+    /// failed refresh keeps the old token; requests it no longer signs are
+    /// refused with 403, which triggers another attempt.
+    fn sas_token(&self) -> Option<String> {
+        let sas = self.sas_token.as_ref()?;
+        let mut token = sas.lock().unwrap_or_else(|e| e.into_inner());
+        if token.is_expiring(Utc::now())
+            && let Ok(fresh) = self.get_azure_sas_token()
+        {
+            *token = fresh;
         }
+        Some(token.token.clone())
     }
 
-    fn get_azure_sas_token(&self) -> EResult<String> {
-        let known = match self.opts.sas_known_key.as_str() {
-            "ecmwf" => Some("https://planetarycomputer.microsoft.com/api/sas/v1/token/ai4edataeuwest/ecmwf"),
-            _ => None,
-        };
-
-        let url = if let Some(u) = known {
-            u.to_string()
-        } else if let Some(custom) = &self.opts.sas_custom_url {
-            custom.clone()
-        } else {
-            return Err(Error::InvalidRequest(
-                "no known sas token url and no custom provided".into(),
-            ));
+    /// Replace the SAS token after a request was refused. Returns whether
+    /// this client uses one.
+    fn refresh_sas_token(&self) -> EResult<bool> {
+        let Some(sas) = &self.sas_token else {
+            return Ok(false);
         };
+        let fresh = self.get_azure_sas_token()?;
+        *sas.lock().unwrap_or_else(|e| e.into_inner()) = fresh;
+        Ok(true)
+    }
 
-        let v: serde_json::Value = self.http.get(url).send()?.error_for_status()?.json()?;
-        let token = v
-            .get("token")
-            .and_then(|x| x.as_str())
-            .ok_or_else(|| Error::InvalidRequest("invalid sas token response".into()))?;
-        Ok(token.to_string())
+    pub(crate) fn request_url(&self, url: &str) -> String {
+        request_url(&self.opts, self.sas_token().as_deref(), url)
     }
 
-    fn apply_sas_to_url(&self, url: &str) -> String {
-        let Some(token) = &self.sas_token else {
-            return url.to_string();
-        };
-        if url.contains("sig=") {
-            return url.to_string();
-        }
-        if url.contains('?') {
-            format!("{url}&{token}")
-        } else {
-            format!("{url}?{token}")
+    /// Fetch the `.index` sidecar of a data URL.
+    ///
+    /// Returns `None` for tropical cyclone tracks (BUFR) published without an
+    /// index, which are then downloaded whole.
+    pub(crate) fn fetch_index_body(&self, url: &str) -> EResult<Option<String>> {
+        let index_url = self.request_url(&index_url_for(url));
+        if let Some(path) = local::file_path(&index_url) {
+            if self.opts.is_track_file(url) && local::status(&path) == 404 {
+                return Ok(None);
+            }
+            return Ok(Some(String::from_utf8_lossy(&local::read(&path, None)?).into_owned()));
         }
+
+        let _slot = self.rate_limiter.slot();
+        let resp = match self.get_throttled(&index_url, None) {
+            Ok(resp) => resp,
+            Err(Error::Http(e)) if self.opts.is_track_file(url) && e.status() == Some(StatusCode::NOT_FOUND) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let mut body = String::new();
+        let mut reader = resp;
+        reader.read_to_string(&mut body)?;
+        Ok(Some(body))
     }
 
     /// Expand each data URL to (url, ranges) by reading its `.index`.
@@ -638,197 +1488,821 @@ impl Client {
     /// This returns a list of synthetic URLs with embedded range data encoded as
     /// `url|start-end;start-end;...`.
     /// The actual download uses these to issue HTTP Range requests.
-    fn expand_urls_to_ranges(
+    pub(crate) fn expand_urls_to_ranges(
         &self,
         urls: &[String],
         for_index: &BTreeMap<String, Vec<String>>,
-    ) -> EResult<Vec<String>> {
-        // Keep index keyword order consistent with upstream.
-        let ordered_keys: Vec<&str> = INDEX_COMPONENTS
-            .iter()
-            .copied()
-            .filter(|k| for_index.contains_key(*k))
-            .collect();
-
+        order_by: &[String],
+        warnings: &mut Vec<String>,
+    ) -> EResult<(Vec<String>, Vec<MatchedField>)> {
         let mut out = Vec::new();
+        let mut fields = Vec::new();
         for url in urls {
-            let base = url.rsplit_once('.').map(|(b, _)| b).unwrap_or(url);
-            let index_url = format!("{base}.index");
-            let index_url = self.apply_sas_to_url(&index_url);
-
-            let resp = self.http.get(index_url).send()?.error_for_status()?;
-            let mut body = String::new();
-            let mut reader = resp;
-            reader.read_to_string(&mut body)?;
-
-            if ordered_keys.is_empty() {
-                // No index keywords, nothing to do.
+            let Some(body) = self.fetch_index_body(url)? else {
                 out.push(url.clone());
                 continue;
-            }
+            };
 
-            if self.opts.preserve_request_order {
-                // (sort_key, (offset,length)) where sort_key is a lexicographic tuple
-                // capturing requested keyword/value order.
-                let mut parts: Vec<(Vec<(usize, usize)>, (u64, u64))> = Vec::new();
+            if let Some((encoded, url_fields)) =
+                select_index_entries(&self.opts, url, &body, for_index, order_by, warnings)?
+            {
+                out.push(encoded);
+                fields.extend(url_fields);
+            }
+        }
 
-                for line in body.lines() {
-                    if line.trim().is_empty() {
-                        continue;
-                    }
-                    let v: serde_json::Value = serde_json::from_str(line)?;
-                    let offset = v
-                        .get("_offset")
-                        .and_then(|x| x.as_u64())
-                        .ok_or_else(|| Error::InvalidRequest("index missing _offset".into()))?;
-                    let length = v
-                        .get("_length")
-                        .and_then(|x| x.as_u64())
-                        .ok_or_else(|| Error::InvalidRequest("index missing _length".into()))?;
-
-                    let mut key: Vec<(usize, usize)> = Vec::with_capacity(ordered_keys.len());
-
-                    let mut ok = true;
-                    for (i, k) in ordered_keys.iter().enumerate() {
-                        let Some(val) = v.get(*k).and_then(|x| x.as_str()) else {
-                            ok = false;
-                            break;
-                        };
-                        let allowed = for_index
-                            .get(*k)
-                            .ok_or_else(|| Error::InvalidRequest("internal for_index missing key".into()))?;
-                        let Some(j) = allowed.iter().position(|a| a == val) else {
-                            ok = false;
-                            break;
-                        };
-                        key.push((i, j));
-                    }
+        if out.is_empty() {
+            return Err(Error::NoMatchingIndex);
+        }
 
-                    if ok {
-                        parts.push((key, (offset, length)));
-                    }
-                }
+        Ok((out, fields))
+    }
 
-                if parts.is_empty() {
-                    continue;
-                }
+    /// Download a data URL (or one range of it), sharing the transfer with any
+    /// identical request already in flight on this client.
+    pub(crate) fn fetch_data(&self, data_url: &str, range: Option<&str>) -> EResult<Transfer> {
+        let url = self.request_url(data_url);
+        if let Some(path) = local::file_path(&url) {
+            return Ok(Transfer {
+                bytes: Arc::new(local::read(&path, range)?),
+                final_url: data_url.to_string(),
+                version: local::version(&path),
+                status: None,
+            });
+        }
+        self.usage.check()?;
+        self.inflight.run(&url, range, || {
+            let _slot = self.rate_limiter.slot();
+            let mut resp = self.get_throttled(&url, range)?;
+            let final_url = without_query(resp.url());
+            let content_type = content_type(resp.headers());
+            let version = RemoteVersion::from_headers(resp.headers());
+            let status = resp.status().as_u16();
+            let mut buf = Vec::new();
+            resp.copy_to(&mut buf)?;
+            telemetry::transfer(&self.opts.source, range.is_some(), buf.len());
+            self.usage.record(&self.opts.source, buf.len() as u64);
+            check_data_response(data_url, content_type.as_deref(), &buf)?;
+            Ok(Transfer {
+                bytes: Arc::new(buf),
+                final_url,
+                version,
+                status: Some(status),
+            })
+        })
+    }
 
-                parts.sort_by(|a, b| a.0.cmp(&b.0));
+    fn emit(&self, event: DownloadEvent) {
+        emit(self.opts.download_events.as_ref(), event);
+    }
 
-                let ranges: Vec<(u64, u64)> = parts.into_iter().map(|(_, r)| r).collect();
-                let merged = merge_ranges(ranges);
+    fn emit_written(&self, target: &str, bytes: u64, written: u64) {
+        self.emit(DownloadEvent::BytesWritten {
+            target: target.to_string(),
+            bytes,
+            written,
+        });
+    }
 
-                let mut enc = String::new();
-                for (i, (start, end)) in merged.iter().enumerate() {
-                    if i > 0 {
-                        enc.push(';');
-                    }
-                    enc.push_str(&format!("{start}-{end}"));
-                }
+    /// Download the planned ranges of one data file, up to
+    /// [`ClientOptions::max_concurrent_ranges`] at a time. Transfers are
+    /// returned in the order of `fetches`.
+    pub(crate) fn fetch_ranges(&self, data_url: &str, fetches: &[Fetch]) -> EResult<Vec<Transfer>> {
+        let fetch_one = |fetch: &Fetch| {
+            self.check_interrupted()?;
+            let _permit = self.throttle.acquire();
+            self.emit(DownloadEvent::RangeStarted {
+                url: data_url.to_string(),
+                range: Some((fetch.start, fetch.end)),
+            });
+            self.fetch_data(data_url, Some(&format!("bytes={}-{}", fetch.start, fetch.end)))
+        };
 
-                out.push(format!("{url}|{enc}"));
-            } else {
-                // Fast path: sort by file offset (minimize HTTP requests).
-                let mut matches: Vec<(u64, u64)> = Vec::new();
+        let workers = self.opts.max_concurrent_ranges.clamp(1, fetches.len().max(1));
+        if workers == 1 {
+            return fetches.iter().map(fetch_one).collect();
+        }
 
-                for line in body.lines() {
-                    if line.trim().is_empty() {
-                        continue;
-                    }
-                    let v: serde_json::Value = serde_json::from_str(line)?;
-                    let offset = v
-                        .get("_offset")
-                        .and_then(|x| x.as_u64())
-                        .ok_or_else(|| Error::InvalidRequest("index missing _offset".into()))?;
-                    let length = v
-                        .get("_length")
-                        .and_then(|x| x.as_u64())
-                        .ok_or_else(|| Error::InvalidRequest("index missing _length".into()))?;
-
-                    let mut ok = true;
-                    for k in &ordered_keys {
-                        let Some(val) = v.get(*k).and_then(|x| x.as_str()) else {
-                            ok = false;
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let slots: Vec<Mutex<Option<EResult<Transfer>>>> = fetches.iter().map(|_| Mutex::new(None)).collect();
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while !failed.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(fetch) = fetches.get(i) else {
                             break;
                         };
-                        let allowed = for_index
-                            .get(*k)
-                            .ok_or_else(|| Error::InvalidRequest("internal for_index missing key".into()))?;
-                        if !allowed.iter().any(|a| a == val) {
-                            ok = false;
-                            break;
+                        let outcome = fetch_one(fetch);
+                        if outcome.is_err() {
+                            failed.store(true, Ordering::Relaxed);
                         }
+                        *slots[i].lock().unwrap_or_else(|e| e.into_inner()) = Some(outcome);
                     }
+                });
+            }
+        });
 
-                    if ok {
-                        matches.push((offset, length));
-                    }
-                }
+        let mut out = Vec::with_capacity(fetches.len());
+        // Ranges skipped after a failure have no outcome; the failure is returned.
+        for slot in slots {
+            if let Some(outcome) = slot.into_inner().unwrap_or_else(|e| e.into_inner()) {
+                out.push(outcome?);
+            }
+        }
+        Ok(out)
+    }
 
-                if matches.is_empty() {
-                    continue;
-                }
+    /// Download `res.urls` into `res.target`, truncating it unless `append` is set.
+    pub(crate) fn download_result(&self, res: &Result, is_partial: bool, append: bool) -> EResult<Result> {
+        self.emit(DownloadEvent::Planned {
+            target: res.target.clone(),
+            urls: res.urls.len(),
+            total_bytes: planned_bytes(res, is_partial)?,
+        });
+
+        let outcome = self.write_result(res, is_partial, append);
+        self.emit(match &outcome {
+            Ok(done) => DownloadEvent::Completed {
+                target: done.target.clone(),
+                size_bytes: done.size_bytes,
+            },
+            Err(e) => DownloadEvent::Failed {
+                target: res.target.clone(),
+                error: e.to_string(),
+            },
+        });
+        outcome
+    }
 
-                matches.sort_by_key(|(o, _)| *o);
-                let merged = merge_ranges(matches);
+    fn write_result(&self, res: &Result, is_partial: bool, append: bool) -> EResult<Result> {
+        if !writes_tree(&self.opts) {
+            return self.write_target(res, is_partial, append);
+        }
+        let bases = self.base_urls();
+        let mut parts = Vec::with_capacity(res.urls.len());
+        for part in tree_parts(res, &bases) {
+            parts.push(self.write_target(&part, is_partial, append)?);
+        }
+        Ok(join_parts(res, parts))
+    }
+
+    /// Roots of the sources URLs may come from, fallback included.
+    fn base_urls(&self) -> Vec<&str> {
+        let mut bases = vec![self.base_url.as_str()];
+        bases.extend(self.fallback.iter().map(|f| f.base_url.as_str()));
+        bases
+    }
+
+    fn write_target(&self, res: &Result, is_partial: bool, append: bool) -> EResult<Result> {
+        let mut total: u64 = 0;
+        let mut final_urls = BTreeMap::new();
+        let mut remote_versions = BTreeMap::new();
+        let path = target_path(&res.target);
+        if self.opts.layout != Layout::Flat || self.opts.target_namer.is_some() {
+            create_parent_dirs(&path)?;
+        }
+        let file = if append {
+            OpenOptions::new().create(true).append(true).open(&path)?
+        } else {
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&path)?
+        };
+        // Without a buffer, every range goes straight to the file.
+        let capacity = if res.fast_writes { FAST_WRITE_BUFFER } else { 0 };
+        let mut file = HashingWriter {
+            inner: BufWriter::with_capacity(capacity, file),
+            hasher: (!append).then(Sha256::default),
+        };
+        let mut failed = Vec::new();
+        let mut reports = Vec::with_capacity(res.urls.len());
 
-                let mut enc = String::new();
-                for (i, (start, end)) in merged.iter().enumerate() {
-                    if i > 0 {
-                        enc.push(';');
+        for u in &res.urls {
+            self.check_interrupted()?;
+            let started = Instant::now();
+            // Products without index selection (e.g. tf tracks) are fetched whole.
+            if is_partial && u.contains('|') {
+                let (data_url, mut ranges) = split_url_ranges(u)?;
+                let mut report = UrlReport::new(data_url);
+                if self.opts.sequential_ranges {
+                    ranges.sort_unstable();
+                }
+                let fetches = plan_fetches(&ranges, self.opts.max_requests_per_url, self.opts.max_range_gap_bytes);
+                // Write each batch before fetching the next, as `produce` does,
+                // so memory is bounded by the batch rather than the file.
+                let batch = if self.opts.sequential_ranges { 1 } else { self.opts.max_concurrent_ranges.max(1) };
+                for fetches in fetches.chunks(batch) {
+                    let transfers = match self.fetch_ranges(data_url, fetches) {
+                        Ok(transfers) => fetches.iter().cloned().zip(transfers).collect(),
+                        Err(e) if self.opts.skip_failed_fields && is_field_failure(&e) => {
+                            let mut transfers = Vec::new();
+                            for fetch in fetches {
+                                transfers.extend(self.fetch_isolating_fields(
+                                    data_url,
+                                    fetch.clone(),
+                                    &res.fields,
+                                    &mut failed,
+                                )?);
+                            }
+                            transfers
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    for (fetch, transfer) in transfers {
+                        report.add(&fetch, &transfer);
+                        final_urls.insert(data_url.to_string(), transfer.final_url);
+                        remote_versions.insert(data_url.to_string(), transfer.version);
+                        let bytes = write_fetch_parts(&mut file, data_url, &fetch, &transfer.bytes)?;
+                        total += bytes;
+                        self.emit_written(&res.target, bytes, total);
                     }
-                    enc.push_str(&format!("{start}-{end}"));
                 }
-
-                out.push(format!("{url}|{enc}"));
+                report.elapsed = started.elapsed();
+                reports.push(report);
+            } else {
+                self.emit(DownloadEvent::RangeStarted {
+                    url: u.clone(),
+                    range: None,
+                });
+                let transfer = self.fetch_data(u, None)?;
+                final_urls.insert(u.clone(), transfer.final_url);
+                remote_versions.insert(u.clone(), transfer.version);
+                file.write_all(&transfer.bytes)?;
+                total += transfer.bytes.len() as u64;
+                self.emit_written(&res.target, transfer.bytes.len() as u64, total);
+                reports.push(UrlReport {
+                    status: transfer.status,
+                    bytes: transfer.bytes.len() as u64,
+                    elapsed: started.elapsed(),
+                    ..UrlReport::new(u)
+                });
             }
         }
+        file.flush()?;
 
-        if out.is_empty() {
-            return Err(Error::NoMatchingIndex);
+        let mut out = res.clone();
+        out.size_bytes = total;
+        out.final_urls = final_urls;
+        out.remote_versions = remote_versions;
+        out.sha256 = file.hasher.map(checksum::hex);
+        out.url_reports = reports;
+        out.warnings.extend(self.usage.warning());
+        if self.opts.sequential_ranges {
+            sort_fields_in_file_order(&mut out);
         }
+        out.fields.retain(|f| !failed.contains(f));
+        out.failed_fields = failed;
+        Ok(out)
+    }
 
+    /// Fetch one range; if it fails, fetch its fields one by one and skip
+    /// (into `failed`) those that fail again.
+    fn fetch_isolating_fields(
+        &self,
+        data_url: &str,
+        fetch: Fetch,
+        fields: &[MatchedField],
+        failed: &mut Vec<MatchedField>,
+    ) -> EResult<Vec<(Fetch, Transfer)>> {
+        let e = match self.fetch_ranges(data_url, std::slice::from_ref(&fetch)) {
+            Ok(transfers) => return Ok(transfers.into_iter().map(|t| (fetch.clone(), t)).collect()),
+            Err(e) => e,
+        };
+        let Some(singles) = field_fetches(data_url, &fetch, fields).filter(|_| is_field_failure(&e)) else {
+            return Err(e);
+        };
+        let mut out = Vec::new();
+        for (single, field) in singles {
+            match self.fetch_ranges(data_url, std::slice::from_ref(&single)) {
+                Ok(transfers) => out.extend(transfers.into_iter().map(|t| (single.clone(), t))),
+                Err(e) if is_field_failure(&e) => failed.push(field.clone()),
+                Err(e) => return Err(e),
+            }
+        }
         Ok(out)
     }
+}
 
-    fn download_result(&self, res: &Result, is_partial: bool) -> EResult<Result> {
-        let mut total: u64 = 0;
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&res.target)?;
+/// Request parameters with the client defaults (model, resol, type, stream)
+/// filled in, and the effective model.
+pub(crate) fn request_defaults(
+    opts: &ClientOptions,
+    request: Option<&Request>,
+) -> (String, BTreeMap<String, RequestValue>) {
+    let mut params = match request {
+        Some(r) => r.clone().into_inner(),
+        None => BTreeMap::new(),
+    };
 
-        for u in &res.urls {
-            if is_partial {
-                let (url, ranges) = split_url_ranges(u)?;
-                for (start, end) in ranges {
-                    let url = self.apply_sas_to_url(url);
-                    let range_header = format!("bytes={start}-{end}");
-                    let mut resp = self
-                        .http
-                        .get(url)
-                        .header(RANGE, range_header)
-                        .send()?
-                        .error_for_status()?;
-                    let mut buf = Vec::new();
-                    resp.copy_to(&mut buf)?;
-                    file.write_all(&buf)?;
-                    total += buf.len() as u64;
+    // defaults
+    let model = params
+        .get("model")
+        .map(|v| v.as_strings().first().cloned().unwrap_or_else(|| opts.model.clone()))
+        .unwrap_or_else(|| opts.model.clone());
+
+    if model == "aifs-ens" && !params.contains_key("stream") {
+        params.insert("stream".to_string(), RequestValue::Str("enfo".to_string()));
+    }
+
+    params.entry("model".to_string()).or_insert(RequestValue::Str(model.clone()));
+    params
+        .entry("resol".to_string())
+        .or_insert(RequestValue::Str(opts.resol.clone()));
+
+    params.entry("type".to_string()).or_insert(RequestValue::Str("fc".to_string()));
+    let requested_params: Vec<String> = params
+        .get("param")
+        .map(|v| v.as_strings().iter().flat_map(|p| split_slash_list(p)).collect())
+        .unwrap_or_default();
+    let default_stream = match wave::infer_stream(&requested_params) {
+        Some(stream) if opts.infer_stream_keyword => stream,
+        _ => "oper",
+    };
+    params
+        .entry("stream".to_string())
+        .or_insert(RequestValue::Str(default_stream.to_string()));
+
+    (model, params)
+}
+
+/// Cycles to probe for [`Client::latest`], newest first, each with the request
+/// to probe it with.
+///
+/// Without `time` in the request this walks back over 6-hourly cycles;
+/// with `time` it keeps that hour and walks back by days. The search covers
+/// `retention` (see [`Source::retention`]).
+pub(crate) fn latest_candidates(request: &Request, retention: Duration) -> EResult<Vec<(DateTime<Utc>, Request)>> {
+    let mut params = request.clone().into_inner();
+    let now = Utc::now();
+
+    let has_time = params.contains_key("time");
+    let delta = if has_time { Duration::days(1) } else { Duration::hours(6) };
+
+    let time_hour = if let Some(tv) = params.get("time") {
+        let t = tv.as_strings().first().cloned().unwrap_or_else(|| "18".into());
+        canonical_time_to_hour(&t)?
+    } else {
+        18
+    };
+
+    let mut candidate = if has_time {
+        // Start at today with that hour, but never in the future.
+        let start_date = now.date_naive();
+        let mut dt = Utc
+            .with_ymd_and_hms(
+                start_date.year(),
+                start_date.month(),
+                start_date.day(),
+                time_hour,
+                0,
+                0,
+            )
+            .single()
+            .ok_or_else(|| Error::InvalidRequest("invalid start datetime".into()))?;
+        if dt > now {
+            dt -= Duration::days(1);
+        }
+        dt
+    } else {
+        // Round down to the nearest 6-hour cycle: 00/06/12/18.
+        let hour = (now.hour() / 6) * 6;
+        Utc.with_ymd_and_hms(now.year(), now.month(), now.day(), hour, 0, 0)
+            .single()
+            .ok_or_else(|| Error::InvalidRequest("invalid start datetime".into()))?
+    };
+
+    let stop = candidate - retention;
+    let mut out = Vec::new();
+    while candidate > stop {
+        params.insert(
+            "date".to_string(),
+            RequestValue::Str(candidate.format("%Y%m%d").to_string()),
+        );
+        let probe_hour = if has_time { time_hour } else { candidate.hour() };
+        params.insert("time".to_string(), RequestValue::Int(probe_hour as i64));
+        out.push((candidate, Request::from_inner(params.clone())));
+        candidate -= delta;
+    }
+    Ok(out)
+}
+
+/// Bookkeeping of a latest-cycle search, for error reporting.
+#[derive(Debug)]
+pub(crate) struct LatestSearch {
+    newest: Option<DateTime<Utc>>,
+    /// (candidate, status that ruled it out)
+    probed: Vec<(DateTime<Utc>, u16)>,
+    /// Set when the newest candidate has some, but not all, of its files.
+    newest_partial: Option<String>,
+}
+
+impl LatestSearch {
+    pub(crate) fn new(candidates: &[(DateTime<Utc>, Request)]) -> Self {
+        Self {
+            newest: candidates.first().map(|(dt, _)| *dt),
+            probed: Vec::new(),
+            newest_partial: None,
+        }
+    }
+
+    /// Record that file `index` (`url`) of `candidate` is absent.
+    pub(crate) fn absent(&mut self, candidate: DateTime<Utc>, status: u16, index: usize, url: &str) {
+        self.probed.push((candidate, status));
+        if index > 0 && Some(candidate) == self.newest {
+            self.newest_partial = Some(url.to_string());
+        }
+    }
+
+    /// Record that `candidate` is published but lacks `missing`.
+    pub(crate) fn incomplete(&mut self, candidate: DateTime<Utc>, missing: String) {
+        self.probed.push((candidate, 200));
+        if Some(candidate) == self.newest {
+            self.newest_partial = Some(missing);
+        }
+    }
+
+    pub(crate) fn into_error(self) -> Error {
+        match (self.newest, self.newest_partial) {
+            (Some(datetime), Some(first_missing)) => Error::CyclePartiallyPublished {
+                datetime,
+                first_missing,
+            },
+            _ => Error::CannotEstablishLatest { probed: self.probed },
+        }
+    }
+}
+
+/// Outcome of latest-cycle probing, falling back to a clock-based cycle on
+/// transient failures when [`ClientOptions::latest_fallback`] allows it.
+pub(crate) fn latest_or_fallback(
+    opts: &ClientOptions,
+    params: &BTreeMap<String, RequestValue>,
+    outcome: EResult<DateTime<Utc>>,
+    warnings: &mut Vec<String>,
+) -> EResult<DateTime<Utc>> {
+    match outcome {
+        Ok(dt) => Ok(dt),
+        Err(e @ (Error::ProbeUnavailable { .. } | Error::Http(_))) => {
+            let time_hour = match params.get("time").and_then(|v| v.as_strings().first().cloned()) {
+                Some(t) => Some(canonical_time_to_hour(&t)?),
+                None => None,
+            };
+            let Some(dt) = opts.latest_fallback.cycle(Utc::now(), time_hour) else {
+                return Err(e);
+            };
+            warnings.push(format!(
+                "could not establish latest cycle ({e}); assuming {}",
+                dt.format("%Y-%m-%d %Hz")
+            ));
+            Ok(dt)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Set `date` (and `time`, unless requested) to a resolved cycle.
+pub(crate) fn set_cycle(params: &mut BTreeMap<String, RequestValue>, cycle: DateTime<Utc>) {
+    params.insert(
+        "date".to_string(),
+        RequestValue::Str(cycle.format("%Y%m%d").to_string()),
+    );
+    // Keep request's time if present; else use the cycle's hour.
+    if !params.contains_key("time") {
+        params.insert("time".to_string(), RequestValue::Int(cycle.hour() as i64));
+    }
+}
+
+/// Expand request parameters that carry a `date` into data URLs. No network
+/// access is involved.
+pub(crate) fn resolve_urls(
+    opts: &ClientOptions,
+    base_url: &str,
+    model: &str,
+    params: &BTreeMap<String, RequestValue>,
+    target: Option<&str>,
+    mut warnings: Vec<String>,
+) -> EResult<Result> {
+    // Normalize / expand into for_urls and for_index
+    let now = Utc::now();
+
+    let mut for_urls: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut for_index: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    // Build for_urls types first to allow step mapping for probabilities.
+    let typ_values_user: Vec<String> = params
+        .get("type")
+        .map(|v| v.as_strings())
+        .unwrap_or_else(|| vec!["fc".to_string()]);
+
+    let mut for_urls_type: Vec<String> = Vec::new();
+    for tv in typ_values_user {
+        for_urls_type.push(user_to_url_value(model, "type", &tv, &[]));
+    }
+    if for_urls_type.is_empty() {
+        for_urls_type.push("fc".to_string());
+    }
+    for_urls.insert("type".to_string(), unique_preserve(for_urls_type));
+
+    // (keyword, original, used) for every value rewritten on its way into a URL.
+    let mut substitutions: BTreeSet<(String, String, String)> = BTreeSet::new();
+
+    // Process each param
+    for (k, v) in params.iter() {
+        let mut values = v.as_strings();
+
+        // allow slash-separated lists, unless opted out for this keyword
+        if !opts.no_split_keywords.iter().any(|x| x == k) {
+            values = values.iter().flat_map(|x| split_slash_list(x)).collect();
+        }
+
+        let expanded = expand_keyword_values(k, values, now)?;
+
+        if URL_COMPONENTS.contains(&k.as_str()) {
+            let mut mapped = Vec::new();
+            for x in &expanded {
+                let url_t = for_urls.get("type").cloned().unwrap_or_default();
+                let used = user_to_url_value(model, k, x, &url_t);
+                if used != *x {
+                    substitutions.insert((k.clone(), x.clone(), used.clone()));
+                }
+                mapped.push(used);
+            }
+            for_urls
+                .entry(k.clone())
+                .or_default()
+                .extend(mapped);
+        }
+
+        if INDEX_COMPONENTS.contains(&k.as_str()) {
+            // user_to_index: type=ef expands to cf/pf for index selection.
+            let mut mapped = Vec::new();
+            if k == "type" {
+                for x in &expanded {
+                    if x == "ef" {
+                        mapped.push("cf".to_string());
+                        mapped.push("pf".to_string());
+                    } else {
+                        mapped.push(x.clone());
+                    }
                 }
             } else {
-                let url = self.apply_sas_to_url(u);
-                let mut resp = self.http.get(url).send()?.error_for_status()?;
-                let mut buf = Vec::new();
-                resp.copy_to(&mut buf)?;
-                file.write_all(&buf)?;
-                total += buf.len() as u64;
+                mapped = expanded.clone();
             }
+            for_index.entry(k.clone()).or_default().extend(mapped);
         }
+    }
 
-        let mut out = res.clone();
-        out.size_bytes = total;
-        Ok(out)
+    // Canonicalize time: store hour string (00/06/12/18)
+    if let Some(times) = for_urls.get_mut("time") {
+        let mut out = Vec::new();
+        for t in times.drain(..) {
+            let hour = canonical_time_to_hour(&t)?;
+            out.push(format!("{hour:02}"));
+        }
+        *times = unique_preserve(out);
+    }
+
+    // Infer/patch stream in URL building; we keep stream values but will patch later per product.
+    for (k, vals) in for_urls.iter_mut() {
+        *vals = unique_preserve(std::mem::take(vals));
+        if k == "stream" || k == "type" {
+            vals.iter_mut().for_each(|s| s.make_ascii_lowercase());
+        }
+    }
+    for (k, vals) in for_index.iter_mut() {
+        *vals = unique_preserve(std::mem::take(vals));
+        if k == "stream" || k == "type" {
+            vals.iter_mut().for_each(|s| s.make_ascii_lowercase());
+        }
+    }
+    // `number=all` selects whatever members the index lists.
+    if for_index.get("number").is_some_and(|n| n.iter().any(|x| x == "all")) {
+        for_index.remove("number");
+    }
+    // Levels are deduplicated and, unless request order is preserved, sorted
+    // ascending in the reported index components. This does not reorder the
+    // written fields, which follow file order in that case.
+    if !opts.preserve_request_order
+        && let Some(levels) = for_index.get_mut("levelist")
+    {
+        sort_levels(levels);
+    }
+
+    // If time missing (possible if date contains time), default time based on date.
+    if !for_urls.contains_key("time") {
+        for_urls.insert("time".to_string(), vec!["18".to_string()]);
+    }
+
+    // Expand into canonical files first: aliases (cf/pf → ef, oper → scda at
+    // 06/18, ...) can map different request values onto the same file.
+    let mut files: Vec<FileKey> = Vec::new();
+    let mut seen: HashSet<FileKey> = HashSet::new();
+    let mut dates = BTreeSet::new();
+
+    let date_vals = for_urls
+        .get("date")
+        .cloned()
+        .ok_or_else(|| Error::InvalidRequest("date missing after normalization".into()))?;
+    let time_vals = for_urls
+        .get("time")
+        .cloned()
+        .ok_or_else(|| Error::InvalidRequest("time missing after normalization".into()))?;
+
+    let model_vals = for_urls.get("model").cloned().unwrap_or_else(|| vec![model.to_string()]);
+    let resol_vals = for_urls
+        .get("resol")
+        .cloned()
+        .unwrap_or_else(|| vec![opts.resol.clone()]);
+    let stream_vals = for_urls
+        .get("stream")
+        .cloned()
+        .unwrap_or_else(|| vec!["oper".to_string()]);
+    let type_vals = for_urls
+        .get("type")
+        .cloned()
+        .unwrap_or_else(|| vec!["fc".to_string()]);
+    let step_vals_opt = for_urls.get("step").cloned();
+    let fcmonth_vals = for_urls
+        .get("fcmonth")
+        .cloned()
+        .unwrap_or_else(|| vec!["1".to_string()]);
+
+    if !type_vals.iter().any(|t| matches!(t.as_str(), "ep" | "tf"))
+        && let Some(requested) = for_index.get("param")
+    {
+        warnings.extend(wave::check_params(&stream_vals, requested)?);
+    }
+
+    for d in &date_vals {
+        for t in &time_vals {
+            let dt = full_datetime_from_date_time(d, t.parse::<u32>().map_err(|_| {
+                Error::InvalidRequest(format!("invalid canonical time hour: {t}"))
+            })?)?;
+            dates.insert(dt);
+
+            for m in &model_vals {
+                for r in &resol_vals {
+                    for s in &stream_vals {
+                        for ty in &type_vals {
+                            // patch stream based on time and type
+                            let hour_2d = dt.format("%H").to_string();
+                            let patched_stream = patch_stream(
+                                opts.infer_stream_keyword,
+                                m,
+                                s,
+                                &hour_2d,
+                                ty,
+                            );
+                            if patched_stream != *s {
+                                substitutions.insert((
+                                    "stream".to_string(),
+                                    s.clone(),
+                                    patched_stream.clone(),
+                                ));
+                            }
+
+                            let is_monthly = s == "mmsa" || s == "mmsf";
+                            let file = FileKey {
+                                cycle: dt,
+                                model: m.clone(),
+                                resol: r.clone(),
+                                stream: patched_stream.clone(),
+                                typ: ty.clone(),
+                                step: None,
+                                fcmonth: None,
+                            };
+
+                            if is_monthly {
+                                for fcmonth in &fcmonth_vals {
+                                    let f = FileKey {
+                                        fcmonth: Some(fcmonth.clone()),
+                                        ..file.clone()
+                                    };
+                                    if seen.insert(f.clone()) {
+                                        files.push(f);
+                                    }
+                                }
+                            } else {
+                                let steps_for_url: Vec<String> = match &step_vals_opt {
+                                    Some(v) => v.clone(),
+                                    None => vec![default_step_for_url(&patched_stream, ty, dt.hour())],
+                                };
+                                for step in steps_for_url {
+                                    wave::check_step(&patched_stream, ty, dt.hour(), &step)?;
+                                    let f = FileKey {
+                                        step: Some(step),
+                                        ..file.clone()
+                                    };
+                                    if seen.insert(f.clone()) {
+                                        files.push(f);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let urls = files
+        .iter()
+        .map(|f| f.url(opts, base_url))
+        .collect::<EResult<Vec<_>>>()?;
+
+    if dates.is_empty() {
+        return Err(Error::InvalidRequest("no datetime".into()));
+    }
+
+    let target_path = target
+        .map(|s| s.to_string())
+        .or_else(|| params.get("target").and_then(|v| v.as_strings().first().cloned()));
+    let extension = opts.extension_for(type_vals.first().map(|t| t.as_str()).unwrap_or("fc"));
+    let target_path = if writes_tree(opts) {
+        // The root of the mirror; files keep their upstream names.
+        target_path.unwrap_or_else(|| ".".to_string())
+    } else {
+        target_with_extension(&target_path.unwrap_or_else(|| "data.grib2".to_string()), &extension)
+    };
+
+    Ok(Result {
+        urls,
+        target: target_path,
+        datetimes: dates.into_iter().collect(),
+        url_components: UrlComponents::from_map(for_urls),
+        index_components: IndexComponents::from_map(for_index),
+        size_bytes: 0,
+        final_urls: BTreeMap::new(),
+        remote_versions: BTreeMap::new(),
+        extension,
+        source: opts.source.clone(),
+        substitutions: substitutions
+            .into_iter()
+            .map(|(keyword, original, used)| Substitution {
+                keyword,
+                original,
+                used,
+            })
+            .collect(),
+        fields: Vec::new(),
+        warnings,
+        sha256: None,
+        order_by: Vec::new(),
+        failed_fields: Vec::new(),
+        url_reports: Vec::new(),
+        fast_writes: false,
+    })
+}
+
+/// Canonical identity of one data file, after type/stream aliasing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FileKey {
+    cycle: DateTime<Utc>,
+    model: String,
+    resol: String,
+    stream: String,
+    typ: String,
+    /// Set for hourly products.
+    step: Option<String>,
+    /// Set for monthly products.
+    fcmonth: Option<String>,
+}
+
+impl FileKey {
+    fn url(&self, opts: &ClientOptions, base_url: &str) -> EResult<String> {
+        // beta tweaks
+        let resol = if opts.beta {
+            format!("{}/experimental", self.resol)
+        } else {
+            self.resol.clone()
+        };
+
+        let mut builder = UrlBuilder::new(base_url)
+            .date(self.cycle)
+            .model(self.model.as_str())
+            .resol(resol)
+            .stream(self.stream.as_str())
+            .r#type(self.typ.as_str())
+            .extension(opts.extension_for(&self.typ));
+        builder = match (&self.step, &self.fcmonth) {
+            (_, Some(fcmonth)) => builder.pattern(opts.monthly_pattern.as_str()).fcmonth(fcmonth.as_str()),
+            (step, None) => builder
+                .pattern(opts.hourly_pattern.as_str())
+                .step(step.clone().unwrap_or_default()),
+        };
+        Ok(fix_0p4_beta(opts, builder.build()?))
+    }
+}
+
+fn fix_0p4_beta(opts: &ClientOptions, url: String) -> String {
+    if opts.resol == "0p4-beta" {
+        url.replace("/ifs/", "/")
+    } else {
+        url
     }
 }
 
@@ -865,9 +2339,558 @@ fn default_step_for_url(patched_stream: &str, typ: &str, hour: u32) -> String {
     }
 }
 
+/// Rename `res.target` with [`ClientOptions::target_namer`], once its fields
+/// are known.
+pub(crate) fn name_target(opts: &ClientOptions, res: &mut Result) {
+    let (Some(namer), Some(url)) = (&opts.target_namer, res.urls.first()) else {
+        return;
+    };
+    res.target = namer.name(&TargetContext {
+        target: &res.target,
+        datetime: res.first_datetime(),
+        url: url.split('|').next().unwrap_or(url),
+        components: &res.url_components,
+        fields: &res.fields,
+    });
+}
+
+/// Order `res.fields` as [`ClientOptions::sequential_ranges`] writes them:
+/// by data URL, then by offset.
+pub(crate) fn sort_fields_in_file_order(res: &mut Result) {
+    let data_urls: Vec<&str> = res.urls.iter().map(|u| u.split('|').next().unwrap_or(u)).collect();
+    res.fields
+        .sort_by_key(|f| (data_urls.iter().position(|u| *u == f.url), f.offset));
+}
+
+/// Whether targets are the roots of [`Layout::EcmwfTree`] mirrors.
+pub(crate) fn writes_tree(opts: &ClientOptions) -> bool {
+    opts.layout == Layout::EcmwfTree && opts.target_namer.is_none()
+}
+
+/// `res` split into one result per URL, each targeting the data file's
+/// upstream path below `res.target` ([`Layout::EcmwfTree`]).
+pub(crate) fn tree_parts(res: &Result, base_urls: &[&str]) -> Vec<Result> {
+    res.urls
+        .iter()
+        .map(|u| {
+            let data_url = u.split('|').next().unwrap_or(u);
+            let mut part = res.clone();
+            part.target = tree_path(&res.target, u, base_urls);
+            part.urls = vec![u.clone()];
+            part.fields.retain(|f| f.url == data_url);
+            part
+        })
+        .collect()
+}
+
+/// `res` once its [`tree_parts`] are written, with what each part reported.
+/// The parts are separate files, so there is no checksum of the whole.
+pub(crate) fn join_parts(res: &Result, parts: Vec<Result>) -> Result {
+    let mut out = res.clone();
+    out.size_bytes = 0;
+    out.final_urls.clear();
+    out.remote_versions.clear();
+    out.url_reports.clear();
+    out.fields.clear();
+    out.failed_fields.clear();
+    out.sha256 = None;
+    for part in parts {
+        out.size_bytes += part.size_bytes;
+        out.final_urls.extend(part.final_urls);
+        out.remote_versions.extend(part.remote_versions);
+        out.url_reports.extend(part.url_reports);
+        out.fields.extend(part.fields);
+        out.failed_fields.extend(part.failed_fields);
+        for warning in part.warnings {
+            if !out.warnings.contains(&warning) {
+                out.warnings.push(warning);
+            }
+        }
+    }
+    out
+}
+
+/// Bytes a download of `res` will write, known when every URL is range-selected.
+pub(crate) fn planned_bytes(res: &Result, is_partial: bool) -> EResult<Option<u64>> {
+    if !is_partial || !res.urls.iter().all(|u| u.contains('|')) {
+        return Ok(None);
+    }
+    let mut sum = 0;
+    for u in &res.urls {
+        sum += split_url_ranges(u)?.1.iter().map(|(start, end)| end - start + 1).sum::<u64>();
+    }
+    Ok(Some(sum))
+}
+
+/// Write the requested parts of one (possibly coalesced) range response.
+pub(crate) fn write_fetch_parts(out: &mut impl Write, data_url: &str, fetch: &Fetch, bytes: &[u8]) -> EResult<u64> {
+    let mut written = 0;
+    for (start, end) in &fetch.parts {
+        let from = (start - fetch.start) as usize;
+        let to = (end - fetch.start + 1) as usize;
+        let part = bytes
+            .get(from..to)
+            .ok_or_else(|| Error::InvalidRequest(format!("short range response from {data_url}")))?;
+        out.write_all(part)?;
+        written += part.len() as u64;
+    }
+    Ok(written)
+}
+
+/// Whether a failed range transfer may be skipped under
+/// [`ClientOptions::skip_failed_fields`]: the mirror refused or garbled it, as
+/// opposed to the call being interrupted or the target being unwritable.
+pub(crate) fn is_field_failure(e: &Error) -> bool {
+    matches!(e, Error::Http(_) | Error::UnexpectedContent { .. } | Error::SharedTransfer(_))
+}
+
+/// One single-field fetch per field of `fields` making up `fetch`, or `None`
+/// if the fields do not cover exactly the wanted parts of `fetch` (e.g. a
+/// resumed range starting mid-field).
+pub(crate) fn field_fetches<'a>(
+    data_url: &str,
+    fetch: &Fetch,
+    fields: &'a [MatchedField],
+) -> Option<Vec<(Fetch, &'a MatchedField)>> {
+    let singles: Vec<_> = fields
+        .iter()
+        .filter(|f| f.url == data_url && f.length > 0)
+        .map(|f| (f.offset, f.offset + f.length - 1, f))
+        .filter(|(start, end, _)| fetch.parts.iter().any(|(s, e)| s <= start && end <= e))
+        .map(|(start, end, f)| {
+            let single = Fetch {
+                start,
+                end,
+                parts: vec![(start, end)],
+            };
+            (single, f)
+        })
+        .collect();
+    let wanted: u64 = fetch.parts.iter().map(|(s, e)| e - s + 1).sum();
+    let covered: u64 = singles.iter().map(|(f, _)| f.end - f.start + 1).sum();
+    (!singles.is_empty() && covered == wanted).then_some(singles)
+}
+
+/// Fail on the first keyword of `request` that no part of the client reads,
+/// or `stream`/`type`/`model` value none of [`crate::Stream`],
+/// [`crate::DataType`] and [`crate::Model`] knows, suggesting the closest
+/// known one.
+pub(crate) fn check_keywords(request: &Request) -> EResult<()> {
+    match unknown_keywords(request).into_iter().chain(unknown_values(request)).next() {
+        Some(message) => Err(Error::InvalidRequest(message)),
+        None => Ok(()),
+    }
+}
+
+/// One message per keyword of `request` that no part of the client reads.
+fn unknown_keywords(request: &Request) -> Vec<String> {
+    let known = || URL_COMPONENTS.iter().chain(&INDEX_COMPONENTS).chain(&ANCILLARY_KEYWORDS);
+    let mut out = Vec::new();
+    for (k, _) in request.iter() {
+        if known().any(|x| x == k) {
+            continue;
+        }
+        let hint = known()
+            .map(|x| (edit_distance(k, x), x))
+            .filter(|(d, _)| *d <= 2)
+            .min()
+            .map(|(_, x)| format!("; did you mean `{x}`?"))
+            .unwrap_or_default();
+        out.push(format!("unknown request keyword `{k}`{hint}"));
+    }
+    out
+}
+
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            cur.push((prev[j] + usize::from(ca != *cb)).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Whether `e` means the source does not have the requested data (as
+/// opposed to a bad request or a transient failure).
+pub(crate) fn is_missing_from_source(e: &Error) -> bool {
+    match e {
+        Error::CannotEstablishLatest { .. }
+        | Error::CyclePartiallyPublished { .. }
+        | Error::NoMatchingIndex
+        | Error::IndexNotReady { .. } => true,
+        Error::Http(e) => matches!(e.status().map(|s| s.as_u16()), Some(403 | 404 | 410)),
+        Error::Io(e) => e.kind() == std::io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+/// Whether a client for `opts` needs an Azure SAS token.
+pub(crate) fn uses_sas_token(opts: &ClientOptions) -> bool {
+    opts.use_sas_token.unwrap_or(opts.source == Source::Azure)
+}
+
+/// Endpoint issuing the SAS token for [`ClientOptions::sas_known_key`] or
+/// [`ClientOptions::sas_custom_url`].
+pub(crate) fn sas_token_url(opts: &ClientOptions) -> EResult<String> {
+    match opts.sas_known_key.as_str() {
+        "ecmwf" => Ok("https://planetarycomputer.microsoft.com/api/sas/v1/token/ai4edataeuwest/ecmwf".to_string()),
+        _ => opts.sas_custom_url.clone().ok_or_else(|| {
+            Error::InvalidRequest("no known sas token url and no custom provided".into())
+        }),
+    }
+}
+
+/// `url` without its query string (query parameters and SAS token).
+pub(crate) fn unsigned(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(u, _)| u)
+}
+
+/// URL actually requested for a generated URL: [`ClientOptions::query_params`]
+/// appended, then the SAS token unless the URL already carries a signature.
+pub(crate) fn request_url(opts: &ClientOptions, token: Option<&str>, url: &str) -> String {
+    let mut out = url.to_string();
+    if !opts.query_params.is_empty() {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&opts.query_params)
+            .finish();
+        out = append_query(&out, &query);
+    }
+    match token {
+        Some(token) if !out.contains("sig=") => append_query(&out, token),
+        _ => out,
+    }
+}
+
+fn append_query(url: &str, query: &str) -> String {
+    if url.contains('?') {
+        format!("{url}&{query}")
+    } else {
+        format!("{url}?{query}")
+    }
+}
+
+/// URL of the `.index` sidecar of a data URL.
+pub(crate) fn index_url_for(url: &str) -> String {
+    let base = url.rsplit_once('.').map(|(b, _)| b).unwrap_or(url);
+    format!("{base}.index")
+}
+
+/// Select the entries of one `.index` body matching `for_index`, resolving
+/// fields listed more than once by [`ClientOptions::duplicate_fields`].
+///
+/// Returns the data URL with the selected byte ranges encoded as
+/// `url|start-end;...` and the matched fields, or `None` when nothing matches.
+pub(crate) fn select_index_entries(
+    opts: &ClientOptions,
+    url: &str,
+    body: &str,
+    for_index: &BTreeMap<String, Vec<String>>,
+    order_by: &[String],
+    warnings: &mut Vec<String>,
+) -> EResult<Option<(String, Vec<MatchedField>)>> {
+    let ordered_keys = ordered_index_keys(for_index, order_by);
+    if ordered_keys.is_empty() {
+        // No index keywords, nothing to do.
+        return Ok(Some((url.to_string(), Vec::new())));
+    }
+    if body.trim().is_empty() {
+        return Err(Error::IndexNotReady {
+            url: index_url_for(url),
+        });
+    }
+    let entries: Vec<IndexEntry> = IndexEntry::parse(body)?
+        .into_iter()
+        .filter(|entry| index_matches(entry, &ordered_keys, for_index))
+        .collect();
+    let entries = opts.duplicate_fields.apply(&index_url_for(url), entries, warnings)?;
+
+    let mut url_fields = Vec::new();
+    let encoded = if opts.preserve_request_order {
+        // (sort_key, field) where sort_key is a lexicographic tuple capturing
+        // requested keyword/value order.
+        let mut parts: Vec<OrderedPart> = Vec::new();
+
+        for entry in &entries {
+            let mut key: Vec<(usize, usize)> = Vec::with_capacity(ordered_keys.len());
+
+            let mut ok = true;
+            for (i, k) in ordered_keys.iter().enumerate() {
+                let Some(val) = entry.get(k) else {
+                    ok = false;
+                    break;
+                };
+                let allowed = for_index
+                    .get(*k)
+                    .ok_or_else(|| Error::InvalidRequest("internal for_index missing key".into()))?;
+                let Some(j) = allowed.iter().position(|a| a == val) else {
+                    ok = false;
+                    break;
+                };
+                key.push((i, j));
+            }
+
+            if ok {
+                parts.push((key, MatchedField::from_index_entry(url, entry)));
+            }
+        }
+
+        if parts.is_empty() {
+            return Ok(None);
+        }
+
+        parts.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Ranges are written in request order.
+        url_fields = parts.into_iter().map(|(_, f)| f).collect();
+        let ranges: Vec<(u64, u64)> = url_fields.iter().map(|f| (f.offset, f.length)).collect();
+        encode_url_ranges(url, &merge_ranges_in_order(ranges))
+    } else {
+        // Fast path: sort by file offset (minimize HTTP requests).
+        let mut matches: Vec<(u64, u64)> = Vec::new();
+
+        for entry in &entries {
+            matches.push((entry.offset, entry.length));
+            url_fields.push(MatchedField::from_index_entry(url, entry));
+        }
+
+        if matches.is_empty() {
+            return Ok(None);
+        }
+
+        // Ranges are written in file order.
+        url_fields.sort_by_key(|f| f.offset);
+        encode_url_ranges(url, &merge_ranges(matches))
+    };
+
+    Ok(Some((encoded, url_fields)))
+}
+
+/// Index keywords whose values are spread over several files (one file per
+/// step, `cf` and `pf` sharing the `ef` file): a file is only expected to
+/// carry the requested values it lists at all.
+const FILE_SCOPED_KEYWORDS: [&str; 3] = ["type", "step", "fcmonth"];
+
+/// First requested index value (as `keyword=value`) that no entry of the
+/// given `.index` bodies carries, or `None` when every value is listed.
+///
+/// Each file must also list every combination of the requested values it is
+/// expected to hold, so a file publishing `2t` but not yet `msl` at step 6
+/// is reported as `param=msl,step=6` even when `msl` is listed at step 0.
+pub(crate) fn missing_index_value(
+    bodies: &[String],
+    for_index: &BTreeMap<String, Vec<String>>,
+) -> EResult<Option<String>> {
+    let ordered_keys = ordered_index_keys(for_index, &[]);
+    let mut seen: BTreeSet<(&str, String)> = BTreeSet::new();
+    let mut files = Vec::with_capacity(bodies.len());
+    for body in bodies {
+        let mut fields: BTreeSet<Vec<String>> = BTreeSet::new();
+        for entry in IndexEntry::parse(body)? {
+            if !index_matches(&entry, &ordered_keys, for_index) {
+                continue;
+            }
+            let field: Vec<String> = ordered_keys
+                .iter()
+                .map(|k| entry.get(k).unwrap_or_default().to_string())
+                .collect();
+            for (k, val) in ordered_keys.iter().zip(&field) {
+                seen.insert((k, val.clone()));
+            }
+            fields.insert(field);
+        }
+        files.push(fields);
+    }
+
+    for k in &ordered_keys {
+        for val in &for_index[*k] {
+            if !seen.contains(&(*k, val.clone())) {
+                return Ok(Some(format!("{k}={val}")));
+            }
+        }
+    }
+
+    for fields in &files {
+        // The values this file is expected to carry, per keyword.
+        let scope: Vec<Vec<&String>> = ordered_keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| {
+                let requested = for_index[*k].iter();
+                if FILE_SCOPED_KEYWORDS.contains(k) {
+                    requested.filter(|v| fields.iter().any(|f| &f[i] == *v)).collect()
+                } else {
+                    requested.collect()
+                }
+            })
+            .collect();
+        if scope.iter().any(Vec::is_empty) {
+            continue;
+        }
+        let mut combination = vec![0; scope.len()];
+        loop {
+            let field: Vec<String> = combination.iter().zip(&scope).map(|(&j, vals)| vals[j].clone()).collect();
+            if !fields.contains(&field) {
+                let missing: Vec<String> =
+                    ordered_keys.iter().zip(&field).map(|(k, v)| format!("{k}={v}")).collect();
+                return Ok(Some(missing.join(",")));
+            }
+            // Advance the last keyword fastest.
+            let Some(i) = (0..scope.len()).rev().find(|&i| combination[i] + 1 < scope[i].len()) else {
+                break;
+            };
+            combination[i] += 1;
+            combination[i + 1..].fill(0);
+        }
+    }
+    Ok(None)
+}
+
+/// Requested index keywords: those named in `order_by` first, then the rest
+/// in upstream's keyword order.
+pub(crate) fn ordered_index_keys<'a>(
+    for_index: &'a BTreeMap<String, Vec<String>>,
+    order_by: &[String],
+) -> Vec<&'a str> {
+    let mut keys: Vec<&str> = Vec::new();
+    let requested = order_by
+        .iter()
+        .filter_map(|k| for_index.get_key_value(k.as_str()).map(|(k, _)| k.as_str()));
+    for k in requested.chain(INDEX_COMPONENTS.iter().copied().filter(|k| for_index.contains_key(*k))) {
+        if !keys.contains(&k) {
+            keys.push(k);
+        }
+    }
+    keys
+}
+
+/// Whether an index entry has one of the requested values for every keyword.
+pub(crate) fn index_matches(
+    entry: &IndexEntry,
+    ordered_keys: &[&str],
+    for_index: &BTreeMap<String, Vec<String>>,
+) -> bool {
+    ordered_keys.iter().all(|k| {
+        match (entry.get(k), for_index.get(*k)) {
+            (Some(val), Some(allowed)) => allowed.iter().any(|a| a == val),
+            _ => false,
+        }
+    })
+}
+
+pub(crate) fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// Numeric ascending sort; non-numeric levels keep their order after the numeric ones.
+fn sort_levels(levels: &mut [String]) {
+    levels.sort_by_key(|l| {
+        l.parse::<f64>()
+            .map(|x| (x * 1000.0).round() as i64)
+            .unwrap_or(i64::MAX)
+    });
+}
+
+fn unique_preserve(xs: Vec<String>) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    let mut out = Vec::new();
+    for x in xs {
+        if seen.insert(x.clone()) {
+            out.push(x);
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod client_tests {
-    use super::default_step_for_url;
+    use std::collections::BTreeMap;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{
+        check_keywords, default_step_for_url, index_url_for, latest_candidates, missing_index_value, request_url,
+        sort_levels, Client, ClientOptions, MatchedField, RemoteVersion, Result,
+    };
+    use crate::components::{IndexComponents, UrlComponents};
+    use crate::index::IndexEntry;
+    use crate::sources::Source;
+    use crate::request::Request;
+
+    #[test]
+    fn request_url_appends_query_params_before_sas() {
+        let opts = ClientOptions {
+            query_params: vec![("key".to_string(), "a b".to_string()), ("trace".to_string(), "1".to_string())],
+            ..ClientOptions::default()
+        };
+        assert_eq!(
+            request_url(&opts, Some("sv=1&sig=x"), "https://h/a.grib2"),
+            "https://h/a.grib2?key=a+b&trace=1&sv=1&sig=x"
+        );
+        assert_eq!(
+            request_url(&ClientOptions::default(), None, "https://h/a.grib2"),
+            "https://h/a.grib2"
+        );
+    }
+
+    #[test]
+    fn missing_index_value_reports_unpublished_steps() {
+        let mut for_index = BTreeMap::new();
+        for_index.insert("param".to_string(), vec!["2t".to_string(), "msl".to_string()]);
+        for_index.insert("step".to_string(), vec!["0".to_string(), "6".to_string()]);
+        let bodies = vec![
+            r#"{"param": "2t", "step": "0", "_offset": 0, "_length": 10}
+{"param": "msl", "step": "0", "_offset": 10, "_length": 10}"#
+                .to_string(),
+            r#"{"param": "2t", "step": "6", "_offset": 0, "_length": 10}"#.to_string(),
+        ];
+        assert_eq!(
+            missing_index_value(&bodies, &for_index).unwrap(),
+            Some("param=msl,step=6".to_string())
+        );
+        assert_eq!(
+            missing_index_value(&bodies[..1], &for_index).unwrap(),
+            Some("step=6".to_string())
+        );
+
+        let complete = [
+            bodies[0].clone(),
+            r#"{"param": "2t", "step": "6", "_offset": 0, "_length": 10}
+{"param": "msl", "step": "6", "_offset": 10, "_length": 10}"#
+                .to_string(),
+        ];
+        assert_eq!(missing_index_value(&complete, &for_index).unwrap(), None);
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_keywords() {
+        let req = Request::new().r#type("fc").levelist(850).target("a.grib2");
+        assert!(check_keywords(&req).is_ok());
+
+        let err = check_keywords(&req.kw("levellist", 500)).unwrap_err();
+        assert!(err.to_string().contains("`levellist`; did you mean `levelist`?"), "{err}");
+        let err = check_keywords(&Request::new().kw("area", "50/0/40/10")).unwrap_err();
+        assert!(!err.to_string().contains("did you mean"), "{err}");
+        let err = check_keywords(&Request::new().stream("enso")).unwrap_err();
+        assert!(err.to_string().contains("unknown stream `enso`; did you mean `enfo`?"), "{err}");
+    }
+
+    #[test]
+    fn remote_version_reads_validators() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("last-modified", "Mon, 01 Jan 2024 08:12:00 GMT".parse().unwrap());
+        headers.insert("etag", "\"abc\"".parse().unwrap());
+        let v = RemoteVersion::from_headers(&headers);
+        assert_eq!(v.last_modified.as_deref(), Some("Mon, 01 Jan 2024 08:12:00 GMT"));
+        assert_eq!(v.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(RemoteVersion::from_headers(&Default::default()), RemoteVersion::default());
+    }
 
     #[test]
     fn default_step_matches_readme_table() {
@@ -882,64 +2905,180 @@ mod client_tests {
         // Probabilities
         assert_eq!(default_step_for_url("enfo", "ep", 0), "360");
     }
-}
 
-fn unique_preserve(xs: Vec<String>) -> Vec<String> {
-    let mut seen = BTreeSet::new();
-    let mut out = Vec::new();
-    for x in xs {
-        if seen.insert(x.clone()) {
-            out.push(x);
-        }
+    #[test]
+    fn iter_lead_times_groups_params_per_step() {
+        let mut for_index = BTreeMap::new();
+        for_index.insert("step".to_string(), vec!["0".to_string(), "0-24".to_string()]);
+        for_index.insert("param".to_string(), vec!["2t".to_string(), "msl".to_string()]);
+        let res = Result {
+            urls: Vec::new(),
+            target: "data.grib2".to_string(),
+            datetimes: vec![Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap()],
+            url_components: UrlComponents::default(),
+            index_components: IndexComponents::from_map(for_index),
+            size_bytes: 0,
+            final_urls: BTreeMap::new(),
+            remote_versions: BTreeMap::new(),
+            extension: "grib2".to_string(),
+            source: Source::default(),
+            substitutions: Vec::new(),
+            fields: Vec::new(),
+            warnings: Vec::new(),
+            sha256: None,
+            order_by: Vec::new(),
+            failed_fields: Vec::new(),
+            url_reports: Vec::new(),
+            fast_writes: false,
+        };
+
+        let lts: Vec<_> = res.iter_lead_times().collect();
+        assert_eq!(lts.len(), 2);
+        assert_eq!(lts[0].step, "0");
+        assert_eq!(lts[1].valid_time, Utc.with_ymd_and_hms(2024, 1, 2, 12, 0, 0).unwrap());
+        assert_eq!(lts[1].params, vec!["2t", "msl"]);
+
+        // Each cycle's steps count from that cycle, with its own fields.
+        let field = |param: &str, file: &str| {
+            let entry = IndexEntry {
+                param: Some(param.into()),
+                step: Some("0".into()),
+                ..IndexEntry::default()
+            };
+            MatchedField::from_index_entry(&format!("https://h/{file}"), &entry)
+        };
+        let res = Result {
+            datetimes: vec![
+                Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap(),
+            ],
+            fields: vec![
+                field("2t", "20240101000000-0h-oper-fc.grib2"),
+                field("msl", "20240101120000-0h-oper-fc.grib2"),
+            ],
+            ..res
+        };
+        let lts: Vec<_> = res.iter_lead_times().filter(|lt| lt.step == "0").collect();
+        assert_eq!(lts.len(), 2);
+        assert_eq!(lts[1].datetime, Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap());
+        assert_eq!(lts[1].valid_time, lts[1].datetime);
+        assert_eq!(lts[0].params, ["2t"]);
+        assert_eq!(lts[1].params, ["msl"]);
+    }
+
+    #[test]
+    fn control_forecast_is_member_zero() {
+        let cf = IndexEntry::parse_line(r#"{"type": "cf", "param": "2t", "_offset": 0, "_length": 1}"#).unwrap();
+        let pf = IndexEntry::parse_line(r#"{"type": "pf", "number": "3", "_offset": 1, "_length": 1}"#).unwrap();
+        assert_eq!(cf.get("number"), Some("0"));
+        assert_eq!(pf.get("number"), Some("3"));
+        assert_eq!(pf.get("param"), None);
     }
-    out
-}
 
-fn merge_ranges(mut matches: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
-    // input is (offset, length) -> convert to inclusive (start,end)
-    if matches.is_empty() {
-        return Vec::new();
+    #[test]
+    fn records_type_and_stream_substitutions() {
+        let client = Client::new(ClientOptions::default()).unwrap();
+        let req = Request::new().date(20240101).time(6).r#type("cf").step(12);
+        let res = client.get_urls(Some(&req), false, None).unwrap();
+
+        assert!(res.urls[0].contains("/06z/ifs/0p25/enfo/20240101060000-12h-enfo-ef.grib2"));
+        let subs: Vec<_> = res
+            .substitutions
+            .iter()
+            .map(|s| (s.keyword.as_str(), s.original.as_str(), s.used.as_str()))
+            .collect();
+        assert!(subs.contains(&("type", "cf", "ef")));
+        assert!(subs.contains(&("stream", "oper", "enfo")));
     }
-    if matches.len() == 1 {
-        let (o, l) = matches[0];
-        return vec![(o, o + l - 1)];
+
+    #[test]
+    fn wave_params_infer_wave_streams() {
+        let client = Client::new(ClientOptions::default()).unwrap();
+        let urls = |req: Request| client.get_urls(Some(&req), false, None).map(|r| r.urls);
+        let req = Request::new().date(20240101).param(["swh", "mwp"]);
+
+        let hres = urls(req.clone().time(6).step(90)).unwrap();
+        assert!(hres[0].ends_with("/06z/ifs/0p25/scwv/20240101060000-90h-scwv-fc.grib2"), "{hres:?}");
+        let ens = urls(req.clone().time(0).r#type("pf").step(360)).unwrap();
+        assert!(ens[0].ends_with("/00z/ifs/0p25/waef/20240101000000-360h-waef-ef.grib2"), "{ens:?}");
+
+        assert!(urls(req.clone().time(6).step(96)).is_err());
+        assert!(urls(req.clone().time(0).stream("oper")).is_err());
+        let mixed = client.get_urls(Some(&req.param(["swh", "2t"]).time(0)), false, None).unwrap();
+        assert!(mixed.warnings.iter().any(|w| w.contains("are in different streams")), "{:?}", mixed.warnings);
     }
 
-    // Ensure sorted by offset.
-    matches.sort_by_key(|(o, _)| *o);
+    #[test]
+    fn aliased_types_and_streams_share_one_url() {
+        let client = Client::new(ClientOptions::default()).unwrap();
+        let req = Request::new()
+            .date(20240101)
+            .time(6)
+            .r#type(["cf", "pf"])
+            .stream(["oper", "scda"])
+            .step([12, 12]);
+        let res = client.get_urls(Some(&req), false, None).unwrap();
+        assert_eq!(
+            res.urls,
+            vec!["https://data.ecmwf.int/forecasts/20240101/06z/ifs/0p25/enfo/20240101060000-12h-enfo-ef.grib2"]
+        );
+    }
 
-    let mut out: Vec<(u64, u64)> = Vec::new();
-    for (o, l) in matches {
-        let start = o;
-        let end = o + l - 1;
-        if let Some(last) = out.last_mut() {
-            if start <= last.1 + 1 {
-                last.1 = last.1.max(end);
-                continue;
-            }
-        }
-        out.push((start, end));
+    #[test]
+    fn custom_patterns_shape_urls() {
+        let client = Client::new(ClientOptions {
+            hourly_pattern: "{url}/{model}/{yyyymmdd}{H}/{type}_{step}.{ext}".to_string(),
+            ..ClientOptions::default()
+        })
+        .unwrap();
+        let req = Request::new().date(20240101).time(12).step(6);
+        let res = client.get_urls(Some(&req), false, None).unwrap();
+        assert_eq!(res.urls, vec!["https://data.ecmwf.int/forecasts/ifs/2024010112/fc_6.grib2"]);
+        assert_eq!(index_url_for(&res.urls[0]), "https://data.ecmwf.int/forecasts/ifs/2024010112/fc_6.index");
     }
-    out
-}
 
-fn split_url_ranges(s: &str) -> EResult<(&str, Vec<(u64, u64)>)> {
-    let Some((url, enc)) = s.split_once('|') else {
-        return Err(Error::InvalidRequest("expected ranged url encoding".into()));
-    };
+    #[test]
+    fn type_extensions_override_the_payload_extension() {
+        let mut opts = ClientOptions::default();
+        opts.type_extensions.insert("tf".to_string(), "bin".to_string());
+        let client = Client::new(opts).unwrap();
+        let req = Request::new().date(20240101).time(0).r#type("tf").step(240).target("tracks.grib2");
+        let res = client.get_urls(Some(&req), false, None).unwrap();
+        assert_eq!(res.extension, "bin");
+        assert!(res.urls[0].ends_with("-240h-oper-tf.bin"), "{}", res.urls[0]);
+        assert_eq!(res.target, "tracks.bin");
+    }
 
-    let mut ranges = Vec::new();
-    for part in enc.split(';').filter(|p| !p.is_empty()) {
-        let Some((a, b)) = part.split_once('-') else {
-            return Err(Error::InvalidRequest(format!("bad range: {part}")));
-        };
-        let start: u64 = a.parse().map_err(|_| Error::InvalidRequest(format!("bad range: {part}")))?;
-        let end: u64 = b.parse().map_err(|_| Error::InvalidRequest(format!("bad range: {part}")))?;
-        if end < start {
-            return Err(Error::InvalidRequest(format!("bad range: {part}")));
-        }
-        ranges.push((start, end));
+    #[test]
+    fn esuites_urls_use_the_operational_layout() {
+        let client = Client::new(ClientOptions {
+            source: Source::Esuites,
+            ..ClientOptions::default()
+        })
+        .unwrap();
+        let req = Request::new().date(20240101).time(0).r#type("cf").step(24);
+        let res = client.get_urls(Some(&req), false, None).unwrap();
+        assert_eq!(
+            res.urls,
+            vec!["https://xdiss.ecmwf.int/ecpds/home/opendata/20240101/00z/ifs/0p25/enfo/20240101000000-24h-enfo-ef.grib2"]
+        );
+
+        let candidates = latest_candidates(&Request::new(), Source::Esuites.retention()).unwrap();
+        assert_eq!(candidates.len(), 8);
+    }
+
+    #[test]
+    fn step_ranges_survive_slash_splitting() {
+        let client = Client::new(ClientOptions::default()).unwrap();
+        let req = Request::new().date(20240101).time(0).step("0/to/12/by/6");
+        let res = client.get_urls(Some(&req), false, None).unwrap();
+        assert_eq!(res.urls.len(), 3);
     }
 
-    Ok((url, ranges))
+    #[test]
+    fn levels_sort_numerically() {
+        let mut levels: Vec<String> = ["850", "1000", "50", "500"].iter().map(|s| s.to_string()).collect();
+        sort_levels(&mut levels);
+        assert_eq!(levels, vec!["50", "500", "850", "1000"]);
+    }
 }
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+/// Struct with one optional value list per keyword, convertible to and from
+/// the keyword → values map the resolver works with.
+macro_rules! components {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $($(#[$field_meta:meta])* $field:ident => $key:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, PartialEq, Eq)]
+        pub struct $name {
+            $($(#[$field_meta])* pub $field: Option<Vec<String>>,)*
+        }
+
+        impl $name {
+            /// Take the keywords of this struct out of `map`; other keys
+            /// are dropped.
+            pub(crate) fn from_map(mut map: BTreeMap<String, Vec<String>>) -> Self {
+                Self {
+                    $($field: map.remove($key),)*
+                }
+            }
+
+            /// Values of keyword `key`, as spelt in requests (`"type"`).
+            pub fn get(&self, key: &str) -> Option<&[String]> {
+                match key {
+                    $($key => self.$field.as_deref(),)*
+                    _ => None,
+                }
+            }
+
+            /// Keyword → values map of the keywords that are set.
+            pub fn to_map(&self) -> BTreeMap<String, Vec<String>> {
+                let mut map = BTreeMap::new();
+                $(
+                    if let Some(values) = &self.$field {
+                        map.insert($key.to_string(), values.clone());
+                    }
+                )*
+                map
+            }
+
+            /// Whether no keyword is set.
+            pub fn is_empty(&self) -> bool {
+                true $(&& self.$field.is_none())*
+            }
+        }
+    };
+}
+
+components! {
+    /// Keywords a resolved request builds its data URLs from
+    /// ([`crate::Result::url_components`]), after aliases and normalisation:
+    /// `type=cf` becomes `ef`, times two-digit hours, and so on. `None` for
+    /// keywords the request leaves to the default.
+    pub struct UrlComponents {
+        date => "date",
+        /// Cycle hours (`"00"`, `"12"`, ...).
+        time => "time",
+        model => "model",
+        resol => "resol",
+        stream => "stream",
+        /// `type` as used in URLs.
+        typ => "type",
+        step => "step",
+        fcmonth => "fcmonth",
+    }
+}
+
+components! {
+    /// Keywords a resolved request selects fields of the `.index` files by
+    /// ([`crate::Result::index_components`]). `None` for keywords that do not
+    /// restrict the selection, e.g. `number` with `number="all"`.
+    pub struct IndexComponents {
+        param => "param",
+        /// `type` as written in index files: `ef` stands for `cf` and `pf`.
+        typ => "type",
+        step => "step",
+        fcmonth => "fcmonth",
+        number => "number",
+        levelist => "levelist",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components_round_trip_through_maps() {
+        let pairs = [("type", vec!["cf", "pf"]), ("param", vec!["2t"]), ("area", vec!["x"])];
+        let map: BTreeMap<String, Vec<String>> = pairs
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.into_iter().map(String::from).collect()))
+            .collect();
+        let index = IndexComponents::from_map(map.clone());
+        assert_eq!(index.typ.as_deref(), Some(&["cf".to_string(), "pf".to_string()][..]));
+        assert_eq!(index.get("param"), Some(&["2t".to_string()][..]));
+        assert_eq!(index.get("area"), None);
+        assert_eq!(index.to_map().len(), 2);
+        assert_eq!(IndexComponents::from_map(index.to_map()), index);
+
+        assert!(UrlComponents::from_map(BTreeMap::new()).is_empty());
+        assert_eq!(UrlComponents::from_map(map).to_map().keys().collect::<Vec<_>>(), ["type"]);
+    }
+}
@@ -0,0 +1,455 @@
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+
+use crate::client::ClientOptions;
+use crate::error::{Error, Result as EResult};
+use crate::http::Identity;
+use crate::quota::{Quota, QuotaAction};
+use crate::rate_limit::RateLimit;
+use crate::request::{Request, RequestValue};
+
+/// Keys of a config file that are sections rather than client options.
+const SECTIONS: [&str; 2] = ["request", "profiles"];
+
+impl ClientOptions {
+    /// Read client options from a JSON, TOML (feature `toml`) or YAML
+    /// (feature `yaml`) file, chosen by extension.
+    ///
+    /// Top-level keys name [`ClientOptions`] fields (`source`, `model`,
+    /// `resol`, `query_params`, `identity`, ...); fields not given keep their
+    /// defaults. The `request` section is read by [`Request::from_file`], and
+    /// `profiles` by [`ClientOptions::from_file_profile`]:
+    ///
+    /// ```toml
+    /// source = "ecmwf"
+    /// resol = "0p25"
+    ///
+    /// [request]
+    /// type = "fc"
+    /// param = ["2t", "msl"]
+    ///
+    /// [profiles.aws]
+    /// source = "aws"
+    ///
+    /// [profiles.aws.request]
+    /// step = [0, 6, 12]
+    /// ```
+    pub fn from_file(path: impl AsRef<Path>) -> EResult<Self> {
+        Self::from_config(&client_section(&load(path.as_ref())?, None)?)
+    }
+
+    /// Like [`ClientOptions::from_file`], with the keys of
+    /// `profiles.<profile>` overriding the top-level ones.
+    pub fn from_file_profile(path: impl AsRef<Path>, profile: &str) -> EResult<Self> {
+        Self::from_config(&client_section(&load(path.as_ref())?, Some(profile))?)
+    }
+
+    fn from_config(config: &Map<String, Value>) -> EResult<Self> {
+        let mut opts = ClientOptions::default();
+        for (key, value) in config {
+            match key.as_str() {
+                "source" => opts.source = string(key, value)?.parse()?,
+                "fallback_source" => opts.fallback_source = Some(string(key, value)?.parse()?),
+                "layout" => opts.layout = string(key, value)?.parse()?,
+                "probe_target" => opts.probe_target = string(key, value)?.parse()?,
+                "model" => opts.model = string(key, value)?,
+                "resol" => opts.resol = string(key, value)?,
+                "hourly_pattern" => opts.hourly_pattern = string(key, value)?,
+                "monthly_pattern" => opts.monthly_pattern = string(key, value)?,
+                "beta" => opts.beta = boolean(key, value)?,
+                "preserve_request_order" => opts.preserve_request_order = boolean(key, value)?,
+                "duplicate_fields" => opts.duplicate_fields = string(key, value)?.parse()?,
+                "infer_stream_keyword" => opts.infer_stream_keyword = boolean(key, value)?,
+                "strict_keywords" => opts.strict_keywords = boolean(key, value)?,
+                "strict_params" => opts.strict_params = boolean(key, value)?,
+                "verify_tls" => opts.verify_tls = boolean(key, value)?,
+                "use_sas_token" => opts.use_sas_token = Some(boolean(key, value)?),
+                "sas_known_key" => opts.sas_known_key = string(key, value)?,
+                "sas_custom_url" => opts.sas_custom_url = Some(string(key, value)?),
+                "max_requests_per_url" => opts.max_requests_per_url = Some(integer(key, value)?),
+                "max_range_gap_bytes" => opts.max_range_gap_bytes = integer(key, value)? as u64,
+                "max_concurrent_ranges" => opts.max_concurrent_ranges = integer(key, value)?,
+                "sequential_ranges" => opts.sequential_ranges = boolean(key, value)?,
+                "skip_failed_fields" => opts.skip_failed_fields = boolean(key, value)?,
+                "stream_buffer" => opts.stream_buffer = integer(key, value)?,
+                "connect_timeout_secs" => opts.connect_timeout = Some(seconds(key, value)?),
+                "read_timeout_secs" => opts.read_timeout = Some(seconds(key, value)?),
+                "deadline_secs" => opts.deadline = Some(seconds(key, value)?),
+                "query_params" => {
+                    opts.query_params = table(key, value)?
+                        .iter()
+                        .map(|(k, v)| Ok((k.clone(), string(k, v)?)))
+                        .collect::<EResult<_>>()?;
+                }
+                "user_agent" => opts.user_agent = Some(string(key, value)?),
+                "type_extensions" => {
+                    opts.type_extensions = table(key, value)?
+                        .iter()
+                        .map(|(k, v)| Ok((k.clone(), string(k, v)?)))
+                        .collect::<EResult<_>>()?;
+                }
+                "headers" => {
+                    opts.headers = table(key, value)?
+                        .iter()
+                        .map(|(k, v)| Ok((k.clone(), string(k, v)?)))
+                        .collect::<EResult<_>>()?;
+                }
+                "no_split_keywords" => {
+                    opts.no_split_keywords = list(key, value)?
+                        .iter()
+                        .map(|v| string(key, v))
+                        .collect::<EResult<_>>()?;
+                }
+                "allowed_hosts" => {
+                    opts.allowed_hosts = Some(
+                        list(key, value)?
+                            .iter()
+                            .map(|v| string(key, v))
+                            .collect::<EResult<_>>()?,
+                    );
+                }
+                "rate_limit" => {
+                    let t = table(key, value)?;
+                    let mut limit = RateLimit::default();
+                    for (k, v) in t {
+                        match k.as_str() {
+                            "requests_per_second" => {
+                                limit.requests_per_second = v
+                                    .as_f64()
+                                    .ok_or_else(|| Error::Config(format!("`{k}` must be a number, got {v}")))?
+                            }
+                            "burst" => limit.burst = integer(k, v)? as u32,
+                            "max_concurrent" => limit.max_concurrent = integer(k, v)?,
+                            _ => return Err(Error::Config(format!("unknown rate_limit option `{k}`"))),
+                        }
+                    }
+                    opts.rate_limit = Some(limit);
+                }
+                "quota" => {
+                    let (mut max_bytes, mut action) = (None, QuotaAction::default());
+                    for (k, v) in table(key, value)? {
+                        match k.as_str() {
+                            "max_bytes" => max_bytes = Some(integer(k, v)? as u64),
+                            "action" => action = string(k, v)?.parse()?,
+                            _ => return Err(Error::Config(format!("unknown quota option `{k}`"))),
+                        }
+                    }
+                    let max_bytes = max_bytes.ok_or_else(|| Error::Config("quota needs `max_bytes`".into()))?;
+                    opts.quota = Some(Quota { max_bytes, action });
+                }
+                "identity" => {
+                    let t = table(key, value)?;
+                    let field = |name: &str| {
+                        t.get(name)
+                            .ok_or_else(|| Error::Config(format!("identity needs `{name}`")))
+                            .and_then(|v| string(name, v))
+                    };
+                    opts.identity = Some(Identity {
+                        contact_email: field("contact_email")?,
+                        app_name: field("app_name")?,
+                    });
+                }
+                _ => return Err(Error::Config(format!("unknown client option `{key}`"))),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+impl Request {
+    /// Read the `request` section of a config file (see
+    /// [`ClientOptions::from_file`]). Values are strings, integers or lists,
+    /// with strings interpreted like [`Request::from_str_pairs`].
+    pub fn from_file(path: impl AsRef<Path>) -> EResult<Self> {
+        Self::from_config(&request_section(&load(path.as_ref())?, None)?)
+    }
+
+    /// Like [`Request::from_file`], with `profiles.<profile>.request`
+    /// overriding keywords of the top-level `request` section.
+    pub fn from_file_profile(path: impl AsRef<Path>, profile: &str) -> EResult<Self> {
+        Self::from_config(&request_section(&load(path.as_ref())?, Some(profile))?)
+    }
+
+    fn from_config(config: &Map<String, Value>) -> EResult<Self> {
+        let mut r = Request::new();
+        for (key, value) in config {
+            r.set(key.clone(), request_value(key, value)?);
+        }
+        Ok(r)
+    }
+}
+
+fn client_section(doc: &Map<String, Value>, profile: Option<&str>) -> EResult<Map<String, Value>> {
+    let mut out = doc.clone();
+    if let Some(name) = profile {
+        out.extend(profile_table(doc, name)?.clone());
+    }
+    out.retain(|k, _| !SECTIONS.contains(&k.as_str()));
+    Ok(out)
+}
+
+fn request_section(doc: &Map<String, Value>, profile: Option<&str>) -> EResult<Map<String, Value>> {
+    let mut out = match doc.get("request") {
+        Some(v) => table("request", v)?.clone(),
+        None => Map::new(),
+    };
+    if let Some(name) = profile
+        && let Some(v) = profile_table(doc, name)?.get("request")
+    {
+        out.extend(table("request", v)?.clone());
+    }
+    Ok(out)
+}
+
+fn profile_table<'a>(doc: &'a Map<String, Value>, name: &str) -> EResult<&'a Map<String, Value>> {
+    doc.get("profiles")
+        .map(|p| table("profiles", p))
+        .transpose()?
+        .and_then(|p| p.get(name))
+        .map(|v| table(name, v))
+        .transpose()?
+        .ok_or_else(|| Error::Config(format!("no profile `{name}`")))
+}
+
+fn request_value(key: &str, value: &Value) -> EResult<RequestValue> {
+    match value {
+        Value::String(s) => Ok(RequestValue::parse_auto(s)),
+        Value::Number(_) => Ok(RequestValue::Int(value.as_i64().ok_or_else(|| {
+            Error::Config(format!("`{key}` must be an integer, got {value}"))
+        })?)),
+        Value::Array(items) => {
+            if let Some(ints) = items.iter().map(Value::as_i64).collect::<Option<Vec<_>>>() {
+                return Ok(RequestValue::IntList(ints));
+            }
+            if let Some(strs) = items.iter().map(|v| v.as_str().map(str::to_string)).collect::<Option<Vec<_>>>() {
+                return Ok(RequestValue::StrList(strs));
+            }
+            Ok(RequestValue::ValueList(
+                items.iter().map(|v| request_value(key, v)).collect::<EResult<_>>()?,
+            ))
+        }
+        _ => Err(Error::Config(format!(
+            "`{key}` must be a string, integer or list, got {value}"
+        ))),
+    }
+}
+
+fn string(key: &str, value: &Value) -> EResult<String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| Error::Config(format!("`{key}` must be a string, got {value}")))
+}
+
+fn boolean(key: &str, value: &Value) -> EResult<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| Error::Config(format!("`{key}` must be a boolean, got {value}")))
+}
+
+fn integer(key: &str, value: &Value) -> EResult<usize> {
+    value
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| Error::Config(format!("`{key}` must be a non-negative integer, got {value}")))
+}
+
+fn seconds(key: &str, value: &Value) -> EResult<Duration> {
+    value
+        .as_f64()
+        .and_then(|n| Duration::try_from_secs_f64(n).ok())
+        .ok_or_else(|| Error::Config(format!("`{key}` must be a non-negative number of seconds, got {value}")))
+}
+
+fn list<'a>(key: &str, value: &'a Value) -> EResult<&'a Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| Error::Config(format!("`{key}` must be a list, got {value}")))
+}
+
+fn table<'a>(key: &str, value: &'a Value) -> EResult<&'a Map<String, Value>> {
+    value
+        .as_object()
+        .ok_or_else(|| Error::Config(format!("`{key}` must be a table, got {value}")))
+}
+
+/// Parse a config file into a JSON object, whatever its format.
+fn load(path: &Path) -> EResult<Map<String, Value>> {
+    let text = std::fs::read_to_string(path)?;
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let doc = match ext.to_ascii_lowercase().as_str() {
+        "json" => serde_json::from_str(&text)?,
+        "toml" => parse_toml(&text)?,
+        "yaml" | "yml" => parse_yaml(&text)?,
+        _ => {
+            return Err(Error::Config(format!(
+                "unsupported config file {} (expected .json, .toml, .yaml or .yml)",
+                path.display()
+            )));
+        }
+    };
+    match doc {
+        Value::Object(map) => Ok(map),
+        other => Err(Error::Config(format!("config file must hold a table, got {other}"))),
+    }
+}
+
+#[cfg(feature = "toml")]
+fn parse_toml(text: &str) -> EResult<Value> {
+    let table: toml::Table = toml::from_str(text).map_err(|e| Error::Config(e.to_string()))?;
+    Ok(serde_json::to_value(table)?)
+}
+
+#[cfg(not(feature = "toml"))]
+fn parse_toml(_text: &str) -> EResult<Value> {
+    Err(Error::Config("reading TOML files requires the `toml` feature".into()))
+}
+
+#[cfg(feature = "yaml")]
+fn parse_yaml(text: &str) -> EResult<Value> {
+    use yaml_rust2::{Yaml, YamlLoader};
+
+    fn convert(y: &Yaml) -> EResult<Value> {
+        Ok(match y {
+            Yaml::String(s) => Value::String(s.clone()),
+            Yaml::Integer(i) => Value::from(*i),
+            Yaml::Real(r) => r
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+                .unwrap_or_else(|| Value::String(r.clone())),
+            Yaml::Boolean(b) => Value::Bool(*b),
+            Yaml::Array(items) => Value::Array(items.iter().map(convert).collect::<EResult<_>>()?),
+            Yaml::Hash(hash) => {
+                let mut map = Map::new();
+                for (k, v) in hash {
+                    let key = match k {
+                        Yaml::String(s) => s.clone(),
+                        Yaml::Integer(i) => i.to_string(),
+                        other => return Err(Error::Config(format!("unsupported YAML key {other:?}"))),
+                    };
+                    map.insert(key, convert(v)?);
+                }
+                Value::Object(map)
+            }
+            Yaml::Null => Value::Null,
+            Yaml::Alias(_) | Yaml::BadValue => {
+                return Err(Error::Config("unsupported YAML value (aliases are not supported)".into()));
+            }
+        })
+    }
+
+    let docs = YamlLoader::load_from_str(text).map_err(|e| Error::Config(e.to_string()))?;
+    match docs.first() {
+        Some(doc) => convert(doc),
+        None => Ok(Value::Object(Map::new())),
+    }
+}
+
+#[cfg(not(feature = "yaml"))]
+fn parse_yaml(_text: &str) -> EResult<Value> {
+    Err(Error::Config("reading YAML files requires the `yaml` feature".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::Source;
+
+    const CONFIG: &str = r#"{
+        "source": "ecmwf",
+        "resol": "0p25",
+        "query_params": {"key": "abc"},
+        "request": {"type": "fc", "param": ["2t", "msl"], "step": "0,6"},
+        "profiles": {
+            "aws": {"source": "aws", "request": {"step": [12, 24]}}
+        }
+    }"#;
+
+    #[test]
+    fn reads_options_and_request_with_profile_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("opendata.json");
+        std::fs::write(&path, CONFIG).unwrap();
+
+        let opts = ClientOptions::from_file(&path).unwrap();
+        assert_eq!(opts.source, Source::Ecmwf);
+        assert_eq!(opts.query_params, vec![("key".to_string(), "abc".to_string())]);
+        let req = Request::from_file(&path).unwrap();
+        assert_eq!(req.get("step"), Some(&RequestValue::IntList(vec![0, 6])));
+        assert_eq!(
+            req.get("param"),
+            Some(&RequestValue::StrList(vec!["2t".to_string(), "msl".to_string()]))
+        );
+
+        let opts = ClientOptions::from_file_profile(&path, "aws").unwrap();
+        assert_eq!(opts.source, Source::Aws);
+        assert_eq!(opts.resol, "0p25");
+        let req = Request::from_file_profile(&path, "aws").unwrap();
+        assert_eq!(req.get("step"), Some(&RequestValue::IntList(vec![12, 24])));
+        assert_eq!(req.get("type"), Some(&RequestValue::Str("fc".to_string())));
+
+        assert!(ClientOptions::from_file_profile(&path, "gcp").is_err());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_and_json_agree() {
+        let toml = r#"
+            source = "ecmwf"
+            resol = "0p25"
+            query_params = { key = "abc" }
+
+            [request]
+            type = "fc"
+            param = ["2t", "msl"]
+            step = "0,6"
+
+            [profiles.aws]
+            source = "aws"
+
+            [profiles.aws.request]
+            step = [12, 24]
+        "#;
+        let json: Map<String, Value> = serde_json::from_str(CONFIG).unwrap();
+        let Value::Object(toml) = parse_toml(toml).unwrap() else {
+            panic!("expected a table");
+        };
+        assert_eq!(toml, json);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml_and_json_agree() {
+        let yaml = "
+source: ecmwf
+resol: 0p25
+query_params:
+  key: abc
+request:
+  type: fc
+  param: [2t, msl]
+  step: '0,6'
+profiles:
+  aws:
+    source: aws
+    request:
+      step: [12, 24]
+";
+        let json: Map<String, Value> = serde_json::from_str(CONFIG).unwrap();
+        let Value::Object(yaml) = parse_yaml(yaml).unwrap() else {
+            panic!("expected a mapping");
+        };
+        assert_eq!(yaml, json);
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let doc: Map<String, Value> = serde_json::from_str(r#"{"sourse": "aws"}"#).unwrap();
+        let err = ClientOptions::from_config(&client_section(&doc, None).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("sourse"), "{err}");
+    }
+}
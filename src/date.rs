@@ -50,7 +50,9 @@ pub fn yyyymmdd(date: &NaiveDate) -> String {
 }
 
 /// Parse date inputs similar to upstream:
-/// - "YYYYMMDD" or "YYYY-MM-DD" or "YYYY-MM-DD HH:MM:SS"
+/// - "YYYYMMDD" or "YYYY-MM-DD" or "YYYY-MM-DD HH:MM:SS" (or with `T`)
+/// - RFC 3339 timestamps with an offset ("2024-01-01T06:00:00Z",
+///   "...+02:00"), converted to UTC
 /// - integer <= 0 means today + delta days
 pub fn parse_date_like(s: &str, now: DateTime<Utc>) -> Result<(NaiveDate, Option<u32>)> {
     let trimmed = s.trim();
@@ -82,8 +84,15 @@ pub fn parse_date_like(s: &str, now: DateTime<Utc>) -> Result<(NaiveDate, Option
         return Ok((d, None));
     }
 
-    if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%d %H:%M:%S") {
-        return Ok((dt.date(), Some(dt.hour())));
+    for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(trimmed, fmt) {
+            return Ok((dt.date(), Some(dt.hour())));
+        }
+    }
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(trimmed) {
+        let dt = dt.with_timezone(&Utc);
+        return Ok((dt.date_naive(), Some(dt.hour())));
     }
 
     Err(Error::InvalidRequest(format!(
@@ -154,10 +163,10 @@ pub fn full_datetime_from_date_time(
     let d = NaiveDate::from_ymd_opt(year, month, day)
         .ok_or_else(|| Error::InvalidRequest(format!("invalid date: {date_yyyymmdd}")))?;
 
-    Ok(Utc
+    Utc
         .with_ymd_and_hms(d.year(), d.month(), d.day(), time_hour, 0, 0)
         .single()
-        .ok_or_else(|| Error::InvalidRequest("invalid datetime".into()))?)
+        .ok_or_else(|| Error::InvalidRequest("invalid datetime".into()))
 }
 
 /// For probability steps like "0-24" return the end portion.
@@ -191,6 +200,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_rfc3339_dates_in_utc() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap();
+        let d = |y, m, dd| NaiveDate::from_ymd_opt(y, m, dd).unwrap();
+        assert_eq!(parse_date_like("2024-01-01T06:00:00Z", now).unwrap(), (d(2024, 1, 1), Some(6)));
+        assert_eq!(parse_date_like("2024-01-01T00:00:00+00:00", now).unwrap(), (d(2024, 1, 1), Some(0)));
+        assert_eq!(parse_date_like("2024-01-01T01:00:00+02:00", now).unwrap(), (d(2023, 12, 31), Some(23)));
+        assert_eq!(parse_date_like("2024-01-01T12:00:00", now).unwrap(), (d(2024, 1, 1), Some(12)));
+    }
+
     #[test]
     fn expands_date_ranges() {
         let now = Utc.with_ymd_and_hms(2022, 1, 31, 12, 0, 0).unwrap();
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::client::RemoteVersion;
+use crate::error::{Error, Result};
+
+/// Bytes of one completed data transfer, shareable between callers.
+#[derive(Debug, Clone)]
+pub(crate) struct Transfer {
+    pub bytes: Arc<Vec<u8>>,
+    /// URL the data was finally served from (query string removed).
+    pub final_url: String,
+    pub version: RemoteVersion,
+    /// HTTP status of the response; `None` for local files.
+    pub status: Option<u16>,
+}
+
+/// (url, Range header)
+type Key = (String, Option<String>);
+
+type Outcome = std::result::Result<Transfer, String>;
+
+#[derive(Debug, Default)]
+struct Slot {
+    outcome: Mutex<Option<Outcome>>,
+    done: Condvar,
+}
+
+/// Coalesces identical in-flight transfers issued concurrently through clones
+/// of the same client: the first caller downloads, later callers for the same
+/// URL and range wait for it and share its bytes.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    slots: Mutex<HashMap<Key, Arc<Slot>>>,
+}
+
+impl InFlight {
+    pub(crate) fn run(&self, url: &str, range: Option<&str>, fetch: impl FnOnce() -> Result<Transfer>) -> Result<Transfer> {
+        let key: Key = (url.to_string(), range.map(|r| r.to_string()));
+
+        let (slot, leader) = {
+            let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+            match slots.get(&key) {
+                Some(slot) => (slot.clone(), false),
+                None => {
+                    let slot = Arc::new(Slot::default());
+                    slots.insert(key.clone(), slot.clone());
+                    (slot, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut outcome = slot.outcome.lock().unwrap_or_else(|e| e.into_inner());
+            while outcome.is_none() {
+                outcome = slot.done.wait(outcome).unwrap_or_else(|e| e.into_inner());
+            }
+            return match outcome.as_ref() {
+                Some(Ok(t)) => Ok(t.clone()),
+                Some(Err(msg)) => Err(Error::SharedTransfer(msg.clone())),
+                None => unreachable!(),
+            };
+        }
+
+        // Publish an outcome even if `fetch` panics, so waiters never hang.
+        let mut guard = Publish {
+            inflight: self,
+            key,
+            slot,
+            outcome: Some(Err("transfer aborted".to_string())),
+        };
+        let result = fetch();
+        guard.outcome = Some(match &result {
+            Ok(t) => Ok(t.clone()),
+            Err(e) => Err(e.to_string()),
+        });
+        drop(guard);
+        result
+    }
+}
+
+struct Publish<'a> {
+    inflight: &'a InFlight,
+    key: Key,
+    slot: Arc<Slot>,
+    outcome: Option<Outcome>,
+}
+
+impl Drop for Publish<'_> {
+    fn drop(&mut self) {
+        self.inflight
+            .slots
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+        *self.slot.outcome.lock().unwrap_or_else(|e| e.into_inner()) = self.outcome.take();
+        self.slot.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn concurrent_identical_transfers_run_once() {
+        let inflight = Arc::new(InFlight::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(4));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let inflight = inflight.clone();
+                let calls = calls.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    inflight
+                        .run("https://h/a.grib2", Some("bytes=0-9"), || {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(100));
+                            Ok(Transfer {
+                                bytes: Arc::new(vec![1, 2, 3]),
+                                final_url: "https://h/a.grib2".to_string(),
+                                version: RemoteVersion::default(),
+                                status: Some(206),
+                            })
+                        })
+                        .unwrap()
+                })
+            })
+            .collect();
+
+        for h in handles {
+            assert_eq!(*h.join().unwrap().bytes, vec![1, 2, 3]);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn failures_are_not_cached() {
+        let inflight = InFlight::default();
+        assert!(inflight
+            .run("u", None, || Err(Error::InvalidRequest("boom".into())))
+            .is_err());
+        let ok = inflight.run("u", None, || {
+            Ok(Transfer {
+                bytes: Arc::new(Vec::new()),
+                final_url: "u".to_string(),
+                version: RemoteVersion::default(),
+                status: Some(200),
+            })
+        });
+        assert!(ok.is_ok());
+    }
+}
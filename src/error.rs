@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -7,6 +8,11 @@ pub enum Error {
     #[error("invalid request: {0}")]
     InvalidRequest(String),
 
+    /// A config file read by `ClientOptions::from_file` or `Request::from_file`
+    /// is malformed.
+    #[error("invalid config: {0}")]
+    Config(String),
+
     #[error("http error: {0}")]
     Http(#[from] reqwest::Error),
 
@@ -19,9 +25,81 @@ pub enum Error {
     #[error("url parse error: {0}")]
     Url(#[from] url::ParseError),
 
+    #[error("{url} returned {content_type} instead of data (likely an error page)")]
+    UnexpectedContent { url: String, content_type: String },
+
+    #[error(
+        "request selects neither param nor levelist and would download whole files; \
+         call Request::allow_full_files(true) to confirm"
+    )]
+    FullFileRequest,
+
+    /// A concurrent identical transfer this call was waiting on failed.
+    #[error("shared transfer failed: {0}")]
+    SharedTransfer(String),
+
+    /// The call was interrupted through its [`crate::CancellationToken`].
+    #[error("request cancelled")]
+    Cancelled,
+
+    /// The call ran past [`crate::ClientOptions::deadline`].
+    #[error("request did not complete within its deadline of {0:?}")]
+    DeadlineExceeded(std::time::Duration),
+
+    /// The session has downloaded [`crate::Quota::max_bytes`] with
+    /// [`crate::QuotaAction::Error`] in effect.
+    #[error("session quota of {max_bytes} bytes used up ({used_bytes} bytes downloaded)")]
+    QuotaExceeded { used_bytes: u64, max_bytes: u64 },
+
+    /// A request or redirect would have contacted a host outside
+    /// [`crate::ClientOptions::allowed_hosts`].
+    #[error("host `{host}` is not in the allowed hosts")]
+    HostNotAllowed { host: String },
+
+    /// A request was redirected more than
+    /// [`crate::RedirectPolicy::max_redirects`] times.
+    #[error("{url} was redirected more than {max} times")]
+    TooManyRedirects { url: String, max: usize },
+
     #[error("no matching index entries for request")]
     NoMatchingIndex,
 
-    #[error("cannot establish latest date for request")]
-    CannotEstablishLatest,
+    /// The index exists but is still empty, which happens while a cycle is
+    /// being published; retrying later may succeed.
+    #[error("index {url} is empty (not fully published yet)")]
+    IndexNotReady { url: String },
+
+    /// The index lists a selected field more than once and
+    /// [`crate::DuplicateFieldPolicy::Error`] is in effect.
+    #[error("index {url} lists {field} more than once")]
+    DuplicateIndexEntry { url: String, field: String },
+
+    #[error("probing {url} failed with HTTP status {status}")]
+    ProbeUnavailable { url: String, status: u16 },
+
+    /// No probed cycle had all requested files. `probed` lists each candidate
+    /// cycle with the HTTP status that ruled it out, newest first.
+    #[error("cannot establish latest date for request ({})", describe_probed(.probed))]
+    CannotEstablishLatest { probed: Vec<(DateTime<Utc>, u16)> },
+
+    /// The newest cycle has some of the requested files but not all of them,
+    /// which usually means it is still being published.
+    #[error("cycle {datetime} is only partially published (missing {first_missing})")]
+    CyclePartiallyPublished {
+        datetime: DateTime<Utc>,
+        first_missing: String,
+    },
+}
+
+fn describe_probed(probed: &[(DateTime<Utc>, u16)]) -> String {
+    match (probed.first(), probed.last()) {
+        (Some((newest, _)), Some((oldest, status))) => format!(
+            "probed {} cycles from {} back to {}, last status {}",
+            probed.len(),
+            newest.format("%Y%m%d %Hz"),
+            oldest.format("%Y%m%d %Hz"),
+            status
+        ),
+        _ => "no cycles probed".to_string(),
+    }
 }
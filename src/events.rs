@@ -0,0 +1,47 @@
+use std::sync::mpsc::Sender;
+
+/// Progress of a download, sent to [`crate::ClientOptions::download_events`]
+/// so frontends can drive their own rendering loop.
+///
+/// ```no_run
+/// use std::sync::mpsc;
+/// use ecmwf_opendata::{Client, ClientOptions, DownloadEvent, Request};
+///
+/// let (tx, rx) = mpsc::channel();
+/// let client = Client::new(ClientOptions {
+///     download_events: Some(tx),
+///     ..ClientOptions::default()
+/// })?;
+/// std::thread::spawn(move || {
+///     for event in rx {
+///         if let DownloadEvent::BytesWritten { written, .. } = event {
+///             println!("{written} bytes");
+///         }
+///     }
+/// });
+/// client.retrieve_request(Request::new().param("msl").step(0))?;
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadEvent {
+    /// Transfers for `target` are about to start. `total_bytes` is known when
+    /// every URL is range-selected.
+    Planned {
+        target: String,
+        urls: usize,
+        total_bytes: Option<u64>,
+    },
+    /// A request for `url` started; `range` is `None` for a whole file.
+    RangeStarted { url: String, range: Option<(u64, u64)> },
+    /// `bytes` more were written to `target`, `written` in total.
+    BytesWritten { target: String, bytes: u64, written: u64 },
+    Completed { target: String, size_bytes: u64 },
+    Failed { target: String, error: String },
+}
+
+/// Send an event if a channel is configured. A dropped receiver is ignored.
+pub(crate) fn emit(sender: Option<&Sender<DownloadEvent>>, event: DownloadEvent) {
+    if let Some(tx) = sender {
+        let _ = tx.send(event);
+    }
+}
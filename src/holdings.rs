@@ -0,0 +1,144 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::client::MatchedField;
+use crate::error::Result as EResult;
+use crate::manifest::Manifest;
+
+/// What is already on disk, read from the [`Manifest`]s below a directory,
+/// so processing can be planned without touching the network.
+///
+/// ```no_run
+/// use ecmwf_opendata::Holdings;
+///
+/// let holdings = Holdings::scan("/data/opendata")?;
+/// for cycle in holdings.cycles_with("2t") {
+///     println!("{}", cycle.format("%Y%m%d %Hz"));
+/// }
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Holdings {
+    /// Manifests found, ordered by cycle then target.
+    pub manifests: Vec<Manifest>,
+}
+
+impl Holdings {
+    /// Read every `*.manifest.json` below `dir`, recursively.
+    pub fn scan(dir: impl AsRef<Path>) -> EResult<Self> {
+        let mut manifests = Vec::new();
+        scan_dir(dir.as_ref(), &mut manifests)?;
+        manifests.sort_by(|a, b| (a.datetimes.first(), &a.target).cmp(&(b.datetimes.first(), &b.target)));
+        Ok(Self { manifests })
+    }
+
+    /// Every field held, with the manifest of the target holding it.
+    pub fn fields(&self) -> impl Iterator<Item = (&Manifest, &MatchedField)> {
+        self.manifests.iter().flat_map(|m| m.fields.iter().map(move |f| (m, f)))
+    }
+
+    /// Cycles held, oldest first.
+    pub fn cycles(&self) -> Vec<DateTime<Utc>> {
+        let cycles: BTreeSet<_> = self.manifests.iter().flat_map(|m| m.datetimes.iter().copied()).collect();
+        cycles.into_iter().collect()
+    }
+
+    /// Cycles for which at least one field of `param` is held, oldest first.
+    pub fn cycles_with(&self, param: &str) -> Vec<DateTime<Utc>> {
+        let cycles: BTreeSet<_> = self
+            .fields()
+            .filter(|(_, f)| f.param == param)
+            .filter_map(|(m, f)| field_cycle(m, f))
+            .collect();
+        cycles.into_iter().collect()
+    }
+
+    /// Steps of `param` held for `cycle`, as written in the index files.
+    pub fn steps_of(&self, cycle: DateTime<Utc>, param: &str) -> Vec<String> {
+        let mut steps = Vec::new();
+        for (_, f) in self.fields().filter(|(m, f)| field_cycle(m, f) == Some(cycle) && f.param == param) {
+            if let Some(step) = &f.step
+                && !steps.contains(step)
+            {
+                steps.push(step.clone());
+            }
+        }
+        steps
+    }
+}
+
+/// Cycle of a field: the manifest's when it holds one, otherwise read from
+/// the field's data file.
+fn field_cycle(manifest: &Manifest, field: &MatchedField) -> Option<DateTime<Utc>> {
+    match manifest.datetimes.as_slice() {
+        [datetime] => Some(*datetime),
+        _ => field.cycle(),
+    }
+}
+
+fn scan_dir(dir: &Path, out: &mut Vec<Manifest>) -> EResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            scan_dir(&path, out)?;
+        } else if path.to_string_lossy().ends_with(".manifest.json") {
+            out.push(Manifest::load(&path)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::index::IndexEntry;
+
+    fn manifest(target: &str, hour: u32, fields: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            target: target.to_string(),
+            datetimes: vec![Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap()],
+            size_bytes: 0,
+            files: Vec::new(),
+            fields: fields
+                .iter()
+                .map(|(param, step)| MatchedField {
+                    param: param.to_string(),
+                    levelist: None,
+                    step: Some(step.to_string()),
+                    number: None,
+                    offset: 0,
+                    length: 10,
+                    url: "https://h/a.grib2".to_string(),
+                    entry: IndexEntry::default(),
+                })
+                .collect(),
+            remote_versions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn answers_queries_from_manifests_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("12z")).unwrap();
+        manifest("a.grib2", 0, &[("2t", "0"), ("2t", "6"), ("msl", "0")])
+            .save(dir.path().join("a.grib2.manifest.json"))
+            .unwrap();
+        manifest("b.grib2", 12, &[("msl", "0")])
+            .save(dir.path().join("12z/b.grib2.manifest.json"))
+            .unwrap();
+        fs::write(dir.path().join("a.grib2"), b"GRIB").unwrap();
+
+        let holdings = Holdings::scan(dir.path()).unwrap();
+        let at = |hour| Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap();
+        assert_eq!(holdings.cycles(), vec![at(0), at(12)]);
+        assert_eq!(holdings.cycles_with("2t"), vec![at(0)]);
+        assert_eq!(holdings.cycles_with("msl"), vec![at(0), at(12)]);
+        assert_eq!(holdings.steps_of(at(0), "2t"), ["0", "6"]);
+        assert!(holdings.cycles_with("10u").is_empty());
+    }
+}
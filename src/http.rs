@@ -0,0 +1,439 @@
+use std::fmt;
+use std::sync::Arc;
+
+use reqwest::blocking::Client as HttpClient;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, FROM, LOCATION, USER_AGENT};
+use reqwest::{redirect, StatusCode, Url};
+
+use crate::client::ClientOptions;
+use crate::error::{Error, Result};
+
+/// How HTTP redirects are followed.
+///
+/// Some mirrors redirect data requests to CDN hosts; set
+/// `allow_cross_host = false` to refuse redirects that leave the host of the
+/// original request. The host actually used for each transfer is recorded in
+/// [`crate::Result::final_urls`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// Maximum number of redirect hops; `0` disables redirects.
+    pub max_redirects: usize,
+    pub allow_cross_host: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: 10,
+            allow_cross_host: true,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// The policy for reqwest, also refusing redirects to hosts outside
+    /// `allowed_hosts`. With `stop_cross_host`, redirects to another host are
+    /// handed back instead of followed, so that [`SourceHeaders`] can follow
+    /// them without the custom headers.
+    fn to_reqwest(&self, allowed_hosts: Option<&[String]>, stop_cross_host: bool) -> redirect::Policy {
+        if self.max_redirects == 0 {
+            return redirect::Policy::none();
+        }
+        let max = self.max_redirects;
+        let allow_cross_host = self.allow_cross_host;
+        let allowed_hosts = allowed_hosts.map(<[String]>::to_vec);
+        redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max {
+                return attempt.error(format!("too many redirects (max {max})"));
+            }
+            if let Some(allowed) = &allowed_hosts
+                && let Err(e) = check_host(Some(allowed), attempt.url())
+            {
+                return attempt.error(e);
+            }
+            if !allow_cross_host {
+                let origin = attempt.previous().first().and_then(|u| u.host_str());
+                if origin != attempt.url().host_str() {
+                    let to = attempt.url().host_str().unwrap_or_default().to_string();
+                    return attempt.error(format!("cross-host redirect to {to} not allowed"));
+                }
+            }
+            if stop_cross_host && attempt.previous().last().is_some_and(|from| !same_origin(from, attempt.url())) {
+                return attempt.stop();
+            }
+            attempt.follow()
+        })
+    }
+}
+
+/// One outgoing request or incoming response, as reported to an [`HttpHook`].
+///
+/// URLs are reported without their query string, so SAS tokens do not leak
+/// into logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpEvent {
+    Request {
+        method: String,
+        url: String,
+        range: Option<String>,
+    },
+    Response {
+        method: String,
+        url: String,
+        status: u16,
+        content_length: Option<u64>,
+    },
+    /// The request failed before a response was received.
+    Failed {
+        method: String,
+        url: String,
+        error: String,
+    },
+}
+
+/// Opt-in callback receiving every [`HttpEvent`] (probes, index and data
+/// requests), for debugging mirror-specific behaviour without a proxy.
+#[derive(Clone)]
+pub struct HttpHook(Arc<dyn Fn(&HttpEvent) + Send + Sync>);
+
+impl HttpHook {
+    pub fn new(f: impl Fn(&HttpEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    pub(crate) fn call(&self, event: &HttpEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for HttpHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HttpHook(..)")
+    }
+}
+
+const LIBRARY_AGENT: &str = "ecmwf-opendata-rs/0.1";
+
+/// Who is behind an automated client, sent with every request so the data
+/// provider can get in touch with heavy users instead of blocking them.
+///
+/// The application name is prepended to the `User-Agent` and the contact
+/// address is added to it as a `mailto:` comment, as well as sent in the
+/// `From` header:
+///
+/// ```text
+/// User-Agent: my-pipeline ecmwf-opendata-rs/0.1 (mailto:ops@example.org)
+/// From: ops@example.org
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub contact_email: String,
+    pub app_name: String,
+}
+
+impl Identity {
+    fn user_agent(&self) -> String {
+        format!("{} {LIBRARY_AGENT} (mailto:{})", self.app_name, self.contact_email)
+    }
+}
+
+fn default_headers(identity: Option<&Identity>) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let Some(identity) = identity else {
+        headers.insert(USER_AGENT, HeaderValue::from_static(LIBRARY_AGENT));
+        return Ok(headers);
+    };
+    if identity.app_name.trim().is_empty() || !identity.contact_email.contains('@') {
+        return Err(Error::InvalidRequest(format!(
+            "identity needs an application name and a contact email address, got {identity:?}"
+        )));
+    }
+    let header = |value: String| {
+        HeaderValue::from_str(&value)
+            .map_err(|_| Error::InvalidRequest(format!("identity is not a valid HTTP header value: {value:?}")))
+    };
+    headers.insert(USER_AGENT, header(identity.user_agent())?);
+    headers.insert(FROM, header(identity.contact_email.clone())?);
+    Ok(headers)
+}
+
+/// Headers sent with every request: the identity, then
+/// [`ClientOptions::user_agent`], which wins.
+fn client_headers(opts: &ClientOptions) -> Result<HeaderMap> {
+    let mut headers = default_headers(opts.identity.as_ref())?;
+    if let Some(agent) = &opts.user_agent {
+        let value = HeaderValue::from_str(agent)
+            .map_err(|_| Error::InvalidRequest(format!("invalid user agent: {agent:?}")))?;
+        headers.insert(USER_AGENT, value);
+    }
+    Ok(headers)
+}
+
+/// [`ClientOptions::headers`], attached to each request for the host of the
+/// primary source only: requests to the fallback source, the SAS token
+/// endpoint or hosts redirected to go without them.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceHeaders {
+    origin: Option<Url>,
+    headers: HeaderMap,
+    /// The HTTP client hands cross-host redirects back (see
+    /// [`SourceHeaders::redirect`]).
+    follow_cross_host: bool,
+}
+
+impl SourceHeaders {
+    pub(crate) fn new(opts: &ClientOptions) -> Result<Self> {
+        let invalid = |what: &str, value: &str| Error::InvalidRequest(format!("invalid {what}: {value:?}"));
+        let mut headers = HeaderMap::new();
+        for (name, value) in &opts.headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid("header name", name))?;
+            let mut value = HeaderValue::from_str(value).map_err(|_| invalid("header value", value))?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        Ok(Self {
+            origin: Url::parse(opts.source.base_url()).ok(),
+            follow_cross_host: stops_cross_host(opts),
+            headers,
+        })
+    }
+
+    /// For a fallback client sharing the HTTP client: no headers, but the
+    /// same redirect handling.
+    pub(crate) fn without_headers(&self) -> Self {
+        Self {
+            origin: None,
+            headers: HeaderMap::new(),
+            follow_cross_host: self.follow_cross_host,
+        }
+    }
+
+    /// Add the headers to a request for the primary source's host, or
+    /// remove them from a request for any other host.
+    pub(crate) fn attach(&self, url: &Url, headers: &mut HeaderMap) {
+        let primary = self.origin.as_ref().is_some_and(|origin| same_origin(origin, url));
+        for (name, value) in &self.headers {
+            if primary {
+                headers.insert(name, value.clone());
+            } else {
+                headers.remove(name);
+            }
+        }
+    }
+
+    /// Where to go next when the HTTP client handed back a cross-host
+    /// redirect of a response for `url`.
+    pub(crate) fn redirect(&self, url: &Url, status: StatusCode, headers: &HeaderMap) -> Option<Url> {
+        if !self.follow_cross_host || !status.is_redirection() {
+            return None;
+        }
+        let next = url.join(headers.get(LOCATION)?.to_str().ok()?).ok()?;
+        (!same_origin(url, &next)).then_some(next)
+    }
+}
+
+/// Whether the HTTP client built from `opts` hands cross-host redirects back.
+fn stops_cross_host(opts: &ClientOptions) -> bool {
+    !opts.headers.is_empty() && opts.redirect_policy.max_redirects > 0
+}
+
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.host_str() == b.host_str() && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Build the blocking HTTP client used for probes, index and data requests.
+pub(crate) fn build_http_client(opts: &ClientOptions) -> Result<HttpClient> {
+    let mut builder = HttpClient::builder()
+        .default_headers(client_headers(opts)?)
+        .redirect(opts.redirect_policy.to_reqwest(opts.allowed_hosts.as_deref(), stops_cross_host(opts)))
+        .connect_timeout(opts.connect_timeout);
+    if let Some(timeout) = opts.read_timeout {
+        builder = builder.timeout(timeout);
+    }
+    if !opts.verify_tls {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(feature = "async")]
+pub(crate) fn build_async_http_client(opts: &ClientOptions) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .default_headers(client_headers(opts)?)
+        .redirect(opts.redirect_policy.to_reqwest(opts.allowed_hosts.as_deref(), stops_cross_host(opts)));
+    if let Some(timeout) = opts.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = opts.read_timeout {
+        builder = builder.read_timeout(timeout);
+    }
+    if !opts.verify_tls {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder.build()?)
+}
+
+/// Refuse `url` unless its host is one of `allowed` (see
+/// [`ClientOptions::allowed_hosts`]); `None` allows every host.
+pub(crate) fn check_host(allowed: Option<&[String]>, url: &reqwest::Url) -> Result<()> {
+    let Some(allowed) = allowed else {
+        return Ok(());
+    };
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let matches = |pattern: &String| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.')),
+            None => host == pattern,
+        }
+    };
+    if host.is_empty() || !allowed.iter().any(matches) {
+        return Err(Error::HostNotAllowed { host });
+    }
+    Ok(())
+}
+
+/// The error of a failed send: [`Error::HostNotAllowed`] if the redirect
+/// policy refused a host outside `allowed_hosts`, [`Error::Http`] otherwise.
+pub(crate) fn redirect_error(e: reqwest::Error) -> Error {
+    let mut source = std::error::Error::source(&e);
+    while let Some(inner) = source {
+        if let Some(Error::HostNotAllowed { host }) = inner.downcast_ref::<Error>() {
+            return Error::HostNotAllowed { host: host.clone() };
+        }
+        source = inner.source();
+    }
+    Error::Http(e)
+}
+
+/// Strip the query string (which may carry a SAS token) from a URL.
+pub(crate) fn without_query(url: &reqwest::Url) -> String {
+    let mut u = url.clone();
+    u.set_query(None);
+    u.to_string()
+}
+
+/// Reject data responses that are evidently not GRIB/BUFR payloads.
+///
+/// Some mirrors answer with an HTML error page and status 200; writing that
+/// into the target would only fail much later, in the GRIB decoder.
+pub(crate) fn check_data_response(url: &str, content_type: Option<&str>, body: &[u8]) -> Result<()> {
+    let ct = content_type.unwrap_or_default().to_ascii_lowercase();
+    if ct.starts_with("text/html") || looks_like_html(body) {
+        return Err(Error::UnexpectedContent {
+            url: url.to_string(),
+            content_type: content_type.unwrap_or("unknown").to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn looks_like_html(body: &[u8]) -> bool {
+    let start = body.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(body.len());
+    let head = &body[start..body.len().min(start + 15)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    head.starts_with("<!doctype html") || head.starts_with("<html")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_match_exactly_or_by_subdomain() {
+        let allowed = ["data.ecmwf.int".to_string(), "*.Example.org".to_string()];
+        let check = |url: &str| check_host(Some(&allowed), &reqwest::Url::parse(url).unwrap()).is_ok();
+        assert!(check("https://DATA.ecmwf.int/forecasts"));
+        assert!(check("https://mirror.example.org/x"));
+        assert!(!check("https://example.org/x"));
+        assert!(!check("https://badexample.org/x"));
+        assert!(!check("https://data.ecmwf.int.evil.net/x"));
+        assert!(!check("file:///data"));
+        assert!(check_host(None, &reqwest::Url::parse("https://anywhere.net").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn without_query_drops_sas_token() {
+        let u = reqwest::Url::parse("https://cdn.example.org/a/b.grib2?sv=1&sig=x").unwrap();
+        assert_eq!(without_query(&u), "https://cdn.example.org/a/b.grib2");
+    }
+
+    #[test]
+    fn rejects_html_error_pages() {
+        assert!(check_data_response("u", Some("application/octet-stream"), b"GRIB\x00\x00").is_ok());
+        assert!(check_data_response("u", Some("text/html; charset=utf-8"), b"").is_err());
+        assert!(check_data_response("u", None, b"\n  <!DOCTYPE html><html>").is_err());
+    }
+
+    #[test]
+    fn identity_sets_user_agent_and_from() {
+        let identity = Identity {
+            contact_email: "ops@example.org".to_string(),
+            app_name: "my-pipeline/2".to_string(),
+        };
+        let headers = default_headers(Some(&identity)).unwrap();
+        assert_eq!(
+            headers[USER_AGENT],
+            "my-pipeline/2 ecmwf-opendata-rs/0.1 (mailto:ops@example.org)"
+        );
+        assert_eq!(headers[FROM], "ops@example.org");
+
+        assert_eq!(default_headers(None).unwrap()[USER_AGENT], LIBRARY_AGENT);
+        let bad = Identity {
+            contact_email: "nobody".to_string(),
+            ..identity
+        };
+        assert!(default_headers(Some(&bad)).is_err());
+    }
+
+    #[test]
+    fn custom_user_agent_and_headers_override_defaults() {
+        let opts = ClientOptions {
+            identity: Some(Identity {
+                contact_email: "ops@example.org".to_string(),
+                app_name: "my-pipeline".to_string(),
+            }),
+            user_agent: Some("institute-mirror-sync/1.0".to_string()),
+            headers: vec![("X-Api-Key".to_string(), "secret".to_string())],
+            ..ClientOptions::default()
+        };
+        let headers = client_headers(&opts).unwrap();
+        assert_eq!(headers[USER_AGENT], "institute-mirror-sync/1.0");
+        assert_eq!(headers[FROM], "ops@example.org");
+        assert!(!headers.contains_key("x-api-key"));
+
+        let bad = ClientOptions {
+            headers: vec![("X Api Key".to_string(), "secret".to_string())],
+            ..ClientOptions::default()
+        };
+        assert!(SourceHeaders::new(&bad).is_err());
+    }
+
+    #[test]
+    fn source_headers_only_go_to_the_primary_host() {
+        let opts = ClientOptions {
+            source: "https://mirror.example.org/data".parse().unwrap(),
+            headers: vec![("X-Api-Key".to_string(), "secret".to_string())],
+            ..ClientOptions::default()
+        };
+        let source = SourceHeaders::new(&opts).unwrap();
+        let attached = |source: &SourceHeaders, url: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-api-key", HeaderValue::from_static("stale"));
+            source.attach(&Url::parse(url).unwrap(), &mut headers);
+            headers.get("x-api-key").map(|v| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(attached(&source, "https://mirror.example.org/data/a.index").as_deref(), Some("secret"));
+        assert_eq!(attached(&source, "https://cdn.example.org/a.index"), None);
+        assert_eq!(attached(&source, "https://mirror.example.org:8443/a.index"), None);
+
+        let from = Url::parse("https://mirror.example.org/data/a.index").unwrap();
+        let location = |to: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(LOCATION, HeaderValue::from_static(to));
+            source.redirect(&from, StatusCode::FOUND, &headers)
+        };
+        assert_eq!(location("https://cdn.example.org/a.index").unwrap().as_str(), "https://cdn.example.org/a.index");
+        assert_eq!(location("/other/a.index"), None);
+    }
+}
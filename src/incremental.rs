@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+
+use crate::client::{index_url_for, name_target, Client, MatchedField, Result};
+use crate::error::{Error, Result as EResult};
+use crate::probe::ProbeOutcome;
+use crate::request::Request;
+
+/// State of a retrieval of a cycle that may still be being published.
+///
+/// During the dissemination window some steps appear before others.
+/// [`Client::retrieve_available`] downloads the files that exist now and keeps
+/// the rest in `pending`; [`Client::resume_available`] appends whatever has
+/// been published since.
+#[derive(Debug, Clone)]
+pub struct Incremental {
+    /// What has been written to the target so far (`urls` and `size_bytes`
+    /// accumulate across passes).
+    pub result: Result,
+    /// Data URLs (without range encoding) that were not published yet.
+    pub pending: Vec<String>,
+    use_index: bool,
+}
+
+impl Incremental {
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Client {
+    /// Retrieve the parts of a request that are published now.
+    ///
+    /// A file counts as published once its `.index` exists and is not empty
+    /// (or, for `use_index = false`, the data file itself). Throttled or
+    /// failing probes leave the file pending rather than failing the pass.
+    pub fn retrieve_available(&self, request: Request, use_index: bool) -> EResult<Incremental> {
+        if use_index && request.is_unguarded_full_file() {
+            return Err(Error::FullFileRequest);
+        }
+        let base = self.get_unnamed_urls(Some(&request), false, None)?;
+        let candidates = base.urls.clone();
+
+        let mut start = base;
+        start.urls.clear();
+        self.available_pass(start, &candidates, use_index, false)
+    }
+
+    /// Append the files of `previous.pending` that have been published since.
+    pub fn resume_available(&self, previous: &Incremental) -> EResult<Incremental> {
+        self.available_pass(
+            previous.result.clone(),
+            &previous.pending,
+            previous.use_index,
+            true,
+        )
+    }
+
+    /// Retrieve available parts and keep polling every `interval` until the
+    /// request is complete or `timeout` has elapsed. The returned state tells
+    /// whether it completed.
+    pub fn retrieve_until_complete(
+        &self,
+        request: Request,
+        use_index: bool,
+        interval: Duration,
+        timeout: Duration,
+    ) -> EResult<Incremental> {
+        let started = Instant::now();
+        let mut state = self.retrieve_available(request, use_index)?;
+        while !state.is_complete() && started.elapsed() + interval <= timeout {
+            std::thread::sleep(interval);
+            state = self.resume_available(&state)?;
+        }
+        Ok(state)
+    }
+
+    fn available_pass(
+        &self,
+        mut acc: Result,
+        candidates: &[String],
+        use_index: bool,
+        append: bool,
+    ) -> EResult<Incremental> {
+        let use_ranges = use_index && !acc.index_components.is_empty();
+        let (urls, fields, pending) = self.split_published(&mut acc, candidates, use_ranges)?;
+
+        if !urls.is_empty() {
+            let mut pass = acc.clone();
+            pass.urls = urls;
+            if !append {
+                // The first pass names the target from the fields it selected.
+                pass.fields = fields.clone();
+                name_target(&self.opts, &mut pass);
+                acc.target = pass.target.clone();
+            }
+            acc.fields.extend(fields);
+            let done = self.download_result(&pass, use_ranges, append)?;
+
+            acc.urls.extend(done.urls);
+            acc.size_bytes += done.size_bytes;
+            acc.final_urls.extend(done.final_urls);
+            acc.url_reports.extend(done.url_reports);
+            acc.remote_versions.extend(done.remote_versions);
+            acc.sha256 = done.sha256;
+        } else if !append {
+            // Create/truncate the target so it reflects this retrieval.
+            self.download_result(&acc, use_ranges, false)?;
+        }
+
+        Ok(Incremental {
+            result: acc,
+            pending,
+            use_index,
+        })
+    }
+
+    /// Split `candidates` into the files published now and those still
+    /// pending. Published files come back range-encoded with their selected
+    /// fields when `use_ranges` is set, and as plain URLs otherwise. Warnings
+    /// about the published indexes are added to `res`.
+    pub(crate) fn split_published(
+        &self,
+        res: &mut Result,
+        candidates: &[String],
+        use_ranges: bool,
+    ) -> EResult<(Vec<String>, Vec<MatchedField>, Vec<String>)> {
+        let mut ready = Vec::new();
+        let mut pending = Vec::new();
+        for u in candidates {
+            let probe_url = if use_ranges { index_url_for(u) } else { u.clone() };
+            match self.probe(&self.request_url(&probe_url))? {
+                ProbeOutcome::Exists => ready.push(u.clone()),
+                _ => pending.push(u.clone()),
+            }
+        }
+
+        while use_ranges && !ready.is_empty() {
+            let mut warnings = Vec::new();
+            match self.expand_urls_to_ranges(&ready, &res.for_index(), &res.order_by, &mut warnings) {
+                Ok((urls, fields)) => {
+                    res.warnings.extend(warnings);
+                    return Ok((urls, fields, pending));
+                }
+                // An index still being written counts as not published.
+                Err(Error::IndexNotReady { url }) => {
+                    let Some(i) = ready.iter().position(|u| index_url_for(u) == url) else {
+                        return Err(Error::IndexNotReady { url });
+                    };
+                    pending.push(ready.remove(i));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((ready, Vec::new(), pending))
+    }
+}
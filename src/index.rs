@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// One line of a `.index` sidecar: the MARS keys of a GRIB message and where
+/// it sits in the data file.
+///
+/// Keys the index does not carry are `None`; keys not listed here (e.g.
+/// `fcmonth`) are kept in `other`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub domain: Option<String>,
+    pub date: Option<String>,
+    pub time: Option<String>,
+    pub expver: Option<String>,
+    pub class: Option<String>,
+    #[serde(rename = "type")]
+    pub typ: Option<String>,
+    pub stream: Option<String>,
+    pub step: Option<String>,
+    pub param: Option<String>,
+    pub levtype: Option<String>,
+    pub levelist: Option<String>,
+    pub number: Option<String>,
+    /// Byte offset of the message in the data file (`_offset`).
+    #[serde(rename = "_offset")]
+    pub offset: u64,
+    /// Byte length of the message (`_length`).
+    #[serde(rename = "_length")]
+    pub length: u64,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl IndexEntry {
+    /// Parse one line of a `.index` file.
+    pub fn parse_line(line: &str) -> Result<Self> {
+        Ok(serde_json::from_str(line)?)
+    }
+
+    /// Parse every non-blank line of a `.index` file.
+    pub fn parse(body: &str) -> Result<Vec<Self>> {
+        body.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(Self::parse_line)
+            .collect()
+    }
+
+    /// Value of keyword `key`. The control forecast carries no `number`; it
+    /// is reported as member `0`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let value = match key {
+            "domain" => &self.domain,
+            "date" => &self.date,
+            "time" => &self.time,
+            "expver" => &self.expver,
+            "class" => &self.class,
+            "type" => &self.typ,
+            "stream" => &self.stream,
+            "step" => &self.step,
+            "param" => &self.param,
+            "levtype" => &self.levtype,
+            "levelist" => &self.levelist,
+            "number" => &self.number,
+            _ => return self.other.get(key).and_then(|v| v.as_str()),
+        };
+        match value.as_deref() {
+            None if key == "number" && self.typ.as_deref() == Some("cf") => Some("0"),
+            v => v,
+        }
+    }
+
+    /// The entry without its position, identifying the field it describes.
+    fn identity(&self) -> String {
+        let field = IndexEntry { offset: 0, length: 0, ..self.clone() };
+        serde_json::to_string(&field).unwrap_or_default()
+    }
+
+    /// Short description of the field, for messages.
+    fn describe(&self) -> String {
+        ["param", "levelist", "step", "number"]
+            .iter()
+            .filter_map(|k| self.get(k).map(|v| format!("{k}={v}")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// What happens when an `.index` lists a selected field more than once
+/// (entries equal but for their offset and length).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateFieldPolicy {
+    /// Keep the first entry.
+    KeepFirst,
+    /// Keep the last entry.
+    KeepLast,
+    /// Fail with [`Error::DuplicateIndexEntry`].
+    Error,
+    /// Download every entry, as before this policy existed, and say so in
+    /// [`crate::Result::warnings`].
+    #[default]
+    Warn,
+}
+
+impl DuplicateFieldPolicy {
+    /// Apply the policy to `entries`, selected from the index at `index_url`.
+    pub(crate) fn apply(
+        self,
+        index_url: &str,
+        entries: Vec<IndexEntry>,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<IndexEntry>> {
+        let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+        let mut out: Vec<IndexEntry> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let identity = entry.identity();
+            let Some(&i) = seen.get(&identity) else {
+                seen.insert(identity, out.len());
+                out.push(entry);
+                continue;
+            };
+            match self {
+                DuplicateFieldPolicy::KeepFirst => {}
+                DuplicateFieldPolicy::KeepLast => out[i] = entry,
+                DuplicateFieldPolicy::Error => {
+                    return Err(Error::DuplicateIndexEntry {
+                        url: index_url.to_string(),
+                        field: entry.describe(),
+                    });
+                }
+                DuplicateFieldPolicy::Warn => {
+                    warnings.push(format!(
+                        "{index_url} lists {} more than once; every copy is downloaded",
+                        entry.describe()
+                    ));
+                    out.push(entry);
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl std::str::FromStr for DuplicateFieldPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(DuplicateFieldPolicy::KeepFirst),
+            "last" => Ok(DuplicateFieldPolicy::KeepLast),
+            "error" => Ok(DuplicateFieldPolicy::Error),
+            "warn" => Ok(DuplicateFieldPolicy::Warn),
+            _ => Err(Error::InvalidRequest(format!(
+                "unknown duplicate field policy: {s} (expected first, last, error or warn)"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lines_and_reports_control_as_member_zero() {
+        let body = concat!(
+            r#"{"domain": "g", "date": "20240101", "time": "0000", "expver": "0001", "class": "od", "type": "cf", "stream": "enfo", "step": "0", "levtype": "sfc", "param": "2t", "_offset": 0, "_length": 10}"#,
+            "\n\n",
+            r#"{"type": "pf", "number": "3", "param": "t", "levelist": "850", "fcmonth": "1", "_offset": 10, "_length": 20}"#,
+            "\n",
+        );
+        let entries = IndexEntry::parse(body).unwrap();
+        assert_eq!(entries.len(), 2);
+        let (cf, pf) = (&entries[0], &entries[1]);
+        assert_eq!(cf.get("number"), Some("0"));
+        assert_eq!(cf.get("stream"), Some("enfo"));
+        assert_eq!((pf.offset, pf.length), (10, 20));
+        assert_eq!(pf.get("number"), Some("3"));
+        assert_eq!(pf.get("fcmonth"), Some("1"));
+        assert_eq!(pf.get("levtype"), None);
+
+        assert!(IndexEntry::parse_line(r#"{"param": "2t", "_length": 10}"#).is_err());
+    }
+
+    #[test]
+    fn duplicate_fields_follow_the_policy() {
+        let body = concat!(
+            r#"{"param": "2t", "step": "0", "_offset": 0, "_length": 10}"#,
+            "\n",
+            r#"{"param": "msl", "step": "0", "_offset": 10, "_length": 10}"#,
+            "\n",
+            r#"{"param": "2t", "step": "0", "_offset": 20, "_length": 12}"#,
+        );
+        let entries = IndexEntry::parse(body).unwrap();
+        let offsets = |policy: DuplicateFieldPolicy, warnings: &mut Vec<String>| -> Vec<u64> {
+            let kept = policy.apply("a.index", entries.clone(), warnings).unwrap();
+            kept.iter().map(|e| e.offset).collect()
+        };
+
+        let mut warnings = Vec::new();
+        assert_eq!(offsets(DuplicateFieldPolicy::KeepFirst, &mut warnings), [0, 10]);
+        assert_eq!(offsets(DuplicateFieldPolicy::KeepLast, &mut warnings), [20, 10]);
+        assert!(warnings.is_empty());
+        assert_eq!(offsets(DuplicateFieldPolicy::Warn, &mut warnings), [0, 10, 20]);
+        assert_eq!(warnings, ["a.index lists param=2t step=0 more than once; every copy is downloaded"]);
+
+        let err = DuplicateFieldPolicy::Error.apply("a.index", entries, &mut warnings).unwrap_err();
+        assert!(matches!(err, Error::DuplicateIndexEntry { field, .. } if field == "param=2t step=0"));
+        assert_eq!("last".parse::<DuplicateFieldPolicy>().unwrap(), DuplicateFieldPolicy::KeepLast);
+    }
+}
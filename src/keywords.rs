@@ -0,0 +1,184 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::client::edit_distance;
+use crate::error::{Error, Result as EResult};
+use crate::request::{Request, RequestValue};
+
+/// Enum of the known values of a request keyword, with an `Other` variant for
+/// values this crate does not know yet. Parsing only accepts known values, so
+/// a typo fails (with a suggestion) instead of resolving to a missing file.
+macro_rules! keyword_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident ($keyword:literal) {
+            $($(#[$variant_meta:meta])* $variant:ident => $value:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+            /// A value not listed above, passed through unchecked.
+            Other(String),
+        }
+
+        impl $name {
+            /// Values of the listed variants, as spelt in requests.
+            pub const KNOWN: &'static [&'static str] = &[$($value),*];
+
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $value,)*
+                    $name::Other(s) => s,
+                }
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            /// Known values only; use `Other` for anything else.
+            fn from_str(s: &str) -> EResult<Self> {
+                match s {
+                    $($value => Ok($name::$variant),)*
+                    _ => Err(Error::InvalidRequest(unknown_value($keyword, s, Self::KNOWN))),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl From<$name> for RequestValue {
+            fn from(v: $name) -> Self {
+                RequestValue::Str(v.to_string())
+            }
+        }
+
+        impl From<Vec<$name>> for RequestValue {
+            fn from(v: Vec<$name>) -> Self {
+                RequestValue::StrList(v.into_iter().map(|x| x.to_string()).collect())
+            }
+        }
+
+        impl<const N: usize> From<[$name; N]> for RequestValue {
+            fn from(v: [$name; N]) -> Self {
+                Vec::from(v).into()
+            }
+        }
+    };
+}
+
+keyword_enum! {
+    /// Forecasting system (`stream`) of a request.
+    pub enum Stream("stream") {
+        /// HRES, 00/12z.
+        Oper => "oper",
+        /// HRES waves, 00/12z.
+        Wave => "wave",
+        /// HRES, 06/18z.
+        Scda => "scda",
+        /// HRES waves, 06/18z.
+        Scwv => "scwv",
+        /// Ensemble.
+        Enfo => "enfo",
+        /// Ensemble waves.
+        Waef => "waef",
+        /// Seasonal forecast.
+        Mmsf => "mmsf",
+        /// Seasonal monthly means, published under `mmsf`.
+        Mmsa => "mmsa",
+    }
+}
+
+keyword_enum! {
+    /// Product (`type`) of a request.
+    pub enum DataType("type") {
+        /// Forecast.
+        Fc => "fc",
+        /// Ensemble control forecast.
+        Cf => "cf",
+        /// Ensemble perturbed forecast.
+        Pf => "pf",
+        /// Ensemble mean.
+        Em => "em",
+        /// Ensemble standard deviation.
+        Es => "es",
+        /// Event probabilities.
+        Ep => "ep",
+        /// Tropical cyclone tracks (BUFR).
+        Tf => "tf",
+        /// Seasonal forecast mean.
+        Fcmean => "fcmean",
+    }
+}
+
+keyword_enum! {
+    /// Model producing the data ([`crate::ClientOptions::model`] or the
+    /// request's `model`).
+    pub enum Model("model") {
+        Ifs => "ifs",
+        AifsSingle => "aifs-single",
+        AifsEns => "aifs-ens",
+    }
+}
+
+impl From<Model> for String {
+    fn from(model: Model) -> Self {
+        model.to_string()
+    }
+}
+
+/// `unknown <keyword> `<value>``, with the closest known value if it is
+/// likely a typo.
+fn unknown_value(keyword: &str, value: &str, known: &[&str]) -> String {
+    let hint = known
+        .iter()
+        .map(|k| (edit_distance(value, k), k))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, k)| format!("; did you mean `{k}`?"))
+        .unwrap_or_default();
+    format!("unknown {keyword} `{value}`{hint}")
+}
+
+/// Messages for `stream`, `type` and `model` values of `request` that none of
+/// [`Stream`], [`DataType`] or [`Model`] knows.
+pub(crate) fn unknown_values(request: &Request) -> Vec<String> {
+    let keywords = [("stream", Stream::KNOWN), ("type", DataType::KNOWN), ("model", Model::KNOWN)];
+    let mut out = Vec::new();
+    for (key, known) in keywords {
+        for value in request.get(key).map(RequestValue::as_strings).unwrap_or_default() {
+            if !known.contains(&value.as_str()) {
+                out.push(unknown_value(key, &value, known));
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values_parse_and_typos_are_caught() {
+        assert_eq!("enfo".parse::<Stream>().unwrap(), Stream::Enfo);
+        assert_eq!(DataType::Pf.to_string(), "pf");
+        assert_eq!(Model::AifsSingle.as_str(), "aifs-single");
+        assert_eq!(String::from(Model::AifsEns), "aifs-ens");
+        assert_eq!(Stream::Other("eefo".into()).to_string(), "eefo");
+
+        let err = "enso".parse::<Stream>().unwrap_err().to_string();
+        assert!(err.contains("unknown stream `enso`; did you mean `enfo`?"), "{err}");
+        assert!(!"xyzzy".parse::<Model>().unwrap_err().to_string().contains("did you mean"));
+
+        let req = Request::new().stream([Stream::Oper, Stream::Other("enso".into())]).r#type(DataType::Fc);
+        assert_eq!(req.get("stream").unwrap().as_strings(), ["oper", "enso"]);
+        assert_eq!(unknown_values(&req), ["unknown stream `enso`; did you mean `enfo`?"]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::client::{request_defaults, Client, ClientOptions};
+use crate::date::end_step;
+use crate::error::{Error, Result as EResult};
+use crate::manifest::Manifest;
+use crate::request::Request;
+use crate::steps::published_steps;
+use crate::target::target_path;
+use crate::wave::is_wave_stream;
+
+/// The same fields retrieved from consecutive cycles (a lagged ensemble) by
+/// [`Client::retrieve_lagged`]: one target per cycle, described by its
+/// [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaggedEnsemble {
+    /// Manifests of the per-cycle targets, newest cycle first.
+    pub members: Vec<Manifest>,
+}
+
+impl LaggedEnsemble {
+    /// Conventional location of the combined manifest for `target`:
+    /// `<target>.lagged.json`.
+    pub fn path_for(target: &str) -> PathBuf {
+        target_path(&format!("{target}.lagged.json"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> EResult<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> EResult<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Cycles of the members, newest first.
+    pub fn cycles(&self) -> Vec<DateTime<Utc>> {
+        self.members.iter().flat_map(|m| m.datetimes.iter().copied()).collect()
+    }
+}
+
+impl Client {
+    /// Retrieve `request` from the newest `cycles` cycles into one target per
+    /// cycle, and write the combined manifest next to the request's target
+    /// ([`LaggedEnsemble::path_for`]).
+    ///
+    /// The newest cycle is the request's `date`/`time`, or the latest one
+    /// published. Older cycles are taken every 6 hours, or every day at the
+    /// same hour when the request has a `time` (as for [`Client::latest`]).
+    /// Each target is the request's `target` with the cycle inserted before
+    /// the extension (`data.grib2` becomes `data_2024010100.grib2`). Steps
+    /// are the same in every cycle, not shifted to a common valid time, so
+    /// cycles whose forecasts stop short of the requested steps (the 06/18z
+    /// ensemble ends at 144h) are skipped for older ones.
+    pub fn retrieve_lagged(&self, request: Request, cycles: usize) -> EResult<LaggedEnsemble> {
+        let client = self.bounded();
+        let newest = match request.get("date") {
+            Some(_) => client.get_urls(Some(&request), false, None)?.first_datetime(),
+            None => client.latest(request.clone())?,
+        };
+        let every = if request.get("time").is_some() { Duration::days(1) } else { Duration::hours(6) };
+        let target = request
+            .get("target")
+            .and_then(|v| v.as_strings().first().cloned())
+            .unwrap_or_else(|| "data.grib2".to_string());
+
+        let mut members = Vec::with_capacity(cycles);
+        let mut cycle = newest;
+        let mut skipped = 0;
+        while members.len() < cycles {
+            if !publishes_steps(&client.opts, &request, cycle.hour()) {
+                // Every hour of a day was tried, or every cycle is at this hour.
+                skipped += 1;
+                if skipped == 4 || every == Duration::days(1) {
+                    return Err(Error::InvalidRequest(format!(
+                        "no cycle up to {} publishes every requested step",
+                        cycle.format("%Y%m%d %Hz")
+                    )));
+                }
+                cycle -= every;
+                continue;
+            }
+            skipped = 0;
+            let request = request
+                .clone()
+                .date(cycle.format("%Y%m%d").to_string())
+                .time(cycle.hour())
+                .target(cycle_target(&target, cycle));
+            members.push(Manifest::from_result(&client.retrieve_request(request)?));
+            cycle -= every;
+        }
+        let lagged = LaggedEnsemble { members };
+        lagged.save(LaggedEnsemble::path_for(&target))?;
+        Ok(lagged)
+    }
+}
+
+/// Whether a cycle at `hour` publishes every step of `request`: the end of
+/// each step (range) is within the horizon of each product requested.
+fn publishes_steps(opts: &ClientOptions, request: &Request, hour: u32) -> bool {
+    let Some(steps) = request.get("step").map(|v| v.as_strings()) else {
+        return true;
+    };
+    let (model, params) = request_defaults(opts, Some(request));
+    let strings = |key: &str| params.get(key).map(|v| v.as_strings()).unwrap_or_default();
+    for stream in strings("stream") {
+        for typ in strings("type") {
+            let stream = match (is_wave_stream(&stream), matches!(typ.as_str(), "cf" | "pf" | "em" | "es" | "ep")) {
+                (true, true) => "waef",
+                (false, true) => "enfo",
+                _ => stream.as_str(),
+            };
+            let horizon = published_steps(&model, stream, &typ, hour).last().map_or(-1, |h| i64::from(*h));
+            if steps.iter().any(|s| end_step(s).is_some_and(|end| end > horizon)) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// `target` with the cycle inserted before the extension of its file name.
+fn cycle_target(target: &str, cycle: DateTime<Utc>) -> String {
+    let stamp = cycle.format("%Y%m%d%H");
+    let name = target.rfind(['/', '\\']).map_or(0, |i| i + 1);
+    match target[name..].rfind('.').filter(|dot| *dot > 0) {
+        Some(dot) => format!("{}_{stamp}{}", &target[..name + dot], &target[name + dot..]),
+        None => format!("{target}_{stamp}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn cycle_goes_before_the_extension() {
+        let cycle = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(cycle_target("out/data.grib2", cycle), "out/data_2024010112.grib2");
+        assert_eq!(cycle_target("out.d/data", cycle), "out.d/data_2024010112");
+        assert_eq!(cycle_target(".hidden", cycle), ".hidden_2024010112");
+    }
+
+    #[test]
+    fn short_cycles_do_not_publish_long_steps() {
+        let opts = ClientOptions::default();
+        let ens = Request::new().stream("enfo").r#type("pf").step(["0", "240"]);
+        assert!(publishes_steps(&opts, &ens, 0));
+        assert!(!publishes_steps(&opts, &ens, 6));
+        assert!(publishes_steps(&opts, &ens.clone().step(144), 18));
+        assert!(!publishes_steps(&opts, &Request::new().step("0-120"), 18));
+        assert!(publishes_steps(&opts, &Request::new().param("2t"), 18));
+    }
+}
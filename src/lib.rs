@@ -10,18 +10,14 @@
 //!
 //! **Quick start**
 //! ```no_run
-//! use ecmwf_opendata::{Client, ClientOptions, Request};
+//! use ecmwf_opendata::{Client, Request, Source};
 //!
-//! let opts = ClientOptions {
-//!     // Python: Client(source="ecmwf", model="ifs", resol="0p25", ...)
-//!     source: "ecmwf".to_string(),
-//!     model: "ifs".to_string(),
-//!     resol: "0p25".to_string(),
-//!     preserve_request_order: false,
-//!     infer_stream_keyword: true,
-//!     ..ClientOptions::default()
-//! };
-//! let client = Client::new(opts)?;
+//! // Python: Client(source="ecmwf", model="ifs", resol="0p25", ...)
+//! let client = Client::builder()
+//!     .source(Source::Ecmwf)
+//!     .model("ifs")
+//!     .resol("0p25")
+//!     .build()?;
 //!
 //! // Builder style
 //! let req = Request::new().r#type("fc").param("msl").step(240).target("data.grib2");
@@ -41,7 +37,7 @@
 //!     ("step", 240.into()),
 //!     ("target", "data.grib2".into()),
 //! ])?;
-//! println!("{}", result.datetime);
+//! println!("{:?}", result.datetimes);
 //! # Ok::<(), ecmwf_opendata::Error>(())
 //! ```
 //!
@@ -61,7 +57,7 @@
 //!     param = ["tpg1", "tpg5", "10fgg10"],
 //!     target = "data.grib2",
 //! )?;
-//! println!("{}", result.datetime);
+//! println!("{:?}", result.datetimes);
 //! # Ok::<(), ecmwf_opendata::Error>(())
 //! ```
 //!
@@ -88,16 +84,86 @@
 //!   specify `date`/`time` explicitly in your request.
 //! - In line with the upstream Python client, omitting `step` means “retrieve all available steps”.
 
+#[cfg(feature = "async")]
+mod async_client;
+mod availability;
+mod builder;
+mod cancel;
+mod checksum;
 mod client;
+mod components;
+mod config;
 mod date;
+mod dedup;
 mod error;
+mod events;
+mod holdings;
+mod http;
+mod incremental;
+mod index;
+mod keywords;
+mod lagged;
+mod lint;
+mod local;
+mod manifest;
+mod param_catalog;
+mod param_groups;
+mod plan;
+mod probe;
+mod quota;
+mod ranges;
+mod rate_limit;
 mod request;
+mod retry;
+mod sas;
+mod routing;
 mod sources;
+mod steps;
+mod stream;
+mod target;
+mod telemetry;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod throttle;
 mod url_builder;
+mod wave;
 
-pub use crate::client::{Client, ClientOptions, Result};
+#[cfg(feature = "async")]
+pub use crate::async_client::AsyncClient;
+pub use crate::availability::Availability;
+pub use crate::builder::ClientBuilder;
+pub use crate::cancel::CancellationToken;
+pub use crate::components::{IndexComponents, UrlComponents};
+pub use crate::client::{Client, ClientOptions, LeadTime, MatchedField, RemoteVersion, Result, Substitution, UrlReport};
 pub use crate::error::{Error, Result as EResult};
-pub use crate::request::{Request, RequestValue};
+pub use crate::events::DownloadEvent;
+pub use crate::holdings::Holdings;
+pub use crate::http::{HttpEvent, HttpHook, Identity, RedirectPolicy};
+pub use crate::incremental::Incremental;
+pub use crate::index::{DuplicateFieldPolicy, IndexEntry};
+pub use crate::keywords::{DataType, Model, Stream};
+pub use crate::lagged::LaggedEnsemble;
+pub use crate::lint::{Lint, Severity};
+pub use crate::manifest::{Manifest, ManifestDiff};
+pub use crate::param_catalog::ParamCatalog;
+pub use crate::param_groups::ParamGroups;
+pub use crate::plan::{
+    plan, plan_index_urls, DownloadPlan, FileRanges, PlannedTransfer, RangeReport, RangeRequest, SourceAssignment,
+    SourceCost, SourcePlan,
+};
+pub use crate::probe::{LatestFallback, ProbePolicy, ProbeTarget};
+pub use crate::quota::{Quota, QuotaAction, Usage};
+pub use crate::rate_limit::RateLimit;
+pub use crate::request::{DuplicateKeyPolicy, Members, Request, RequestValue};
+pub use crate::retry::RetryPolicy;
+pub use crate::routing::Route;
+pub use crate::sources::Source;
+pub use crate::stream::RetrieveStream;
+pub use crate::target::{Layout, TargetContext, TargetNamer};
+pub use bytes::Bytes;
+pub use crate::throttle::ThrottlePolicy;
+pub use crate::url_builder::{extension_for_type, UrlBuilder, HOURLY_PATTERN, MONTHLY_PATTERN};
+pub use crate::wave::{is_wave_param, is_wave_stream, wave_steps, WAVE_PARAMS};
 
 /// Build a [`Request`] using a kwargs-like syntax.
 ///
@@ -156,7 +222,7 @@ macro_rules! request {
 ///     param = ["tpg1", "tpg5", "10fgg10"],
 ///     target = "data.grib2",
 /// )?;
-/// println!("{}", result.datetime);
+/// println!("{:?}", result.datetimes);
 /// # Ok::<(), ecmwf_opendata::Error>(())
 /// ```
 #[macro_export]
@@ -0,0 +1,133 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use url::Url;
+
+use crate::client::RemoteVersion;
+use crate::error::{Error, Result};
+
+/// Path of a `file://` URL (any query string is ignored), or `None` for other
+/// URLs.
+pub(crate) fn file_path(url: &str) -> Option<PathBuf> {
+    if !url.starts_with("file:") {
+        return None;
+    }
+    Url::parse(url).ok()?.to_file_path().ok()
+}
+
+/// Whether a local file exists, answered as the HTTP status a mirror would
+/// send for it.
+pub(crate) fn status(path: &Path) -> u16 {
+    if path.is_file() { 200 } else { 404 }
+}
+
+/// Contents of a local file, or of the inclusive byte range given as a
+/// `Range` header value (`bytes=start-end`). Like HTTP, a range ending past
+/// the end of the file is cut short.
+pub(crate) fn read(path: &Path, range: Option<&str>) -> Result<Vec<u8>> {
+    let read = || -> io::Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+        let Some((start, end)) = range.map(parse_range).transpose()? else {
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+            return Ok(buf);
+        };
+        let len = file.metadata()?.len();
+        if start >= len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("range starts at {start}, file has {len} bytes"),
+            ));
+        }
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = Vec::new();
+        file.take(end.min(len - 1) - start + 1).read_to_end(&mut buf)?;
+        Ok(buf)
+    };
+    read().map_err(|e| with_path(path, e))
+}
+
+/// [`read`] without blocking the async runtime.
+#[cfg(feature = "async")]
+pub(crate) async fn read_async(path: &Path, range: Option<&str>) -> Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let read = async || -> io::Result<Vec<u8>> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let Some((start, end)) = range.map(parse_range).transpose()? else {
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf).await?;
+            return Ok(buf);
+        };
+        let len = file.metadata().await?.len();
+        if start >= len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("range starts at {start}, file has {len} bytes"),
+            ));
+        }
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut buf = Vec::new();
+        file.take(end.min(len - 1) - start + 1).read_to_end(&mut buf).await?;
+        Ok(buf)
+    };
+    read().await.map_err(|e| with_path(path, e))
+}
+
+pub(crate) fn size(path: &Path) -> Result<u64> {
+    Ok(fs::metadata(path).map_err(|e| with_path(path, e))?.len())
+}
+
+/// Modification time of a local file, reported like a `Last-Modified` header.
+pub(crate) fn version(path: &Path) -> RemoteVersion {
+    let last_modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .map(|t| DateTime::<Utc>::from(t).format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+    RemoteVersion {
+        last_modified,
+        etag: None,
+    }
+}
+
+fn parse_range(range: &str) -> io::Result<(u64, u64)> {
+    let bad = || io::Error::new(io::ErrorKind::InvalidInput, format!("bad range: {range}"));
+    let (start, end) = range
+        .strip_prefix("bytes=")
+        .and_then(|r| r.split_once('-'))
+        .ok_or_else(bad)?;
+    let start: u64 = start.parse().map_err(|_| bad())?;
+    let end: u64 = end.parse().map_err(|_| bad())?;
+    if end < start {
+        return Err(bad());
+    }
+    Ok((start, end))
+}
+
+fn with_path(path: &Path, e: io::Error) -> Error {
+    Error::Io(io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ranges_like_a_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.grib2");
+        fs::write(&path, b"0123456789").unwrap();
+        let url = Url::from_file_path(&path).unwrap();
+
+        assert_eq!(file_path(url.as_str()).unwrap(), path);
+        assert_eq!(file_path("https://h/a.grib2"), None);
+        assert_eq!(read(&path, None).unwrap(), b"0123456789");
+        assert_eq!(read(&path, Some("bytes=2-4")).unwrap(), b"234");
+        assert_eq!(read(&path, Some("bytes=8-20")).unwrap(), b"89");
+        assert!(read(&path, Some("bytes=10-12")).is_err());
+        assert_eq!(status(&path), 200);
+        assert_eq!(status(&dir.path().join("b.grib2")), 404);
+    }
+}
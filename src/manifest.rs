@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Timelike, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::client::{writes_tree, Client, MatchedField, RemoteVersion, Result};
+use crate::error::{Error, Result as EResult};
+use crate::ranges::{encode_url_ranges, merge_ranges_in_order, split_url_ranges};
+use crate::request::Request;
+use crate::target::target_path;
+
+/// What a target holds: its cycles, the fields (or whole files) written to it
+/// and its size, so a later run can tell what is missing.
+///
+/// Build one with [`Manifest::from_result`] and keep it next to the target
+/// ([`Manifest::path_for`]); [`Client::retrieve_delta`] appends what has been
+/// published since and updates it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub target: String,
+    /// Cycles the target holds, in ascending order (see
+    /// [`Result::datetimes`]).
+    #[serde(alias = "datetime", deserialize_with = "one_or_more")]
+    pub datetimes: Vec<DateTime<Utc>>,
+    pub size_bytes: u64,
+    /// Data URLs downloaded whole (products without index selection).
+    pub files: Vec<String>,
+    /// Fields written to the target, in write order.
+    pub fields: Vec<MatchedField>,
+    /// Data URL → `Last-Modified`/`ETag` when it was last downloaded (see
+    /// [`Result::remote_versions`]). Empty in manifests written before it was
+    /// recorded.
+    #[serde(default)]
+    pub remote_versions: BTreeMap<String, RemoteVersion>,
+}
+
+impl Manifest {
+    pub fn from_result(res: &Result) -> Self {
+        let mut manifest = Self {
+            target: res.target.clone(),
+            datetimes: res.datetimes.clone(),
+            size_bytes: 0,
+            files: Vec::new(),
+            fields: Vec::new(),
+            remote_versions: BTreeMap::new(),
+        };
+        manifest.record(res);
+        manifest
+    }
+
+    /// Conventional location of the manifest of `target`:
+    /// `<target>.manifest.json`.
+    pub fn path_for(target: &str) -> PathBuf {
+        target_path(&format!("{target}.manifest.json"))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> EResult<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> EResult<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Whether the target already holds `field` (same file, param, level,
+    /// step and member).
+    pub fn contains(&self, field: &MatchedField) -> bool {
+        self.fields.iter().any(|f| same_field(f, field))
+    }
+
+    /// What this manifest holds compared to `previous`, e.g. the manifest of
+    /// an earlier retrieval of the same request before the cycle was
+    /// republished.
+    pub fn diff(&self, previous: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff {
+            size_before: previous.size_bytes,
+            size_after: self.size_bytes,
+            ..ManifestDiff::default()
+        };
+        for field in &self.fields {
+            match previous.fields.iter().find(|f| same_field(f, field)) {
+                None => diff.added.push(field.clone()),
+                Some(before) if before.length != field.length => diff.resized.push((before.clone(), field.clone())),
+                Some(_) => {}
+            }
+        }
+        diff.removed = previous.fields.iter().filter(|f| !self.contains(f)).cloned().collect();
+        diff.added_files = self.files.iter().filter(|u| !previous.files.contains(u)).cloned().collect();
+        diff.removed_files = previous.files.iter().filter(|u| !self.files.contains(u)).cloned().collect();
+        diff.republished = self
+            .remote_versions
+            .iter()
+            .filter(|(url, version)| previous.remote_versions.get(*url).is_some_and(|before| version.changed_since(before)))
+            .map(|(url, _)| url.clone())
+            .collect();
+        diff
+    }
+
+    /// Add what a download appended to the target.
+    fn record(&mut self, res: &Result) {
+        self.size_bytes += res.size_bytes;
+        self.fields.extend(res.fields.iter().cloned());
+        self.files
+            .extend(res.urls.iter().filter(|u| !u.contains('|')).cloned());
+        self.remote_versions
+            .extend(res.remote_versions.iter().map(|(url, version)| (url.clone(), version.clone())));
+    }
+}
+
+/// Manifests written before several cycles were recorded hold a single
+/// `datetime`.
+fn one_or_more<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<DateTime<Utc>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Cycles {
+        One(DateTime<Utc>),
+        More(Vec<DateTime<Utc>>),
+    }
+    Ok(match Cycles::deserialize(d)? {
+        Cycles::One(datetime) => vec![datetime],
+        Cycles::More(datetimes) => datetimes,
+    })
+}
+
+/// Same file, param, level, step and member; offsets may differ.
+fn same_field(a: &MatchedField, b: &MatchedField) -> bool {
+    a.url == b.url && a.param == b.param && a.levelist == b.levelist && a.step == b.step && a.number == b.number
+}
+
+/// Changes between two retrievals of the same request, from
+/// [`Manifest::diff`] or [`Result::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    /// Fields held now but not before.
+    pub added: Vec<MatchedField>,
+    /// Fields held before but not now.
+    pub removed: Vec<MatchedField>,
+    /// Fields held in both whose length changed, as `(before, now)`.
+    pub resized: Vec<(MatchedField, MatchedField)>,
+    /// Whole files (no index selection) downloaded now but not before.
+    pub added_files: Vec<String>,
+    /// Whole files downloaded before but not now.
+    pub removed_files: Vec<String>,
+    /// Data URLs downloaded both times whose `Last-Modified`/`ETag` changed:
+    /// the file was republished in between.
+    pub republished: Vec<String>,
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+impl ManifestDiff {
+    /// Whether the same fields and files were retrieved with the same sizes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.resized.is_empty()
+            && self.added_files.is_empty()
+            && self.removed_files.is_empty()
+            && self.republished.is_empty()
+            && self.size_before == self.size_after
+    }
+}
+
+impl Result {
+    /// Compare this retrieval with `previous`, the manifest of an earlier
+    /// retrieval of the same request (see [`Manifest::diff`]).
+    pub fn diff(&self, previous: &Manifest) -> ManifestDiff {
+        Manifest::from_result(self).diff(previous)
+    }
+}
+
+impl Client {
+    /// Append to `manifest.target` the parts of `request` published since the
+    /// manifest was written, and record them in `manifest`.
+    ///
+    /// A request without `date` is pinned to the manifest's cycles, and must
+    /// resolve to exactly those cycles. Fields
+    /// and whole files the manifest already lists are skipped, as are files
+    /// not published yet (they are picked up by a later call). Fails when the
+    /// target's size differs from the one recorded, since appending would
+    /// then corrupt it. Returns what this call appended, with a warning for
+    /// each file republished since the manifest recorded it (its new fields
+    /// may not match those already held). Not available with
+    /// [`crate::Layout::EcmwfTree`], which writes one file per URL.
+    pub fn retrieve_delta(&self, request: Request, manifest: &mut Manifest) -> EResult<Result> {
+        if request.is_unguarded_full_file() {
+            return Err(Error::FullFileRequest);
+        }
+        if writes_tree(&self.opts) {
+            return Err(Error::InvalidRequest(
+                "retrieve_delta appends to a single target; Layout::EcmwfTree writes one file per URL".into(),
+            ));
+        }
+        let request = if request.get("date").is_none() {
+            let dates: BTreeSet<String> = manifest.datetimes.iter().map(|d| d.format("%Y%m%d").to_string()).collect();
+            let hours: BTreeSet<u32> = manifest.datetimes.iter().map(|d| d.hour()).collect();
+            request
+                .date(dates.into_iter().collect::<Vec<_>>())
+                .time(hours.into_iter().collect::<Vec<_>>())
+        } else {
+            request
+        };
+
+        let client = self.bounded();
+        let mut base = client.get_unnamed_urls(Some(&request), false, Some(&manifest.target))?;
+        if base.datetimes != manifest.datetimes {
+            let cycles = |datetimes: &[DateTime<Utc>]| {
+                let cycles: Vec<String> = datetimes.iter().map(|d| d.format("%Y%m%d %Hz").to_string()).collect();
+                cycles.join(", ")
+            };
+            return Err(Error::InvalidRequest(format!(
+                "manifest of {} is for cycles {}, request is for {}",
+                manifest.target,
+                cycles(&manifest.datetimes),
+                cycles(&base.datetimes)
+            )));
+        }
+        let on_disk = fs::metadata(target_path(&manifest.target))?.len();
+        if on_disk != manifest.size_bytes {
+            return Err(Error::InvalidRequest(format!(
+                "{} is {on_disk} bytes but its manifest records {}",
+                manifest.target, manifest.size_bytes
+            )));
+        }
+
+        let candidates = std::mem::take(&mut base.urls);
+        let use_ranges = !base.index_components.is_empty();
+        let (urls, fields, _pending) = client.split_published(&mut base, &candidates, use_ranges)?;
+
+        let fields: Vec<MatchedField> = fields.into_iter().filter(|f| !manifest.contains(f)).collect();
+        for u in urls {
+            if !u.contains('|') {
+                if !manifest.files.contains(&u) {
+                    base.urls.push(u);
+                }
+                continue;
+            }
+            let (data_url, _) = split_url_ranges(&u)?;
+            let ranges: Vec<(u64, u64)> = fields
+                .iter()
+                .filter(|f| f.url == data_url)
+                .map(|f| (f.offset, f.length))
+                .collect();
+            if !ranges.is_empty() {
+                base.urls.push(encode_url_ranges(data_url, &merge_ranges_in_order(ranges)));
+            }
+        }
+        base.fields = fields;
+
+        if base.urls.is_empty() {
+            return Ok(base);
+        }
+        let mut done = client.download_result(&base, use_ranges, true)?;
+        for (url, version) in &done.remote_versions {
+            if manifest.remote_versions.get(url).is_some_and(|before| version.changed_since(before)) {
+                done.warnings.push(format!(
+                    "{url} was republished since {} was written; its fields may not match those already held",
+                    manifest.target
+                ));
+            }
+        }
+        manifest.record(&done);
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::index::IndexEntry;
+
+    fn field(param: &str, step: &str, offset: u64) -> MatchedField {
+        MatchedField {
+            param: param.to_string(),
+            levelist: None,
+            step: Some(step.to_string()),
+            number: None,
+            offset,
+            length: 10,
+            url: "https://h/a.grib2".to_string(),
+            entry: IndexEntry::default(),
+        }
+    }
+
+    #[test]
+    fn manifest_round_trips_and_matches_fields_by_identity() {
+        let manifest = Manifest {
+            target: "data.grib2".to_string(),
+            datetimes: vec![Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()],
+            size_bytes: 10,
+            files: Vec::new(),
+            fields: vec![field("2t", "0", 0)],
+            remote_versions: BTreeMap::from([(
+                "https://h/a.grib2".to_string(),
+                RemoteVersion { last_modified: None, etag: Some("\"v1\"".to_string()) },
+            )]),
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("m.json");
+        manifest.save(&path).unwrap();
+        assert_eq!(Manifest::load(&path).unwrap(), manifest);
+
+        // Manifests from before several cycles were recorded.
+        let old = r#"{"target": "data.grib2", "datetime": "2024-01-01T00:00:00Z", "size_bytes": 10,
+            "files": [], "fields": []}"#;
+        fs::write(&path, old).unwrap();
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded.datetimes, manifest.datetimes);
+        assert!(loaded.remote_versions.is_empty());
+
+        // Offsets may move when a file is republished; the field is the same.
+        assert!(manifest.contains(&field("2t", "0", 40)));
+        assert!(!manifest.contains(&field("2t", "6", 0)));
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_resized_fields() {
+        let before = Manifest {
+            target: "data.grib2".to_string(),
+            datetimes: vec![Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()],
+            size_bytes: 20,
+            files: vec!["https://h/tracks.bufr".to_string()],
+            fields: vec![field("2t", "0", 0), field("msl", "0", 10)],
+            remote_versions: BTreeMap::from([(
+                "https://h/a.grib2".to_string(),
+                RemoteVersion { last_modified: Some("Mon, 01 Jan 2024 08:00:00 GMT".to_string()), etag: None },
+            )]),
+        };
+        assert!(before.diff(&before).is_empty());
+
+        let mut resized = field("2t", "0", 0);
+        resized.length = 12;
+        let now = Manifest {
+            size_bytes: 22,
+            files: Vec::new(),
+            fields: vec![resized.clone(), field("msl", "6", 12)],
+            remote_versions: BTreeMap::from([(
+                "https://h/a.grib2".to_string(),
+                RemoteVersion { last_modified: Some("Tue, 02 Jan 2024 08:00:00 GMT".to_string()), etag: None },
+            )]),
+            ..before.clone()
+        };
+        let diff = now.diff(&before);
+        assert_eq!(diff.added, [field("msl", "6", 12)]);
+        assert_eq!(diff.removed, [field("msl", "0", 10)]);
+        assert_eq!(diff.resized, [(field("2t", "0", 0), resized)]);
+        assert_eq!(diff.removed_files, ["https://h/tracks.bufr"]);
+        assert!(diff.added_files.is_empty());
+        assert_eq!(diff.republished, ["https://h/a.grib2"]);
+        assert_eq!((diff.size_before, diff.size_after), (20, 22));
+        assert!(!diff.is_empty());
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::client::edit_distance;
+use crate::error::{Error, Result};
+use crate::request::Request;
+use crate::wave::WAVE_PARAMS;
+
+/// Single-level params of the IFS streams.
+const SURFACE: &[&str] = &[
+    "100u", "100v", "10fg", "10u", "10v", "2d", "2t", "asn", "cp", "ewss", "fdir", "hcc", "lcc", "lsm", "mcc",
+    "mn2t3", "msl", "mucape", "mx2t3", "nsss", "ptype", "ro", "sf", "sithick", "skt", "sp", "ssr", "ssrd", "str",
+    "strd", "sve", "svn", "tcc", "tcw", "tcwv", "tp", "tprate", "ttr", "z", "zos",
+];
+
+/// Soil params (`levtype=sol`).
+const SOIL: &[&str] = &["sot", "vsw"];
+
+/// Pressure-level params (`levtype=pl`).
+const PRESSURE: &[&str] = &["d", "gh", "q", "r", "t", "u", "v", "vo", "w"];
+
+/// Probabilities of the ensemble, published only for `type=ep`.
+const PROBABILITY_SURFACE: &[&str] = &[
+    "10fgg10", "10fgg15", "10fgg25", "2tag0", "2tag2", "2tal-2", "2tal0", "tpg1", "tpg5", "tpg10", "tpg20", "tpg25",
+    "tpg50", "tpg100",
+];
+const PROBABILITY_PRESSURE: &[&str] = &[
+    "ptsa_gt_1p5stdev", "ptsa_gt_1stdev", "ptsa_gt_2stdev", "ptsa_lt_1p5stdev", "ptsa_lt_1stdev", "ptsa_lt_2stdev",
+];
+
+/// Params published by the IFS in the open-data catalogue, per stream and
+/// levtype, used by [`Request::validate_params`] and
+/// [`crate::ClientOptions::strict_params`] to reject unknown params before
+/// any index is read.
+///
+/// The default catalogue covers the `ifs` model; requests for other models
+/// are not checked. Params given as numeric ids (`167`, `167.128`) are always
+/// accepted. Add params the catalogue lacks with [`ParamCatalog::insert`], or
+/// [`ParamCatalog::insert_for_type`] for params of a single type (like the
+/// `ep` probabilities).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamCatalog {
+    /// Keyed by stream, type (empty for every type) and levtype.
+    params: BTreeMap<(String, String, String), BTreeSet<String>>,
+}
+
+impl Default for ParamCatalog {
+    fn default() -> Self {
+        let mut c = Self::empty();
+        for stream in ["oper", "scda", "enfo"] {
+            c.insert(stream, "sfc", SURFACE.iter().copied());
+            c.insert(stream, "sol", SOIL.iter().copied());
+            c.insert(stream, "pl", PRESSURE.iter().copied());
+        }
+        c.insert_for_type("enfo", "ep", "sfc", PROBABILITY_SURFACE.iter().copied());
+        c.insert_for_type("enfo", "ep", "pl", PROBABILITY_PRESSURE.iter().copied());
+        for stream in ["wave", "scwv", "waef"] {
+            c.insert(stream, "sfc", WAVE_PARAMS);
+        }
+        c
+    }
+}
+
+impl ParamCatalog {
+    /// A catalogue without the built-in params.
+    pub fn empty() -> Self {
+        Self { params: BTreeMap::new() }
+    }
+
+    /// Add params to those of `stream` at `levtype`, for every type.
+    pub fn insert<S: Into<String>>(
+        &mut self,
+        stream: impl Into<String>,
+        levtype: impl Into<String>,
+        params: impl IntoIterator<Item = S>,
+    ) {
+        self.insert_for_type(stream, "", levtype, params);
+    }
+
+    /// Add params to those of `stream` at `levtype` published only for
+    /// `type` (every type if empty).
+    pub fn insert_for_type<S: Into<String>>(
+        &mut self,
+        stream: impl Into<String>,
+        r#type: impl Into<String>,
+        levtype: impl Into<String>,
+        params: impl IntoIterator<Item = S>,
+    ) {
+        self.params
+            .entry((stream.into(), r#type.into(), levtype.into()))
+            .or_default()
+            .extend(params.into_iter().map(Into::into));
+    }
+
+    pub fn streams(&self) -> Vec<&str> {
+        let streams: BTreeSet<_> = self.params.keys().map(|(s, _, _)| s.as_str()).collect();
+        streams.into_iter().collect()
+    }
+
+    /// Params of `stream` at `levtype` for `type`, including those published
+    /// for every type.
+    pub fn params(&self, stream: &str, r#type: &str, levtype: &str) -> Vec<&str> {
+        let params = self.select(&[stream.to_string()], &[r#type.to_string()], &[levtype.to_string()]);
+        params.into_iter().collect()
+    }
+
+    /// Params of the given streams (every stream if empty) at the given
+    /// levtypes (every levtype if empty), for the given types: params
+    /// published for a single type are only included when it is listed.
+    fn select(&self, streams: &[String], types: &[String], levtypes: &[String]) -> BTreeSet<&str> {
+        self.params
+            .iter()
+            .filter(|((s, t, l), _)| {
+                (streams.is_empty() || streams.contains(s))
+                    && (t.is_empty() || types.contains(t))
+                    && (levtypes.is_empty() || levtypes.contains(l))
+            })
+            .flat_map(|(_, params)| params.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Types whose single-type params at `streams` and `levtypes` include
+    /// `param`.
+    fn types_publishing(&self, param: &str, streams: &[String], levtypes: &[String]) -> BTreeSet<&str> {
+        self.params
+            .iter()
+            .filter(|((s, t, l), params)| {
+                !t.is_empty()
+                    && (streams.is_empty() || streams.contains(s))
+                    && (levtypes.is_empty() || levtypes.contains(l))
+                    && params.contains(param)
+            })
+            .map(|((_, t, _), _)| t.as_str())
+            .collect()
+    }
+
+    /// One message per param of `request` that its streams, types and
+    /// levtypes do not publish, with the closest known param or the streams
+    /// or types that do publish it. Requests for a model other than `ifs` are not checked.
+    pub fn unknown_params(&self, request: &Request) -> Vec<String> {
+        self.unknown_params_for(request, "ifs")
+    }
+
+    /// Like [`ParamCatalog::unknown_params`], for a request without a
+    /// `model` using `default_model`.
+    pub(crate) fn unknown_params_for(&self, request: &Request, default_model: &str) -> Vec<String> {
+        let strings = |key: &str| request.get(key).map(|v| v.as_strings()).unwrap_or_default();
+        let model = strings("model").into_iter().next().unwrap_or_else(|| default_model.to_string());
+        if model != "ifs" {
+            return Vec::new();
+        }
+        let known = self.streams();
+        let streams = strings("stream");
+        if streams.iter().any(|s| !known.contains(&s.as_str())) {
+            return Vec::new();
+        }
+        let types = strings("type");
+        let levtypes = strings("levtype");
+        let published = self.select(&streams, &types, &levtypes);
+
+        let mut out = Vec::new();
+        for param in strings("param") {
+            if published.contains(param.as_str()) || is_param_id(&param) {
+                continue;
+            }
+            let scope = match streams.as_slice() {
+                [] => String::new(),
+                streams => format!(" for stream {}", streams.join("/")),
+            };
+            let elsewhere: Vec<_> = known
+                .iter()
+                .filter(|s| self.select(&[s.to_string()], &types, &levtypes).contains(param.as_str()))
+                .copied()
+                .collect();
+            if !elsewhere.is_empty() {
+                out.push(format!("param `{param}` is not published{scope}; it is in {}", elsewhere.join(", ")));
+                continue;
+            }
+            let only_for: Vec<_> = self.types_publishing(&param, &streams, &levtypes).into_iter().collect();
+            if !only_for.is_empty() {
+                out.push(format!("param `{param}` is only published for type {}", only_for.join("/")));
+                continue;
+            }
+            if !levtypes.is_empty() && self.select(&streams, &types, &[]).contains(param.as_str()) {
+                out.push(format!("param `{param}` is not published at levtype {}", levtypes.join("/")));
+                continue;
+            }
+            let hint = published
+                .iter()
+                .map(|p| (edit_distance(&param, p), *p))
+                .filter(|(d, _)| *d <= 2)
+                .min()
+                .map(|(_, p)| format!("; did you mean `{p}`?"))
+                .unwrap_or_default();
+            out.push(format!("unknown param `{param}`{scope}{hint}"));
+        }
+        out
+    }
+}
+
+/// Whether `param` is a GRIB param id (`167`, `167.128`) rather than a
+/// short name.
+fn is_param_id(param: &str) -> bool {
+    !param.is_empty() && param.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+/// Fail on the first param of `request` the default [`ParamCatalog`] does
+/// not know.
+pub(crate) fn check_params(request: &Request, default_model: &str) -> Result<()> {
+    match ParamCatalog::default().unknown_params_for(request, default_model).into_iter().next() {
+        Some(message) => Err(Error::InvalidRequest(message)),
+        None => Ok(()),
+    }
+}
+
+impl Request {
+    /// Fail on params the IFS open-data catalogue does not publish for the
+    /// request's streams and levtypes (see [`ParamCatalog::default`]),
+    /// suggesting the closest known param, instead of finding no matching
+    /// index entries after reading the indexes.
+    pub fn validate_params(&self) -> Result<()> {
+        check_params(self, "ifs")
+    }
+
+    /// Like [`Request::validate_params`], against `catalog`.
+    pub fn validate_params_in(&self, catalog: &ParamCatalog) -> Result<()> {
+        match catalog.unknown_params(self).into_iter().next() {
+            Some(message) => Err(Error::InvalidRequest(message)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_params_are_reported_with_suggestions() {
+        let err = |req: Request| req.validate_params().unwrap_err().to_string();
+        assert!(Request::new().param(["2t", "msl", "167"]).validate_params().is_ok());
+        let enfo = || Request::new().stream("enfo").levtype("pl");
+        assert!(enfo().r#type("ep").param(["t", "ptsa_gt_1stdev"]).validate_params().is_ok());
+        assert!(err(enfo().r#type("pf").param("ptsa_gt_1stdev")).ends_with("is only published for type ep"));
+        assert!(err(Request::new().stream("enfo").r#type("cf").param("tpg1")).ends_with("published for type ep"));
+        assert!(Request::new().model("aifs-single").param("whatever").validate_params().is_ok());
+
+        assert!(err(Request::new().param("2tt")).ends_with("unknown param `2tt`; did you mean `2t`?"));
+        assert!(err(Request::new().stream("oper").param("mssl")).ends_with("for stream oper; did you mean `msl`?"));
+        assert!(err(Request::new().stream("oper").param("swh")).ends_with("it is in scwv, waef, wave"));
+        assert!(err(Request::new().levtype("pl").param("2t")).ends_with("`2t` is not published at levtype pl"));
+        assert!(err(Request::new().levtype("pl").param("tt")).ends_with("unknown param `tt`; did you mean `t`?"));
+
+        let mut catalog = ParamCatalog::empty();
+        catalog.insert("oper", "sfc", ["newparam"]);
+        assert!(Request::new().param("newparam").validate_params_in(&catalog).is_ok());
+        catalog.insert_for_type("oper", "fc", "sfc", ["fconly"]);
+        assert_eq!(catalog.params("oper", "fc", "sfc"), ["fconly", "newparam"]);
+        assert_eq!(catalog.params("oper", "cf", "sfc"), ["newparam"]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+use crate::request::{Request, RequestValue};
+
+/// Named bundles of params, expanded by [`Request::param_group`].
+///
+/// The default registry holds a few common bundles (`wind10m` → `10u`,
+/// `10v`, ...); add project-specific ones with [`ParamGroups::insert`] and use
+/// them through [`Request::param_group_in`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamGroups {
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for ParamGroups {
+    fn default() -> Self {
+        let mut g = Self::empty();
+        g.insert("wind10m", ["10u", "10v"]);
+        g.insert("wind100m", ["100u", "100v"]);
+        g.insert("wind", ["u", "v"]);
+        g.insert("surface", ["2t", "2d", "msl", "sp"]);
+        g.insert("radiation", ["ssrd", "strd"]);
+        g
+    }
+}
+
+impl ParamGroups {
+    /// A registry without the built-in groups.
+    pub fn empty() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Add or replace a group.
+    pub fn insert<S: Into<String>>(&mut self, name: impl Into<String>, params: impl IntoIterator<Item = S>) {
+        self.groups
+            .insert(name.into(), params.into_iter().map(Into::into).collect());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(name).map(|v| v.as_slice())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.groups.keys().map(|k| k.as_str())
+    }
+}
+
+impl Request {
+    /// Add the params of a built-in group (see [`ParamGroups::default`]) to
+    /// `param`, e.g. `param_group("wind10m")` for `10u` and `10v`.
+    pub fn param_group(self, name: &str) -> Result<Self> {
+        self.param_group_in(&ParamGroups::default(), name)
+    }
+
+    /// Add the params of group `name` from `groups` to `param`, keeping any
+    /// params already requested.
+    pub fn param_group_in(mut self, groups: &ParamGroups, name: &str) -> Result<Self> {
+        let Some(params) = groups.get(name) else {
+            return Err(Error::InvalidRequest(format!(
+                "unknown param group: {name} (known: {})",
+                groups.names().collect::<Vec<_>>().join(", ")
+            )));
+        };
+        let params = RequestValue::StrList(params.to_vec());
+        let merged = match self.get("param") {
+            Some(prev) => prev.clone().merge(params),
+            None => params,
+        };
+        self.set("param", merged);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_expand_into_param() {
+        let req = Request::new().param("msl").param_group("wind10m").unwrap();
+        assert_eq!(req.get("param").unwrap().as_strings(), ["msl", "10u", "10v"]);
+
+        let mut groups = ParamGroups::default();
+        groups.insert("fire", ["2t", "2d", "10u", "10v", "tp"]);
+        let req = Request::new().param_group_in(&groups, "fire").unwrap();
+        assert_eq!(req.get("param").unwrap().as_strings().len(), 5);
+
+        let err = Request::new().param_group("wind10").unwrap_err();
+        assert!(err.to_string().contains("wind10m"), "{err}");
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use crate::client::{
+    check_keywords, index_url_for, name_target, request_defaults, resolve_urls, select_index_entries, Client,
+    ClientOptions, MatchedField, Result,
+};
+use crate::error::{Error, Result as EResult};
+use crate::param_catalog::check_params;
+use crate::probe::ProbeOutcome;
+use crate::ranges::{plan_fetches, split_url_ranges};
+use crate::request::Request;
+use crate::sources::Source;
+
+/// What [`Client::retrieve_request`] would transfer for a request, resolved
+/// without downloading any data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadPlan {
+    /// Forecast base times the request resolved to, in ascending order (see
+    /// [`Result::datetimes`]).
+    pub datetimes: Vec<DateTime<Utc>>,
+    pub target: String,
+    /// Data files to fetch, in the order they are written to the target.
+    pub transfers: Vec<PlannedTransfer>,
+    /// Index entries selected, empty when whole files are fetched.
+    pub fields: Vec<MatchedField>,
+}
+
+/// One data file of a [`DownloadPlan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedTransfer {
+    pub url: String,
+    /// Inclusive byte ranges to request; empty for a whole-file download.
+    pub ranges: Vec<(u64, u64)>,
+    /// File extension of the payload (`grib2`, `bufr`, ...), from the URL.
+    pub extension: String,
+    /// Source the URL was resolved against (see [`Result::source`]).
+    pub source: Source,
+}
+
+impl PlannedTransfer {
+    /// Bytes selected by the ranges, or `None` for a whole file.
+    pub fn range_bytes(&self) -> Option<u64> {
+        if self.ranges.is_empty() {
+            return None;
+        }
+        Some(self.ranges.iter().map(|(start, end)| end - start + 1).sum())
+    }
+}
+
+/// How the selected fields of a request map onto HTTP range requests, as
+/// returned by [`Client::range_report`]. Useful to tune
+/// [`ClientOptions::max_requests_per_url`](crate::ClientOptions::max_requests_per_url):
+/// fewer requests per file trade latency for downloaded-but-discarded bytes.
+///
+/// `Display` renders a plain-text table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeReport {
+    /// Cycles of the planned request, in ascending order.
+    pub datetimes: Vec<DateTime<Utc>>,
+    /// Ranged data files, in download order. Whole-file transfers are omitted.
+    pub files: Vec<FileRanges>,
+}
+
+/// Range planning of one data file of a [`RangeReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRanges {
+    pub url: String,
+    /// `(offset, length)` of each selected index entry, in file order.
+    pub fields: Vec<(u64, u64)>,
+    /// Inclusive ranges left after merging adjacent fields.
+    pub merged: Vec<(u64, u64)>,
+    /// The HTTP range requests that will be sent.
+    pub requests: Vec<RangeRequest>,
+}
+
+/// One HTTP range request of a [`FileRanges`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeRequest {
+    /// Inclusive byte range requested.
+    pub start: u64,
+    pub end: u64,
+    /// Merged ranges it covers; the bytes between them are discarded.
+    pub parts: Vec<(u64, u64)>,
+}
+
+impl RangeRequest {
+    pub fn fetched_bytes(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Bytes downloaded only because they lie between two wanted ranges.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.fetched_bytes() - self.parts.iter().map(|(s, e)| e - s + 1).sum::<u64>()
+    }
+}
+
+impl FileRanges {
+    pub fn wanted_bytes(&self) -> u64 {
+        self.merged.iter().map(|(s, e)| e - s + 1).sum()
+    }
+
+    pub fn wasted_bytes(&self) -> u64 {
+        self.requests.iter().map(RangeRequest::wasted_bytes).sum()
+    }
+}
+
+impl RangeReport {
+    pub fn requests(&self) -> usize {
+        self.files.iter().map(|f| f.requests.len()).sum()
+    }
+
+    pub fn wanted_bytes(&self) -> u64 {
+        self.files.iter().map(FileRanges::wanted_bytes).sum()
+    }
+
+    pub fn wasted_bytes(&self) -> u64 {
+        self.files.iter().map(FileRanges::wasted_bytes).sum()
+    }
+}
+
+impl fmt::Display for RangeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cycles: Vec<String> = self.datetimes.iter().map(|d| d.format("%Y-%m-%d %H:%M").to_string()).collect();
+        let label = if cycles.len() == 1 { "Cycle" } else { "Cycles" };
+        writeln!(f, "{label} {}", cycles.join(", "))?;
+        for file in &self.files {
+            writeln!(
+                f,
+                "{}: {} field(s), {} merged range(s), {} request(s)",
+                file.url,
+                file.fields.len(),
+                file.merged.len(),
+                file.requests.len()
+            )?;
+            for r in &file.requests {
+                writeln!(
+                    f,
+                    "  bytes={}-{}  {} part(s)  fetched {}  wasted {}",
+                    r.start,
+                    r.end,
+                    r.parts.len(),
+                    r.fetched_bytes(),
+                    r.wasted_bytes()
+                )?;
+            }
+        }
+        write!(
+            f,
+            "Total: {} request(s), {} bytes wanted, {} bytes wasted",
+            self.requests(),
+            self.wanted_bytes(),
+            self.wasted_bytes()
+        )
+    }
+}
+
+/// Price and allowance of one source, for [`Client::plan_sources`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceCost {
+    pub source: Source,
+    /// Cost of one GB (10^9 bytes) downloaded, in any unit; `0.0` for free
+    /// egress.
+    pub cost_per_gb: f64,
+    /// Bytes that may be taken from this source by one plan; `None` is
+    /// unlimited.
+    pub max_bytes: Option<u64>,
+}
+
+impl SourceCost {
+    pub fn new(source: Source, cost_per_gb: f64) -> Self {
+        Self {
+            source,
+            cost_per_gb,
+            max_bytes: None,
+        }
+    }
+
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+}
+
+/// Which source each data file of a [`DownloadPlan`] is fetched from, as
+/// returned by [`Client::plan_sources`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourcePlan {
+    pub plan: DownloadPlan,
+    /// One entry per transfer of `plan`, in the same order.
+    pub assignments: Vec<SourceAssignment>,
+}
+
+/// One data file of a [`SourcePlan`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceAssignment {
+    pub source: Source,
+    /// Data URL on `source`.
+    pub url: String,
+    /// Bytes transferred: the selected ranges, or the whole file.
+    pub bytes: u64,
+    pub cost: f64,
+}
+
+impl SourcePlan {
+    pub fn total_cost(&self) -> f64 {
+        self.assignments.iter().map(|a| a.cost).sum()
+    }
+
+    /// Bytes taken from `source`.
+    pub fn bytes_from(&self, source: &Source) -> u64 {
+        self.assignments
+            .iter()
+            .filter(|a| &a.source == source)
+            .map(|a| a.bytes)
+            .sum()
+    }
+}
+
+impl Client {
+    /// Resolve the latest cycle, data URLs and (through the `.index` files)
+    /// byte ranges of a request without downloading data, so transfers can be
+    /// audited or scheduled. Uses the same index selection as
+    /// [`Client::retrieve_request`], including [`Request::force_full_files`].
+    ///
+    /// [`plan()`](fn@crate::plan) does the same from index content fetched
+    /// elsewhere.
+    pub fn plan(&self, request: Request) -> EResult<DownloadPlan> {
+        let use_index = !request.force_full_files;
+        DownloadPlan::from_result(self.get_urls(Some(&request), use_index, None)?)
+    }
+
+    /// Data file URLs of a request, whole (no `|range` encoding, no index
+    /// read), with [`ClientOptions::query_params`] and the Azure SAS token
+    /// appended as this client would send them, to hand to an external
+    /// downloader.
+    pub fn urls_for(&self, request: &Request) -> EResult<Vec<String>> {
+        let res = self.bounded().get_urls(Some(request), false, None)?;
+        Ok(res.urls.iter().map(|u| self.request_url(u)).collect())
+    }
+
+    /// Plan a request like [`Client::plan`] and report, per data file, the
+    /// selected fields, merged ranges and the range requests the current
+    /// options produce, with the bytes each request wastes.
+    pub fn range_report(&self, request: Request) -> EResult<RangeReport> {
+        let plan = self.plan(request)?;
+        Ok(range_report(&plan, self.opts.max_requests_per_url, self.opts.max_range_gap_bytes))
+    }
+
+    /// Plan a request like [`Client::plan`] and pick, per data file, the
+    /// cheapest of `costs` that has the file and whose `max_bytes` allowance
+    /// is not used up yet. Files are assigned in plan order, so with tight
+    /// allowances the result is a good assignment rather than the optimal one.
+    ///
+    /// The mirrors hold identical files, so the ranges read from this
+    /// client's indexes apply to every source, whether the plan was served
+    /// by `source` or the fallback source. Fails if some file is not
+    /// available from any source within its allowance.
+    pub fn plan_sources(&self, request: Request, costs: &[SourceCost]) -> EResult<SourcePlan> {
+        let plan = self.plan(request)?;
+
+        let mut costs: Vec<&SourceCost> = costs.iter().collect();
+        costs.sort_by(|a, b| a.cost_per_gb.total_cmp(&b.cost_per_gb));
+        let mut clients = Vec::with_capacity(costs.len());
+        for cost in &costs {
+            clients.push(if cost.source == self.opts.source {
+                self.clone()
+            } else {
+                Client::new(ClientOptions {
+                    source: cost.source.clone(),
+                    fallback_source: None,
+                    ..self.opts.clone()
+                })?
+            });
+        }
+        let mut used = vec![0u64; costs.len()];
+
+        let mut assignments = Vec::with_capacity(plan.transfers.len());
+        'transfers: for t in &plan.transfers {
+            // The plan may have been served by the fallback source.
+            let path = t.url.strip_prefix(t.source.base_url()).unwrap_or(&t.url);
+            let bytes = match t.range_bytes() {
+                Some(bytes) => bytes,
+                None => self.estimate_url_size(&t.url)?,
+            };
+            for (i, (cost, client)) in costs.iter().zip(&clients).enumerate() {
+                if cost.max_bytes.is_some_and(|max| used[i] + bytes > max) {
+                    continue;
+                }
+                let url = format!("{}{path}", cost.source.base_url());
+                if client.probe(&client.request_url(&url))? != ProbeOutcome::Exists {
+                    continue;
+                }
+                used[i] += bytes;
+                assignments.push(SourceAssignment {
+                    source: cost.source.clone(),
+                    url,
+                    bytes,
+                    cost: bytes as f64 / 1e9 * cost.cost_per_gb,
+                });
+                continue 'transfers;
+            }
+            return Err(Error::InvalidRequest(format!(
+                "{path} is not available from any source within its allowance"
+            )));
+        }
+
+        Ok(SourcePlan { plan, assignments })
+    }
+}
+
+/// Plan `request` like [`Client::plan`], from `.index` content fetched
+/// elsewhere rather than by a client: nothing is sent and no client state is
+/// used, so the same inputs always give the same plan.
+///
+/// `index_bytes` maps each URL of [`plan_index_urls`] to the body of that
+/// `.index` file. The request needs a `date`, since finding the latest cycle
+/// takes requests. Index files missing from `index_bytes` are an error,
+/// except for tropical cyclone tracks (BUFR), which have none and are planned
+/// as whole files.
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use ecmwf_opendata::{plan, plan_index_urls, ClientOptions, Request};
+///
+/// let opts = ClientOptions::default();
+/// let req = Request::new().date(20240101).time(0).step(6).param("2t");
+/// let mut indexes = BTreeMap::new();
+/// for url in plan_index_urls(&opts, &req)? {
+///     // Fetched by another component.
+///     let body = r#"{"type": "fc", "param": "2t", "step": "6", "_offset": 100, "_length": 50}"#;
+///     indexes.insert(url, body.as_bytes().to_vec());
+/// }
+/// let plan = plan(&opts, &req, &indexes)?;
+/// assert_eq!(plan.transfers[0].ranges, [(100, 149)]);
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+pub fn plan(
+    options: &ClientOptions,
+    request: &Request,
+    index_bytes: &BTreeMap<String, Vec<u8>>,
+) -> EResult<DownloadPlan> {
+    let mut res = resolve_offline(options, request)?;
+    if !request.force_full_files && !res.index_components.is_empty() {
+        let for_index = res.for_index();
+        let (mut urls, mut fields) = (Vec::new(), Vec::new());
+        for url in &res.urls {
+            let index_url = index_url_for(url);
+            let body = match index_bytes.get(&index_url) {
+                Some(bytes) => String::from_utf8_lossy(bytes),
+                None if options.is_track_file(url) => {
+                    urls.push(url.clone());
+                    continue;
+                }
+                None => return Err(Error::InvalidRequest(format!("no index content given for {index_url}"))),
+            };
+            if let Some((encoded, url_fields)) =
+                select_index_entries(options, url, &body, &for_index, &res.order_by, &mut res.warnings)?
+            {
+                urls.push(encoded);
+                fields.extend(url_fields);
+            }
+        }
+        if urls.is_empty() {
+            return Err(Error::NoMatchingIndex);
+        }
+        res.urls = urls;
+        res.fields = fields;
+    }
+    name_target(options, &mut res);
+    DownloadPlan::from_result(res)
+}
+
+/// URLs of the `.index` files [`plan`] needs for `request`, one per data
+/// file, without the query parameters or SAS token a client would append.
+pub fn plan_index_urls(options: &ClientOptions, request: &Request) -> EResult<Vec<String>> {
+    let res = resolve_offline(options, request)?;
+    Ok(res.urls.iter().map(|u| index_url_for(u)).collect())
+}
+
+/// Resolve `request` to data URLs as a client would, for a request with a
+/// `date`.
+fn resolve_offline(options: &ClientOptions, request: &Request) -> EResult<Result> {
+    if !request.force_full_files && request.is_unguarded_full_file() {
+        return Err(Error::FullFileRequest);
+    }
+    if options.strict_keywords {
+        check_keywords(request)?;
+    }
+    if options.strict_params {
+        check_params(request, &options.model)?;
+    }
+    let (model, params) = request_defaults(options, Some(request));
+    if !params.contains_key("date") {
+        return Err(Error::InvalidRequest(
+            "planning from index content needs a `date`; the latest cycle cannot be looked up".into(),
+        ));
+    }
+    let mut res = resolve_urls(options, options.source.base_url(), &model, &params, None, Vec::new())?;
+    res.order_by = request.order_by.clone();
+    Ok(res)
+}
+
+impl DownloadPlan {
+    fn from_result(res: Result) -> EResult<Self> {
+        let mut transfers = Vec::with_capacity(res.urls.len());
+        for u in &res.urls {
+            transfers.push(if u.contains('|') {
+                let (url, ranges) = split_url_ranges(u)?;
+                PlannedTransfer {
+                    url: url.to_string(),
+                    ranges,
+                    extension: url_extension(url),
+                    source: res.source.clone(),
+                }
+            } else {
+                PlannedTransfer {
+                    url: u.clone(),
+                    ranges: Vec::new(),
+                    extension: url_extension(u),
+                    source: res.source.clone(),
+                }
+            });
+        }
+
+        Ok(DownloadPlan {
+            datetimes: res.datetimes,
+            target: res.target,
+            transfers,
+            fields: res.fields,
+        })
+    }
+}
+
+/// Extension of the last path segment of a URL (query string ignored).
+fn url_extension(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.').map(|(_, ext)| ext.to_string()).unwrap_or_default()
+}
+
+fn range_report(plan: &DownloadPlan, max_requests_per_url: Option<usize>, max_range_gap_bytes: u64) -> RangeReport {
+    let files = plan
+        .transfers
+        .iter()
+        .filter(|t| !t.ranges.is_empty())
+        .map(|t| FileRanges {
+            url: t.url.clone(),
+            fields: plan
+                .fields
+                .iter()
+                .filter(|f| f.url == t.url)
+                .map(|f| (f.offset, f.length))
+                .collect(),
+            merged: t.ranges.clone(),
+            requests: plan_fetches(&t.ranges, max_requests_per_url, max_range_gap_bytes)
+                .into_iter()
+                .map(|f| RangeRequest {
+                    start: f.start,
+                    end: f.end,
+                    parts: f.parts,
+                })
+                .collect(),
+        })
+        .collect();
+
+    RangeReport {
+        datetimes: plan.datetimes.clone(),
+        files,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientOptions;
+    use crate::index::IndexEntry;
+
+    #[test]
+    fn whole_file_plan_lists_urls_without_ranges() {
+        let client = Client::new(ClientOptions::default()).unwrap();
+        let req = Request::new().date(20240101).time(0).step([0, 6]).force_full_files(true);
+        let plan = client.plan(req).unwrap();
+        assert_eq!(plan.transfers.len(), 2);
+        assert!(plan.transfers.iter().all(|t| t.range_bytes().is_none()));
+        assert!(plan.transfers.iter().all(|t| t.extension == "grib2"));
+        assert_eq!(plan.datetimes.len(), 1);
+        assert_eq!(plan.datetimes[0].format("%Y%m%d%H").to_string(), "2024010100");
+    }
+
+    #[test]
+    fn range_report_counts_wasted_bytes() {
+        let field = |offset, length| MatchedField {
+            param: "2t".to_string(),
+            levelist: None,
+            step: None,
+            number: None,
+            offset,
+            length,
+            url: "https://h/a.grib2".to_string(),
+            entry: IndexEntry::default(),
+        };
+        let plan = DownloadPlan {
+            datetimes: vec![Utc::now()],
+            target: "data.grib2".to_string(),
+            transfers: vec![PlannedTransfer {
+                url: "https://h/a.grib2".to_string(),
+                ranges: vec![(0, 19), (30, 39), (1000, 1009)],
+                extension: "grib2".to_string(),
+                source: Source::default(),
+            }],
+            fields: vec![field(0, 10), field(10, 10), field(30, 10), field(1000, 10)],
+        };
+
+        let report = range_report(&plan, Some(2), 0);
+        let file = &report.files[0];
+        assert_eq!(file.fields.len(), 4);
+        assert_eq!(file.requests.len(), 2);
+        assert_eq!((file.requests[0].start, file.requests[0].end), (0, 39));
+        assert_eq!(file.requests[0].wasted_bytes(), 10);
+        assert_eq!(report.wanted_bytes(), 40);
+        assert_eq!(report.wasted_bytes(), 10);
+        assert_eq!(report.requests(), 2);
+        assert!(report.to_string().ends_with("Total: 2 request(s), 40 bytes wanted, 10 bytes wasted"));
+
+        assert_eq!(range_report(&plan, None, 0).wasted_bytes(), 0);
+    }
+}
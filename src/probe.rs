@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Duration as TimeDelta, TimeZone, Timelike, Utc};
+
+use crate::client::{index_url_for, ClientOptions};
+
+/// How HTTP statuses seen while probing for a cycle (see [`crate::Client::latest`])
+/// are interpreted.
+///
+/// A status listed in `absent_statuses` means the file is definitely not there,
+/// so probing walks back to the previous cycle. A status listed in
+/// `unavailable_statuses` means the endpoint is temporarily unable to answer
+/// (throttling, overload); the probe is retried and, if that keeps happening,
+/// probing stops with [`crate::Error::ProbeUnavailable`] instead of silently
+/// returning an older cycle. Statuses in neither list are unexpected and are
+/// surfaced the same way as "unavailable" ones (without retrying).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbePolicy {
+    pub absent_statuses: Vec<u16>,
+    pub unavailable_statuses: Vec<u16>,
+    /// Extra attempts for a probe answered with an "unavailable" status.
+    pub unavailable_retries: u32,
+    /// Pause between those attempts.
+    pub retry_delay: Duration,
+}
+
+impl Default for ProbePolicy {
+    fn default() -> Self {
+        Self {
+            absent_statuses: vec![403, 404, 410],
+            unavailable_statuses: vec![429, 500, 502, 503, 504],
+            unavailable_retries: 2,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Which files are requested to decide whether a cycle is published (see
+/// [`crate::Client::latest`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProbeTarget {
+    /// Every data file of the request.
+    #[default]
+    DataFiles,
+    /// The `.index` sidecar of every data file: small files, so probes that
+    /// fall back to a GET (endpoints refusing `HEAD`) stay cheap. BUFR
+    /// tracks, which have no index, are probed directly.
+    IndexFiles,
+    /// Only the `.index` sidecar of the first data file: one request per
+    /// cycle, at the cost of accepting a cycle whose other files are not yet
+    /// published (use [`crate::Client::latest_complete`] to check them).
+    FirstIndex,
+}
+
+impl ProbeTarget {
+    /// URLs to probe for a cycle whose data files are `urls`.
+    pub(crate) fn urls(self, opts: &ClientOptions, urls: &[String]) -> Vec<String> {
+        let index = |u: &String| {
+            if opts.is_track_file(u) {
+                u.clone()
+            } else {
+                index_url_for(u)
+            }
+        };
+        match self {
+            ProbeTarget::DataFiles => urls.to_vec(),
+            ProbeTarget::IndexFiles => urls.iter().map(index).collect(),
+            ProbeTarget::FirstIndex => urls.first().map(index).into_iter().collect(),
+        }
+    }
+}
+
+impl std::str::FromStr for ProbeTarget {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> crate::error::Result<Self> {
+        match s {
+            "data_files" => Ok(ProbeTarget::DataFiles),
+            "index_files" => Ok(ProbeTarget::IndexFiles),
+            "first_index" => Ok(ProbeTarget::FirstIndex),
+            _ => Err(crate::error::Error::InvalidRequest(format!(
+                "unknown probe target: {s} (expected data_files, index_files or first_index)"
+            ))),
+        }
+    }
+}
+
+/// Outcome of probing a single URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProbeOutcome {
+    Exists,
+    Absent(u16),
+    Unavailable(u16),
+    Unexpected(u16),
+}
+
+impl ProbePolicy {
+    pub(crate) fn classify(&self, status: u16) -> ProbeOutcome {
+        if matches!(status, 200 | 206) {
+            ProbeOutcome::Exists
+        } else if self.absent_statuses.contains(&status) {
+            ProbeOutcome::Absent(status)
+        } else if self.unavailable_statuses.contains(&status) {
+            ProbeOutcome::Unavailable(status)
+        } else {
+            ProbeOutcome::Unexpected(status)
+        }
+    }
+}
+
+/// What to do when a request has no `date` and the latest cycle cannot be
+/// established because the endpoint is temporarily unavailable
+/// ([`crate::Error::ProbeUnavailable`] or a transport error).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatestFallback {
+    /// Fail the retrieval.
+    #[default]
+    Fail,
+    /// Assume the newest cycle that started at least `lag` ago by the clock
+    /// (keeping the requested `time`, if any) and record a warning in
+    /// [`crate::Result::warnings`]. Meant for best-effort display applications.
+    ClockCycle { lag: Duration },
+}
+
+impl LatestFallback {
+    /// Cycle to fall back to, or `None` when falling back is disabled.
+    pub(crate) fn cycle(&self, now: DateTime<Utc>, time_hour: Option<u32>) -> Option<DateTime<Utc>> {
+        let LatestFallback::ClockCycle { lag } = self else {
+            return None;
+        };
+        let reference = now - TimeDelta::from_std(*lag).unwrap_or(TimeDelta::zero());
+        let day = reference.date_naive();
+        match time_hour {
+            Some(hour) => {
+                let dt = Utc.from_utc_datetime(&day.and_hms_opt(hour, 0, 0)?);
+                Some(if dt > reference { dt - TimeDelta::days(1) } else { dt })
+            }
+            None => Some(Utc.from_utc_datetime(&day.and_hms_opt(reference.hour() / 6 * 6, 0, 0)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_statuses() {
+        let p = ProbePolicy::default();
+        assert_eq!(p.classify(200), ProbeOutcome::Exists);
+        assert_eq!(p.classify(206), ProbeOutcome::Exists);
+        assert_eq!(p.classify(404), ProbeOutcome::Absent(404));
+        assert_eq!(p.classify(429), ProbeOutcome::Unavailable(429));
+        assert_eq!(p.classify(418), ProbeOutcome::Unexpected(418));
+    }
+
+    #[test]
+    fn custom_policy_can_treat_403_as_unavailable() {
+        let p = ProbePolicy {
+            absent_statuses: vec![404, 503],
+            unavailable_statuses: vec![403],
+            ..ProbePolicy::default()
+        };
+        assert_eq!(p.classify(403), ProbeOutcome::Unavailable(403));
+        assert_eq!(p.classify(503), ProbeOutcome::Absent(503));
+    }
+
+    #[test]
+    fn probe_targets_pick_index_sidecars() {
+        let opts = ClientOptions::default();
+        let urls = ["https://h/a-0h-oper-fc.grib2".to_string(), "https://h/b-240h-oper-tf.bufr".to_string()];
+        assert_eq!(ProbeTarget::DataFiles.urls(&opts, &urls), urls);
+        assert_eq!(
+            ProbeTarget::IndexFiles.urls(&opts, &urls),
+            ["https://h/a-0h-oper-fc.index", "https://h/b-240h-oper-tf.bufr"]
+        );
+        assert_eq!(ProbeTarget::FirstIndex.urls(&opts, &urls), ["https://h/a-0h-oper-fc.index"]);
+
+        let mut opts = ClientOptions::default();
+        opts.type_extensions.insert("tf".to_string(), "bin".to_string());
+        let urls = ["https://h/a-0h-oper-fc.grib2".to_string(), "https://h/b-240h-oper-tf.bin".to_string()];
+        assert_eq!(ProbeTarget::IndexFiles.urls(&opts, &urls)[1], "https://h/b-240h-oper-tf.bin");
+        assert_eq!("first_index".parse::<ProbeTarget>().unwrap(), ProbeTarget::FirstIndex);
+    }
+
+    #[test]
+    fn clock_cycle_fallback_rounds_down_after_lag() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 2, 7, 30, 0).unwrap();
+        let lag = LatestFallback::ClockCycle {
+            lag: Duration::from_secs(8 * 3600),
+        };
+        assert_eq!(lag.cycle(now, None), Utc.with_ymd_and_hms(2024, 3, 1, 18, 0, 0).single());
+        assert_eq!(lag.cycle(now, Some(12)), Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).single());
+        assert_eq!(LatestFallback::Fail.cycle(now, None), None);
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::error::{Error, Result as EResult};
+use crate::sources::Source;
+
+/// Informal cap on the data a client downloads in one session, to stay under
+/// fair-use thresholds.
+///
+/// Data transfers of a [`crate::Client`] (or `AsyncClient`), its
+/// clones and its fallback source count towards the quota; probes and index
+/// fetches do not, nor do local mirrors. The quota is checked before each
+/// data request, so the transfer that crosses it completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub max_bytes: u64,
+    pub action: QuotaAction,
+}
+
+/// What happens once a [`Quota`] is used up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuotaAction {
+    /// Keep downloading, with a warning in [`crate::Result::warnings`].
+    #[default]
+    Warn,
+    /// Fail further data requests with [`Error::QuotaExceeded`].
+    Error,
+}
+
+impl std::str::FromStr for QuotaAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> EResult<Self> {
+        match s {
+            "warn" => Ok(QuotaAction::Warn),
+            "error" => Ok(QuotaAction::Error),
+            _ => Err(Error::Config(format!("unknown quota action `{s}` (expected warn or error)"))),
+        }
+    }
+}
+
+/// Bytes downloaded in a session, as reported by [`crate::Client::usage`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub total_bytes: u64,
+    /// Bytes per source, keyed by the source's name or URL.
+    pub per_source: BTreeMap<String, u64>,
+}
+
+/// Shared state accounting downloads against an optional [`Quota`].
+#[derive(Debug, Default)]
+pub(crate) struct UsageMeter {
+    quota: Option<Quota>,
+    usage: Mutex<Usage>,
+}
+
+impl UsageMeter {
+    pub(crate) fn new(quota: Option<Quota>) -> Self {
+        Self { quota, usage: Mutex::default() }
+    }
+
+    pub(crate) fn record(&self, source: &Source, bytes: u64) {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        usage.total_bytes += bytes;
+        *usage.per_source.entry(source.to_string()).or_default() += bytes;
+    }
+
+    pub(crate) fn snapshot(&self) -> Usage {
+        self.usage.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn reset(&self) {
+        *self.usage.lock().unwrap_or_else(|e| e.into_inner()) = Usage::default();
+    }
+
+    /// Bytes used, when they have reached the quota.
+    fn exceeded(&self) -> Option<(Quota, u64)> {
+        let quota = self.quota?;
+        let used = self.usage.lock().unwrap_or_else(|e| e.into_inner()).total_bytes;
+        (used >= quota.max_bytes).then_some((quota, used))
+    }
+
+    /// Refuse another data request once a [`QuotaAction::Error`] quota is
+    /// used up.
+    pub(crate) fn check(&self) -> EResult<()> {
+        match self.exceeded() {
+            Some((quota, used)) if quota.action == QuotaAction::Error => Err(Error::QuotaExceeded {
+                used_bytes: used,
+                max_bytes: quota.max_bytes,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Warning for a result downloaded once a [`QuotaAction::Warn`] quota is
+    /// used up.
+    pub(crate) fn warning(&self) -> Option<String> {
+        let (quota, used) = self.exceeded().filter(|(q, _)| q.action == QuotaAction::Warn)?;
+        Some(format!(
+            "session quota of {} bytes exceeded: {used} bytes downloaded",
+            quota.max_bytes
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_is_counted_per_source_against_the_quota() {
+        let meter = UsageMeter::new(Some(Quota { max_bytes: 100, action: QuotaAction::Error }));
+        meter.record(&Source::Ecmwf, 60);
+        assert!(meter.check().is_ok());
+        meter.record(&Source::Aws, 40);
+        assert!(matches!(meter.check(), Err(Error::QuotaExceeded { used_bytes: 100, max_bytes: 100 })));
+        assert_eq!(meter.warning(), None);
+        let usage = meter.snapshot();
+        assert_eq!(usage.total_bytes, 100);
+        assert_eq!(usage.per_source.len(), 2);
+
+        meter.reset();
+        assert!(meter.check().is_ok());
+
+        let meter = UsageMeter::new(Some(Quota { max_bytes: 10, action: QuotaAction::Warn }));
+        meter.record(&Source::Ecmwf, 10);
+        assert!(meter.check().is_ok());
+        assert!(meter.warning().unwrap().contains("10 bytes downloaded"));
+    }
+}
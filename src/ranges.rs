@@ -0,0 +1,212 @@
+use crate::error::{Error, Result as EResult};
+
+pub(crate) fn merge_ranges(mut matches: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    // input is (offset, length) -> convert to inclusive (start,end)
+    if matches.is_empty() {
+        return Vec::new();
+    }
+    if matches.len() == 1 {
+        let (o, l) = matches[0];
+        return vec![(o, o + l - 1)];
+    }
+
+    // Ensure sorted by offset.
+    matches.sort_by_key(|(o, _)| *o);
+
+    let mut out: Vec<(u64, u64)> = Vec::new();
+    for (o, l) in matches {
+        let start = o;
+        let end = o + l - 1;
+        if let Some(last) = out.last_mut()
+            && start <= last.1 + 1
+        {
+            last.1 = last.1.max(end);
+            continue;
+        }
+        out.push((start, end));
+    }
+    out
+}
+
+/// Like [`merge_ranges`], but keep the given order: only a range starting
+/// right after the previous one is merged into it.
+pub(crate) fn merge_ranges_in_order(matches: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut out: Vec<(u64, u64)> = Vec::new();
+    for (o, l) in matches {
+        let end = o + l - 1;
+        if let Some(last) = out.last_mut()
+            && o == last.1 + 1
+        {
+            last.1 = end;
+            continue;
+        }
+        out.push((o, end));
+    }
+    out
+}
+
+/// Encode inclusive ranges as `url|start-end;start-end;...`.
+pub(crate) fn encode_url_ranges(url: &str, ranges: &[(u64, u64)]) -> String {
+    let enc: Vec<String> = ranges.iter().map(|(start, end)| format!("{start}-{end}")).collect();
+    format!("{url}|{}", enc.join(";"))
+}
+
+pub(crate) fn split_url_ranges(s: &str) -> EResult<(&str, Vec<(u64, u64)>)> {
+    let Some((url, enc)) = s.split_once('|') else {
+        return Err(Error::InvalidRequest("expected ranged url encoding".into()));
+    };
+
+    let mut ranges = Vec::new();
+    for part in enc.split(';').filter(|p| !p.is_empty()) {
+        let Some((a, b)) = part.split_once('-') else {
+            return Err(Error::InvalidRequest(format!("bad range: {part}")));
+        };
+        let start: u64 = a.parse().map_err(|_| Error::InvalidRequest(format!("bad range: {part}")))?;
+        let end: u64 = b.parse().map_err(|_| Error::InvalidRequest(format!("bad range: {part}")))?;
+        if end < start {
+            return Err(Error::InvalidRequest(format!("bad range: {part}")));
+        }
+        ranges.push((start, end));
+    }
+
+    Ok((url, ranges))
+}
+
+/// Drop the first `skip` bytes (already written) from a sequence of inclusive
+/// ranges, decrementing `skip` by the amount consumed.
+pub(crate) fn skip_written(ranges: &[(u64, u64)], skip: &mut u64) -> Vec<(u64, u64)> {
+    let mut out = Vec::new();
+    for &(start, end) in ranges {
+        let len = end - start + 1;
+        if *skip >= len {
+            *skip -= len;
+            continue;
+        }
+        out.push((start + *skip, end));
+        *skip = 0;
+    }
+    out
+}
+
+/// One HTTP range request covering one or more wanted (inclusive) byte ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fetch {
+    pub start: u64,
+    pub end: u64,
+    pub parts: Vec<(u64, u64)>,
+}
+
+/// Group wanted ranges into range requests.
+///
+/// Neighbouring ranges at most `max_gap` bytes apart share a fetch. Beyond
+/// that, if `max_requests` is set, the two neighbouring fetches separated by
+/// the smallest gap are merged until the count fits. The gap bytes are
+/// downloaded but not written. Only ranges in ascending offset order can be
+/// merged, so the write order never changes.
+pub(crate) fn plan_fetches(ranges: &[(u64, u64)], max_requests: Option<usize>, max_gap: u64) -> Vec<Fetch> {
+    let mut fetches: Vec<Fetch> = Vec::with_capacity(ranges.len());
+    for &(start, end) in ranges {
+        if let Some(cur) = fetches.last_mut()
+            && start > cur.end
+            && start - cur.end - 1 <= max_gap
+        {
+            cur.end = end;
+            cur.parts.push((start, end));
+            continue;
+        }
+        fetches.push(Fetch {
+            start,
+            end,
+            parts: vec![(start, end)],
+        });
+    }
+
+    let Some(max) = max_requests else {
+        return fetches;
+    };
+    let max = max.max(1);
+
+    while fetches.len() > max {
+        let best = fetches
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[1].start > w[0].end)
+            .min_by_key(|(_, w)| w[1].start - w[0].end);
+        let Some((i, _)) = best else {
+            break;
+        };
+        let next = fetches.remove(i + 1);
+        let cur = &mut fetches[i];
+        cur.end = next.end;
+        cur.parts.extend(next.parts);
+    }
+
+    fetches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_already_written_bytes() {
+        let mut skip = 15;
+        assert_eq!(skip_written(&[(0, 9), (100, 109), (200, 209)], &mut skip), vec![(105, 109), (200, 209)]);
+        assert_eq!(skip, 0);
+
+        let mut skip = 25;
+        assert!(skip_written(&[(0, 9), (100, 109)], &mut skip).is_empty());
+        assert_eq!(skip, 5);
+    }
+
+    #[test]
+    fn merges_adjacent_ranges() {
+        assert_eq!(merge_ranges(vec![(0, 10), (10, 5), (100, 1)]), vec![(0, 14), (100, 100)]);
+    }
+
+    #[test]
+    fn in_order_merge_keeps_request_order() {
+        assert_eq!(
+            merge_ranges_in_order(vec![(100, 10), (0, 10), (10, 5), (110, 1)]),
+            vec![(100, 109), (0, 14), (110, 110)]
+        );
+    }
+
+    #[test]
+    fn encodes_and_splits_ranges() {
+        let enc = encode_url_ranges("https://h/a.grib2", &[(0, 9), (20, 29)]);
+        assert_eq!(enc, "https://h/a.grib2|0-9;20-29");
+        let (url, ranges) = split_url_ranges(&enc).unwrap();
+        assert_eq!(url, "https://h/a.grib2");
+        assert_eq!(ranges, vec![(0, 9), (20, 29)]);
+    }
+
+    #[test]
+    fn plan_fetches_without_budget_is_one_per_range() {
+        let f = plan_fetches(&[(0, 9), (20, 29)], None, 0);
+        assert_eq!(f.len(), 2);
+    }
+
+    #[test]
+    fn plan_fetches_merges_smallest_gaps_first() {
+        let f = plan_fetches(&[(0, 9), (20, 29), (1000, 1009), (1012, 1019)], Some(2), 0);
+        assert_eq!(f.len(), 2);
+        assert_eq!((f[0].start, f[0].end), (0, 29));
+        assert_eq!(f[0].parts, vec![(0, 9), (20, 29)]);
+        assert_eq!((f[1].start, f[1].end), (1000, 1019));
+    }
+
+    #[test]
+    fn plan_fetches_bridges_small_gaps() {
+        let f = plan_fetches(&[(0, 9), (20, 29), (1000, 1009), (1012, 1019)], None, 10);
+        assert_eq!(f.len(), 2);
+        assert_eq!((f[0].start, f[0].end), (0, 29));
+        assert_eq!(f[0].parts, vec![(0, 9), (20, 29)]);
+        assert_eq!(f[1].parts, vec![(1000, 1009), (1012, 1019)]);
+
+        let f = plan_fetches(&[(0, 9), (20, 29)], None, 9);
+        assert_eq!(f.len(), 2);
+        let f = plan_fetches(&[(100, 109), (0, 9)], None, 1000);
+        assert_eq!(f.len(), 2);
+    }
+}
@@ -0,0 +1,144 @@
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Client-side cap on the request rate and on concurrent requests.
+///
+/// Every HTTP request (probes, index fetches, range and whole-file GETs,
+/// including retries) takes a token from a bucket refilled at
+/// `requests_per_second` that holds up to `burst` tokens. `max_concurrent`
+/// bounds the requests whose responses are being read at the same time by a
+/// blocking [`crate::Client`] and its clones, across threads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    pub burst: u32,
+    pub max_concurrent: usize,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 10.0,
+            burst: 10,
+            max_concurrent: 8,
+        }
+    }
+}
+
+/// Shared state enforcing an optional [`RateLimit`].
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    policy: Option<RateLimit>,
+    bucket: Mutex<Bucket>,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// May go negative: each missing token is a request already waiting.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(policy: Option<RateLimit>) -> Self {
+        let burst = policy.as_ref().map_or(0.0, |p| p.burst.max(1) as f64);
+        Self {
+            policy,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled: Instant::now(),
+            }),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Take a token and return how long to wait before sending.
+    pub(crate) fn reserve(&self) -> Duration {
+        let Some(policy) = &self.policy else {
+            return Duration::ZERO;
+        };
+        let rate = policy.requests_per_second;
+        if rate <= 0.0 || !rate.is_finite() {
+            return Duration::ZERO;
+        }
+
+        let mut b = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(b.refilled).as_secs_f64();
+        b.tokens = (b.tokens + elapsed * rate).min(policy.burst.max(1) as f64);
+        b.refilled = now;
+        b.tokens -= 1.0;
+        if b.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-b.tokens / rate)
+        }
+    }
+
+    /// Wait until fewer than `max_concurrent` requests are in flight.
+    pub(crate) fn slot(&self) -> Slot<'_> {
+        let Some(policy) = &self.policy else {
+            return Slot { limiter: None };
+        };
+        let max = policy.max_concurrent.max(1);
+        let mut n = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        while *n >= max {
+            n = self.released.wait(n).unwrap_or_else(|e| e.into_inner());
+        }
+        *n += 1;
+        Slot { limiter: Some(self) }
+    }
+}
+
+/// One concurrent-request slot of a [`RateLimiter`], released on drop.
+pub(crate) struct Slot<'a> {
+    limiter: Option<&'a RateLimiter>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter {
+            *limiter.in_flight.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+            limiter.released.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_spent_then_paced() {
+        let limiter = RateLimiter::new(Some(RateLimit {
+            requests_per_second: 10.0,
+            burst: 2,
+            max_concurrent: 1,
+        }));
+        assert_eq!(limiter.reserve(), Duration::ZERO);
+        assert_eq!(limiter.reserve(), Duration::ZERO);
+        let third = limiter.reserve();
+        let fourth = limiter.reserve();
+        assert!(third > Duration::from_millis(80) && third <= Duration::from_millis(100), "{third:?}");
+        assert!(fourth > Duration::from_millis(180) && fourth <= Duration::from_millis(200), "{fourth:?}");
+
+        assert_eq!(RateLimiter::new(None).reserve(), Duration::ZERO);
+    }
+
+    #[test]
+    fn slots_are_released_on_drop() {
+        let limiter = RateLimiter::new(Some(RateLimit {
+            max_concurrent: 2,
+            ..RateLimit::default()
+        }));
+        let a = limiter.slot();
+        let _b = limiter.slot();
+        assert_eq!(*limiter.in_flight.lock().unwrap(), 2);
+        drop(a);
+        let _c = limiter.slot();
+        assert_eq!(*limiter.in_flight.lock().unwrap(), 2);
+    }
+}
@@ -1,5 +1,9 @@
 use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
 
+use chrono::{DateTime, Utc};
+
+use crate::date::{expand_date_value, expand_time_value};
 use crate::error::{Error, Result};
 
 /// Value type for a request keyword.
@@ -9,6 +13,8 @@ pub enum RequestValue {
     Int(i64),
     StrList(Vec<String>),
     IntList(Vec<i64>),
+    /// Heterogeneous list, e.g. `step = ["0-24", 36, 48]`.
+    ValueList(Vec<RequestValue>),
 }
 
 impl From<&str> for RequestValue {
@@ -77,6 +83,18 @@ impl<const N: usize> From<[String; N]> for RequestValue {
     }
 }
 
+impl From<Vec<RequestValue>> for RequestValue {
+    fn from(value: Vec<RequestValue>) -> Self {
+        RequestValue::ValueList(value)
+    }
+}
+
+impl<const N: usize> From<[RequestValue; N]> for RequestValue {
+    fn from(value: [RequestValue; N]) -> Self {
+        RequestValue::ValueList(value.into_iter().collect())
+    }
+}
+
 impl From<Vec<i64>> for RequestValue {
     fn from(value: Vec<i64>) -> Self {
         RequestValue::IntList(value)
@@ -175,29 +193,147 @@ impl RequestValue {
         }
     }
 
+    /// Combine two values for the same keyword into one list value.
+    ///
+    /// Integer values stay integers; anything else becomes a string list.
+    pub fn merge(self, other: RequestValue) -> RequestValue {
+        match (self.as_ints(), other.as_ints()) {
+            (Some(mut a), Some(b)) => {
+                a.extend(b);
+                RequestValue::IntList(a)
+            }
+            _ => {
+                let mut a = self.as_strings();
+                a.extend(other.as_strings());
+                RequestValue::StrList(a)
+            }
+        }
+    }
+
+    fn as_ints(&self) -> Option<Vec<i64>> {
+        match self {
+            RequestValue::Int(i) => Some(vec![*i]),
+            RequestValue::IntList(xs) => Some(xs.clone()),
+            RequestValue::ValueList(xs) => {
+                let mut out = Vec::new();
+                for x in xs {
+                    out.extend(x.as_ints()?);
+                }
+                Some(out)
+            }
+            _ => None,
+        }
+    }
+
     pub fn as_strings(&self) -> Vec<String> {
         match self {
             RequestValue::Str(s) => vec![s.clone()],
             RequestValue::Int(i) => vec![i.to_string()],
             RequestValue::StrList(xs) => xs.clone(),
             RequestValue::IntList(xs) => xs.iter().map(|x| x.to_string()).collect(),
+            RequestValue::ValueList(xs) => xs.iter().flat_map(|x| x.as_strings()).collect(),
         }
     }
 }
 
+/// Ensemble member selection for [`Request::members`].
+///
+/// The control forecast is `type=cf` (member 0) and perturbed members are
+/// `type=pf` with `number=1..=50`; both live in the same `ef` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Members {
+    Control,
+    Perturbed(RangeInclusive<u32>),
+    ControlPlus(RangeInclusive<u32>),
+}
+
+/// What [`Request::from_pairs_with`] does when a keyword appears more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateKeyPolicy {
+    /// Combine all values into one list (`param=2t`, `param=msl` → `[2t, msl]`).
+    #[default]
+    Merge,
+    KeepFirst,
+    KeepLast,
+    Error,
+}
+
 /// MARS-like request expressed as keyword/value pairs.
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Request {
     pub(crate) inner: BTreeMap<String, RequestValue>,
+    pub(crate) allow_full_files: bool,
+    pub(crate) force_full_files: bool,
+    pub(crate) order_by: Vec<String>,
+    pub(crate) fast_writes: bool,
 }
 
 impl Request {
     pub fn new() -> Self {
         Self {
             inner: BTreeMap::new(),
+            allow_full_files: false,
+            force_full_files: false,
+            order_by: Vec::new(),
+            fast_writes: false,
         }
     }
 
+    /// Confirm that whole files may be retrieved.
+    ///
+    /// Without `param` or `levelist`, index-based retrieval selects every field
+    /// of every file, which for ensemble products means tens of gigabytes.
+    /// Such requests are rejected with [`Error::FullFileRequest`] unless this
+    /// is set.
+    pub fn allow_full_files(mut self, allow: bool) -> Self {
+        self.allow_full_files = allow;
+        self
+    }
+
+    /// Download whole data files even through [`crate::Client::retrieve_request`],
+    /// skipping `.index` selection. Useful when every field of the files is
+    /// needed anyway, since it avoids fetching the indexes.
+    pub fn force_full_files(mut self, force: bool) -> Self {
+        self.force_full_files = force;
+        self
+    }
+
+    /// Keyword precedence for ordering fields within a file when
+    /// [`crate::ClientOptions::preserve_request_order`] is set, e.g.
+    /// `order_by(["param", "step"])` writes all steps of the first param
+    /// before the next param. Keywords not listed follow in the default
+    /// precedence.
+    pub fn order_by<S: Into<String>>(mut self, keys: impl IntoIterator<Item = S>) -> Self {
+        self.order_by = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Write the target through a large in-memory buffer instead of range by
+    /// range, for throwaway scratch downloads on fast local disks with many
+    /// small ranges.
+    ///
+    /// Not crash-consistent: data already reported by
+    /// [`crate::DownloadEvent::BytesWritten`] may still be in the buffer, so a
+    /// process killed mid-download leaves a shorter target than reported.
+    /// Errors are still reported, and the buffer is flushed before the call
+    /// returns.
+    pub fn fast_writes(mut self, fast: bool) -> Self {
+        self.fast_writes = fast;
+        self
+    }
+
+    /// Whether retrieving this request would select whole files without an
+    /// explicit opt-in.
+    pub(crate) fn is_unguarded_full_file(&self) -> bool {
+        let is_tracks = self
+            .get("type")
+            .is_some_and(|v| v.as_strings().iter().all(|t| t == "tf"));
+        !self.allow_full_files
+            && !is_tracks
+            && self.get("param").is_none()
+            && self.get("levelist").is_none()
+    }
+
     /// Insert a keyword/value pair (value can be a scalar or list).
     pub fn kw(mut self, key: impl Into<String>, value: impl Into<RequestValue>) -> Self {
         self.inner.insert(key.into(), value.into());
@@ -205,6 +341,8 @@ impl Request {
     }
 
     /// Construct a request from an iterator of keyword/value pairs.
+    ///
+    /// Repeated keywords are merged into a list (see [`DuplicateKeyPolicy::Merge`]).
     pub fn from_pairs<K, V>(pairs: impl IntoIterator<Item = (K, V)>) -> Self
     where
         K: Into<String>,
@@ -212,13 +350,43 @@ impl Request {
     {
         let mut r = Self::new();
         for (k, v) in pairs {
-            r = r.kw(k, v);
+            r.merge_kw(k.into(), v.into());
         }
         r
     }
 
+    /// Construct a request from pairs, handling repeated keywords according to `policy`.
+    pub fn from_pairs_with<K, V>(
+        pairs: impl IntoIterator<Item = (K, V)>,
+        policy: DuplicateKeyPolicy,
+    ) -> Result<Self>
+    where
+        K: Into<String>,
+        V: Into<RequestValue>,
+    {
+        let mut r = Self::new();
+        for (k, v) in pairs {
+            let k = k.into();
+            let v = v.into();
+            match policy {
+                DuplicateKeyPolicy::Merge => r.merge_kw(k, v),
+                DuplicateKeyPolicy::KeepFirst => {
+                    r.inner.entry(k).or_insert(v);
+                }
+                DuplicateKeyPolicy::KeepLast => r.set(k, v),
+                DuplicateKeyPolicy::Error => {
+                    if r.inner.contains_key(&k) {
+                        return Err(Error::InvalidRequest(format!("duplicate keyword: {k}")));
+                    }
+                    r.set(k, v);
+                }
+            }
+        }
+        Ok(r)
+    }
+
     /// Construct a request from string pairs (typical for GUI/config inputs).
-    /// Values are parsed with [`RequestValue::parse_auto`].
+    /// Values are parsed with [`RequestValue::parse_auto`]; repeated keywords are merged.
     pub fn from_str_pairs<K, V>(pairs: impl IntoIterator<Item = (K, V)>) -> Self
     where
         K: Into<String>,
@@ -226,11 +394,38 @@ impl Request {
     {
         let mut r = Self::new();
         for (k, v) in pairs {
-            r = r.kw(k, RequestValue::parse_auto(v.as_ref()));
+            r.merge_kw(k.into(), RequestValue::parse_auto(v.as_ref()));
         }
         r
     }
 
+    /// Construct a request from a JSON object shaped like a request dict of
+    /// the Python client, e.g. one saved with `json.dump`:
+    /// `{"type": "fc", "step": [0, 6], "param": "2t/msl", "levelist": [[1000, 850], 500]}`.
+    ///
+    /// Strings are kept as written (slash lists and `to`/`by` ranges are
+    /// expanded later, as for any request), integers stay integers and lists
+    /// may nest. Other values (floats, booleans, `null`, objects) and empty
+    /// lists are rejected.
+    pub fn from_json_value(value: serde_json::Value) -> Result<Self> {
+        let serde_json::Value::Object(map) = value else {
+            return Err(Error::InvalidRequest(format!("a request must be a JSON object, got {value}")));
+        };
+        let mut r = Self::new();
+        for (k, v) in &map {
+            r.set(k.clone(), json_request_value(k, v)?);
+        }
+        Ok(r)
+    }
+
+    fn merge_kw(&mut self, key: String, value: RequestValue) {
+        let merged = match self.inner.remove(&key) {
+            Some(prev) => prev.merge(value),
+            None => value,
+        };
+        self.inner.insert(key, merged);
+    }
+
     pub fn insert(mut self, key: impl Into<String>, value: RequestValue) -> Self {
         self.inner.insert(key.into(), value);
         self
@@ -281,6 +476,27 @@ impl Request {
         self.kw("number", v)
     }
 
+    /// Select ensemble members, setting `type` and `number` consistently.
+    ///
+    /// `members(Members::ControlPlus(1..=50))` requests the control forecast
+    /// plus 50 perturbed members.
+    pub fn members(mut self, members: Members) -> Self {
+        match members {
+            Members::Control => {
+                self.remove("number");
+                self.kw("type", "cf")
+            }
+            Members::Perturbed(range) => self
+                .kw("type", "pf")
+                .kw("number", range.collect::<Vec<u32>>()),
+            Members::ControlPlus(range) => {
+                let mut numbers = vec![0];
+                numbers.extend(range);
+                self.kw("type", ["cf", "pf"]).kw("number", numbers)
+            }
+        }
+    }
+
     pub fn model(self, v: impl Into<RequestValue>) -> Self {
         self.kw("model", v)
     }
@@ -301,6 +517,22 @@ impl Request {
         self.inner.remove(key);
     }
 
+    /// Values of every keyword after the expansions applied when resolving
+    /// URLs (slash lists, `a/to/b/by/c` ranges, relative dates), e.g. to
+    /// preview `step: 0, 6, 12, …, 240` before submitting. Values that fail to
+    /// expand are kept as given; resolving the request reports the error.
+    pub fn expanded(&self) -> BTreeMap<String, Vec<String>> {
+        let now = Utc::now();
+        self.inner
+            .iter()
+            .map(|(k, v)| {
+                let values: Vec<String> = v.as_strings().iter().flat_map(|x| split_slash_list(x)).collect();
+                let expanded = expand_keyword_values(k, values.clone(), now).unwrap_or(values);
+                (k.clone(), expanded)
+            })
+            .collect()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&String, &RequestValue)> {
         self.inner.iter()
     }
@@ -310,13 +542,19 @@ impl Request {
     }
 
     pub(crate) fn from_inner(inner: BTreeMap<String, RequestValue>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            allow_full_files: false,
+            force_full_files: false,
+            order_by: Vec::new(),
+            fast_writes: false,
+        }
     }
 }
 
 #[cfg(test)]
 mod parse_tests {
-    use super::{Request, RequestValue};
+    use super::{DuplicateKeyPolicy, Members, Request, RequestValue};
 
     #[test]
     fn parse_auto_int_and_string() {
@@ -351,6 +589,164 @@ mod parse_tests {
         assert_eq!(r.get("step"), Some(&RequestValue::IntList(vec![12, 24, 36])));
         assert_eq!(r.get("param"), Some(&RequestValue::Str("msl".to_string())));
     }
+
+    #[test]
+    fn from_json_value_takes_python_request_dicts() {
+        let dict = serde_json::json!({
+            "time": 0,
+            "type": ["cf", "pf"],
+            "step": [[0, 6], "12-24", 36],
+            "levelist": [1000, 850],
+            "param": "2t/msl",
+            "date": -1,
+        });
+        let r = Request::from_json_value(dict).unwrap();
+        assert_eq!(r.get("time"), Some(&RequestValue::Int(0)));
+        assert_eq!(r.get("levelist"), Some(&RequestValue::IntList(vec![1000, 850])));
+        assert_eq!(r.get("type").unwrap().as_strings(), ["cf", "pf"]);
+        assert_eq!(r.get("step").unwrap().as_strings(), ["0", "6", "12-24", "36"]);
+        assert_eq!(r.get("param"), Some(&RequestValue::Str("2t/msl".to_string())));
+        assert_eq!(r.get("date"), Some(&RequestValue::Int(-1)));
+
+        let err = |v| Request::from_json_value(v).unwrap_err().to_string();
+        assert!(err(serde_json::json!(["step", 0])).contains("JSON object"));
+        assert!(err(serde_json::json!({"step": 1.5})).contains("`step` must be an integer"));
+        assert!(err(serde_json::json!({"step": []})).contains("empty list"));
+        assert!(err(serde_json::json!({"number": [1, null]})).contains("`number` must be"));
+    }
+
+    #[test]
+    fn mixed_lists_flatten_to_strings() {
+        let v = RequestValue::from(["0-24".into(), 36.into(), RequestValue::from(vec![48, 60])]);
+        assert_eq!(v.as_strings(), vec!["0-24", "36", "48", "60"]);
+
+        let r = Request::new().step(vec![RequestValue::from(0), "6".into()]);
+        assert_eq!(r.get("step").unwrap().as_strings(), vec!["0", "6"]);
+    }
+
+    #[test]
+    fn duplicate_keywords_follow_policy() {
+        let pairs = [("param", "2t"), ("step", "0"), ("param", "msl")];
+
+        let r = Request::from_pairs(pairs);
+        assert_eq!(
+            r.get("param"),
+            Some(&RequestValue::StrList(vec!["2t".to_string(), "msl".to_string()]))
+        );
+
+        let r = Request::from_pairs_with(pairs, DuplicateKeyPolicy::KeepFirst).unwrap();
+        assert_eq!(r.get("param"), Some(&RequestValue::Str("2t".to_string())));
+
+        let r = Request::from_pairs_with(pairs, DuplicateKeyPolicy::KeepLast).unwrap();
+        assert_eq!(r.get("param"), Some(&RequestValue::Str("msl".to_string())));
+
+        assert!(Request::from_pairs_with(pairs, DuplicateKeyPolicy::Error).is_err());
+
+        let r = Request::from_pairs([("step", 0), ("step", 6)]);
+        assert_eq!(r.get("step"), Some(&RequestValue::IntList(vec![0, 6])));
+    }
+
+    #[test]
+    fn full_file_requests_need_opt_in() {
+        assert!(Request::new().step(240).is_unguarded_full_file());
+        assert!(!Request::new().step(240).allow_full_files(true).is_unguarded_full_file());
+        assert!(!Request::new().param("msl").is_unguarded_full_file());
+        assert!(!Request::new().r#type("tf").is_unguarded_full_file());
+    }
+
+    #[test]
+    fn members_sets_type_and_number() {
+        let r = Request::new().number(5).members(Members::Control);
+        assert_eq!(r.get("type"), Some(&RequestValue::Str("cf".to_string())));
+        assert_eq!(r.get("number"), None);
+
+        let r = Request::new().members(Members::ControlPlus(1..=3));
+        assert_eq!(
+            r.get("type"),
+            Some(&RequestValue::StrList(vec!["cf".to_string(), "pf".to_string()]))
+        );
+        assert_eq!(r.get("number"), Some(&RequestValue::IntList(vec![0, 1, 2, 3])));
+    }
+}
+
+/// One value of a Python request dict (see [`Request::from_json_value`]).
+fn json_request_value(key: &str, value: &serde_json::Value) -> Result<RequestValue> {
+    use serde_json::Value;
+
+    match value {
+        Value::String(s) => Ok(RequestValue::Str(s.clone())),
+        Value::Number(n) => n
+            .as_i64()
+            .map(RequestValue::Int)
+            .ok_or_else(|| Error::InvalidRequest(format!("`{key}` must be an integer, got {n}"))),
+        Value::Array(items) if items.is_empty() => Err(Error::InvalidRequest(format!("`{key}` is an empty list"))),
+        Value::Array(items) => {
+            if let Some(ints) = items.iter().map(Value::as_i64).collect::<Option<Vec<_>>>() {
+                return Ok(RequestValue::IntList(ints));
+            }
+            if let Some(strs) = items.iter().map(|v| v.as_str().map(str::to_string)).collect::<Option<Vec<_>>>() {
+                return Ok(RequestValue::StrList(strs));
+            }
+            Ok(RequestValue::ValueList(
+                items.iter().map(|v| json_request_value(key, v)).collect::<Result<_>>()?,
+            ))
+        }
+        _ => Err(Error::InvalidRequest(format!(
+            "`{key}` must be a string, integer or list, got {value}"
+        ))),
+    }
+}
+
+/// Split a slash-separated list (`"2t/msl"`) into its elements.
+///
+/// Range syntax (`"0/to/120/by/6"`) is kept whole for later expansion, and an
+/// escaped slash (`\/`) is kept as a literal `/` inside an element.
+pub fn split_slash_list(s: &str) -> Vec<String> {
+    if !s.contains('/') {
+        return vec![s.to_string()];
+    }
+    if s.split('/').nth(1).is_some_and(|t| t.eq_ignore_ascii_case("to")) {
+        return vec![s.to_string()];
+    }
+
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'/') => {
+                chars.next();
+                cur.push('/');
+            }
+            '/' => {
+                if !cur.is_empty() {
+                    out.push(std::mem::take(&mut cur));
+                }
+            }
+            _ => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        out.push(cur);
+    }
+    out
+}
+
+/// Expand the values of one keyword the way URL building does: `date`
+/// values (relative days, ranges), `time` values and numeric `a/to/b/by/c`
+/// ranges for `step`, `fcmonth`, `number` and `levelist`.
+pub(crate) fn expand_keyword_values(key: &str, values: Vec<String>, now: DateTime<Utc>) -> Result<Vec<String>> {
+    let expand: fn(&str, DateTime<Utc>) -> Result<Vec<String>> = match key {
+        "date" => expand_date_value,
+        "time" => |x, _| expand_time_value(x),
+        "step" | "fcmonth" | "number" | "levelist" => |x, _| expand_numeric_syntax(x),
+        _ => return Ok(values),
+    };
+    let mut out = Vec::new();
+    for x in values {
+        out.extend(expand(&x, now)?);
+    }
+    Ok(out)
 }
 
 /// Expand a list-like value, accepting strings like "0/to/120/by/6".
@@ -414,6 +810,23 @@ pub fn expand_numeric_syntax(s: &str) -> Result<Vec<String>> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn expanded_previews_ranges() {
+        let r = Request::new().step("0/to/12/by/6").param("2t/msl").kw("levelist", "1/to/x");
+        let e = r.expanded();
+        assert_eq!(e["step"], vec!["0", "6", "12"]);
+        assert_eq!(e["param"], vec!["2t", "msl"]);
+        assert_eq!(e["levelist"], vec!["1/to/x"]);
+    }
+
+    #[test]
+    fn splits_slash_lists() {
+        assert_eq!(split_slash_list("2t/msl"), vec!["2t", "msl"]);
+        assert_eq!(split_slash_list("0/to/12/by/6"), vec!["0/to/12/by/6"]);
+        assert_eq!(split_slash_list("a\\/b/c"), vec!["a/b", "c"]);
+        assert_eq!(split_slash_list("msl"), vec!["msl"]);
+    }
+
     #[test]
     fn expands_single_value() {
         assert_eq!(expand_numeric_syntax("0").unwrap(), vec!["0"]);
@@ -0,0 +1,89 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+/// Retries of transient failures: connection errors, timeouts and the
+/// `statuses` below (5xx answers the portal tends to give during publication
+/// windows).
+///
+/// Applies to probes, `.index` fetches and data downloads. The pause before
+/// retry `n` (starting at 0) is `base_delay * 2^n`, capped at `max_delay`, plus
+/// a random extra of up to `jitter` so that parallel clients do not retry in
+/// lockstep. Throttling statuses are handled separately by
+/// [`crate::ThrottlePolicy`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries of a single request; `0` disables retrying.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: Duration,
+    pub statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(20),
+            jitter: Duration::from_millis(250),
+            statuses: vec![500, 502, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn is_retryable_status(&self, status: u16) -> bool {
+        self.statuses.contains(&status)
+    }
+
+    pub(crate) fn is_retryable_error(&self, e: &reqwest::Error) -> bool {
+        e.is_connect() || e.is_timeout()
+    }
+
+    /// Pause before retry `attempt` (0-based).
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        backoff + self.jitter.mul_f64(random_unit())
+    }
+}
+
+/// Pseudo-random number in `[0, 1)`, good enough to spread retries.
+fn random_unit() -> f64 {
+    (RandomState::new().hash_one(0u8) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_and_is_capped() {
+        let p = RetryPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            jitter: Duration::ZERO,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(p.delay(0), Duration::from_secs(1));
+        assert_eq!(p.delay(2), Duration::from_secs(4));
+        assert_eq!(p.delay(10), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn jitter_stays_within_bound() {
+        let p = RetryPolicy {
+            base_delay: Duration::from_secs(1),
+            jitter: Duration::from_millis(100),
+            ..RetryPolicy::default()
+        };
+        for _ in 0..20 {
+            let d = p.delay(0);
+            assert!(d >= Duration::from_secs(1) && d < Duration::from_millis(1100));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use crate::client::{index_matches, index_url_for, name_target, ordered_index_keys, Client, MatchedField, Result};
+use crate::error::{Error, Result as EResult};
+use crate::index::IndexEntry;
+use crate::ranges::{encode_url_ranges, merge_ranges};
+use crate::request::{Request, RequestValue};
+
+/// Data URL → fields matched for one target.
+type FieldsByUrl = BTreeMap<String, Vec<MatchedField>>;
+
+/// Send the index entries matching some conditions to their own target file.
+///
+/// ```no_run
+/// use ecmwf_opendata::{Client, ClientOptions, Request, Route};
+///
+/// let client = Client::new(ClientOptions::default())?;
+/// let req = Request::new().param(["2t", "msl", "t"]).levelist(850).step(24);
+/// let results = client.retrieve_routed(
+///     req,
+///     &[
+///         Route::new("surface.grib2").when("param", ["2t", "msl"]),
+///         Route::new("pl.grib2").when_present("levelist"),
+///     ],
+/// )?;
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub target: String,
+    conditions: Vec<(String, Condition)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    AnyOf(Vec<String>),
+    Present,
+}
+
+impl Route {
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Require index keyword `key` to have one of `values`.
+    pub fn when(mut self, key: impl Into<String>, values: impl Into<RequestValue>) -> Self {
+        self.conditions
+            .push((key.into(), Condition::AnyOf(values.into().as_strings())));
+        self
+    }
+
+    /// Require index keyword `key` to be present (e.g. `levelist` for pressure levels).
+    pub fn when_present(mut self, key: impl Into<String>) -> Self {
+        self.conditions.push((key.into(), Condition::Present));
+        self
+    }
+
+    pub(crate) fn matches(&self, entry: &IndexEntry) -> bool {
+        self.conditions.iter().all(|(k, c)| match (c, entry.get(k)) {
+            (Condition::AnyOf(values), Some(v)) => values.iter().any(|x| x == v),
+            (Condition::Present, v) => v.is_some(),
+            _ => false,
+        })
+    }
+}
+
+impl Client {
+    /// Retrieve a request into several targets in one pass over the indexes.
+    ///
+    /// Each matched field goes to the first route whose conditions it meets;
+    /// fields matching no route go to the request's own target. One [`Result`]
+    /// is returned per target that received fields, in route order. Fields are
+    /// written in file order (`preserve_request_order` does not apply). A
+    /// [`crate::ClientOptions::target_namer`] names each target from the
+    /// route's target and fields.
+    pub fn retrieve_routed(&self, request: Request, routes: &[Route]) -> EResult<Vec<Result>> {
+        if request.is_unguarded_full_file() {
+            return Err(Error::FullFileRequest);
+        }
+        let mut base = self.get_unnamed_urls(Some(&request), false, None)?;
+        let for_index = base.for_index();
+        let ordered_keys = ordered_index_keys(&for_index, &[]);
+
+        let mut per_target: Vec<(String, FieldsByUrl)> = routes
+            .iter()
+            .map(|r| (r.target.clone(), BTreeMap::new()))
+            .collect();
+        per_target.push((base.target.clone(), BTreeMap::new()));
+
+        for url in &base.urls {
+            let Some(body) = self.fetch_index_body(url)? else {
+                continue;
+            };
+            let entries: Vec<IndexEntry> = IndexEntry::parse(&body)?
+                .into_iter()
+                .filter(|e| index_matches(e, &ordered_keys, &for_index))
+                .collect();
+            for entry in self.opts.duplicate_fields.apply(&index_url_for(url), entries, &mut base.warnings)? {
+                let slot = routes.iter().position(|r| r.matches(&entry)).unwrap_or(routes.len());
+                per_target[slot]
+                    .1
+                    .entry(url.clone())
+                    .or_default()
+                    .push(MatchedField::from_index_entry(url, &entry));
+            }
+        }
+
+        let mut results: Vec<Result> = Vec::new();
+        for (target, by_url) in per_target {
+            if by_url.is_empty() {
+                continue;
+            }
+            let mut res = base.clone();
+            res.target = target;
+            res.urls.clear();
+            res.fields.clear();
+            for u in &base.urls {
+                let Some(fields) = by_url.get(u) else {
+                    continue;
+                };
+                let parts = fields.iter().map(|f| (f.offset, f.length)).collect();
+                res.urls.push(encode_url_ranges(u, &merge_ranges(parts)));
+                let mut fields = fields.clone();
+                fields.sort_by_key(|f| f.offset);
+                res.fields.extend(fields);
+            }
+            name_target(&self.opts, &mut res);
+
+            // Several routes may share a target: append to what was written already.
+            let append = results.iter().any(|r| r.target == res.target);
+            results.push(self.download_result(&res, true, append)?);
+        }
+
+        if results.is_empty() {
+            return Err(Error::NoMatchingIndex);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_match_on_values_and_presence() {
+        let sfc = Route::new("surface.grib2").when("param", ["2t", "msl"]);
+        let pl = Route::new("pl.grib2").when_present("levelist");
+
+        let e2t = IndexEntry::parse_line(r#"{"param": "2t", "levtype": "sfc", "_offset": 0, "_length": 1}"#).unwrap();
+        let et = IndexEntry::parse_line(r#"{"param": "t", "levelist": "850", "_offset": 1, "_length": 1}"#).unwrap();
+        assert!(sfc.matches(&e2t));
+        assert!(!sfc.matches(&et));
+        assert!(pl.matches(&et));
+        assert!(!pl.matches(&e2t));
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::error::{Error, Result};
+
+/// An Azure SAS token and its expiry (`msft:expiry` in the Planetary
+/// Computer token response).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SasToken {
+    pub token: String,
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+impl SasToken {
+    pub(crate) fn from_response(v: &serde_json::Value) -> Result<Self> {
+        let token = v
+            .get("token")
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| Error::InvalidRequest("invalid sas token response".into()))?;
+        let expiry = v
+            .get("msft:expiry")
+            .and_then(|x| x.as_str())
+            .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+            .map(|x| x.with_timezone(&Utc));
+        Ok(Self {
+            token: token.to_string(),
+            expiry,
+        })
+    }
+
+    /// Whether the token should be replaced before being used at `now`:
+    /// less than five minutes of validity left. Tokens without a known
+    /// expiry are kept until a request is refused.
+    pub(crate) fn is_expiring(&self, now: DateTime<Utc>) -> bool {
+        self.expiry.is_some_and(|expiry| expiry - now < Duration::minutes(5))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn parses_token_and_expiry() {
+        let v = serde_json::json!({"msft:expiry": "2024-01-01T12:00:00Z", "token": "sv=1&sig=x"});
+        let t = SasToken::from_response(&v).unwrap();
+        assert_eq!(t.token, "sv=1&sig=x");
+        assert_eq!(t.expiry, Some(Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap()));
+
+        assert!(!t.is_expiring(Utc.with_ymd_and_hms(2024, 1, 1, 11, 50, 0).unwrap()));
+        assert!(t.is_expiring(Utc.with_ymd_and_hms(2024, 1, 1, 11, 56, 0).unwrap()));
+        assert!(!SasToken::from_response(&serde_json::json!({"token": "sig=x"}))
+            .unwrap()
+            .is_expiring(Utc::now()));
+        assert!(SasToken::from_response(&serde_json::json!({})).is_err());
+    }
+}
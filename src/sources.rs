@@ -1,17 +1,133 @@
-/// Built-in base URLs (same values as upstream ecmwf-opendata).
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::Duration;
+use url::Url;
+
+use crate::error::{Error, Result};
+
+/// Where data is downloaded from.
 ///
-/// If `source` is already an `http(s)` URL, it is used as-is.
-pub fn source_to_base_url(source: &str) -> Option<&'static str> {
-    match source {
-        "ecmwf" => Some("https://data.ecmwf.int/forecasts"),
-        "azure" => Some("https://ai4edataeuwest.blob.core.windows.net/ecmwf"),
-        "aws" => Some("https://ecmwf-forecasts.s3.eu-central-1.amazonaws.com"),
-        "google" => Some("https://storage.googleapis.com/ecmwf-open-data"),
-        "ecmwf-esuites" => Some("https://xdiss.ecmwf.int/ecpds/home/opendata"),
-        _ => None,
+/// The built-in mirrors use the same base URLs as upstream ecmwf-opendata.
+/// Strings are accepted through [`FromStr`] (`"ecmwf"`, `"azure"`, `"aws"`,
+/// `"google"`, `"ecmwf-esuites"`, an `http(s)` base URL, a `file://` URL or
+/// the path of an existing directory), so an unknown name is rejected when
+/// parsed instead of when the first download fails.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Source {
+    #[default]
+    Ecmwf,
+    Azure,
+    Aws,
+    Google,
+    /// ECMWF e-suite: the next model version, run in parallel with the
+    /// operational one and published in the same layout. Cycles may be
+    /// missing and are kept for a shorter time; pair it with
+    /// [`crate::ClientOptions::fallback_source`] to fall back to operational
+    /// data.
+    Esuites,
+    Custom(Url),
+    /// Local mirror directory in the open-data layout, as a `file://` URL.
+    /// Indexes and data are read from disk, byte ranges included.
+    Local(Url),
+}
+
+impl Source {
+    /// Base URL that data paths are appended to (without trailing slash).
+    pub fn base_url(&self) -> &str {
+        match self {
+            Source::Ecmwf => "https://data.ecmwf.int/forecasts",
+            Source::Azure => "https://ai4edataeuwest.blob.core.windows.net/ecmwf",
+            Source::Aws => "https://ecmwf-forecasts.s3.eu-central-1.amazonaws.com",
+            Source::Google => "https://storage.googleapis.com/ecmwf-open-data",
+            Source::Esuites => "https://xdiss.ecmwf.int/ecpds/home/opendata",
+            Source::Custom(url) | Source::Local(url) => url.as_str().trim_end_matches('/'),
+        }
+    }
+
+    /// How far back published cycles are kept, and so how far back
+    /// [`crate::Client::latest`] looks. The e-suite (pre-operational test
+    /// suite) keeps fewer cycles than the operational mirrors.
+    pub fn retention(&self) -> Duration {
+        match self {
+            Source::Esuites => Duration::days(2),
+            _ => Duration::days(5),
+        }
+    }
+
+    /// A local mirror rooted at `dir`, which must exist.
+    pub fn local(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().canonicalize()?;
+        Url::from_directory_path(&dir)
+            .map(Source::Local)
+            .map_err(|_| Error::InvalidRequest(format!("not a local directory: {}", dir.display())))
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ecmwf" => Ok(Source::Ecmwf),
+            "azure" => Ok(Source::Azure),
+            "aws" => Ok(Source::Aws),
+            "google" => Ok(Source::Google),
+            "ecmwf-esuites" => Ok(Source::Esuites),
+            _ if is_http_url(s) => Ok(Source::Custom(Url::parse(s)?)),
+            _ if s.starts_with("file://") => Ok(Source::Local(Url::parse(s)?)),
+            _ if Path::new(s).is_dir() => Source::local(s),
+            _ => Err(Error::InvalidRequest(format!(
+                "unknown source: {s} (expected ecmwf, azure, aws, google, ecmwf-esuites, \
+                 an http(s) or file:// URL, or a local directory)"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Ecmwf => f.write_str("ecmwf"),
+            Source::Azure => f.write_str("azure"),
+            Source::Aws => f.write_str("aws"),
+            Source::Google => f.write_str("google"),
+            Source::Esuites => f.write_str("ecmwf-esuites"),
+            Source::Custom(url) | Source::Local(url) => f.write_str(url.as_str()),
+        }
     }
 }
 
 pub fn is_http_url(s: &str) -> bool {
     s.starts_with("http://") || s.starts_with("https://")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_sources_and_urls() {
+        assert_eq!("aws".parse::<Source>().unwrap(), Source::Aws);
+        assert_eq!("ecmwf-esuites".parse::<Source>().unwrap().to_string(), "ecmwf-esuites");
+
+        let custom: Source = "https://mirror.example.org/opendata/".parse().unwrap();
+        assert_eq!(custom.base_url(), "https://mirror.example.org/opendata");
+    }
+
+    #[test]
+    fn parses_local_mirrors() {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Source = dir.path().to_str().unwrap().parse().unwrap();
+        assert!(matches!(source, Source::Local(_)));
+        assert!(source.base_url().starts_with("file:///"));
+        assert!(!source.base_url().ends_with('/'));
+        assert_eq!(source.to_string().parse::<Source>().unwrap(), source);
+    }
+
+    #[test]
+    fn rejects_typos() {
+        assert!("gogle".parse::<Source>().is_err());
+    }
+}
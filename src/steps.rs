@@ -0,0 +1,54 @@
+use crate::wave::{is_wave_stream, wave_steps};
+
+/// Every 3h to `three_hourly`, then every 6h to `six_hourly`.
+fn ladder(three_hourly: u32, six_hourly: u32) -> Vec<u32> {
+    (0..=three_hourly)
+        .step_by(3)
+        .chain((three_hourly + 6..=six_hourly).step_by(6))
+        .collect()
+}
+
+/// Steps of the files of `stream` and `typ` that the open-data catalogue of
+/// `model` publishes at cycle hour `hour`, in order; empty when the product
+/// is not published at that hour. `stream` is the stream of the file (`scda`
+/// rather than `oper` at 06/18z). Probabilities (`ep`) and tropical cyclone
+/// tracks (`tf`) are single files named after their horizon.
+pub(crate) fn published_steps(model: &str, stream: &str, typ: &str, hour: u32) -> Vec<u32> {
+    let main_run = hour.is_multiple_of(12);
+    match (model, stream, typ) {
+        ("aifs-single" | "aifs-ens", _, _) => (0..=360).step_by(6).collect(),
+        (_, _, "tf") if main_run => vec![240],
+        (_, "enfo", "tf") => vec![144],
+        (_, _, "tf") => vec![90],
+        (_, _, "ep") if main_run => vec![360],
+        (_, _, "ep") => Vec::new(),
+        (_, s, _) if is_wave_stream(s) => wave_steps(s, hour),
+        (_, _, "em" | "es") if main_run => (0..=360).step_by(12).collect(),
+        (_, _, "em" | "es") => (0..=144).step_by(12).collect(),
+        (_, "enfo", _) if main_run => ladder(144, 360),
+        (_, "enfo", _) => ladder(144, 144),
+        _ if main_run => ladder(144, 240),
+        _ => ladder(90, 90),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_follow_the_product_and_run() {
+        let last = |stream, typ, hour| published_steps("ifs", stream, typ, hour).last().copied();
+        assert_eq!(last("oper", "fc", 0), Some(240));
+        assert_eq!(last("scda", "fc", 6), Some(90));
+        assert_eq!(last("enfo", "pf", 12), Some(360));
+        assert_eq!(last("enfo", "pf", 18), Some(144));
+        assert_eq!(last("enfo", "em", 6), Some(144));
+        assert_eq!(last("waef", "cf", 6), Some(144));
+        assert_eq!(published_steps("ifs", "enfo", "ep", 0), [360]);
+        assert!(published_steps("ifs", "enfo", "ep", 6).is_empty());
+        assert_eq!(published_steps("ifs", "oper", "tf", 12), [240]);
+        assert_eq!(published_steps("ifs", "oper", "fc", 0)[48..50], [144, 150]);
+        assert_eq!(published_steps("aifs-single", "oper", "fc", 6).len(), 61);
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::client::{write_fetch_parts, Client, Result};
+use crate::error::Result as EResult;
+use crate::ranges::{plan_fetches, split_url_ranges};
+use crate::request::Request;
+
+/// Data of a retrieval, handed out while later ranges are still downloading.
+/// Returned by [`Client::retrieve_stream`].
+///
+/// Each item holds one or more complete GRIB/BUFR messages, in the order
+/// [`Client::retrieve_request`] would write them. After an error the stream
+/// ends. Dropping the stream stops the download at the next chunk.
+#[derive(Debug)]
+pub struct RetrieveStream {
+    result: Result,
+    rx: Receiver<EResult<Bytes>>,
+}
+
+impl RetrieveStream {
+    /// The resolved request (cycle, URLs, selected fields). `size_bytes` is
+    /// not filled in.
+    pub fn result(&self) -> &Result {
+        &self.result
+    }
+}
+
+impl Iterator for RetrieveStream {
+    type Item = EResult<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+impl Client {
+    /// Like [`Client::retrieve_request`], but yield the data instead of
+    /// writing it to the target, so decoding can overlap with the download.
+    ///
+    /// A background thread downloads ahead of the consumer by at most
+    /// [`ClientOptions::stream_buffer`](crate::ClientOptions::stream_buffer)
+    /// chunks. The request is resolved (latest cycle, index selection) before
+    /// this returns.
+    pub fn retrieve_stream(&self, request: Request) -> EResult<RetrieveStream> {
+        let use_index = !request.force_full_files;
+        let result = self.get_urls(Some(&request), use_index, None)?;
+
+        let (tx, rx) = sync_channel(self.opts.stream_buffer.max(1));
+        let client = self.clone();
+        let urls = result.urls.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = client.produce(&urls, use_index, &tx) {
+                let _ = tx.send(Err(e));
+            }
+        });
+
+        Ok(RetrieveStream { result, rx })
+    }
+
+    /// Download `urls` in order into `tx`. Stops quietly once the receiver
+    /// is gone.
+    fn produce(&self, urls: &[String], is_partial: bool, tx: &SyncSender<EResult<Bytes>>) -> EResult<()> {
+        for u in urls {
+            if is_partial && u.contains('|') {
+                let (data_url, ranges) = split_url_ranges(u)?;
+                let fetches = plan_fetches(&ranges, self.opts.max_requests_per_url, self.opts.max_range_gap_bytes);
+                // Download a batch of ranges in parallel, then hand it over
+                // before starting the next one.
+                for batch in fetches.chunks(self.opts.max_concurrent_ranges.max(1)) {
+                    for (fetch, transfer) in batch.iter().zip(self.fetch_ranges(data_url, batch)?) {
+                        let mut buf = Vec::new();
+                        write_fetch_parts(&mut buf, data_url, fetch, &transfer.bytes)?;
+                        if tx.send(Ok(buf.into())).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            } else {
+                let transfer = self.fetch_data(u, None)?;
+                let bytes = Arc::try_unwrap(transfer.bytes).unwrap_or_else(|shared| (*shared).clone());
+                if tx.send(Ok(bytes.into())).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::client::MatchedField;
+use crate::components::UrlComponents;
+
+/// Paths at least this long need the extended-length prefix on Windows.
+#[cfg_attr(not(windows), allow(dead_code))]
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Filesystem path to open for a target.
+///
+/// On Windows, `/` separators are normalised to `\`, and paths whose
+/// absolute form reaches `MAX_PATH` are given the `\\?\` prefix, so long
+/// target names work without enabling long-path support system-wide.
+/// Elsewhere the target is used as given.
+pub(crate) fn target_path(target: &str) -> PathBuf {
+    #[cfg(windows)]
+    {
+        let path = target.replace('/', "\\");
+        if path.starts_with(r"\\?\") {
+            return PathBuf::from(path);
+        }
+        // The limit applies to the path Windows resolves a relative one to.
+        let absolute = std::path::absolute(&path)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.clone());
+        if !reaches_max_path(&absolute) {
+            return PathBuf::from(path);
+        }
+        PathBuf::from(windows_extended_path(&absolute))
+    }
+    #[cfg(not(windows))]
+    PathBuf::from(target)
+}
+
+/// Where targets are placed relative to the path given for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Write the target where it is named.
+    #[default]
+    Flat,
+    /// Treat the target as the root of a local mirror: each data file is
+    /// written below it at its upstream path relative to the source root,
+    /// under its upstream name, so `out` receives
+    /// `out/20240101/00z/ifs/0p25/oper/20240101000000-0h-oper-fc.grib2`.
+    /// The root defaults to the current directory. Missing directories are
+    /// created.
+    EcmwfTree,
+}
+
+/// Path below `root` of the data file at `url`: its path relative to the
+/// first of `base_urls` it is under, or only its file name when it is under
+/// none of them.
+pub(crate) fn tree_path(root: &str, url: &str, base_urls: &[&str]) -> String {
+    let url = url.split(['?', '|']).next().unwrap_or(url);
+    let relative = base_urls
+        .iter()
+        .find_map(|base| url.strip_prefix(base.trim_end_matches('/'))?.strip_prefix('/'))
+        .unwrap_or_else(|| url.rsplit('/').next().unwrap_or(url));
+    Path::new(root).join(relative).to_string_lossy().into_owned()
+}
+
+/// What a [`TargetNamer`] knows about the target it names.
+#[derive(Debug, Clone, Copy)]
+pub struct TargetContext<'a> {
+    /// Target given for the request, with its extension, before any
+    /// [`Layout`].
+    pub target: &'a str,
+    /// Cycle of the data.
+    pub datetime: DateTime<Utc>,
+    /// First data URL, without ranges.
+    pub url: &'a str,
+    /// URL keywords of the request (`model`, `resol`, `stream`, `type`,
+    /// `step`, ...), as used to build the URLs.
+    pub components: &'a UrlComponents,
+    /// Fields the target will hold; empty when the index files were not
+    /// read (full-file downloads, [`crate::Client::dry_run`] without index).
+    pub fields: &'a [MatchedField],
+}
+
+/// Names targets from their cycle, URL and fields, for archive naming
+/// conventions a fixed path or [`Layout`] cannot express. Set in
+/// [`crate::ClientOptions::target_namer`], it replaces the layout; missing
+/// directories of the returned path are created.
+///
+/// Closures taking a [`TargetContext`] are namers:
+///
+/// ```
+/// use std::sync::Arc;
+/// use ecmwf_opendata::{ClientOptions, TargetContext};
+///
+/// let opts = ClientOptions {
+///     target_namer: Some(Arc::new(|ctx: &TargetContext<'_>| {
+///         let params: Vec<_> = ctx.fields.iter().map(|f| f.param.as_str()).collect();
+///         format!("archive/{}/{}.grib2", ctx.datetime.format("%Y/%m/%d/%H"), params.join("_"))
+///     })),
+///     ..ClientOptions::default()
+/// };
+/// # let _ = opts;
+/// ```
+pub trait TargetNamer: Send + Sync {
+    fn name(&self, ctx: &TargetContext<'_>) -> String;
+}
+
+impl<F> TargetNamer for F
+where
+    F: Fn(&TargetContext<'_>) -> String + Send + Sync,
+{
+    fn name(&self, ctx: &TargetContext<'_>) -> String {
+        self(ctx)
+    }
+}
+
+impl fmt::Debug for dyn TargetNamer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TargetNamer(..)")
+    }
+}
+
+impl std::str::FromStr for Layout {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> crate::error::Result<Self> {
+        match s {
+            "flat" => Ok(Layout::Flat),
+            "ecmwf_tree" => Ok(Layout::EcmwfTree),
+            _ => Err(crate::error::Error::InvalidRequest(format!(
+                "unknown layout: {s} (expected flat or ecmwf_tree)"
+            ))),
+        }
+    }
+}
+
+/// Create the missing parent directories of `path`.
+pub(crate) fn create_parent_dirs(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => std::fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `absolute` is too long for Windows APIs without the `\\?\`
+/// prefix; `MAX_PATH` counts UTF-16 code units, not bytes.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn reaches_max_path(absolute: &str) -> bool {
+    absolute.encode_utf16().count() >= WINDOWS_MAX_PATH
+}
+
+/// `\\?\` form of an absolute Windows path (`\\?\UNC\...` for shares).
+#[cfg_attr(not(windows), allow(dead_code))]
+fn windows_extended_path(absolute: &str) -> String {
+    if absolute.starts_with(r"\\?\") {
+        absolute.to_string()
+    } else if let Some(share) = absolute.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{share}")
+    } else {
+        format!(r"\\?\{absolute}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_paths_for_drives_and_shares() {
+        assert_eq!(windows_extended_path(r"C:\data\a.grib2"), r"\\?\C:\data\a.grib2");
+        assert_eq!(windows_extended_path(r"\\srv\share\a.grib2"), r"\\?\UNC\srv\share\a.grib2");
+        assert_eq!(windows_extended_path(r"\\?\C:\a.grib2"), r"\\?\C:\a.grib2");
+
+        // 269 bytes of UTF-8, but 139 UTF-16 code units.
+        let accented = format!(r"C:\{}.grib2", "é".repeat(130));
+        assert!(!reaches_max_path(&accented));
+        assert!(reaches_max_path(&format!(r"C:\{}.grib2", "a".repeat(251))));
+    }
+
+    #[test]
+    fn ecmwf_tree_mirrors_the_remote_directories() {
+        let base = "https://data.ecmwf.int/forecasts";
+        let file = "20240101/00z/ifs/0p25/oper/20240101000000-0h-oper-fc.grib2";
+        let url = format!("{base}/{file}|0-9");
+        assert_eq!(tree_path("out", &url, &[base]), Path::new("out").join(file).to_string_lossy());
+        assert_eq!(
+            tree_path("out", &url, &["https://mirror.example", &format!("{base}/")]),
+            Path::new("out").join(file).to_string_lossy()
+        );
+        assert_eq!(
+            tree_path(".", &url, &["https://mirror.example"]),
+            Path::new(".").join("20240101000000-0h-oper-fc.grib2").to_string_lossy()
+        );
+        assert_eq!("ecmwf_tree".parse::<Layout>().unwrap(), Layout::EcmwfTree);
+        assert!("tree".parse::<Layout>().is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn targets_are_kept_elsewhere() {
+        assert_eq!(target_path("out/data.grib2"), PathBuf::from("out/data.grib2"));
+    }
+}
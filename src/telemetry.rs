@@ -0,0 +1,79 @@
+//! Download metrics reported through the `metrics` crate facade (feature
+//! `metrics`); without the feature every function here is a no-op. The
+//! application installs the recorder (Prometheus exporter, StatsD, ...).
+//!
+//! | Name | Kind | Labels |
+//! |------|------|--------|
+//! | `ecmwf_opendata_requests_total` | counter | `source`, `status` (`error` when no response) |
+//! | `ecmwf_opendata_request_duration_seconds` | histogram | `source` |
+//! | `ecmwf_opendata_range_requests_total` | counter | `source` |
+//! | `ecmwf_opendata_downloaded_bytes_total` | counter | `source` |
+//! | `ecmwf_opendata_retries_total` | counter | `source`, `reason` |
+
+use std::time::Duration;
+
+use crate::sources::Source;
+
+/// Why a request was sent again.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum RetryReason {
+    /// Connection error or timeout.
+    Transport,
+    /// Retryable HTTP status (see [`crate::RetryPolicy`]).
+    Status,
+    /// Throttling status (see [`crate::ThrottlePolicy`]).
+    Throttled,
+    /// "Unavailable" answer to a probe (see [`crate::ProbePolicy`]).
+    Unavailable,
+    /// SAS token refreshed after a 403.
+    Resigned,
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+impl RetryReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            RetryReason::Transport => "transport",
+            RetryReason::Status => "status",
+            RetryReason::Throttled => "throttled",
+            RetryReason::Unavailable => "unavailable",
+            RetryReason::Resigned => "resigned",
+        }
+    }
+}
+
+/// An HTTP request answered with `status` (`None` if it failed) after
+/// `latency` (time to the response headers).
+#[cfg(feature = "metrics")]
+pub(crate) fn request(source: &Source, status: Option<u16>, latency: Duration) {
+    let status = status.map_or_else(|| "error".to_string(), |s| s.to_string());
+    ::metrics::counter!("ecmwf_opendata_requests_total", "source" => source.to_string(), "status" => status)
+        .increment(1);
+    ::metrics::histogram!("ecmwf_opendata_request_duration_seconds", "source" => source.to_string())
+        .record(latency.as_secs_f64());
+}
+
+/// A data transfer of `bytes`, ranged or not.
+#[cfg(feature = "metrics")]
+pub(crate) fn transfer(source: &Source, ranged: bool, bytes: usize) {
+    if ranged {
+        ::metrics::counter!("ecmwf_opendata_range_requests_total", "source" => source.to_string()).increment(1);
+    }
+    ::metrics::counter!("ecmwf_opendata_downloaded_bytes_total", "source" => source.to_string())
+        .increment(bytes as u64);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn retry(source: &Source, reason: RetryReason) {
+    ::metrics::counter!("ecmwf_opendata_retries_total", "source" => source.to_string(), "reason" => reason.as_str())
+        .increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn request(_source: &Source, _status: Option<u16>, _latency: Duration) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn transfer(_source: &Source, _ranged: bool, _bytes: usize) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn retry(_source: &Source, _reason: RetryReason) {}
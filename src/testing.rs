@@ -0,0 +1,244 @@
+//! Local HTTP server serving fixture cycles in the open-data layout, so
+//! retrieval logic can be tested without network access (feature `testing`).
+//!
+//! The server answers `HEAD`, `GET` and single-range `GET` requests for the
+//! files below a temporary directory, and records the requests it receives.
+//! Helpers panic on I/O errors, as befits test code.
+//!
+//! ```
+//! use ecmwf_opendata::testing::{field_bytes, FixtureServer};
+//! use ecmwf_opendata::{Client, Request};
+//!
+//! let server = FixtureServer::start();
+//! server.add_cycle("20240101", 0, &[0, 6], &["2t", "msl"]);
+//! let client = Client::new(server.options())?;
+//! let target = server.target("msl.grib2");
+//! let result = client.retrieve_request(
+//!     Request::new()
+//!         .date("20240101")
+//!         .time(0)
+//!         .step(6)
+//!         .param("msl")
+//!         .target(target.to_string_lossy().as_ref()),
+//! )?;
+//! assert_eq!(std::fs::read(&result.target)?, field_bytes("msl", 6));
+//! # Ok::<(), ecmwf_opendata::Error>(())
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::client::ClientOptions;
+use crate::sources::Source;
+use tiny_http::{Header, Method, Response, Server};
+
+/// One request received by a [`FixtureServer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seen {
+    pub method: String,
+    pub path: String,
+    pub range: Option<String>,
+    pub user_agent: Option<String>,
+    /// Every header of the request, names in lowercase.
+    pub headers: Vec<(String, String)>,
+}
+
+/// Serves the files below a temporary directory, answering `HEAD`, `GET` and
+/// single-range `GET` requests like the open-data mirrors do.
+pub struct FixtureServer {
+    pub root: tempfile::TempDir,
+    server: Arc<Server>,
+    seen: Arc<Mutex<Vec<Seen>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FixtureServer {
+    pub fn start() -> Self {
+        let root = tempfile::tempdir().unwrap();
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let dir = root.path().to_path_buf();
+        let (srv, log) = (server.clone(), seen.clone());
+        let handle = std::thread::spawn(move || {
+            for req in srv.incoming_requests() {
+                let path = req.url().split('?').next().unwrap_or_default().to_string();
+                let range = req
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Range"))
+                    .map(|h| h.value.to_string());
+                let user_agent = req
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("User-Agent"))
+                    .map(|h| h.value.to_string());
+                let headers = req
+                    .headers()
+                    .iter()
+                    .map(|h| (h.field.as_str().as_str().to_ascii_lowercase(), h.value.to_string()))
+                    .collect();
+                log.lock().unwrap().push(Seen {
+                    method: req.method().to_string(),
+                    path: path.clone(),
+                    range: range.clone(),
+                    user_agent,
+                    headers,
+                });
+
+                let file = dir.join(path.trim_start_matches('/'));
+                let body = match (req.method(), fs::read(&file)) {
+                    (Method::Get | Method::Head, Ok(body)) => body,
+                    _ => {
+                        let _ = req.respond(Response::empty(404));
+                        continue;
+                    }
+                };
+                let response = match range.as_deref().and_then(parse_range) {
+                    Some((start, end)) if start < body.len() as u64 => {
+                        let end = end.min(body.len() as u64 - 1);
+                        let content_range = format!("bytes {start}-{end}/{}", body.len());
+                        Response::from_data(body[start as usize..=end as usize].to_vec())
+                            .with_status_code(206)
+                            .with_header(Header::from_bytes("Content-Range", content_range).unwrap())
+                    }
+                    Some(_) => Response::from_data(Vec::new()).with_status_code(416),
+                    None => Response::from_data(body),
+                };
+                let _ = req.respond(
+                    response.with_header(Header::from_bytes("Content-Type", "application/octet-stream").unwrap()),
+                );
+            }
+        });
+
+        Self {
+            root,
+            server,
+            seen,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.server.server_addr().to_ip().unwrap())
+    }
+
+    /// Client options pointing at this server.
+    pub fn options(&self) -> ClientOptions {
+        ClientOptions {
+            source: self.base_url().parse::<Source>().unwrap(),
+            ..ClientOptions::default()
+        }
+    }
+
+    /// Write a file below the server root (e.g. `20240101/00z/...`).
+    pub fn write(&self, path: &str, body: &[u8]) {
+        let file = self.root.path().join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, body).unwrap();
+    }
+
+    /// Publish an `ifs/0p25/oper` forecast cycle with one `.grib2` + `.index`
+    /// pair per step, holding one field per param (see [`field_bytes`]).
+    pub fn add_cycle(&self, date: &str, hour: u32, steps: &[u32], params: &[&str]) {
+        for step in steps {
+            let (data, index) = fixture_file(date, hour, *step, params, "oper", &[("fc", None)]);
+            let stem = format!("{date}/{hour:02}z/ifs/0p25/oper/{date}{hour:02}0000-{step}h-oper-fc");
+            self.write(&format!("{stem}.grib2"), &data);
+            self.write(&format!("{stem}.index"), index.as_bytes());
+        }
+    }
+
+    /// Publish an `ifs/0p25/enfo` ensemble cycle: one `ef` file per step
+    /// holding the control forecast and perturbed members `1..=members` of
+    /// each param (see [`member_bytes`]).
+    pub fn add_ensemble_cycle(&self, date: &str, hour: u32, steps: &[u32], params: &[&str], members: u32) {
+        let mut kinds = vec![("cf", None)];
+        kinds.extend((1..=members).map(|n| ("pf", Some(n))));
+        for step in steps {
+            let (data, index) = fixture_file(date, hour, *step, params, "enfo", &kinds);
+            let stem = format!("{date}/{hour:02}z/ifs/0p25/enfo/{date}{hour:02}0000-{step}h-enfo-ef");
+            self.write(&format!("{stem}.grib2"), &data);
+            self.write(&format!("{stem}.index"), index.as_bytes());
+        }
+    }
+
+    /// Requests received so far, in order.
+    pub fn seen(&self) -> Vec<Seen> {
+        self.seen.lock().unwrap().clone()
+    }
+
+    /// Path of a target file in a scratch directory next to the fixtures.
+    pub fn target(&self, name: &str) -> PathBuf {
+        let dir = self.root.path().join("out");
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+}
+
+impl Drop for FixtureServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Payload of the fixture field `param` at `step`: a fake GRIB message
+/// whose content identifies the field.
+pub fn field_bytes(param: &str, step: u32) -> Vec<u8> {
+    format!("GRIB-{param}-{step}-7777").into_bytes()
+}
+
+/// Payload of perturbed member `number` of the fixture field `param` at
+/// `step`. The control forecast uses [`field_bytes`].
+pub fn member_bytes(param: &str, step: u32, number: u32) -> Vec<u8> {
+    format!("GRIB-{param}-{step}-{number}-7777").into_bytes()
+}
+
+/// Data and index of one fixture file: every param for each `(type, number)`.
+fn fixture_file(
+    date: &str,
+    hour: u32,
+    step: u32,
+    params: &[&str],
+    stream: &str,
+    kinds: &[(&str, Option<u32>)],
+) -> (Vec<u8>, String) {
+    let mut data = Vec::new();
+    let mut index = String::new();
+    for (typ, number) in kinds {
+        for param in params {
+            let field = match number {
+                Some(n) => member_bytes(param, step, *n),
+                None => field_bytes(param, step),
+            };
+            let mut entry = serde_json::json!({
+                "date": date,
+                "time": format!("{hour:02}00"),
+                "stream": stream,
+                "type": typ,
+                "step": step.to_string(),
+                "levtype": "sfc",
+                "param": param,
+                "_offset": data.len(),
+                "_length": field.len(),
+            });
+            if let Some(n) = number {
+                entry["number"] = n.to_string().into();
+            }
+            index.push_str(&entry.to_string());
+            index.push('\n');
+            data.extend_from_slice(&field);
+        }
+    }
+    (data, index)
+}
+
+fn parse_range(header: &str) -> Option<(u64, u64)> {
+    let (start, end) = header.strip_prefix("bytes=")?.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
@@ -0,0 +1,180 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+/// Reaction to mirror throttling (HTTP 429/503 by default) during downloads.
+///
+/// When a data or index request is throttled, the effective concurrency is
+/// halved (down to 1), the request is paused and retried, and concurrency is
+/// ramped back up by one after `ramp_up_after` consecutive successes. The pause
+/// honours `Retry-After` when the server sends it, otherwise it doubles from
+/// `base_delay` with each consecutive throttled answer, capped at `max_delay`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThrottlePolicy {
+    pub statuses: Vec<u16>,
+    /// Retries of a single request before the throttled status is returned as an error.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub ramp_up_after: u32,
+}
+
+impl Default for ThrottlePolicy {
+    fn default() -> Self {
+        Self {
+            statuses: vec![429, 503],
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            ramp_up_after: 8,
+        }
+    }
+}
+
+/// Shared AIMD controller for the effective number of in-flight requests.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    policy: ThrottlePolicy,
+    state: Mutex<ThrottleState>,
+    released: Condvar,
+}
+
+#[derive(Debug)]
+struct ThrottleState {
+    max: usize,
+    limit: usize,
+    in_flight: usize,
+    successes: u32,
+    consecutive_throttles: u32,
+}
+
+impl Throttle {
+    pub(crate) fn new(policy: ThrottlePolicy, max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            policy,
+            state: Mutex::new(ThrottleState {
+                max,
+                limit: max,
+                in_flight: 0,
+                successes: 0,
+                consecutive_throttles: 0,
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until fewer than the current limit of requests are in flight.
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while st.in_flight >= st.limit {
+            st = self.released.wait(st).unwrap_or_else(|e| e.into_inner());
+        }
+        st.in_flight += 1;
+        Permit { throttle: self }
+    }
+
+    pub(crate) fn max_retries(&self) -> u32 {
+        self.policy.max_retries
+    }
+
+    pub(crate) fn is_throttled(&self, status: u16) -> bool {
+        self.policy.statuses.contains(&status)
+    }
+
+    pub(crate) fn on_success(&self) {
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        st.consecutive_throttles = 0;
+        st.successes += 1;
+        if st.successes >= self.policy.ramp_up_after {
+            st.successes = 0;
+            st.limit = (st.limit + 1).min(st.max);
+            self.released.notify_one();
+        }
+    }
+
+    /// Record a throttled answer and return how long to pause before retrying.
+    pub(crate) fn on_throttle(&self, retry_after: Option<Duration>) -> Duration {
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        st.successes = 0;
+        st.limit = (st.limit / 2).max(1);
+        st.consecutive_throttles += 1;
+
+        let backoff = self
+            .policy
+            .base_delay
+            .saturating_mul(1 << (st.consecutive_throttles - 1).min(16));
+        retry_after.unwrap_or(backoff).min(self.policy.max_delay)
+    }
+
+    #[cfg(test)]
+    fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+}
+
+/// One in-flight request slot of a [`Throttle`], released on drop.
+pub(crate) struct Permit<'a> {
+    throttle: &'a Throttle,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut st = self.throttle.state.lock().unwrap_or_else(|e| e.into_inner());
+        st.in_flight -= 1;
+        self.throttle.released.notify_one();
+    }
+}
+
+/// `Retry-After` in its delay-seconds form (HTTP-date values are ignored).
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_on_throttle_and_ramps_up() {
+        let policy = ThrottlePolicy {
+            ramp_up_after: 2,
+            ..ThrottlePolicy::default()
+        };
+        let t = Throttle::new(policy, 8);
+        t.on_throttle(None);
+        assert_eq!(t.limit(), 4);
+        t.on_throttle(None);
+        t.on_throttle(None);
+        t.on_throttle(None);
+        assert_eq!(t.limit(), 1);
+
+        t.on_success();
+        t.on_success();
+        assert_eq!(t.limit(), 2);
+    }
+
+    #[test]
+    fn backoff_doubles_and_is_capped() {
+        let t = Throttle::new(ThrottlePolicy::default(), 1);
+        assert_eq!(t.on_throttle(None), Duration::from_millis(500));
+        assert_eq!(t.on_throttle(None), Duration::from_secs(1));
+        assert_eq!(t.on_throttle(Some(Duration::from_secs(120))), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn permits_follow_the_limit() {
+        let t = Throttle::new(ThrottlePolicy::default(), 2);
+        let a = t.acquire();
+        let _b = t.acquire();
+        assert_eq!(t.state.lock().unwrap().in_flight, 2);
+        drop(a);
+        let _c = t.acquire();
+        assert_eq!(t.state.lock().unwrap().in_flight, 2);
+    }
+}
@@ -1,10 +1,14 @@
 use chrono::{DateTime, Utc};
 
 use crate::date::end_step;
+use crate::error::{Error, Result};
 
 pub const HOURLY_PATTERN: &str = "{url}/{yyyymmdd}/{H}z/{model}/{resol}/{stream}/{yyyymmddHHMMSS}-{step}h-{stream}-{type}.{ext}";
 pub const MONTHLY_PATTERN: &str = "{url}/{yyyymmdd}/{H}z/{model}/{resol}/{stream}/{yyyymmddHHMMSS}-{fcmonth}m-{stream}-{type}.{ext}";
 
+/// Built-in file extension of a URL type: `bufr` for tropical cyclone tracks
+/// (`tf`), `grib2` otherwise. [`crate::ClientOptions::type_extensions`]
+/// overrides it per type.
 pub fn extension_for_type(typ: &str) -> &'static str {
     if typ == "tf" {
         "bufr"
@@ -13,6 +17,15 @@ pub fn extension_for_type(typ: &str) -> &'static str {
     }
 }
 
+/// Swap a `.grib2` target suffix for the payload's real extension, so e.g. BUFR
+/// track files (`type=tf`) do not end up in `data.grib2`.
+pub fn target_with_extension(target: &str, ext: &str) -> String {
+    match target.strip_suffix(".grib2") {
+        Some(stem) if ext != "grib2" => format!("{stem}.{ext}"),
+        _ => target.to_string(),
+    }
+}
+
 pub fn user_to_url_value(model: &str, key: &str, value: &str, all_url_type_values: &[String]) -> String {
     // Mirrors upstream mapping.
     // type mapping affects file naming.
@@ -38,13 +51,13 @@ pub fn user_to_url_value(model: &str, key: &str, value: &str, all_url_type_value
         };
     }
 
-    if key == "step" {
-        // For probabilities, the URL contains either 240 or 360.
-        if all_url_type_values.len() == 1 && all_url_type_values[0] == "ep" {
-            if let Some(e) = end_step(value) {
-                return if e <= 240 { "240".to_string() } else { "360".to_string() };
-            }
-        }
+    // For probabilities, the URL contains either 240 or 360.
+    if key == "step"
+        && all_url_type_values.len() == 1
+        && all_url_type_values[0] == "ep"
+        && let Some(e) = end_step(value)
+    {
+        return if e <= 240 { "240".to_string() } else { "360".to_string() };
     }
 
     value.to_string()
@@ -79,45 +92,147 @@ pub fn patch_stream(infer_stream_keyword: bool, model: &str, stream: &str, hour_
     s
 }
 
-pub fn format_url(
-    pattern: &str,
-    base_url: &str,
-    date: DateTime<Utc>,
-    model: &str,
-    resol: &str,
-    stream: &str,
-    typ: &str,
-    step: Option<&str>,
-    fcmonth: Option<&str>,
-) -> String {
-    let yyyymmdd = date.format("%Y%m%d").to_string();
-    let hh = date.format("%H").to_string();
-    let yyyymmdd_hhmmss = date.format("%Y%m%d%H%M%S").to_string();
-    let ext = extension_for_type(typ);
-
-    let mut url = pattern
-        .replace("{url}", base_url)
-        .replace("{yyyymmdd}", &yyyymmdd)
-        .replace("{H}", &hh)
-        .replace("{model}", model)
-        .replace("{resol}", resol)
-        .replace("{stream}", stream)
-        .replace("{type}", typ)
-        .replace("{yyyymmddHHMMSS}", &yyyymmdd_hhmmss)
-        .replace("{ext}", ext);
-
-    if let Some(step) = step {
-        url = url.replace("{step}", step);
-    }
-    if let Some(fcmonth) = fcmonth {
-        url = url.replace("{fcmonth}", fcmonth);
-    }
-
-    url
+/// Data URL built from a pattern and its components.
+///
+/// Placeholders of the pattern (`{url}`, `{yyyymmdd}`, `{H}`, `{model}`,
+/// `{resol}`, `{stream}`, `{type}`, `{step}`, `{fcmonth}`, ...) are filled from
+/// the components; the file extension follows the type unless set with
+/// [`UrlBuilder::extension`].
+///
+/// ```
+/// use chrono::{TimeZone, Utc};
+/// use ecmwf_opendata::UrlBuilder;
+///
+/// let url = UrlBuilder::new("https://data.ecmwf.int/forecasts")
+///     .date(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+///     .step("240")
+///     .build()?;
+/// assert_eq!(
+///     url,
+///     "https://data.ecmwf.int/forecasts/20240101/00z/ifs/0p25/oper/20240101000000-240h-oper-fc.grib2"
+/// );
+/// # Ok::<(), ecmwf_opendata::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlBuilder {
+    base_url: String,
+    pattern: String,
+    date: Option<DateTime<Utc>>,
+    model: String,
+    resol: String,
+    stream: String,
+    typ: String,
+    step: Option<String>,
+    fcmonth: Option<String>,
+    extension: Option<String>,
+}
+
+impl UrlBuilder {
+    /// Start from a base URL with [`HOURLY_PATTERN`] and the `ifs`/`0p25`/`oper`/`fc` defaults.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            pattern: HOURLY_PATTERN.to_string(),
+            date: None,
+            model: "ifs".to_string(),
+            resol: "0p25".to_string(),
+            stream: "oper".to_string(),
+            typ: "fc".to_string(),
+            step: None,
+            fcmonth: None,
+            extension: None,
+        }
+    }
+
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = pattern.into();
+        self
+    }
+
+    /// Forecast base date and time.
+    pub fn date(mut self, date: DateTime<Utc>) -> Self {
+        self.date = Some(date);
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    pub fn resol(mut self, resol: impl Into<String>) -> Self {
+        self.resol = resol.into();
+        self
+    }
+
+    pub fn stream(mut self, stream: impl Into<String>) -> Self {
+        self.stream = stream.into();
+        self
+    }
+
+    /// File type as used in URLs (`fc`, `ef`, `ep`, `tf`, ...).
+    pub fn r#type(mut self, typ: impl Into<String>) -> Self {
+        self.typ = typ.into();
+        self
+    }
+
+    pub fn step(mut self, step: impl Into<String>) -> Self {
+        self.step = Some(step.into());
+        self
+    }
+
+    pub fn fcmonth(mut self, fcmonth: impl Into<String>) -> Self {
+        self.fcmonth = Some(fcmonth.into());
+        self
+    }
+
+    /// File extension (`{ext}`) to use instead of [`extension_for_type`].
+    pub fn extension(mut self, extension: impl Into<String>) -> Self {
+        self.extension = Some(extension.into());
+        self
+    }
+
+    /// Fill in the pattern. Fails without a date or when a placeholder is
+    /// left unfilled (e.g. `{step}` without [`UrlBuilder::step`]).
+    pub fn build(&self) -> Result<String> {
+        let date = self
+            .date
+            .ok_or_else(|| Error::InvalidRequest("url builder: date not set".into()))?;
+
+        let mut url = self
+            .pattern
+            .replace("{url}", &self.base_url)
+            .replace("{yyyymmdd}", &date.format("%Y%m%d").to_string())
+            .replace("{H}", &date.format("%H").to_string())
+            .replace("{model}", &self.model)
+            .replace("{resol}", &self.resol)
+            .replace("{stream}", &self.stream)
+            .replace("{type}", &self.typ)
+            .replace("{yyyymmddHHMMSS}", &date.format("%Y%m%d%H%M%S").to_string())
+            .replace("{ext}", self.extension.as_deref().unwrap_or(extension_for_type(&self.typ)));
+
+        if let Some(step) = &self.step {
+            url = url.replace("{step}", step);
+        }
+        if let Some(fcmonth) = &self.fcmonth {
+            url = url.replace("{fcmonth}", fcmonth);
+        }
+
+        if let Some(start) = url.find('{') {
+            let end = url[start..].find('}').map(|i| start + i + 1).unwrap_or(url.len());
+            return Err(Error::InvalidRequest(format!(
+                "url builder: unfilled placeholder {}",
+                &url[start..end]
+            )));
+        }
+        Ok(url)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::TimeZone;
+
     use super::*;
 
     #[test]
@@ -142,4 +257,39 @@ mod tests {
         assert_eq!(user_to_url_value("aifs-ens", "type", "cf", &[]), "cf");
         assert_eq!(user_to_url_value("aifs-ens", "type", "pf", &[]), "pf");
     }
+
+    #[test]
+    fn extension_can_be_overridden() {
+        let builder = UrlBuilder::new("https://h")
+            .date(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+            .r#type("tf")
+            .step("240");
+        assert!(builder.build().unwrap().ends_with("-240h-oper-tf.bufr"));
+        assert!(builder.extension("bin").build().unwrap().ends_with("-240h-oper-tf.bin"));
+    }
+
+    #[test]
+    fn target_extension_follows_payload() {
+        assert_eq!(target_with_extension("data.grib2", "bufr"), "data.bufr");
+        assert_eq!(target_with_extension("data.grib2", "grib2"), "data.grib2");
+        assert_eq!(target_with_extension("tracks.dat", "bufr"), "tracks.dat");
+    }
+
+    #[test]
+    fn url_builder_fills_monthly_pattern() {
+        use chrono::TimeZone;
+
+        let date = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let b = UrlBuilder::new("https://h/")
+            .pattern(MONTHLY_PATTERN)
+            .date(date)
+            .stream("mmsf")
+            .fcmonth("2");
+        assert_eq!(
+            b.build().unwrap(),
+            "https://h/20240501/00z/ifs/0p25/mmsf/20240501000000-2m-mmsf-fc.grib2"
+        );
+        assert!(b.clone().pattern(HOURLY_PATTERN).build().is_err());
+        assert!(UrlBuilder::new("https://h").step("0").build().is_err());
+    }
 }
@@ -0,0 +1,130 @@
+use crate::error::{Error, Result};
+
+/// Params of the wave model (ECWAM) in the open-data catalogue.
+pub const WAVE_PARAMS: [&str; 5] = ["swh", "mwd", "mwp", "mp2", "pp1d"];
+
+/// Whether `stream` holds wave products: `wave` (HRES 00/12z), `scwv`
+/// (HRES 06/18z) or `waef` (ENS).
+pub fn is_wave_stream(stream: &str) -> bool {
+    matches!(stream, "wave" | "scwv" | "waef")
+}
+
+pub fn is_wave_param(param: &str) -> bool {
+    WAVE_PARAMS.contains(&param)
+}
+
+/// Steps published for wave stream `stream` at cycle hour `hour`: every 3h
+/// to 144h, then every 6h to 240h (`wave`) or 360h (`waef`) for the 00/12z
+/// runs; every 3h to 90h (`scwv`) or 144h (`waef`) for the 06/18z runs.
+/// Empty for other streams.
+pub fn wave_steps(stream: &str, hour: u32) -> Vec<u32> {
+    let main_run = hour.is_multiple_of(12);
+    let (three_hourly, six_hourly) = match stream {
+        "wave" | "scwv" if main_run => (144, 240),
+        "wave" | "scwv" => (90, 90),
+        "waef" if main_run => (144, 360),
+        "waef" => (144, 144),
+        _ => return Vec::new(),
+    };
+    (0..=three_hourly)
+        .step_by(3)
+        .chain((three_hourly + 6..=six_hourly).step_by(6))
+        .collect()
+}
+
+/// Stream to assume for a request without one: `wave` when every param is a
+/// wave param. Stream inference then turns it into `scwv` (06/18z) or `waef`
+/// (ensemble types).
+pub(crate) fn infer_stream(params: &[String]) -> Option<&'static str> {
+    (!params.is_empty() && params.iter().all(|p| is_wave_param(p))).then_some("wave")
+}
+
+/// Reject wave params in atmospheric streams and the reverse: they are in
+/// different files, so such requests could only match nothing. A request
+/// mixing both kinds of params is not rejected, as the params of its stream
+/// still match; the returned warning names the params that cannot.
+pub(crate) fn check_params(streams: &[String], params: &[String]) -> Result<Option<String>> {
+    let wave = params.iter().find(|p| is_wave_param(p));
+    let other = params.iter().find(|p| !is_wave_param(p));
+    if let (Some(wave), Some(other)) = (wave, other) {
+        return Ok(Some(format!(
+            "wave param `{wave}` and atmospheric param `{other}` are in different streams; \
+             only the params of the requested stream will match, request the others separately"
+        )));
+    }
+    for stream in streams {
+        match (is_wave_stream(stream), wave, other) {
+            (false, Some(param), _) => {
+                return Err(Error::InvalidRequest(format!(
+                    "`{param}` is a wave param, not in stream `{stream}`; use stream `wave` (`waef` for ensembles)"
+                )));
+            }
+            (true, _, Some(param)) => {
+                return Err(Error::InvalidRequest(format!("`{param}` is not a wave param, so not in stream `{stream}`")));
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Reject a `step` that wave stream `stream` does not publish at `hour`.
+/// Step ranges and products without a step ladder (`ep`, `tf`) are not
+/// checked.
+pub(crate) fn check_step(stream: &str, typ: &str, hour: u32, step: &str) -> Result<()> {
+    if !is_wave_stream(stream) || matches!(typ, "ep" | "tf") {
+        return Ok(());
+    }
+    let Ok(step) = step.parse::<u32>() else {
+        return Ok(());
+    };
+    let steps = wave_steps(stream, hour);
+    if steps.contains(&step) {
+        return Ok(());
+    }
+    Err(Error::InvalidRequest(format!(
+        "step {step} is not published for stream `{stream}` at {hour:02}z (steps 0 to {}, every 3h to 144h then every 6h)",
+        steps.last().copied().unwrap_or_default()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn step_ladders_follow_the_run() {
+        let hres = wave_steps("wave", 0);
+        assert_eq!(hres.len(), 49 + 16);
+        assert_eq!(hres[48..50], [144, 150]);
+        assert_eq!(hres.last(), Some(&240));
+        assert_eq!(wave_steps("scwv", 6).last(), Some(&90));
+        assert_eq!(wave_steps("waef", 12).last(), Some(&360));
+        assert_eq!(wave_steps("waef", 18).last(), Some(&144));
+        assert!(wave_steps("oper", 0).is_empty());
+
+        assert!(check_step("scwv", "fc", 6, "90").is_ok());
+        assert!(check_step("scwv", "fc", 6, "96").is_err());
+        assert!(check_step("wave", "fc", 0, "147").is_err());
+        assert!(check_step("waef", "ep", 0, "0-24").is_ok());
+        assert!(check_step("oper", "fc", 0, "147").is_ok());
+    }
+
+    #[test]
+    fn wave_params_stay_in_wave_streams() {
+        assert_eq!(infer_stream(&strings(&["swh", "mwp"])), Some("wave"));
+        assert_eq!(infer_stream(&strings(&["swh", "2t"])), None);
+        assert_eq!(infer_stream(&[]), None);
+
+        assert_eq!(check_params(&strings(&["waef"]), &strings(&["swh"])).unwrap(), None);
+        assert_eq!(check_params(&strings(&["oper"]), &strings(&["2t"])).unwrap(), None);
+        assert!(check_params(&strings(&["oper"]), &strings(&["swh"])).is_err());
+        assert!(check_params(&strings(&["wave"]), &strings(&["2t"])).is_err());
+        let mixed = check_params(&strings(&["wave"]), &strings(&["swh", "2t"])).unwrap().unwrap();
+        assert!(mixed.starts_with("wave param `swh` and atmospheric param `2t` are in different streams"));
+    }
+}
@@ -0,0 +1,1060 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
+
+use chrono::{Duration, TimeZone, Utc};
+use ecmwf_opendata::{
+    plan, plan_index_urls, CancellationToken, Client, ClientOptions, DownloadEvent, Error, HttpEvent, HttpHook,
+    LaggedEnsemble, Layout, Lint, Manifest, ProbeTarget, Quota, QuotaAction, Request, Route, Severity, Source,
+    SourceCost, TargetContext,
+};
+
+use ecmwf_opendata::testing::{field_bytes, member_bytes, FixtureServer};
+use sha2::{Digest, Sha256};
+
+const DATE: &str = "20240101";
+
+/// A server publishing the 00z cycle of [`DATE`] with `steps` and `params`.
+fn published(steps: &[u32], params: &[&str]) -> FixtureServer {
+    let server = FixtureServer::start();
+    server.add_cycle(DATE, 0, steps, params);
+    server
+}
+
+/// [`published`], with a client reading from it with default options.
+fn fixture(steps: &[u32], params: &[&str]) -> (FixtureServer, Client) {
+    let server = published(steps, params);
+    let client = Client::new(server.options()).unwrap();
+    (server, client)
+}
+
+/// A server answering every request with a `302` to `location(host, path)`,
+/// where `host` is the request's `Host` header; returns its base URL.
+fn redirector(location: impl Fn(&str, &str) -> String + Send + 'static) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let path = line.split(' ').nth(1).unwrap_or("/").to_string();
+            let mut host = String::new();
+            line.clear();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("host")
+                {
+                    host = value.trim().to_string();
+                }
+                line.clear();
+            }
+            let location = location(&host, &path);
+            let response = format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\n\r\n");
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    base_url
+}
+
+fn request(server: &FixtureServer, name: &str) -> Request {
+    Request::new()
+        .date(DATE)
+        .time(0)
+        .r#type("fc")
+        .target(server.target(name).to_string_lossy().as_ref())
+}
+
+#[test]
+fn retrieve_downloads_only_the_selected_fields() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl", "10u"]);
+
+    let result = client
+        .retrieve_request(request(&server, "sel.grib2").step(6).param("msl"))
+        .unwrap();
+
+    let written = fs::read(&result.target).unwrap();
+    assert_eq!(written, field_bytes("msl", 6));
+    assert_eq!(result.size_bytes, written.len() as u64);
+    assert_eq!(result.datetimes, [Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()]);
+    assert_eq!(result.fields.len(), 1);
+    assert_eq!(result.fields[0].param, "msl");
+    let entries: Vec<_> = result.index_entries().collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].get("stream"), entries[0].get("levtype")), (Some("oper"), Some("sfc")));
+    assert_eq!(entries[0].get("step"), Some("6"));
+
+    let data_gets: Vec<_> = server
+        .seen()
+        .into_iter()
+        .filter(|s| s.method == "GET" && s.path.ends_with(".grib2"))
+        .collect();
+    assert_eq!(data_gets.len(), 1);
+    assert_eq!(data_gets[0].path, "/20240101/00z/ifs/0p25/oper/20240101000000-6h-oper-fc.grib2");
+    assert!(data_gets[0].range.is_some());
+}
+
+#[test]
+fn retrieve_writes_files_in_request_order_and_fields_in_index_order() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl", "10u"]);
+
+    let result = client
+        .retrieve_request(request(&server, "order.grib2").step([6, 0]).param(["msl", "2t"]))
+        .unwrap();
+
+    let expected = [field_bytes("2t", 6), field_bytes("msl", 6), field_bytes("2t", 0), field_bytes("msl", 0)];
+    assert_eq!(fs::read(&result.target).unwrap(), expected.concat());
+    let fields: Vec<_> = result.fields.iter().map(|f| f.param.as_str()).collect();
+    assert_eq!(fields, ["2t", "msl", "2t", "msl"]);
+}
+
+#[test]
+fn fast_writes_produce_the_same_target() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl", "10u"]);
+    let req = |name| request(&server, name).step([6, 0]).param(["msl", "2t"]);
+
+    let slow = client.retrieve_request(req("slow.grib2")).unwrap();
+    let fast = client.retrieve_request(req("fast.grib2").fast_writes(true)).unwrap();
+
+    assert_eq!(fs::read(&fast.target).unwrap(), fs::read(&slow.target).unwrap());
+    assert_eq!(fast.sha256, slow.sha256);
+    assert!(fast.fast_writes);
+}
+
+#[test]
+fn order_by_sets_the_primary_sort_keyword() {
+    let server = FixtureServer::start();
+    server.add_ensemble_cycle(DATE, 0, &[6], &["tp", "2t"], 2);
+    let client = Client::new(ClientOptions { preserve_request_order: true, ..server.options() }).unwrap();
+    let req = |name| request(&server, name).r#type("pf").step(6).param(["2t", "tp"]).number([1, 2]);
+
+    let by_param = client.retrieve_request(req("by_param.grib2")).unwrap();
+    let members = |order: [(&str, u32); 4]| order.map(|(param, n)| member_bytes(param, 6, n)).concat();
+    assert_eq!(fs::read(&by_param.target).unwrap(), members([("2t", 1), ("2t", 2), ("tp", 1), ("tp", 2)]));
+
+    let by_number = client.retrieve_request(req("by_number.grib2").order_by(["number"])).unwrap();
+    assert_eq!(fs::read(&by_number.target).unwrap(), members([("2t", 1), ("tp", 1), ("2t", 2), ("tp", 2)]));
+}
+
+#[test]
+fn sequential_ranges_fetch_in_file_order() {
+    let server = published(&[0, 6], &["2t", "msl", "10u"]);
+    let client = Client::new(ClientOptions {
+        preserve_request_order: true,
+        sequential_ranges: true,
+        max_concurrent_ranges: 4,
+        ..server.options()
+    })
+    .unwrap();
+
+    let result = client
+        .retrieve_request(request(&server, "sequential.grib2").step(6).param(["10u", "2t"]))
+        .unwrap();
+
+    assert_eq!(fs::read(&result.target).unwrap(), [field_bytes("2t", 6), field_bytes("10u", 6)].concat());
+    let params: Vec<_> = result.fields.iter().map(|f| f.param.as_str()).collect();
+    assert_eq!(params, ["2t", "10u"]);
+    let starts: Vec<u64> = server
+        .seen()
+        .iter()
+        .filter_map(|s| s.range.as_deref()?.strip_prefix("bytes=")?.split('-').next()?.parse().ok())
+        .collect();
+    assert!(starts.len() > 1);
+    assert!(starts.is_sorted());
+}
+
+#[test]
+fn concurrent_ranges_are_written_batch_by_batch() {
+    let server = published(&[6], &["2t", "msl", "10u", "tp", "sp"]);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let client = Client::new(ClientOptions {
+        max_concurrent_ranges: 2,
+        download_events: Some(tx),
+        ..server.options()
+    })
+    .unwrap();
+
+    let result = client
+        .retrieve_request(request(&server, "batches.grib2").step(6).param(["2t", "10u", "sp"]))
+        .unwrap();
+    drop(client);
+
+    let expected = [field_bytes("2t", 6), field_bytes("10u", 6), field_bytes("sp", 6)].concat();
+    assert_eq!(fs::read(&result.target).unwrap(), expected);
+    let events: Vec<_> = rx
+        .iter()
+        .filter_map(|event| match event {
+            DownloadEvent::RangeStarted { .. } => Some("started"),
+            DownloadEvent::BytesWritten { .. } => Some("written"),
+            _ => None,
+        })
+        .collect();
+    // The third range is only fetched once the first two are written.
+    assert_eq!(events, ["started", "started", "written", "written", "started", "written"]);
+}
+
+#[test]
+fn availability_lists_what_the_cycle_publishes() {
+    let server = published(&[0, 6], &["2t", "msl"]);
+    server.add_ensemble_cycle(DATE, 0, &[6], &["tp"], 2);
+    server.write("20240101/00z/ifs/0p25/oper/20240101000000-240h-oper-tf.bufr", b"BUFR tracks");
+    let client = Client::new(server.options()).unwrap();
+
+    let availability = client.availability(DATE, 0).unwrap();
+
+    assert_eq!(availability.streams(), ["enfo", "oper"]);
+    assert_eq!(availability.types("enfo"), ["cf", "pf"]);
+    assert_eq!(availability.types("oper"), ["fc", "tf"]);
+    assert_eq!(availability.steps("oper", "fc"), ["0", "6"]);
+    assert_eq!(availability.params("oper", "fc", "6"), ["2t", "msl"]);
+    assert_eq!(availability.steps("oper", "tf"), ["240"]);
+    assert!(availability.params("oper", "tf", "240").is_empty());
+    assert!(availability.contains("enfo", "pf", "6", "tp"));
+    let downloads = server.seen().into_iter().filter(|s| s.method == "GET" && !s.path.ends_with(".index"));
+    assert!(downloads.map(|s| s.range).all(|r| r.as_deref() == Some("bytes=0-0")));
+}
+
+#[test]
+fn retrieve_filtered_applies_the_predicate_to_index_entries() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl", "10u", "10v"]);
+
+    let req = request(&server, "filtered.grib2").step(6);
+    let result = client
+        .retrieve_filtered(req, |e| e.param.as_deref().is_some_and(|p| p.starts_with("10")))
+        .unwrap();
+
+    assert_eq!(fs::read(&result.target).unwrap(), [field_bytes("10u", 6), field_bytes("10v", 6)].concat());
+    let params: Vec<_> = result.fields.iter().map(|f| f.param.as_str()).collect();
+    assert_eq!(params, ["10u", "10v"]);
+
+    let none = client.retrieve_filtered(request(&server, "none.grib2").step(6), |_| false);
+    assert!(matches!(none, Err(Error::NoMatchingIndex)));
+
+    server.write("20240101/00z/ifs/0p25/oper/20240101000000-240h-oper-tf.bufr", b"BUFR tracks");
+    let tracks = request(&server, "tracks").r#type("tf").step(240);
+    let err = client.retrieve_filtered(tracks, |_| true).unwrap_err();
+    assert!(matches!(&err, Error::InvalidRequest(m) if m.contains("-240h-oper-tf.bufr has no index")), "{err}");
+}
+
+#[test]
+fn retrieve_stream_yields_what_retrieve_writes() {
+    let server = published(&[0, 6], &["2t", "msl", "10u"]);
+    let client = Client::new(ClientOptions {
+        stream_buffer: 1,
+        ..server.options()
+    })
+    .unwrap();
+    let req = || request(&server, "stream.grib2").step([0, 6]).param(["2t", "10u"]);
+
+    let stream = client.retrieve_stream(req()).unwrap();
+    assert_eq!(stream.result().fields.len(), 4);
+    let chunks: Vec<_> = stream.map(|c| c.unwrap()).collect();
+    assert_eq!(chunks.len(), 4);
+
+    let written = fs::read(client.retrieve_request(req()).unwrap().target).unwrap();
+    assert_eq!(chunks.concat(), written);
+}
+
+#[test]
+fn download_writes_whole_files_and_checksums_them() {
+    let (server, client) = fixture(&[0], &["2t", "msl"]);
+
+    let result = client.download_request(request(&server, "whole.grib2").step(0)).unwrap();
+
+    let written = fs::read(&result.target).unwrap();
+    assert_eq!(written, [field_bytes("2t", 0), field_bytes("msl", 0)].concat());
+    assert_eq!(result.sha256.as_deref().map(str::len), Some(64));
+    assert!(server.seen().iter().all(|s| s.range.is_none()));
+}
+
+#[test]
+fn checksum_is_computed_from_the_ranges_as_they_are_appended() {
+    let server = published(&[0, 6], &["2t", "msl", "10u", "tp"]);
+    let client = Client::new(ClientOptions {
+        max_concurrent_ranges: 1,
+        ..server.options()
+    })
+    .unwrap();
+
+    // Separate ranges from two files, written one after another.
+    let result = client
+        .retrieve_request(request(&server, "digest.grib2").step([0, 6]).param(["2t", "10u"]))
+        .unwrap();
+
+    let written = fs::read(&result.target).unwrap();
+    assert_eq!(result.url_reports.iter().map(|r| r.ranges.len()).sum::<usize>(), 4);
+    let digest: String = Sha256::digest(&written).iter().map(|b| format!("{b:02x}")).collect();
+    assert_eq!(result.sha256, Some(digest));
+}
+
+#[test]
+fn resume_appends_the_missing_bytes() {
+    let (server, client) = fixture(&[0], &["2t", "msl"]);
+    let target = server.target("resume.grib2");
+    let expected = [field_bytes("2t", 0), field_bytes("msl", 0)].concat();
+    fs::write(&target, &expected[..7]).unwrap();
+
+    let result = client
+        .resume(
+            request(&server, "unused.grib2").step(0).param(["2t", "msl"]),
+            target.to_string_lossy(),
+        )
+        .unwrap();
+
+    assert_eq!(fs::read(&target).unwrap(), expected);
+    assert_eq!(result.size_bytes, expected.len() as u64);
+}
+
+#[test]
+fn delta_appends_only_newly_published_fields() {
+    let (server, client) = fixture(&[0], &["2t", "msl"]);
+    let req = || request(&server, "delta.grib2").step([0, 6]).param(["2t", "msl"]);
+
+    let first = client.retrieve_available(req(), true).unwrap();
+    assert_eq!(first.pending.len(), 1);
+    let manifest = Manifest::from_result(&first.result);
+    let path = Manifest::path_for(&manifest.target);
+    manifest.save(&path).unwrap();
+
+    server.add_cycle(DATE, 0, &[6], &["2t", "msl"]);
+    let mut manifest = Manifest::load(&path).unwrap();
+    let delta = client.retrieve_delta(req(), &mut manifest).unwrap();
+    assert_eq!(delta.fields.len(), 2);
+    assert!(delta.fields.iter().all(|f| f.step.as_deref() == Some("6")));
+
+    let expected = [field_bytes("2t", 0), field_bytes("msl", 0), field_bytes("2t", 6), field_bytes("msl", 6)];
+    assert_eq!(fs::read(&manifest.target).unwrap(), expected.concat());
+    assert_eq!(manifest.fields.len(), 4);
+    assert_eq!(manifest.size_bytes, expected.concat().len() as u64);
+
+    let again = client.retrieve_delta(req(), &mut manifest).unwrap();
+    assert!(again.urls.is_empty());
+    assert!(delta.warnings.is_empty(), "{:?}", delta.warnings);
+}
+
+#[test]
+fn delta_warns_about_republished_files() {
+    let server = published(&[0], &["2t"]);
+    let client = Client::new(ClientOptions {
+        source: Source::local(server.root.path()).unwrap(),
+        ..ClientOptions::default()
+    })
+    .unwrap();
+    let req = || request(&server, "republished.grib2").step(0).param(["2t", "msl"]);
+
+    let first = client.retrieve_available(req(), true).unwrap();
+    let mut manifest = Manifest::from_result(&first.result);
+    assert_eq!(manifest.remote_versions.len(), 1);
+    // As if the file had been downloaded before it was republished with msl.
+    for version in manifest.remote_versions.values_mut() {
+        version.last_modified = Some("Mon, 01 Jan 2024 06:00:00 GMT".to_string());
+    }
+    server.add_cycle(DATE, 0, &[0], &["2t", "msl"]);
+
+    let delta = client.retrieve_delta(req(), &mut manifest).unwrap();
+    assert_eq!(delta.fields.len(), 1);
+    assert!(delta.warnings.iter().any(|w| w.contains("was republished")), "{:?}", delta.warnings);
+    let stale = Some("Mon, 01 Jan 2024 06:00:00 GMT");
+    assert!(manifest.remote_versions.values().all(|v| v.last_modified.as_deref() != stale));
+}
+
+#[test]
+fn local_mirror_is_read_from_disk() {
+    let server = published(&[0, 6], &["2t", "msl"]);
+    let client = Client::new(ClientOptions {
+        source: Source::local(server.root.path()).unwrap(),
+        ..ClientOptions::default()
+    })
+    .unwrap();
+
+    let result = client
+        .retrieve_request(request(&server, "local.grib2").step([0, 6]).param("msl"))
+        .unwrap();
+
+    let expected = [field_bytes("msl", 0), field_bytes("msl", 6)].concat();
+    assert_eq!(fs::read(&result.target).unwrap(), expected);
+    assert!(result.urls[0].starts_with("file:///"));
+    assert!(result.remote_versions.values().all(|v| v.last_modified.is_some()));
+    assert!(server.seen().is_empty());
+}
+
+#[test]
+fn expiring_sas_tokens_are_refreshed() {
+    let server = published(&[0], &["2t", "msl"]);
+    let expiry = (Utc::now() + Duration::minutes(1)).to_rfc3339();
+    let token = format!(r#"{{"msft:expiry": "{expiry}", "token": "sv=1&sig=x"}}"#);
+    server.write("token.json", token.as_bytes());
+    let client = Client::new(ClientOptions {
+        use_sas_token: Some(true),
+        sas_known_key: "custom".to_string(),
+        sas_custom_url: Some(format!("{}/token.json", server.base_url())),
+        ..server.options()
+    })
+    .unwrap();
+    assert!(client.sas_token_expiry().is_some());
+
+    let result = client
+        .retrieve_request(request(&server, "sas.grib2").step(0).param("msl"))
+        .unwrap();
+
+    assert_eq!(fs::read(&result.target).unwrap(), field_bytes("msl", 0));
+    let token_fetches = server.seen().iter().filter(|s| s.path == "/token.json").count();
+    assert!(token_fetches > 1, "{token_fetches}");
+}
+
+#[test]
+fn missing_data_falls_back_to_the_fallback_source() {
+    let esuite = FixtureServer::start();
+    let operational = FixtureServer::start();
+    operational.add_cycle(DATE, 0, &[0], &["2t", "msl"]);
+    let client = Client::new(ClientOptions {
+        fallback_source: Some(operational.base_url().parse().unwrap()),
+        ..esuite.options()
+    })
+    .unwrap();
+
+    let result = client
+        .retrieve_request(request(&esuite, "fallback.grib2").step(0).param("msl"))
+        .unwrap();
+
+    assert_eq!(fs::read(&result.target).unwrap(), field_bytes("msl", 0));
+    assert!(result.urls[0].starts_with(&operational.base_url()));
+    assert_eq!(result.warnings.len(), 1, "{:?}", result.warnings);
+    assert!(!esuite.seen().is_empty());
+}
+
+#[test]
+fn plan_lists_ranges_without_downloading_data() {
+    let (server, client) = fixture(&[0], &["2t", "msl"]);
+
+    let plan = client.plan(request(&server, "plan.grib2").step(0).param("msl")).unwrap();
+
+    let len = field_bytes("msl", 0).len() as u64;
+    let offset = field_bytes("2t", 0).len() as u64;
+    assert_eq!(plan.transfers.len(), 1);
+    assert_eq!(plan.transfers[0].ranges, vec![(offset, offset + len - 1)]);
+    assert!(server.seen().iter().all(|s| !s.path.ends_with(".grib2")));
+}
+
+#[test]
+fn pure_plan_matches_the_client_plan() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl", "10u"]);
+    let req = request(&server, "pure.grib2").step([0, 6]).param(["10u", "2t"]);
+
+    let urls = plan_index_urls(&server.options(), &req).unwrap();
+    assert_eq!(urls.len(), 2);
+    let indexes: BTreeMap<String, Vec<u8>> = urls
+        .iter()
+        .map(|u| (u.clone(), reqwest::blocking::get(u).unwrap().bytes().unwrap().to_vec()))
+        .collect();
+    let fetched = server.seen().len();
+
+    let pure = plan(&server.options(), &req, &indexes).unwrap();
+    assert_eq!(server.seen().len(), fetched);
+    assert_eq!(pure, client.plan(req.clone()).unwrap());
+
+    let err = plan(&server.options(), &req, &BTreeMap::new()).unwrap_err();
+    assert!(err.to_string().contains("no index content given"), "{err}");
+    let latest = plan(&server.options(), &Request::new().param("2t"), &indexes).unwrap_err();
+    assert!(latest.to_string().contains("needs a `date`"), "{latest}");
+}
+
+#[test]
+fn strict_params_reject_typos_before_reading_indexes() {
+    let server = published(&[0], &["2t", "msl"]);
+    let client = Client::builder().source(server.base_url().parse().unwrap()).strict_params(true).build().unwrap();
+
+    let err = client.retrieve_request(request(&server, "typo.grib2").step(0).param("2tt")).unwrap_err();
+    assert_eq!(err.to_string(), "invalid request: unknown param `2tt`; did you mean `2t`?");
+    assert!(server.seen().is_empty());
+    client.retrieve_request(request(&server, "ok.grib2").step(0).param("2t")).unwrap();
+}
+
+#[test]
+fn python_request_dicts_retrieve_like_builder_requests() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl"]);
+    let target = server.target("python.grib2");
+    let dict = serde_json::json!({
+        "date": DATE,
+        "time": 0,
+        "type": "fc",
+        "step": [[0], 6],
+        "param": "msl",
+        "target": target.to_string_lossy(),
+    });
+
+    let result = client.retrieve_request(Request::from_json_value(dict).unwrap()).unwrap();
+    assert_eq!(fs::read(&result.target).unwrap(), [field_bytes("msl", 0), field_bytes("msl", 6)].concat());
+}
+
+#[test]
+fn unmatched_and_unpublished_indexes_are_reported() {
+    let server = published(&[0], &["2t"]);
+    server.write("20240101/00z/ifs/0p25/oper/20240101000000-6h-oper-fc.index", b"");
+    let client = Client::new(server.options()).unwrap();
+
+    let err = client
+        .retrieve_request(request(&server, "none.grib2").step(0).param("msl"))
+        .unwrap_err();
+    assert!(matches!(err, Error::NoMatchingIndex), "{err}");
+
+    let err = client
+        .retrieve_request(request(&server, "empty.grib2").step(6).param("2t"))
+        .unwrap_err();
+    match err {
+        Error::IndexNotReady { url } => assert!(url.ends_with("20240101000000-6h-oper-fc.index"), "{url}"),
+        other => panic!("expected IndexNotReady, got {other}"),
+    }
+}
+
+#[test]
+fn latest_finds_the_newest_published_cycle() {
+    let server = FixtureServer::start();
+    let today = Utc::now().format("%Y%m%d").to_string();
+    server.add_cycle(&today, 0, &[0], &["msl"]);
+    let client = Client::new(server.options()).unwrap();
+
+    let latest = client.latest(Request::new().r#type("fc").step(0)).unwrap();
+
+    assert_eq!(latest.format("%Y%m%d %H").to_string(), format!("{today} 00"));
+}
+
+#[test]
+fn latest_can_probe_only_the_first_index() {
+    let server = FixtureServer::start();
+    let today = Utc::now().format("%Y%m%d").to_string();
+    server.add_cycle(&today, 0, &[0], &["msl"]);
+    let client = Client::new(ClientOptions {
+        probe_target: ProbeTarget::FirstIndex,
+        ..server.options()
+    })
+    .unwrap();
+
+    // Step 6 is not published; only the step 0 index is looked at (`scda`
+    // for the 06z and 18z candidates).
+    let latest = client.latest(Request::new().r#type("fc").step([0, 6])).unwrap();
+
+    assert_eq!(latest.format("%Y%m%d %H").to_string(), format!("{today} 00"));
+    let seen = server.seen();
+    assert!(seen.iter().all(|s| s.path.ends_with("-0h-oper-fc.index") || s.path.ends_with("-0h-scda-fc.index")));
+}
+
+#[test]
+fn hung_mirror_fails_at_the_deadline() {
+    // Accepts connections but never answers, like a stalled portal.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let _held: Vec<_> = listener.incoming().collect();
+    });
+
+    let deadline = std::time::Duration::from_millis(300);
+    let client = Client::new(ClientOptions {
+        source: base_url.parse::<Source>().unwrap(),
+        deadline: Some(deadline),
+        ..ClientOptions::default()
+    })
+    .unwrap();
+
+    let started = std::time::Instant::now();
+    let err = client
+        .retrieve_request(Request::new().date(DATE).time(0).step(0).param("msl"))
+        .unwrap_err();
+    assert!(matches!(err, Error::DeadlineExceeded(d) if d == deadline), "{err}");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    // Dry runs probe and read indexes within the same deadline.
+    let started = std::time::Instant::now();
+    let err = client
+        .dry_run(Request::new().date(DATE).time(0).step(0).param("msl"), true)
+        .unwrap_err();
+    assert!(matches!(err, Error::DeadlineExceeded(d) if d == deadline), "{err}");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[test]
+fn plan_sources_prefers_cheap_mirrors_that_have_the_file() {
+    let paid = FixtureServer::start();
+    paid.add_cycle(DATE, 0, &[0, 6], &["2t", "msl"]);
+    let free = FixtureServer::start();
+    free.add_cycle(DATE, 0, &[0], &["2t", "msl"]);
+    let paid_source = paid.base_url().parse::<Source>().unwrap();
+    let free_source = free.base_url().parse::<Source>().unwrap();
+    let client = Client::new(paid.options()).unwrap();
+    let req = || request(&paid, "plan.grib2").step([0, 6]).param("msl");
+
+    let costs = [SourceCost::new(paid_source.clone(), 0.09), SourceCost::new(free_source.clone(), 0.0)];
+    let plan = client.plan_sources(req(), &costs).unwrap();
+    let sources: Vec<_> = plan.assignments.iter().map(|a| a.source.clone()).collect();
+    assert_eq!(sources, vec![free_source.clone(), paid_source.clone()]);
+    assert!(plan.assignments[0].url.starts_with(&free.base_url()));
+    assert_eq!(plan.bytes_from(&free_source), field_bytes("msl", 0).len() as u64);
+    assert!(plan.total_cost() > 0.0);
+
+    let costs = [SourceCost::new(paid_source.clone(), 0.09), SourceCost::new(free_source, 0.0).max_bytes(0)];
+    let plan = client.plan_sources(req(), &costs).unwrap();
+    assert!(plan.assignments.iter().all(|a| a.source == paid_source));
+}
+
+#[test]
+fn plan_sources_maps_files_planned_from_the_fallback() {
+    let primary = FixtureServer::start();
+    let fallback = published(&[0], &["2t", "msl"]);
+    let mirror = published(&[0], &["2t", "msl"]);
+    let mirror_source = mirror.base_url().parse::<Source>().unwrap();
+    let client = Client::new(ClientOptions {
+        fallback_source: Some(fallback.base_url().parse().unwrap()),
+        ..primary.options()
+    })
+    .unwrap();
+
+    let costs = [SourceCost::new(mirror_source.clone(), 0.0)];
+    let plan = client.plan_sources(request(&fallback, "plan.grib2").step(0).param("msl"), &costs).unwrap();
+    assert_eq!(plan.plan.transfers[0].source.base_url(), fallback.base_url());
+    let path = "/20240101/00z/ifs/0p25/oper/20240101000000-0h-oper-fc.grib2";
+    assert_eq!(plan.assignments[0].url, format!("{}{path}", mirror.base_url()));
+    assert_eq!(plan.assignments[0].source, mirror_source);
+}
+
+#[test]
+fn cancelled_retrieval_stops_and_removes_the_target() {
+    let server = published(&[0, 6, 12], &["2t", "msl"]);
+    let cancel = CancellationToken::new();
+    let on_data = cancel.clone();
+    let client = Client::new(ClientOptions {
+        // Cancel as soon as the first data response arrives.
+        http_hook: Some(HttpHook::new(move |event| {
+            if let HttpEvent::Response { url, .. } = event
+                && url.ends_with(".grib2")
+            {
+                on_data.cancel();
+            }
+        })),
+        ..server.options()
+    })
+    .unwrap();
+
+    let req = request(&server, "cancelled.grib2").step([0, 6, 12]).param("msl");
+    let err = client.retrieve_cancellable(req, &cancel).unwrap_err();
+    assert!(matches!(err, Error::Cancelled), "{err}");
+    assert!(!server.target("cancelled.grib2").exists());
+    let data_gets = server.seen().iter().filter(|s| s.path.ends_with(".grib2")).count();
+    assert_eq!(data_gets, 1);
+}
+
+#[test]
+fn failing_fields_are_skipped_when_asked() {
+    let server = published(&[0], &["2t", "msl", "10u"]);
+    // Cut the data file before `10u`: its range is refused with 416.
+    let stem = format!("{DATE}/00z/ifs/0p25/oper/{DATE}000000-0h-oper-fc.grib2");
+    let mut data = field_bytes("2t", 0);
+    data.extend(field_bytes("msl", 0));
+    server.write(&stem, &data);
+    let req = || request(&server, "partial.grib2").step(0).param(["2t", "10u"]);
+
+    assert!(Client::new(server.options()).unwrap().retrieve_request(req()).is_err());
+
+    let client = Client::new(ClientOptions {
+        skip_failed_fields: true,
+        ..server.options()
+    })
+    .unwrap();
+    let result = client.retrieve_request(req()).unwrap();
+    assert_eq!(fs::read(&result.target).unwrap(), field_bytes("2t", 0));
+    assert_eq!(result.fields.len(), 1);
+    assert_eq!(result.fields[0].param, "2t");
+    assert_eq!(result.failed_fields.len(), 1);
+    assert_eq!(result.failed_fields[0].param, "10u");
+}
+
+#[test]
+fn duplicate_index_fields_follow_the_policy() {
+    let server = FixtureServer::start();
+    // `2t` is listed twice: the republished copy is appended to the file.
+    let stem = format!("{DATE}/00z/ifs/0p25/oper/{DATE}000000-0h-oper-fc");
+    let fields = [field_bytes("2t", 0), field_bytes("msl", 0), b"GRIB-2t-0-fixed-7777".to_vec()];
+    let (mut data, mut index) = (Vec::new(), String::new());
+    for (field, param) in fields.iter().zip(["2t", "msl", "2t"]) {
+        index.push_str(&format!(
+            r#"{{"date": "{DATE}", "time": "0000", "stream": "oper", "type": "fc", "step": "0", "levtype": "sfc", "param": "{param}", "_offset": {}, "_length": {}}}"#,
+            data.len(),
+            field.len()
+        ));
+        index.push('\n');
+        data.extend_from_slice(field);
+    }
+    server.write(&format!("{stem}.grib2"), &data);
+    server.write(&format!("{stem}.index"), index.as_bytes());
+    let retrieve = |policy: &str, name: &str| {
+        let client = Client::new(ClientOptions {
+            duplicate_fields: policy.parse().unwrap(),
+            ..server.options()
+        })
+        .unwrap();
+        client.retrieve_request(request(&server, name).step(0).param("2t"))
+    };
+
+    let last = retrieve("last", "last.grib2").unwrap();
+    assert_eq!(fs::read(&last.target).unwrap(), b"GRIB-2t-0-fixed-7777");
+    assert!(last.warnings.is_empty(), "{:?}", last.warnings);
+    let first = retrieve("first", "first.grib2").unwrap();
+    assert_eq!(fs::read(&first.target).unwrap(), field_bytes("2t", 0));
+
+    let both = retrieve("warn", "both.grib2").unwrap();
+    assert_eq!(both.fields.len(), 2);
+    assert!(both.warnings.iter().any(|w| w.contains("param=2t step=0 more than once")), "{:?}", both.warnings);
+
+    let err = retrieve("error", "none.grib2").unwrap_err();
+    assert!(matches!(err, Error::DuplicateIndexEntry { ref url, .. } if url.ends_with("-0h-oper-fc.index")), "{err}");
+}
+
+#[test]
+fn results_report_every_cycle_of_the_request() {
+    let server = published(&[0], &["2t"]);
+    server.add_cycle(DATE, 12, &[0], &["2t"]);
+    let client = Client::new(server.options()).unwrap();
+
+    let result = client
+        .retrieve_request(request(&server, "cycles.grib2").time([12, 0]).step(0).param("2t"))
+        .unwrap();
+    let cycle = |hour| Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap();
+    assert_eq!(result.datetimes, [cycle(0), cycle(12)]);
+    assert_eq!(fs::read(&result.target).unwrap().len(), 2 * field_bytes("2t", 0).len());
+    #[allow(deprecated)]
+    let first = result.datetime();
+    assert_eq!(first, cycle(0));
+    let valid_times: Vec<_> = result.iter_lead_times().map(|lt| lt.valid_time).collect();
+    assert_eq!(valid_times, [cycle(0), cycle(12)]);
+
+    // Both cycles are topped up, without naming them again.
+    let mut manifest = Manifest::from_result(&result);
+    assert_eq!(manifest.datetimes, result.datetimes);
+    server.add_cycle(DATE, 0, &[0], &["2t", "msl"]);
+    server.add_cycle(DATE, 12, &[0], &["2t", "msl"]);
+    let req = Request::new().time([12, 0]).step(0).param(["2t", "msl"]);
+    let delta = client.retrieve_delta(req, &mut manifest).unwrap();
+    assert_eq!(delta.fields.len(), 2);
+    assert!(delta.fields.iter().all(|f| f.param == "msl"));
+
+    let err = client.retrieve_delta(request(&server, "cycles.grib2").step(0).param("2t"), &mut manifest);
+    assert!(matches!(err, Err(Error::InvalidRequest(_))), "{err:?}");
+}
+
+#[test]
+fn session_quota_counts_downloads_per_source() {
+    let server = published(&[0], &["2t", "msl"]);
+    let req = |name: &str| request(&server, name).step(0).param("2t");
+    let quota = |action| Quota { max_bytes: 1, action };
+
+    let client = Client::builder().source(server.base_url().parse().unwrap()).quota(quota(QuotaAction::Error));
+    let client = client.build().unwrap();
+    client.retrieve_request(req("first.grib2")).unwrap();
+    let usage = client.usage();
+    let size = field_bytes("2t", 0).len() as u64;
+    assert_eq!(usage.total_bytes, size);
+    assert_eq!(usage.per_source.get(&server.options().source.to_string()), Some(&size));
+    let err = client.retrieve_request(req("second.grib2")).unwrap_err();
+    assert!(matches!(err, Error::QuotaExceeded { max_bytes: 1, .. }), "{err}");
+    client.reset_usage();
+    assert_eq!(client.usage().total_bytes, 0);
+
+    let client = Client::new(ClientOptions { quota: Some(quota(QuotaAction::Warn)), ..server.options() }).unwrap();
+    let result = client.retrieve_request(req("warned.grib2")).unwrap();
+    assert!(result.warnings.iter().any(|w| w.contains("session quota of 1 bytes exceeded")), "{:?}", result.warnings);
+}
+
+#[test]
+fn allowed_hosts_refuse_other_hosts_and_redirects_to_them() {
+    let server = published(&[0], &["2t", "msl"]);
+    let req = |name: &str| request(&server, name).step(0).param("msl");
+
+    let client = Client::builder().source(server.base_url().parse().unwrap()).allowed_hosts(["127.0.0.1"]);
+    let result = client.build().unwrap().retrieve_request(req("allowed.grib2")).unwrap();
+    assert_eq!(fs::read(&result.target).unwrap(), field_bytes("msl", 0));
+
+    let opts = ClientOptions { allowed_hosts: Some(vec!["data.ecmwf.int".into()]), ..server.options() };
+    let err = Client::new(opts).unwrap().retrieve_request(req("refused.grib2")).unwrap_err();
+    assert!(matches!(&err, Error::HostNotAllowed { host } if host == "127.0.0.1"), "{err}");
+
+    // A mirror on an allowed host redirecting to the same server by another name.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let redirector = format!("http://{}", listener.local_addr().unwrap());
+    let target = server.base_url().replace("127.0.0.1", "localhost");
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            let path = line.split(' ').nth(1).unwrap_or("/");
+            let response = format!("HTTP/1.1 302 Found\r\nLocation: {target}{path}\r\nContent-Length: 0\r\n\r\n");
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    let client = Client::builder().source(redirector.parse().unwrap()).allowed_hosts(["127.0.0.1"]);
+    let before = server.seen().len();
+    let err = client.build().unwrap().retrieve_request(req("redirected.grib2")).unwrap_err();
+    assert!(matches!(&err, Error::HostNotAllowed { host } if host == "localhost"), "{err}");
+    assert_eq!(server.seen().len(), before);
+
+    let http = reqwest::blocking::Client::new();
+    let opts = ClientOptions { allowed_hosts: Some(vec!["127.0.0.1".into()]), ..server.options() };
+    let err = Client::with_http_client(opts, http).unwrap_err();
+    assert!(matches!(err, Error::InvalidRequest(_)), "{err}");
+}
+
+#[test]
+fn custom_headers_only_reach_the_primary_source() {
+    let primary = FixtureServer::start();
+    let fallback = FixtureServer::start();
+    fallback.add_cycle(DATE, 0, &[0], &["2t", "msl"]);
+    let api_key = |seen: &ecmwf_opendata::testing::Seen| seen.headers.iter().any(|(name, _)| name == "x-api-key");
+    let options = |source: &str| ClientOptions {
+        source: source.parse().unwrap(),
+        headers: vec![("X-Api-Key".to_string(), "secret".to_string())],
+        ..fallback.options()
+    };
+
+    let client = Client::new(ClientOptions {
+        fallback_source: Some(fallback.base_url().parse().unwrap()),
+        ..options(&primary.base_url())
+    })
+    .unwrap();
+    let result = client.retrieve_request(request(&fallback, "fallback.grib2").step(0).param("msl")).unwrap();
+    assert_eq!(fs::read(&result.target).unwrap(), field_bytes("msl", 0));
+    assert!(!primary.seen().is_empty() && primary.seen().iter().all(api_key));
+    assert!(!fallback.seen().is_empty() && !fallback.seen().iter().any(api_key));
+
+    // A primary source redirecting to another host.
+    let target = fallback.base_url().replace("127.0.0.1", "localhost");
+    let redirector = redirector(move |_, path| format!("{target}{path}"));
+    let before = fallback.seen().len();
+    let client = Client::new(options(&redirector)).unwrap();
+    let result = client.retrieve_request(request(&fallback, "redirected.grib2").step(0).param("msl")).unwrap();
+    assert_eq!(fs::read(&result.target).unwrap(), field_bytes("msl", 0));
+    let redirected = &fallback.seen()[before..];
+    assert!(!redirected.is_empty() && !redirected.iter().any(api_key), "{redirected:?}");
+}
+
+#[test]
+fn cross_host_redirect_loops_fail() {
+    let server = published(&[0], &["msl"]);
+    // Sends 127.0.0.1 to localhost and back, so every hop changes host.
+    let redirector = redirector(|host, path| {
+        let (name, port) = host.split_once(':').unwrap();
+        let other = if name == "localhost" { "127.0.0.1" } else { "localhost" };
+        format!("http://{other}:{port}{path}")
+    });
+    let client = Client::new(ClientOptions {
+        source: redirector.parse().unwrap(),
+        headers: vec![("X-Api-Key".to_string(), "secret".to_string())],
+        ..server.options()
+    })
+    .unwrap();
+
+    let err = client.retrieve_request(request(&server, "loop.grib2").step(0).param("msl")).unwrap_err();
+    assert!(matches!(err, Error::TooManyRedirects { max: 10, .. }), "{err}");
+}
+
+#[test]
+fn injected_http_client_sends_every_request() {
+    let server = published(&[0], &["2t", "msl"]);
+    let http = reqwest::blocking::Client::builder().user_agent("app-pool/1.0").build().unwrap();
+    let client = Client::with_http_client(server.options(), http).unwrap();
+
+    let result = client.retrieve_request(request(&server, "injected.grib2").step(0).param("msl")).unwrap();
+    assert_eq!(fs::read(&result.target).unwrap(), field_bytes("msl", 0));
+    let seen = server.seen();
+    assert!(!seen.is_empty());
+    assert!(seen.iter().all(|s| s.user_agent.as_deref() == Some("app-pool/1.0")), "{seen:?}");
+}
+
+#[test]
+fn ecmwf_tree_layout_mirrors_the_upstream_directories() {
+    let server = published(&[0, 6], &["2t", "msl"]);
+    server.add_cycle(DATE, 12, &[0, 6], &["2t", "msl"]);
+    let client = Client::new(ClientOptions {
+        layout: Layout::EcmwfTree,
+        ..server.options()
+    })
+    .unwrap();
+
+    let req = request(&server, "mirror").time([0, 12]).step([0, 6]).param("2t");
+    let result = client.retrieve_request(req).unwrap();
+    let mirror = server.target("mirror");
+    assert_eq!(std::path::Path::new(&result.target), mirror);
+    for hour in ["00", "12"] {
+        for step in [0, 6] {
+            let file = format!("{DATE}/{hour}z/ifs/0p25/oper/{DATE}{hour}0000-{step}h-oper-fc.grib2");
+            assert_eq!(fs::read(mirror.join(file)).unwrap(), field_bytes("2t", step));
+        }
+    }
+    assert_eq!(result.url_reports.len(), 4);
+    assert_eq!(result.fields.len(), 4);
+    assert_eq!(result.size_bytes, 2 * (field_bytes("2t", 0).len() + field_bytes("2t", 6).len()) as u64);
+}
+
+#[test]
+fn target_namer_names_targets_from_their_fields() {
+    let server = published(&[0], &["2t", "msl"]);
+    let archive = server.target("archive");
+    let client = Client::new(ClientOptions {
+        layout: Layout::EcmwfTree,
+        target_namer: Some(Arc::new(move |ctx: &TargetContext<'_>| {
+            let params: Vec<_> = ctx.fields.iter().map(|f| f.param.as_str()).collect();
+            let stream = &ctx.components.stream.as_ref().unwrap()[0];
+            let name = format!("{}/{stream}_{}.grib2", ctx.datetime.format("%Y/%H"), params.join("_"));
+            archive.join(name).to_string_lossy().into_owned()
+        })),
+        ..server.options()
+    })
+    .unwrap();
+
+    let result = client
+        .retrieve_request(request(&server, "ignored.grib2").step(0).param(["msl", "2t"]))
+        .unwrap();
+    let expected = server.target("archive").join("2024/00/oper_2t_msl.grib2");
+    assert_eq!(std::path::Path::new(&result.target), expected);
+    assert_eq!(fs::read(expected).unwrap(), [field_bytes("2t", 0), field_bytes("msl", 0)].concat());
+
+    // Routed and filtered targets are named from the fields selected for them.
+    let req = request(&server, "rest.grib2").step(0).param(["msl", "2t"]);
+    let results = client.retrieve_routed(req, &[Route::new("surface.grib2").when("param", "2t")]).unwrap();
+    let targets: Vec<_> = results.iter().map(|r| std::path::PathBuf::from(&r.target)).collect();
+    let archive = server.target("archive").join("2024/00");
+    assert_eq!(targets, [archive.join("oper_2t.grib2"), archive.join("oper_msl.grib2")]);
+    assert_eq!(fs::read(&targets[1]).unwrap(), field_bytes("msl", 0));
+
+    let req = request(&server, "filtered.grib2").step(0);
+    let result = client.retrieve_filtered(req, |e| e.param.as_deref() == Some("2t")).unwrap();
+    assert_eq!(std::path::Path::new(&result.target), targets[0]);
+}
+
+#[test]
+fn lint_reports_problems_offline_and_checks_availability_on_request() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl"]);
+    let messages = |lints: Vec<Lint>| -> Vec<(Severity, String)> {
+        lints.into_iter().map(|l| (l.severity, l.message)).collect()
+    };
+
+    let offline = messages(client.lint(&Request::new().date(DATE).kw("parm", "2t"), false));
+    assert_eq!(offline[0], (Severity::Error, "unknown request keyword `parm`; did you mean `param`?".to_string()));
+    assert_eq!(offline[1].0, Severity::Warning);
+    let mixed = messages(client.lint(&Request::new().date(DATE).param(["swh", "2t"]), false));
+    assert!(mixed[0].1.contains("different streams"), "{mixed:?}");
+    let typo = messages(client.lint(&Request::new().date(DATE).stream("enso").param("2t"), false));
+    assert!(typo.contains(&(Severity::Warning, "unknown stream `enso`; did you mean `enfo`?".to_string())), "{typo:?}");
+    assert!(server.seen().is_empty());
+
+    assert!(client.lint(&request(&server, "ok.grib2").step(6).param("msl"), true).is_empty());
+    let missing = messages(client.lint(&request(&server, "x.grib2").step([0, 12]).param("msl"), true));
+    assert_eq!(missing.len(), 1);
+    assert_eq!(missing[0].0, Severity::Error);
+    assert!(missing[0].1.ends_with("-12h-oper-fc.index is not published"), "{missing:?}");
+    let unpublished = messages(client.lint(&request(&server, "y.grib2").step(0).param(["2t", "tp"]), true));
+    assert_eq!(unpublished[0].1, "param=tp is not in the index files of the 2024-01-01 00z cycle");
+}
+
+#[test]
+fn estimate_size_sums_fields_or_whole_files() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl", "10u"]);
+
+    let fields = request(&server, "estimate.grib2").step([0, 6]).param(["2t", "msl"]);
+    let selected = 2 * (field_bytes("2t", 0).len() + field_bytes("msl", 0).len());
+    assert_eq!(client.estimate_size(&fields).unwrap(), selected as u64);
+    assert!(server.seen().iter().all(|s| s.path.ends_with(".index")));
+
+    let whole = request(&server, "whole.grib2").step(6);
+    let file: usize = ["2t", "msl", "10u"].iter().map(|p| field_bytes(p, 6).len()).sum();
+    assert_eq!(client.estimate_size(&whole).unwrap(), file as u64);
+    assert!(!server.target("estimate.grib2").exists());
+}
+
+#[test]
+fn retrieve_lagged_writes_one_target_per_cycle() {
+    let server = published(&[6], &["2t", "msl"]);
+    server.add_cycle("20231231", 0, &[6], &["2t", "msl"]);
+    let client = Client::new(server.options()).unwrap();
+
+    let lagged = client
+        .retrieve_lagged(request(&server, "lagged.grib2").step(6).param("2t"), 2)
+        .unwrap();
+
+    let days: Vec<_> = lagged.cycles().iter().map(|c| c.format("%Y%m%d").to_string()).collect();
+    assert_eq!(days, ["20240101", "20231231"]);
+    let targets: Vec<_> = lagged.members.iter().map(|m| std::path::PathBuf::from(&m.target)).collect();
+    assert_eq!(targets, [server.target("lagged_2024010100.grib2"), server.target("lagged_2023123100.grib2")]);
+    for member in &lagged.members {
+        assert_eq!(fs::read(&member.target).unwrap(), field_bytes("2t", 6));
+    }
+    let target = server.target("lagged.grib2");
+    let saved = LaggedEnsemble::load(LaggedEnsemble::path_for(&target.to_string_lossy())).unwrap();
+    assert_eq!(saved, lagged);
+}
+
+#[test]
+fn urls_for_lists_whole_data_files() {
+    let server = published(&[0, 6], &["2t", "msl"]);
+    let client = Client::new(ClientOptions {
+        query_params: vec![("key".to_string(), "k".to_string())],
+        ..server.options()
+    })
+    .unwrap();
+
+    let urls = client.urls_for(&request(&server, "urls.grib2").step([0, 6]).param("2t")).unwrap();
+    assert_eq!(urls.len(), 2);
+    assert!(urls[0].ends_with(&format!("/{DATE}000000-0h-oper-fc.grib2?key=k")), "{urls:?}");
+    assert!(urls[1].contains("-6h-oper-fc.grib2"));
+    assert!(server.seen().is_empty());
+}
+
+#[test]
+fn url_reports_break_the_download_down_per_url() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl"]);
+
+    let result = client
+        .retrieve_request(request(&server, "reports.grib2").step([0, 6]).param("msl"))
+        .unwrap();
+    assert_eq!(result.url_reports.len(), 2);
+    let offset = field_bytes("2t", 0).len() as u64;
+    let report = &result.url_reports[0];
+    assert!(report.url.ends_with("-0h-oper-fc.grib2"));
+    assert_eq!(report.ranges, [(offset, offset + field_bytes("msl", 0).len() as u64 - 1)]);
+    assert_eq!(report.status, Some(206));
+    assert_eq!(result.url_reports.iter().map(|r| r.bytes).sum::<u64>(), result.size_bytes);
+
+    let whole = client.download_request(request(&server, "whole.grib2").step(6)).unwrap();
+    let report = &whole.url_reports[0];
+    assert!(report.ranges.is_empty());
+    assert_eq!(report.status, Some(200));
+    assert_eq!(report.bytes, whole.size_bytes);
+}
+
+#[test]
+fn ensemble_size_is_read_from_the_index() {
+    let server = FixtureServer::start();
+    server.add_ensemble_cycle(DATE, 0, &[0], &["2t"], 3);
+    let client = Client::new(server.options()).unwrap();
+
+    let req = || Request::new().date(DATE).time(0).step(0).param("2t");
+    assert_eq!(client.ensemble_size(req().r#type("cf")).unwrap(), 3);
+
+    let target = server.target("members.grib2");
+    let req = req().r#type("pf").number("all").target(target.to_string_lossy().as_ref());
+    let result = client.retrieve_request(req).unwrap();
+    let expected: Vec<u8> = (1..=3).flat_map(|n| member_bytes("2t", 0, n)).collect();
+    assert_eq!(fs::read(&result.target).unwrap(), expected);
+}
+
+#[test]
+fn index_lists_every_entry_without_downloading_data() {
+    let (server, client) = fixture(&[0, 6], &["2t", "msl"]);
+
+    let index = client.index(&request(&server, "unused.grib2").step([0, 6]).param("2t")).unwrap();
+
+    assert_eq!(index.len(), 2);
+    assert!(index[1].0.ends_with("-6h-oper-fc.grib2"));
+    let params: Vec<_> = index[1].1.iter().filter_map(|e| e.param.as_deref()).collect();
+    assert_eq!(params, ["2t", "msl"]);
+    assert_eq!(index[1].1[1].offset, field_bytes("2t", 6).len() as u64);
+    assert!(server.seen().iter().all(|s| s.path.ends_with(".index")));
+}